@@ -5,10 +5,21 @@
  */
 
 use crate::common::*;
+use crate::http_client::{with_stream_timeouts, HttpClientConfig, UpstreamTimeout};
+use crate::retry::{RetryBudget, RetryPolicy};
 use anyhow::Result;
 use async_trait::async_trait;
 use futures::Stream;
+use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::time::Instant;
+
+/// The provider authenticates with a static key and has no token to refresh
+#[derive(Debug, Error)]
+#[error("This provider has no token to refresh")]
+pub struct TokenRefreshUnsupported;
 
 /// Trait defining the interface for all AI service adapters
 #[async_trait]
@@ -27,68 +38,150 @@ pub trait ApiServiceAdapter: Send + Sync {
         request_body: serde_json::Value,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<serde_json::Value>> + Send>>>;
 
+    /// Whether `generate_content_stream` reads the whole upstream response before it returns, so
+    /// its first chunk says nothing about when the upstream started sending
+    fn buffers_stream(&self) -> bool {
+        false
+    }
+
     /// List available models
     async fn list_models(&self) -> Result<ModelListResponse>;
 
     /// Refresh authentication token (if applicable)
     async fn refresh_token(&self) -> Result<()>;
+
+    /// Refresh the authentication token now, even if it is still valid
+    async fn refresh_token_forced(&self) -> Result<()> {
+        Err(TokenRefreshUnsupported.into())
+    }
 }
 
 /// Factory function to create appropriate adapter based on provider type
 pub async fn create_adapter(
     provider: ModelProvider,
     config: &crate::config::Config,
+    retry_budget: &Arc<RetryBudget>,
 ) -> Result<Box<dyn ApiServiceAdapter>> {
-    match provider {
+    let http = config.http_client_for(provider.as_str());
+    let http = &http;
+    let retry = RetryPolicy::new(config.retry_settings(), retry_budget.clone());
+    let service: Box<dyn ApiServiceAdapter> = match provider {
         ModelProvider::GeminiCliOAuth => {
             let service = crate::providers::gemini::GeminiApiService::new(
-                config.gemini_oauth_creds_base64.clone(),
+                config.gemini_oauth_creds_base64.as_ref().map(|s| s.expose().to_string()),
                 config.gemini_oauth_creds_file_path.clone(),
                 config.project_id.clone(),
-                config.request_max_retries,
-                config.request_base_delay,
+                retry,
+                http,
             ).await?;
-            Ok(Box::new(service))
+            Box::new(service)
         }
         ModelProvider::OpenAICustom => {
-            let api_key = config.openai_api_key.clone()
+            let api_key = config.openai_api_key.as_ref().map(|s| s.expose().to_string())
                 .ok_or_else(|| anyhow::anyhow!("OpenAI API key is required"))?;
             let service = crate::providers::openai::OpenAIApiService::new(
                 api_key,
                 config.openai_base_url.clone(),
-                config.request_max_retries,
-                config.request_base_delay,
+                retry,
+                http,
             )?;
-            Ok(Box::new(service))
+            Box::new(service)
         }
         ModelProvider::ClaudeCustom => {
-            let api_key = config.claude_api_key.clone()
+            let api_key = config.claude_api_key.as_ref().map(|s| s.expose().to_string())
                 .ok_or_else(|| anyhow::anyhow!("Claude API key is required"))?;
             let service = crate::providers::claude::ClaudeApiService::new(
                 api_key,
                 config.claude_base_url.clone(),
-                config.request_max_retries,
-                config.request_base_delay,
+                retry,
+                http,
             )?;
-            Ok(Box::new(service))
+            Box::new(service)
         }
         ModelProvider::ClaudeKiroOAuth => {
             let service = crate::providers::kiro::KiroApiService::new(
-                config.kiro_oauth_creds_base64.clone(),
+                config.kiro_oauth_creds_base64.as_ref().map(|s| s.expose().to_string()),
                 config.kiro_oauth_creds_file_path.clone(),
-                config.request_max_retries,
-                config.request_base_delay,
+                config.param_policy(),
+                retry,
+                http,
             ).await?;
-            Ok(Box::new(service))
+            Box::new(service)
         }
         ModelProvider::OpenAIQwenOAuth => {
             let service = crate::providers::qwen::QwenApiService::new(
                 config.qwen_oauth_creds_file_path.clone(),
-                config.request_max_retries,
-                config.request_base_delay,
+                retry,
+                http,
             ).await?;
-            Ok(Box::new(service))
+            Box::new(service)
         }
+    };
+    Ok(Box::new(TimeoutAdapter::new(service, http.clone())))
+}
+
+/// Applies the request, first-byte and idle timeouts of the provider's HTTP settings
+pub struct TimeoutAdapter {
+    inner: Box<dyn ApiServiceAdapter>,
+    http: HttpClientConfig,
+}
+
+impl TimeoutAdapter {
+    pub fn new(inner: Box<dyn ApiServiceAdapter>, http: HttpClientConfig) -> Self {
+        Self { inner, http }
+    }
+
+    async fn with_request_timeout<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        let timeout = self.http.request_timeout();
+        tokio::time::timeout(timeout, call)
+            .await
+            .unwrap_or_else(|_| Err(UpstreamTimeout::Request(timeout).into()))
+    }
+}
+
+#[async_trait]
+impl ApiServiceAdapter for TimeoutAdapter {
+    async fn generate_content(
+        &self,
+        model: &str,
+        request_body: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        self.with_request_timeout(self.inner.generate_content(model, request_body)).await
+    }
+
+    async fn generate_content_stream(
+        &self,
+        model: &str,
+        request_body: serde_json::Value,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<serde_json::Value>> + Send>>> {
+        // A buffered response is a non-streaming request replayed as a stream
+        if self.inner.buffers_stream() {
+            return self.with_request_timeout(self.inner.generate_content_stream(model, request_body)).await;
+        }
+
+        // The first-byte deadline covers sending the request as well as the first chunk
+        let first_byte = self.http.first_byte_timeout();
+        let deadline = Instant::now() + first_byte;
+        let stream = tokio::time::timeout_at(deadline, self.inner.generate_content_stream(model, request_body))
+            .await
+            .unwrap_or_else(|_| Err(UpstreamTimeout::FirstByte(first_byte).into()))?;
+        Ok(with_stream_timeouts(stream, deadline, first_byte, self.http.idle_timeout()))
+    }
+
+    fn buffers_stream(&self) -> bool {
+        self.inner.buffers_stream()
+    }
+
+    async fn list_models(&self) -> Result<ModelListResponse> {
+        self.with_request_timeout(self.inner.list_models()).await
+    }
+
+    async fn refresh_token(&self) -> Result<()> {
+        self.with_request_timeout(self.inner.refresh_token()).await
+    }
+
+    async fn refresh_token_forced(&self) -> Result<()> {
+        self.with_request_timeout(self.inner.refresh_token_forced()).await
     }
 }
 
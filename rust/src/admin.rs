@@ -0,0 +1,361 @@
+/*!
+ * Admin API
+ *
+ * REST endpoints under `/admin/pools` for inspecting and managing provider pools at runtime.
+ * Changes are written back to the provider pools file; adding or removing members goes through
+ * a configuration reload so the member's adapter is built the same way as on startup.
+ */
+
+use crate::adapter::{ApiServiceAdapter, TokenRefreshUnsupported};
+use crate::auth::{constant_time_eq, AuthEndpoint};
+use crate::common::{ModelProtocol, ModelProvider};
+use crate::config::{redact_secrets, ProviderConfig};
+use crate::pool_manager::write_pools_file;
+use crate::server::{authenticate, AppError, AppState, ClientIp};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Upper bound for a forced health check request
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Routes of the admin API
+pub fn admin_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/pools", get(list_pools_handler))
+        .route("/admin/queues", get(list_queues_handler))
+        .route("/admin/keys", get(list_keys_handler))
+        .route("/admin/pools/:provider_type", post(add_member_handler))
+        .route("/admin/pools/:provider_type/:uuid", delete(remove_member_handler))
+        .route("/admin/pools/:provider_type/:uuid/:action", post(member_action_handler))
+}
+
+/// Check the request credentials against `admin_api_key`. The admin API is disabled without one.
+async fn authorize_admin(
+    state: &AppState,
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+) -> Result<(), AppError> {
+    let runtime = state.runtime.current().await;
+    let Some(admin_key) = runtime.config.admin_api_key.as_ref().map(|k| k.expose()).filter(|k| !k.is_empty()) else {
+        return Err(AppError::NotFound("Admin API is disabled".to_string()));
+    };
+
+    authenticate(state, &runtime.config, AuthEndpoint::Admin, peer, headers, params, |keys| {
+        keys.iter().any(|key| constant_time_eq(key, admin_key)).then_some(())
+    })
+}
+
+/// List all pool members with health, usage and error statistics
+async fn list_pools_handler(
+    State(state): State<Arc<AppState>>,
+    ClientIp(peer): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    authorize_admin(&state, peer, &headers, &params).await?;
+
+    let mut pools = serde_json::to_value(state.runtime.pool_manager.status().await)
+        .map_err(anyhow::Error::from)?;
+    redact_secrets(&mut pools);
+
+    Ok(Json(json!({ "pools": pools })).into_response())
+}
+
+/// Concurrency queue depth and wait times per limited credential
+async fn list_queues_handler(
+    State(state): State<Arc<AppState>>,
+    ClientIp(peer): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    authorize_admin(&state, peer, &headers, &params).await?;
+    Ok(Json(json!({ "queues": state.runtime.concurrency.stats() })).into_response())
+}
+
+/// Client API keys (without the keys themselves) and their usage
+async fn list_keys_handler(
+    State(state): State<Arc<AppState>>,
+    ClientIp(peer): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    authorize_admin(&state, peer, &headers, &params).await?;
+
+    let runtime = state.runtime.current().await;
+    let keys: Vec<Value> = runtime
+        .config
+        .api_keys
+        .iter()
+        .map(|client_key| {
+            json!({
+                "label": client_key.label,
+                "allowedProviders": client_key.allowed_providers,
+                "allowedModels": client_key.allowed_models,
+                "rpm": client_key.rpm,
+                "tpm": client_key.tpm,
+                "dailyTokenQuota": client_key.daily_token_quota,
+                "expiresAt": client_key.expires_at,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({ "keys": keys, "usage": state.runtime.client_keys.stats() })).into_response())
+}
+
+/// Add a member to a pool. A missing `uuid` is generated.
+async fn add_member_handler(
+    State(state): State<Arc<AppState>>,
+    Path(provider_type): Path<String>,
+    ClientIp(peer): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    Json(mut body): Json<Value>,
+) -> Result<Response, AppError> {
+    authorize_admin(&state, peer, &headers, &params).await?;
+
+    if ModelProvider::parse(&provider_type).is_none() {
+        return Err(AppError::BadRequest(format!("Unknown provider type: {}", provider_type)));
+    }
+    let Some(fields) = body.as_object_mut() else {
+        return Err(AppError::BadRequest("Member must be a JSON object".to_string()));
+    };
+    if !fields.get("uuid").map(Value::is_string).unwrap_or(false) {
+        fields.insert("uuid".to_string(), json!(Uuid::new_v4().to_string()));
+    }
+    let member: ProviderConfig = serde_json::from_value(body)
+        .map_err(|e| AppError::BadRequest(format!("Invalid pool member: {}", e)))?;
+    // Reading files, running commands and reading the environment is for whoever edits the pools
+    // file, not for API callers; members added here hold literal credentials only
+    let references = member.local_references();
+    if !references.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "file:, cmd: and env: references and ${{VAR}} interpolation are not accepted through the admin API: {}",
+            references.join(", ")
+        )));
+    }
+
+    let uuid = member.uuid.clone();
+    apply_pools(&state, |pools| {
+        if pools.values().flatten().any(|m| m.uuid == member.uuid) {
+            return Err(AppError::BadRequest(format!("Duplicate pool member uuid: {}", member.uuid)));
+        }
+        pools.entry(provider_type.clone()).or_default().push(member);
+        Ok(())
+    })
+    .await?;
+
+    info!("Admin: added pool member {} ({})", provider_type, uuid);
+    Ok((StatusCode::CREATED, Json(json!({ "success": true, "uuid": uuid }))).into_response())
+}
+
+/// Remove a member from a pool
+async fn remove_member_handler(
+    State(state): State<Arc<AppState>>,
+    Path((provider_type, uuid)): Path<(String, String)>,
+    ClientIp(peer): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    authorize_admin(&state, peer, &headers, &params).await?;
+    ensure_member(&state, &provider_type, &uuid).await?;
+
+    apply_pools(&state, |pools| {
+        if let Some(pool) = pools.get_mut(&provider_type) {
+            pool.retain(|m| m.uuid != uuid);
+            if pool.is_empty() {
+                pools.remove(&provider_type);
+            }
+        }
+        Ok(())
+    })
+    .await?;
+
+    info!("Admin: removed pool member {} ({})", provider_type, uuid);
+    Ok(Json(json!({ "success": true })).into_response())
+}
+
+/// Member actions: `enable`, `disable`, `health-check`, `refresh-token` and `reset`
+async fn member_action_handler(
+    State(state): State<Arc<AppState>>,
+    Path((provider_type, uuid, action)): Path<(String, String, String)>,
+    ClientIp(peer): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, AppError> {
+    authorize_admin(&state, peer, &headers, &params).await?;
+    ensure_member(&state, &provider_type, &uuid).await?;
+
+    let pool_manager = &state.runtime.pool_manager;
+    let response = match action.as_str() {
+        "enable" => {
+            pool_manager.mark_provider_healthy(&provider_type, &uuid).await;
+            json!({ "success": true, "is_healthy": true })
+        }
+        "disable" => {
+            pool_manager.mark_provider_unhealthy(&provider_type, &uuid).await;
+            json!({ "success": true, "is_healthy": false })
+        }
+        "reset" => {
+            pool_manager.reset_counters(&provider_type, &uuid).await;
+            json!({ "success": true })
+        }
+        "health-check" => {
+            let result = check_member_health(&state, &provider_type, &uuid).await;
+            match &result {
+                Ok(()) => pool_manager.mark_provider_healthy(&provider_type, &uuid).await,
+                Err(_) => pool_manager.mark_provider_unhealthy(&provider_type, &uuid).await,
+            }
+            json!({
+                "success": true,
+                "is_healthy": result.is_ok(),
+                "error": result.err().map(|e| format!("{:#}", e)),
+            })
+        }
+        "refresh-token" => {
+            let adapter = member_adapter(&state, &uuid).await?;
+            if let Err(e) = adapter.refresh_token_forced().await {
+                if e.is::<TokenRefreshUnsupported>() {
+                    return Ok((
+                        StatusCode::NOT_IMPLEMENTED,
+                        Json(json!({ "success": false, "error": format!("{}: {}", provider_type, e) })),
+                    )
+                        .into_response());
+                }
+                warn!("Admin: token refresh failed for {} ({}): {:#}", provider_type, uuid, e);
+                return Ok((
+                    StatusCode::BAD_GATEWAY,
+                    Json(json!({ "success": false, "error": format!("{:#}", e) })),
+                )
+                    .into_response());
+            }
+            json!({ "success": true })
+        }
+        _ => return Err(AppError::NotFound(format!("Unknown action: {}", action))),
+    };
+
+    persist_pools(&state).await?;
+    info!("Admin: {} on pool member {} ({})", action, provider_type, uuid);
+    Ok(Json(response).into_response())
+}
+
+async fn ensure_member(state: &AppState, provider_type: &str, uuid: &str) -> Result<(), AppError> {
+    if state.runtime.pool_manager.contains(provider_type, uuid).await {
+        Ok(())
+    } else {
+        Err(AppError::NotFound(format!("Pool member not found: {} ({})", provider_type, uuid)))
+    }
+}
+
+async fn member_adapter(state: &AppState, uuid: &str) -> Result<Arc<dyn ApiServiceAdapter>, AppError> {
+    state
+        .runtime
+        .current()
+        .await
+        .pool_adapters
+        .get(uuid)
+        .cloned()
+        .ok_or_else(|| AppError::BadRequest(format!("Pool member {} has no usable adapter", uuid)))
+}
+
+/// Send a minimal request through the member's adapter
+async fn check_member_health(state: &AppState, provider_type: &str, uuid: &str) -> anyhow::Result<()> {
+    let adapter = member_adapter(state, uuid)
+        .await
+        .map_err(|_| anyhow::anyhow!("Pool member {} has no usable adapter", uuid))?;
+    let provider = ModelProvider::parse(provider_type)
+        .ok_or_else(|| anyhow::anyhow!("Unknown provider type: {}", provider_type))?;
+    let check_model = state
+        .runtime
+        .pool_manager
+        .members(provider_type)
+        .await
+        .into_iter()
+        .find(|m| m.uuid == uuid)
+        .and_then(|m| m.check_model_name);
+    let model = check_model.unwrap_or_else(|| default_check_model(&provider).to_string());
+
+    let request = health_check_request(provider.protocol(), &model);
+    tokio::time::timeout(HEALTH_CHECK_TIMEOUT, adapter.generate_content(&model, request))
+        .await
+        .map_err(|_| anyhow::anyhow!("Health check timed out"))??;
+    Ok(())
+}
+
+fn default_check_model(provider: &ModelProvider) -> &'static str {
+    match provider {
+        ModelProvider::GeminiCliOAuth => "gemini-2.5-flash",
+        ModelProvider::OpenAICustom => "gpt-4o-mini",
+        ModelProvider::ClaudeCustom => "claude-3-5-haiku-20241022",
+        ModelProvider::ClaudeKiroOAuth => "claude-sonnet-4-20250514",
+        ModelProvider::OpenAIQwenOAuth => "qwen3-coder-flash",
+    }
+}
+
+/// Smallest valid generation request in the provider's native protocol
+fn health_check_request(protocol: ModelProtocol, model: &str) -> Value {
+    match protocol {
+        ModelProtocol::Gemini => json!({
+            "contents": [{ "role": "user", "parts": [{ "text": "Hi" }] }],
+            "generationConfig": { "maxOutputTokens": 1 }
+        }),
+        ModelProtocol::OpenAI => json!({
+            "model": model,
+            "messages": [{ "role": "user", "content": "Hi" }],
+            "max_tokens": 1
+        }),
+        // The Kiro adapter takes Claude requests and converts them itself
+        ModelProtocol::Claude | ModelProtocol::Kiro => json!({
+            "model": model,
+            "messages": [{ "role": "user", "content": "Hi" }],
+            "max_tokens": 1
+        }),
+    }
+}
+
+/// Write the in-memory pools back to the pools file, if one is configured
+async fn persist_pools(state: &AppState) -> Result<(), AppError> {
+    let _guard = state.pools_file_lock.lock().await;
+    let runtime = state.runtime.current().await;
+    if let Some(path) = runtime.config.provider_pools_file_path.as_deref() {
+        state.runtime.pool_manager.save_to_file(path).await?;
+    }
+    Ok(())
+}
+
+/// Change the pools, write them to the pools file and reload, restoring the previous file if
+/// the reload fails. Admin changes run one at a time so none starts from a stale snapshot.
+async fn apply_pools(
+    state: &AppState,
+    change: impl FnOnce(&mut HashMap<String, Vec<ProviderConfig>>) -> Result<(), AppError>,
+) -> Result<(), AppError> {
+    let _guard = state.pools_file_lock.lock().await;
+    let runtime = state.runtime.current().await;
+    let Some(path) = runtime.config.provider_pools_file_path.clone() else {
+        return Err(AppError::BadRequest(
+            "provider_pools_file_path must be configured to add or remove pool members".to_string(),
+        ));
+    };
+
+    let previous = state.runtime.pool_manager.snapshot().await;
+    let mut pools = previous.clone();
+    change(&mut pools)?;
+    write_pools_file(&path, &pools).await?;
+    if let Err(e) = state.runtime.reload().await {
+        write_pools_file(&path, &previous).await?;
+        return Err(AppError::BadRequest(format!("Pool change rejected: {:#}", e)));
+    }
+    Ok(())
+}
@@ -0,0 +1,244 @@
+/*!
+ * Client Authentication
+ *
+ * Credential extraction and comparison for incoming requests:
+ *
+ * - keys are compared in constant time (both sides are hashed first, so lengths don't leak)
+ * - client keys may be stored as salted SHA-256 hashes (`sha256$<salt>$<digest>`, see
+ *   `hash_key`) instead of plain text
+ * - each endpoint group only accepts credentials from its configured locations
+ * - client IPs are locked out for a while after repeated failed attempts
+ */
+
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+const HASH_SCHEME: &str = "sha256";
+const SALT_LEN: usize = 16;
+
+/// Where a request may carry its API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialLocation {
+    /// `Authorization: Bearer <key>`
+    Bearer,
+    /// `x-api-key` header (Claude clients)
+    XApiKey,
+    /// `x-goog-api-key` header (Gemini clients)
+    XGoogApiKey,
+    /// `?key=` query parameter (Gemini clients)
+    Query,
+}
+
+impl CredentialLocation {
+    pub const HEADERS: [CredentialLocation; 3] = [Self::Bearer, Self::XApiKey, Self::XGoogApiKey];
+    pub const ALL: [CredentialLocation; 4] = [Self::Bearer, Self::XApiKey, Self::XGoogApiKey, Self::Query];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bearer => "bearer",
+            Self::XApiKey => "x-api-key",
+            Self::XGoogApiKey => "x-goog-api-key",
+            Self::Query => "query",
+        }
+    }
+}
+
+/// Endpoint groups with separately configurable credential locations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthEndpoint {
+    OpenAi,
+    Claude,
+    Gemini,
+    Admin,
+}
+
+impl AuthEndpoint {
+    /// Key in the `auth_locations` config map
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::OpenAi => "openai",
+            Self::Claude => "claude",
+            Self::Gemini => "gemini",
+            Self::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "openai" => Some(Self::OpenAi),
+            "claude" => Some(Self::Claude),
+            "gemini" => Some(Self::Gemini),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+
+    /// Headers everywhere; the query string only where Google clients need it
+    pub fn default_locations(&self) -> Vec<CredentialLocation> {
+        match self {
+            Self::Gemini => CredentialLocation::ALL.to_vec(),
+            _ => CredentialLocation::HEADERS.to_vec(),
+        }
+    }
+}
+
+/// Keys presented by a request in the allowed locations
+pub fn presented_keys<'a>(
+    headers: &'a HeaderMap,
+    params: &'a HashMap<String, String>,
+    locations: &[CredentialLocation],
+) -> Vec<&'a str> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    locations
+        .iter()
+        .filter_map(|location| match location {
+            CredentialLocation::Bearer => header("authorization").and_then(|v| v.strip_prefix("Bearer ")),
+            CredentialLocation::XApiKey => header("x-api-key"),
+            CredentialLocation::XGoogApiKey => header("x-goog-api-key"),
+            CredentialLocation::Query => params.get("key").map(String::as_str),
+        })
+        .collect()
+}
+
+/// Compare two keys in constant time
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    let a = digest(&SHA256, a.as_bytes());
+    let b = digest(&SHA256, b.as_bytes());
+    a.as_ref().ct_eq(b.as_ref()).into()
+}
+
+/// Salted hash of a key for the `key_hash` field of `api_keys`
+pub fn hash_key(key: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("system random number generator failed");
+    format!(
+        "{}${}${}",
+        HASH_SCHEME,
+        URL_SAFE_NO_PAD.encode(salt),
+        URL_SAFE_NO_PAD.encode(salted_digest(&salt, key))
+    )
+}
+
+/// Check a presented key against a hash produced by `hash_key`
+pub fn verify_key_hash(key: &str, hash: &str) -> bool {
+    let Some((salt, expected)) = parse_key_hash(hash) else {
+        return false;
+    };
+    salted_digest(&salt, key).ct_eq(&expected).into()
+}
+
+/// Whether `hash` has the format produced by `hash_key`
+pub fn is_valid_key_hash(hash: &str) -> bool {
+    parse_key_hash(hash).is_some()
+}
+
+/// Random key suitable for a client (`sk-` followed by 32 random bytes)
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+    format!("sk-{}", URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn parse_key_hash(hash: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut parts = hash.split('$');
+    if parts.next()? != HASH_SCHEME {
+        return None;
+    }
+    let salt = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+    let digest = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+    if parts.next().is_some() || salt.is_empty() || digest.len() != SHA256.output_len() {
+        return None;
+    }
+    Some((salt, digest))
+}
+
+fn salted_digest(salt: &[u8], key: &str) -> Vec<u8> {
+    let mut input = Vec::with_capacity(salt.len() + key.len());
+    input.extend_from_slice(salt);
+    input.extend_from_slice(key.as_bytes());
+    digest(&SHA256, &input).as_ref().to_vec()
+}
+
+/// Lockout after `max_failures` failed attempts within `window`
+#[derive(Debug, Clone, Copy)]
+pub struct LockoutPolicy {
+    /// Lockout disabled if 0
+    pub max_failures: u32,
+    pub window: Duration,
+    pub duration: Duration,
+}
+
+#[derive(Default)]
+struct FailureRecord {
+    failures: VecDeque<Instant>,
+    locked_until: Option<Instant>,
+}
+
+/// Failed authentication attempts per client IP, kept across configuration reloads
+#[derive(Default)]
+pub struct AuthFailureTracker {
+    records: Mutex<HashMap<IpAddr, FailureRecord>>,
+}
+
+impl AuthFailureTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remaining lockout time of `ip`, if it is locked out
+    pub fn locked_for(&self, ip: IpAddr) -> Option<Duration> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let record = records.get_mut(&ip)?;
+        let remaining = record.locked_until?.checked_duration_since(Instant::now());
+        if remaining.is_none() {
+            records.remove(&ip);
+        }
+        remaining
+    }
+
+    /// Record a failed attempt; returns the number of failures in the window and whether the
+    /// IP is now locked out
+    pub fn record_failure(&self, ip: IpAddr, policy: &LockoutPolicy) -> (usize, bool) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        // Forget IPs whose failures have all expired so the map stays bounded
+        records.retain(|_, r| {
+            r.locked_until.is_some_and(|until| until > now)
+                || r.failures.back().is_some_and(|t| now.duration_since(*t) < policy.window)
+        });
+
+        let record = records.entry(ip).or_default();
+        while record.failures.front().is_some_and(|t| now.duration_since(*t) >= policy.window) {
+            record.failures.pop_front();
+        }
+        record.failures.push_back(now);
+
+        let failures = record.failures.len();
+        let locked = policy.max_failures > 0 && failures >= policy.max_failures as usize;
+        if locked {
+            record.locked_until = Some(now + policy.duration);
+            record.failures.clear();
+        }
+        (failures, locked)
+    }
+
+    /// Clear the failures of an IP after a successful attempt
+    pub fn record_success(&self, ip: IpAddr) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.remove(&ip);
+    }
+}
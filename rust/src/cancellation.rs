@@ -0,0 +1,59 @@
+/*!
+ * Client Cancellation
+ *
+ * When a client aborts a request or closes an SSE connection, hyper drops the handler future
+ * (or the response body stream). The upstream call lives entirely inside it: the reqwest
+ * request, the retry loop and any backoff sleep, so dropping it aborts the upstream request and
+ * stops further retries. Nothing upstream may be spawned onto its own task for this to hold.
+ *
+ * `CancelGuard` notices that drop so it can be recorded as a client cancel rather than silently
+ * disappearing; pool leases that are dropped this way are released without counting as errors.
+ */
+
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+type BoxStream<T> = Pin<Box<dyn Stream<Item = Result<T>> + Send>>;
+type OnCancel = Box<dyn FnOnce(Duration) + Send + Sync>;
+
+/// Calls `on_cancel` with the elapsed time if dropped before `complete` is called
+pub struct CancelGuard {
+    on_cancel: Option<OnCancel>,
+    started: Instant,
+}
+
+impl CancelGuard {
+    pub fn new(on_cancel: impl FnOnce(Duration) + Send + Sync + 'static) -> Self {
+        Self {
+            on_cancel: Some(Box::new(on_cancel)),
+            started: Instant::now(),
+        }
+    }
+
+    /// The request finished (successfully or not) without being cancelled
+    pub fn complete(mut self) {
+        self.on_cancel = None;
+    }
+
+    /// Complete the guard when `stream` ends; dropping the stream early counts as a cancel
+    pub fn guard_stream<T: Send + 'static>(self, stream: BoxStream<T>) -> BoxStream<T> {
+        Box::pin(async_stream::stream! {
+            let guard = self;
+            let mut stream = stream;
+            while let Some(item) = stream.next().await {
+                yield item;
+            }
+            guard.complete();
+        })
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(on_cancel) = self.on_cancel.take() {
+            on_cancel(self.started.elapsed());
+        }
+    }
+}
@@ -0,0 +1,452 @@
+/*!
+ * Command-Line Interface
+ *
+ * clap-derived CLI. Every `Config` field can be set as a flag or through an `AIC2API_*`
+ * environment variable. Layers are applied as defaults, config file, environment, flags.
+ *
+ * List and map fields (`api_keys`, `auth_locations`, `provider_http`) take JSON; `http` is set
+ * field by field through the `http_*` flags. Exempt are the values read from other files
+ * (`provider_pools`, `system_prompt_content`) and the bookkeeping of loading itself
+ * (`config_file_path`, set by `--config`, and `value_sources`).
+ */
+
+use crate::auth::CredentialLocation;
+use crate::config::{ClientKeyConfig, Config, ConfigLayer};
+use crate::http_client::HttpClientConfig;
+use crate::secrets::Secret;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "aiclient2api-rust",
+    version,
+    about = "Unified OpenAI/Claude/Gemini-compatible proxy for client-only model APIs",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Server options when no subcommand is given (same as `serve`)
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the API server (default)
+    Serve(ConfigArgs),
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// List the models of each configured provider
+    Models(ModelsArgs),
+    /// Inspect OAuth credentials
+    #[command(subcommand)]
+    Creds(CredsCommand),
+    /// Manage client API keys
+    #[command(subcommand)]
+    Keys(KeysCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and print the effective values with secrets redacted
+    Check(ConfigArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CredsCommand {
+    /// Show token expiry of the primary provider and all pool members
+    Status(ConfigArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeysCommand {
+    /// Print the salted hash of a key for the `key_hash` field of `api_keys`
+    Hash(HashKeyArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct HashKeyArgs {
+    /// Key to hash; a random key is generated and printed if omitted
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ModelsArgs {
+    /// Only list models of this provider
+    #[arg(long = "provider")]
+    pub only_provider: Option<String>,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+/// Configuration overrides shared by all subcommands
+#[derive(Debug, Default, Clone, Args)]
+pub struct ConfigArgs {
+    /// Path of the configuration file
+    #[arg(short, long = "config", env = "AIC2API_CONFIG", default_value = "config.json")]
+    pub config_file: PathBuf,
+
+    #[arg(long, env = "AIC2API_HOST")]
+    pub host: Option<String>,
+    #[arg(long, env = "AIC2API_PORT")]
+    pub port: Option<u16>,
+    /// Comma-separated listen addresses (`host:port` or `unix:/path/to.sock`)
+    #[arg(long, env = "AIC2API_LISTEN", value_delimiter = ',')]
+    pub listen: Option<Vec<String>>,
+    #[arg(long, env = "AIC2API_TLS_CERT_PATH")]
+    pub tls_cert_path: Option<PathBuf>,
+    #[arg(long, env = "AIC2API_TLS_KEY_PATH")]
+    pub tls_key_path: Option<PathBuf>,
+    /// Comma-separated allowed CORS origins (`*` for any)
+    #[arg(long, env = "AIC2API_CORS_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub cors_allowed_origins: Option<Vec<String>>,
+    #[arg(long, env = "AIC2API_CORS_ALLOWED_METHODS", value_delimiter = ',')]
+    pub cors_allowed_methods: Option<Vec<String>>,
+    #[arg(long, env = "AIC2API_CORS_ALLOWED_HEADERS", value_delimiter = ',')]
+    pub cors_allowed_headers: Option<Vec<String>>,
+    /// Seconds streaming responses may run after SIGTERM/SIGINT before they are ended
+    #[arg(long, env = "AIC2API_SHUTDOWN_DRAIN_TIMEOUT_SECS")]
+    pub shutdown_drain_timeout_secs: Option<u64>,
+    /// API key clients must present
+    #[arg(long, alias = "api-key", env = "AIC2API_REQUIRED_API_KEY")]
+    pub required_api_key: Option<Secret>,
+    /// Client key table as a JSON array (see `api_keys` in the config file)
+    #[arg(long, env = "AIC2API_API_KEYS", value_parser = parse_json::<Vec<ClientKeyConfig>>)]
+    pub api_keys: Option<Json<Vec<ClientKeyConfig>>>,
+    /// Credential locations per endpoint group as a JSON object, e.g. `{"openai":["bearer"]}`
+    #[arg(long, env = "AIC2API_AUTH_LOCATIONS", value_parser = parse_json::<HashMap<String, Vec<CredentialLocation>>>)]
+    pub auth_locations: Option<Json<HashMap<String, Vec<CredentialLocation>>>>,
+    /// API key for the `/admin` endpoints
+    #[arg(long, env = "AIC2API_ADMIN_API_KEY")]
+    pub admin_api_key: Option<Secret>,
+    /// Failed authentication attempts from one IP before it is locked out (0 = never)
+    #[arg(long, env = "AIC2API_AUTH_LOCKOUT_MAX_FAILURES")]
+    pub auth_lockout_max_failures: Option<u32>,
+    #[arg(long, env = "AIC2API_AUTH_LOCKOUT_WINDOW_SECS")]
+    pub auth_lockout_window_secs: Option<u64>,
+    #[arg(long, env = "AIC2API_AUTH_LOCKOUT_DURATION_SECS")]
+    pub auth_lockout_duration_secs: Option<u64>,
+
+    #[arg(long, env = "AIC2API_MODEL_PROVIDER")]
+    pub model_provider: Option<String>,
+    /// Comma-separated list of providers
+    #[arg(long, env = "AIC2API_DEFAULT_MODEL_PROVIDERS", value_delimiter = ',')]
+    pub default_model_providers: Option<Vec<String>>,
+
+    #[arg(long, env = "AIC2API_OPENAI_API_KEY")]
+    pub openai_api_key: Option<Secret>,
+    #[arg(long, env = "AIC2API_OPENAI_BASE_URL")]
+    pub openai_base_url: Option<String>,
+    #[arg(long, env = "AIC2API_CLAUDE_API_KEY")]
+    pub claude_api_key: Option<Secret>,
+    #[arg(long, env = "AIC2API_CLAUDE_BASE_URL")]
+    pub claude_base_url: Option<String>,
+    #[arg(long, env = "AIC2API_GEMINI_OAUTH_CREDS_BASE64")]
+    pub gemini_oauth_creds_base64: Option<Secret>,
+    #[arg(long, alias = "gemini-oauth-creds-file", env = "AIC2API_GEMINI_OAUTH_CREDS_FILE_PATH")]
+    pub gemini_oauth_creds_file_path: Option<PathBuf>,
+    #[arg(long, env = "AIC2API_PROJECT_ID")]
+    pub project_id: Option<String>,
+    #[arg(long, env = "AIC2API_KIRO_OAUTH_CREDS_BASE64")]
+    pub kiro_oauth_creds_base64: Option<Secret>,
+    #[arg(long, alias = "kiro-oauth-creds-file", env = "AIC2API_KIRO_OAUTH_CREDS_FILE_PATH")]
+    pub kiro_oauth_creds_file_path: Option<PathBuf>,
+    #[arg(long, alias = "qwen-oauth-creds-file", env = "AIC2API_QWEN_OAUTH_CREDS_FILE_PATH")]
+    pub qwen_oauth_creds_file_path: Option<PathBuf>,
+
+    #[arg(long, env = "AIC2API_SYSTEM_PROMPT_FILE_PATH")]
+    pub system_prompt_file_path: Option<PathBuf>,
+    /// `overwrite` or `append`
+    #[arg(long, env = "AIC2API_SYSTEM_PROMPT_MODE")]
+    pub system_prompt_mode: Option<String>,
+
+    /// `none`, `console` or `file`
+    #[arg(long, alias = "log-prompts", env = "AIC2API_PROMPT_LOG_MODE")]
+    pub prompt_log_mode: Option<String>,
+    #[arg(long, env = "AIC2API_PROMPT_LOG_BASE_NAME")]
+    pub prompt_log_base_name: Option<String>,
+
+    #[arg(long, env = "AIC2API_VALIDATE_STRUCTURED_OUTPUT", num_args = 0..=1, default_missing_value = "true")]
+    pub validate_structured_output: Option<bool>,
+    /// `lenient` or `strict`
+    #[arg(long, env = "AIC2API_PARAM_MODE")]
+    pub param_mode: Option<String>,
+
+    #[arg(long, env = "AIC2API_REQUEST_MAX_RETRIES")]
+    pub request_max_retries: Option<u32>,
+    /// Base retry delay in milliseconds
+    #[arg(long, env = "AIC2API_REQUEST_BASE_DELAY")]
+    pub request_base_delay: Option<u64>,
+    /// Maximum retry delay in milliseconds
+    #[arg(long, env = "AIC2API_REQUEST_MAX_DELAY")]
+    pub request_max_delay: Option<u64>,
+    /// Total time in milliseconds for a request including its retries
+    #[arg(long, env = "AIC2API_REQUEST_DEADLINE")]
+    pub request_deadline: Option<u64>,
+    /// Retries allowed per request in each budget window (e.g. 0.2 = one retry per five requests)
+    #[arg(long, env = "AIC2API_RETRY_BUDGET_RATIO")]
+    pub retry_budget_ratio: Option<f64>,
+    /// Retries allowed per window regardless of the ratio
+    #[arg(long, env = "AIC2API_RETRY_BUDGET_MIN_RETRIES")]
+    pub retry_budget_min_retries: Option<u32>,
+    #[arg(long, env = "AIC2API_RETRY_BUDGET_WINDOW_SECS")]
+    pub retry_budget_window_secs: Option<u64>,
+
+    #[arg(long, env = "AIC2API_CRON_NEAR_MINUTES")]
+    pub cron_near_minutes: Option<u64>,
+    #[arg(long, env = "AIC2API_CRON_REFRESH_TOKEN", num_args = 0..=1, default_missing_value = "true")]
+    pub cron_refresh_token: Option<bool>,
+
+    #[arg(long, env = "AIC2API_PROVIDER_POOLS_FILE_PATH")]
+    pub provider_pools_file_path: Option<PathBuf>,
+    #[arg(long, env = "AIC2API_DEFAULT_POOL_STRATEGY")]
+    pub default_pool_strategy: Option<String>,
+    /// Per-pool strategies as comma-separated `provider=strategy` pairs
+    #[arg(long, env = "AIC2API_POOL_STRATEGIES", value_delimiter = ',', value_parser = parse_key_value)]
+    pub pool_strategies: Option<Vec<(String, String)>>,
+
+    #[arg(long, env = "AIC2API_STICKY_SESSIONS", num_args = 0..=1, default_missing_value = "true")]
+    pub sticky_sessions: Option<bool>,
+    #[arg(long, env = "AIC2API_STICKY_SESSION_HEADER")]
+    pub sticky_session_header: Option<String>,
+
+    #[arg(long, env = "AIC2API_HOT_RELOAD", num_args = 0..=1, default_missing_value = "true")]
+    pub hot_reload: Option<bool>,
+    #[arg(long, env = "AIC2API_HOT_RELOAD_INTERVAL_SECS")]
+    pub hot_reload_interval_secs: Option<u64>,
+
+    /// Max in-flight requests through the primary adapter (0 = unlimited)
+    #[arg(long, env = "AIC2API_MAX_CONCURRENCY")]
+    pub max_concurrency: Option<u32>,
+    #[arg(long, env = "AIC2API_MAX_QUEUE_SIZE")]
+    pub max_queue_size: Option<usize>,
+    #[arg(long, env = "AIC2API_QUEUE_TIMEOUT_MS")]
+    pub queue_timeout_ms: Option<u64>,
+
+    /// Outbound proxy for all providers (`http://`, `https://`, `socks5://`, or `none`)
+    #[arg(long, env = "AIC2API_HTTP_PROXY")]
+    pub http_proxy: Option<Secret>,
+    /// Total time for a non-streaming upstream request
+    #[arg(long, env = "AIC2API_HTTP_REQUEST_TIMEOUT_MS")]
+    pub http_request_timeout_ms: Option<u64>,
+    #[arg(long, env = "AIC2API_HTTP_CONNECT_TIMEOUT_MS")]
+    pub http_connect_timeout_ms: Option<u64>,
+    /// Time until the first chunk of an upstream stream arrives
+    #[arg(long, env = "AIC2API_HTTP_FIRST_BYTE_TIMEOUT_MS")]
+    pub http_first_byte_timeout_ms: Option<u64>,
+    /// Longest allowed gap between two chunks of an upstream stream
+    #[arg(long, env = "AIC2API_HTTP_IDLE_TIMEOUT_MS")]
+    pub http_idle_timeout_ms: Option<u64>,
+    /// How long an unused upstream connection is kept open
+    #[arg(long, env = "AIC2API_HTTP_POOL_IDLE_TIMEOUT_SECS")]
+    pub http_pool_idle_timeout_secs: Option<u64>,
+    #[arg(long, env = "AIC2API_HTTP_POOL_MAX_IDLE_PER_HOST")]
+    pub http_pool_max_idle_per_host: Option<usize>,
+    /// Per-provider HTTP settings as a JSON object, e.g. `{"gemini-cli-oauth":{"proxy":"none"}}`
+    #[arg(long, env = "AIC2API_PROVIDER_HTTP", value_parser = parse_json::<HashMap<String, HttpClientConfig>>)]
+    pub provider_http: Option<Json<HashMap<String, HttpClientConfig>>>,
+
+    /// Layer (environment or command line) each given value came from, keyed by field name
+    #[arg(skip)]
+    pub sources: HashMap<String, ConfigLayer>,
+}
+
+/// A flag value given as JSON
+#[derive(Debug, Clone)]
+pub struct Json<T>(pub T);
+
+fn parse_json<T: DeserializeOwned>(s: &str) -> Result<Json<T>, String> {
+    serde_json::from_str(s).map(Json).map_err(|e| format!("invalid JSON: {}", e))
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .ok_or_else(|| format!("expected `provider=strategy`, got `{}`", s))
+}
+
+impl Cli {
+    /// Parse arguments, recording whether each config value came from a flag or the environment
+    pub fn try_parse_with_sources<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(args)?;
+        let mut cli = Self::from_arg_matches(&matches)?;
+
+        let mut command_matches = &matches;
+        while let Some((_, sub)) = command_matches.subcommand() {
+            command_matches = sub;
+        }
+        cli.config_args_mut().sources = value_sources(command_matches);
+        Ok(cli)
+    }
+
+    /// Like `try_parse_with_sources` on the process arguments; prints usage and exits on error
+    pub fn parse_with_sources() -> Self {
+        Self::try_parse_with_sources(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    fn config_args_mut(&mut self) -> &mut ConfigArgs {
+        match &mut self.command {
+            Some(Command::Serve(args))
+            | Some(Command::Config(ConfigCommand::Check(args)))
+            | Some(Command::Creds(CredsCommand::Status(args))) => args,
+            Some(Command::Models(args)) => &mut args.config,
+            Some(Command::Keys(_)) | None => &mut self.config,
+        }
+    }
+
+    /// Configuration overrides of whichever command was given
+    pub fn config_args(&self) -> &ConfigArgs {
+        match &self.command {
+            Some(Command::Serve(args))
+            | Some(Command::Config(ConfigCommand::Check(args)))
+            | Some(Command::Creds(CredsCommand::Status(args))) => args,
+            Some(Command::Models(args)) => &args.config,
+            Some(Command::Keys(_)) | None => &self.config,
+        }
+    }
+}
+
+fn value_sources(matches: &ArgMatches) -> HashMap<String, ConfigLayer> {
+    let mut sources: HashMap<String, ConfigLayer> = matches
+        .ids()
+        .filter_map(|id| {
+            let layer = match matches.value_source(id.as_str())? {
+                ValueSource::CommandLine => ConfigLayer::Cli,
+                ValueSource::EnvVariable => ConfigLayer::Env,
+                _ => return None,
+            };
+            Some((id.to_string(), layer))
+        })
+        .collect();
+    // The `http_*` flags set fields of `http`; the command line wins over the environment
+    let http = sources
+        .iter()
+        .filter(|(id, _)| id.starts_with("http_"))
+        .map(|(_, layer)| *layer)
+        .max();
+    if let Some(layer) = http {
+        sources.insert("http".to_string(), layer);
+    }
+    sources
+}
+
+impl ConfigArgs {
+    /// Override config values with the ones given on the command line or in the environment
+    pub fn apply(&self, config: &mut Config) {
+        macro_rules! set {
+            ($($field:ident),* $(,)?) => {
+                $(if let Some(value) = &self.$field {
+                    config.$field = value.clone().into();
+                })*
+            };
+        }
+        macro_rules! set_some {
+            ($($field:ident),* $(,)?) => {
+                $(if let Some(value) = &self.$field {
+                    config.$field = Some(value.clone().into());
+                })*
+            };
+        }
+
+        set!(
+            host,
+            port,
+            listen,
+            cors_allowed_origins,
+            cors_allowed_methods,
+            cors_allowed_headers,
+            shutdown_drain_timeout_secs,
+            required_api_key,
+            auth_lockout_max_failures,
+            auth_lockout_window_secs,
+            auth_lockout_duration_secs,
+            model_provider,
+            default_model_providers,
+            system_prompt_file_path,
+            system_prompt_mode,
+            prompt_log_mode,
+            prompt_log_base_name,
+            validate_structured_output,
+            param_mode,
+            request_max_retries,
+            request_base_delay,
+            request_max_delay,
+            request_deadline,
+            retry_budget_ratio,
+            retry_budget_min_retries,
+            retry_budget_window_secs,
+            cron_near_minutes,
+            cron_refresh_token,
+            default_pool_strategy,
+            sticky_sessions,
+            sticky_session_header,
+            hot_reload,
+            hot_reload_interval_secs,
+            max_queue_size,
+            queue_timeout_ms,
+        );
+        set_some!(
+            tls_cert_path,
+            tls_key_path,
+            admin_api_key,
+            openai_api_key,
+            openai_base_url,
+            claude_api_key,
+            claude_base_url,
+            gemini_oauth_creds_base64,
+            gemini_oauth_creds_file_path,
+            project_id,
+            kiro_oauth_creds_base64,
+            kiro_oauth_creds_file_path,
+            qwen_oauth_creds_file_path,
+            provider_pools_file_path,
+            max_concurrency,
+        );
+
+        if let Some(proxy) = &self.http_proxy {
+            config.http.proxy = Some(proxy.clone());
+        }
+        let timeouts = [
+            (&mut config.http.request_timeout_ms, self.http_request_timeout_ms),
+            (&mut config.http.connect_timeout_ms, self.http_connect_timeout_ms),
+            (&mut config.http.first_byte_timeout_ms, self.http_first_byte_timeout_ms),
+            (&mut config.http.idle_timeout_ms, self.http_idle_timeout_ms),
+            (&mut config.http.pool_idle_timeout_secs, self.http_pool_idle_timeout_secs),
+        ];
+        for (field, value) in timeouts {
+            if value.is_some() {
+                *field = value;
+            }
+        }
+        if self.http_pool_max_idle_per_host.is_some() {
+            config.http.pool_max_idle_per_host = self.http_pool_max_idle_per_host;
+        }
+
+        if let Some(Json(api_keys)) = &self.api_keys {
+            config.api_keys = api_keys.clone();
+        }
+        if let Some(Json(locations)) = &self.auth_locations {
+            config.auth_locations.extend(locations.clone());
+        }
+        if let Some(Json(provider_http)) = &self.provider_http {
+            config.provider_http.extend(provider_http.clone());
+        }
+
+        if let Some(strategies) = &self.pool_strategies {
+            config.pool_strategies.extend(strategies.iter().cloned());
+        }
+    }
+}
@@ -0,0 +1,250 @@
+/*!
+ * Client API Keys
+ *
+ * Key table for clients of the proxy: each key has a label, optional provider/model scopes,
+ * per-minute request and token limits, a daily token quota and an expiry. The registry keeps
+ * the usage counters, which survive configuration reloads.
+ */
+
+use crate::config::ClientKeyConfig;
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum KeyRejection {
+    #[error("API key has expired")]
+    Expired,
+    #[error("API key is not allowed to use provider {0}")]
+    ProviderNotAllowed(String),
+    #[error("API key is not allowed to use model {0}")]
+    ModelNotAllowed(String),
+    #[error("Rate limit exceeded: {0}")]
+    RateLimited(String),
+    #[error("Daily token quota of {0} exhausted")]
+    QuotaExhausted(u64),
+}
+
+/// Usage of one key, as reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyUsageStats {
+    pub label: String,
+    pub requests_last_minute: usize,
+    pub tokens_last_minute: u64,
+    pub daily_tokens: u64,
+    pub total_requests: u64,
+    pub total_tokens: u64,
+    pub rejected: u64,
+    /// Requests the client abandoned before the response was complete
+    pub cancelled: u64,
+}
+
+struct KeyUsage {
+    requests: VecDeque<Instant>,
+    tokens: VecDeque<(Instant, u64)>,
+    day: NaiveDate,
+    daily_tokens: u64,
+    total_requests: u64,
+    total_tokens: u64,
+    rejected: u64,
+    cancelled: u64,
+}
+
+impl KeyUsage {
+    fn new() -> Self {
+        Self {
+            requests: VecDeque::new(),
+            tokens: VecDeque::new(),
+            day: Utc::now().date_naive(),
+            daily_tokens: 0,
+            total_requests: 0,
+            total_tokens: 0,
+            rejected: 0,
+            cancelled: 0,
+        }
+    }
+
+    /// Drop entries older than the rate window and reset the daily counter at UTC midnight
+    fn roll(&mut self, now: Instant) {
+        while self.requests.front().is_some_and(|t| now.duration_since(*t) >= WINDOW) {
+            self.requests.pop_front();
+        }
+        while self.tokens.front().is_some_and(|(t, _)| now.duration_since(*t) >= WINDOW) {
+            self.tokens.pop_front();
+        }
+        let today = Utc::now().date_naive();
+        if self.day != today {
+            self.day = today;
+            self.daily_tokens = 0;
+        }
+    }
+
+    fn tokens_last_minute(&self) -> u64 {
+        self.tokens.iter().map(|(_, n)| n).sum()
+    }
+}
+
+/// Per-key usage counters, keyed by label
+#[derive(Default)]
+pub struct ClientKeyRegistry {
+    usage: Mutex<HashMap<String, KeyUsage>>,
+}
+
+impl ClientKeyRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check scopes, expiry and limits for a request and count it if admitted.
+    ///
+    /// Token limits are checked against tokens already recorded, since the cost of the request
+    /// itself is only known once it completes.
+    pub fn admit(&self, key: &ClientKeyConfig, provider: &str, model: &str) -> Result<(), KeyRejection> {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = usage.entry(key.label.clone()).or_insert_with(KeyUsage::new);
+        let now = Instant::now();
+        entry.roll(now);
+
+        let result = check(key, entry, provider, model);
+        match result {
+            Ok(()) => {
+                entry.requests.push_back(now);
+                entry.total_requests += 1;
+            }
+            Err(_) => entry.rejected += 1,
+        }
+        result
+    }
+
+    /// Record the tokens a completed request consumed
+    pub fn record_tokens(&self, label: &str, tokens: u64) {
+        if tokens == 0 {
+            return;
+        }
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = usage.entry(label.to_string()).or_insert_with(KeyUsage::new);
+        let now = Instant::now();
+        entry.roll(now);
+        entry.tokens.push_back((now, tokens));
+        entry.daily_tokens += tokens;
+        entry.total_tokens += tokens;
+    }
+
+    /// Record a request the client cancelled
+    pub fn record_cancelled(&self, label: &str) {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        usage.entry(label.to_string()).or_insert_with(KeyUsage::new).cancelled += 1;
+    }
+
+    /// Usage of all keys that have been seen, sorted by label
+    pub fn stats(&self) -> Vec<KeyUsageStats> {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let mut stats: Vec<_> = usage
+            .iter_mut()
+            .map(|(label, entry)| {
+                entry.roll(now);
+                KeyUsageStats {
+                    label: label.clone(),
+                    requests_last_minute: entry.requests.len(),
+                    tokens_last_minute: entry.tokens_last_minute(),
+                    daily_tokens: entry.daily_tokens,
+                    total_requests: entry.total_requests,
+                    total_tokens: entry.total_tokens,
+                    rejected: entry.rejected,
+                    cancelled: entry.cancelled,
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.label.cmp(&b.label));
+        stats
+    }
+}
+
+fn check(key: &ClientKeyConfig, usage: &KeyUsage, provider: &str, model: &str) -> Result<(), KeyRejection> {
+    if key.expires_at.is_some_and(|expiry| expiry <= Utc::now()) {
+        return Err(KeyRejection::Expired);
+    }
+    if !key.allowed_providers.is_empty() && !key.allowed_providers.iter().any(|p| p == provider) {
+        return Err(KeyRejection::ProviderNotAllowed(provider.to_string()));
+    }
+    if !key.allowed_models.is_empty() && !key.allowed_models.iter().any(|pattern| model_matches(pattern, model)) {
+        return Err(KeyRejection::ModelNotAllowed(model.to_string()));
+    }
+    if let Some(rpm) = key.rpm {
+        if usage.requests.len() >= rpm as usize {
+            return Err(KeyRejection::RateLimited(format!("{} requests per minute", rpm)));
+        }
+    }
+    if let Some(tpm) = key.tpm {
+        if usage.tokens_last_minute() >= tpm {
+            return Err(KeyRejection::RateLimited(format!("{} tokens per minute", tpm)));
+        }
+    }
+    if let Some(quota) = key.daily_token_quota {
+        if usage.daily_tokens >= quota {
+            return Err(KeyRejection::QuotaExhausted(quota));
+        }
+    }
+    Ok(())
+}
+
+/// Exact model name, or a prefix pattern ending in `*` (e.g. `claude-3-5-*`)
+pub fn model_matches(pattern: &str, model: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => pattern == model,
+    }
+}
+
+/// Token counts of one request, collected from OpenAI `usage`, Claude `usage`/`message.usage`
+/// or Gemini `usageMetadata`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokenUsage {
+    pub input: u64,
+    pub output: u64,
+}
+
+impl TokenUsage {
+    /// Merge usage found in a response or stream chunk. Streams report cumulative counts, so
+    /// the largest value seen per direction wins.
+    pub fn observe(&mut self, chunk: &Value) {
+        let usage = chunk
+            .get("usage")
+            .or_else(|| chunk.get("message").and_then(|m| m.get("usage")))
+            .or_else(|| chunk.get("usageMetadata"));
+        let Some(usage) = usage else {
+            return;
+        };
+
+        let field = |names: &[&str]| names.iter().find_map(|n| usage.get(*n).and_then(Value::as_u64));
+        let extra = |name: &str| usage.get(name).and_then(Value::as_u64).unwrap_or(0);
+        // Claude counts cache reads and writes apart from `input_tokens`, Gemini thoughts apart
+        // from the candidates
+        let input = match field(&["input_tokens"]) {
+            Some(input) => Some(input + extra("cache_read_input_tokens") + extra("cache_creation_input_tokens")),
+            None => field(&["prompt_tokens", "promptTokenCount"]),
+        };
+        if let Some(input) = input {
+            self.input = self.input.max(input);
+        }
+        let output = match field(&["candidatesTokenCount"]) {
+            Some(output) => Some(output + extra("thoughtsTokenCount")),
+            None => field(&["output_tokens", "completion_tokens"]),
+        };
+        if let Some(output) = output {
+            self.output = self.output.max(output);
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.input + self.output
+    }
+}
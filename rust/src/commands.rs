@@ -0,0 +1,177 @@
+/*!
+ * CLI Commands
+ *
+ * Implementations of the non-server subcommands (`config check`, `models`, `creds status`,
+ * `keys hash`).
+ */
+
+use crate::adapter::create_adapter;
+use crate::auth;
+use crate::cli::{ConfigArgs, HashKeyArgs, ModelsArgs};
+use crate::common::{format_expiry_time, ModelProvider};
+use crate::config::Config;
+use crate::retry::RetryBudget;
+use anyhow::{Context, Result};
+use base64::Engine;
+use std::path::Path;
+use std::sync::Arc;
+
+/// `config check`: validate and print the effective configuration with secrets redacted and the
+/// layer (default, file, env or cli) that set each value
+pub fn config_check(args: &ConfigArgs) -> Result<()> {
+    let config = Config::load_from(args)?;
+
+    let config_file_layer = args.sources.get("config_file").map(|l| l.as_str()).unwrap_or("default");
+    println!("config file: {} ({})", config.config_file_path.display(), config_file_layer);
+
+    let redacted = config.redacted();
+    if let Some(fields) = redacted.as_object() {
+        let width = fields.keys().map(String::len).max().unwrap_or(0);
+        for (key, value) in fields {
+            println!("{:<width$}  {:<8}  {}", key, config.value_source(key).as_str(), value, width = width);
+        }
+    }
+
+    config.validate()?;
+    println!("\nConfiguration is valid");
+    Ok(())
+}
+
+/// `models`: list the models of the primary provider, the default providers and every pool
+pub async fn list_models(args: &ModelsArgs) -> Result<()> {
+    let config = Config::load_from(&args.config)?;
+
+    let mut providers = match &args.only_provider {
+        Some(provider) => vec![provider.clone()],
+        None => {
+            let mut providers = vec![config.model_provider.clone()];
+            providers.extend(config.default_model_providers.iter().cloned());
+            providers.extend(config.provider_pools.keys().cloned());
+            providers
+        }
+    };
+    providers.sort();
+    providers.dedup();
+
+    let retry_budget = Arc::new(RetryBudget::new());
+
+    for name in providers {
+        println!("{}:", name);
+        let Some(provider) = ModelProvider::parse(&name) else {
+            println!("  error: unknown provider");
+            continue;
+        };
+
+        // Use the first pool member's credentials for providers that are only configured as a pool
+        let provider_config = match config.provider_pools.get(&name).and_then(|m| m.first()) {
+            Some(member) if name != config.model_provider => config.for_pool_member(&name, member),
+            _ => config.clone(),
+        };
+
+        let result = async { create_adapter(provider, &provider_config, &retry_budget).await?.list_models().await }.await;
+        match result {
+            Ok(list) => {
+                let models = list.data.or(list.models).unwrap_or_default();
+                for model in models {
+                    let id = model.id.or(model.name).unwrap_or_default();
+                    println!("  {}", id.trim_start_matches("models/"));
+                }
+            }
+            Err(e) => println!("  error: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+/// `creds status`: token expiry of the OAuth credentials of the primary provider and all pool members
+pub fn creds_status(args: &ConfigArgs) -> Result<()> {
+    let config = Config::load_from(args)?;
+
+    let mut entries = vec![(config.model_provider.clone(), "primary".to_string(), config.clone())];
+    let mut pool_types: Vec<_> = config.provider_pools.keys().collect();
+    pool_types.sort();
+    for provider_type in pool_types {
+        for member in &config.provider_pools[provider_type] {
+            entries.push((provider_type.clone(), member.uuid.clone(), config.for_pool_member(provider_type, member)));
+        }
+    }
+
+    for (provider_type, label, member_config) in entries {
+        let status = match ModelProvider::parse(&provider_type) {
+            Some(provider) => match credential_expiry(&provider, &member_config) {
+                Ok(Some(CredentialExpiry::At(timestamp))) => format_expiry_time(timestamp),
+                Ok(Some(CredentialExpiry::ApiKey)) => "API key (does not expire)".to_string(),
+                Ok(None) => "No expiry recorded".to_string(),
+                Err(e) => format!("error: {:#}", e),
+            },
+            None => "error: unknown provider".to_string(),
+        };
+        println!("{:<20} {:<38} {}", provider_type, label, status);
+    }
+    Ok(())
+}
+
+enum CredentialExpiry {
+    At(i64),
+    ApiKey,
+}
+
+fn credential_expiry(provider: &ModelProvider, config: &Config) -> Result<Option<CredentialExpiry>> {
+    let (base64, path, default_path) = match provider {
+        ModelProvider::OpenAICustom | ModelProvider::ClaudeCustom => return Ok(Some(CredentialExpiry::ApiKey)),
+        ModelProvider::GeminiCliOAuth => (
+            config.gemini_oauth_creds_base64.as_ref(),
+            &config.gemini_oauth_creds_file_path,
+            ".gemini/oauth_creds.json",
+        ),
+        ModelProvider::ClaudeKiroOAuth => (
+            config.kiro_oauth_creds_base64.as_ref(),
+            &config.kiro_oauth_creds_file_path,
+            ".aws/sso/cache/kiro-auth-token.json",
+        ),
+        ModelProvider::OpenAIQwenOAuth => (None, &config.qwen_oauth_creds_file_path, ".qwen/oauth_creds.json"),
+    };
+
+    let content = match (base64, path) {
+        (Some(encoded), _) => String::from_utf8(
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded.expose())
+                .context("Invalid base64 credentials")?,
+        )?,
+        (None, Some(path)) => read_credentials_file(path)?,
+        (None, None) => {
+            let home = dirs::home_dir().context("Cannot determine home directory")?;
+            read_credentials_file(&home.join(default_path))?
+        }
+    };
+    let creds: serde_json::Value = serde_json::from_str(&content).context("Invalid credentials JSON")?;
+
+    // Gemini and Qwen store `expiry_date` (seconds or milliseconds), Kiro stores an RFC 3339 `expiresAt`
+    if let Some(expiry) = creds.get("expiry_date").and_then(|v| v.as_i64()) {
+        let seconds = if expiry > 10_000_000_000 { expiry / 1000 } else { expiry };
+        return Ok(Some(CredentialExpiry::At(seconds)));
+    }
+    if let Some(expires_at) = creds.get("expiresAt").and_then(|v| v.as_str()) {
+        let expires_at = chrono::DateTime::parse_from_rfc3339(expires_at).context("Invalid expiresAt")?;
+        return Ok(Some(CredentialExpiry::At(expires_at.timestamp())));
+    }
+    Ok(None)
+}
+
+fn read_credentials_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))
+}
+
+/// `keys hash`: print the `key_hash` value for a key, generating the key if none is given
+pub fn hash_key(args: &HashKeyArgs) -> Result<()> {
+    let key = match &args.key {
+        Some(key) => key.clone(),
+        None => {
+            let key = auth::generate_key();
+            println!("key:      {}", key);
+            key
+        }
+    };
+    println!("key_hash: {}", auth::hash_key(&key));
+    Ok(())
+}
@@ -11,6 +11,8 @@ pub enum ModelProtocol {
     Gemini,
     OpenAI,
     Claude,
+    /// CodeWhisperer, spoken upstream by the Kiro provider
+    Kiro,
 }
 
 impl ModelProtocol {
@@ -19,6 +21,7 @@ impl ModelProtocol {
             Self::Gemini => "gemini",
             Self::OpenAI => "openai",
             Self::Claude => "claude",
+            Self::Kiro => "kiro",
         }
     }
 }
@@ -90,155 +93,6 @@ impl ApiAction {
     }
 }
 
-/// Generic API request
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ApiRequest {
-    pub model: Option<String>,
-    pub messages: Option<Vec<Message>>,
-    pub contents: Option<Vec<Content>>,
-    pub system: Option<serde_json::Value>,
-    pub system_instruction: Option<SystemInstruction>,
-    pub max_tokens: Option<u32>,
-    pub temperature: Option<f32>,
-    pub top_p: Option<f32>,
-    pub stream: Option<bool>,
-    pub tools: Option<Vec<serde_json::Value>>,
-    
-    #[serde(flatten)]
-    pub extra: HashMap<String, serde_json::Value>,
-}
-
-/// Message structure (OpenAI/Claude format)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Message {
-    pub role: String,
-    pub content: MessageContent,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_calls: Option<Vec<ToolCall>>,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_call_id: Option<String>,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub name: Option<String>,
-}
-
-/// Message content (can be string or array of content parts)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum MessageContent {
-    Text(String),
-    Parts(Vec<ContentPart>),
-}
-
-/// Content part
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-pub enum ContentPart {
-    #[serde(rename = "text")]
-    Text { text: String },
-    
-    #[serde(rename = "image_url")]
-    ImageUrl { image_url: ImageUrl },
-    
-    #[serde(rename = "image")]
-    Image { source: ImageSource },
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ImageUrl {
-    pub url: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ImageSource {
-    #[serde(rename = "type")]
-    pub source_type: String,
-    pub media_type: String,
-    pub data: String,
-}
-
-/// Tool call
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ToolCall {
-    pub id: String,
-    
-    #[serde(rename = "type")]
-    pub tool_type: String,
-    
-    pub function: ToolFunction,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ToolFunction {
-    pub name: String,
-    pub arguments: String,
-}
-
-/// Content structure (Gemini format)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Content {
-    pub role: String,
-    pub parts: Vec<Part>,
-}
-
-/// Gemini part
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Part {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub text: Option<String>,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub inline_data: Option<InlineData>,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file_data: Option<FileData>,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub function_call: Option<FunctionCall>,
-    
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub function_response: Option<FunctionResponse>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct InlineData {
-    pub mime_type: String,
-    pub data: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FileData {
-    pub mime_type: String,
-    pub file_uri: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FunctionCall {
-    pub name: String,
-    pub args: serde_json::Value,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FunctionResponse {
-    pub name: String,
-    pub response: serde_json::Value,
-}
-
-/// System instruction
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SystemInstruction {
-    pub parts: Vec<Part>,
-}
-
-/// Generic API response
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ApiResponse {
-    #[serde(flatten)]
-    pub data: HashMap<String, serde_json::Value>,
-}
-
 /// Model list response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelListResponse {
@@ -275,7 +129,7 @@ pub fn format_expiry_time(expiry_timestamp: i64) -> String {
     format!("{:02}h {:02}m {:02}s", hours, minutes, seconds)
 }
 
-/// Check if authorization is valid
+/// Check if authorization is valid (keys are compared in constant time)
 pub fn is_authorized(
     auth_header: Option<&str>,
     api_key_header: Option<&str>,
@@ -283,36 +137,10 @@ pub fn is_authorized(
     query_key: Option<&str>,
     required_key: &str,
 ) -> bool {
-    // Check Bearer token
-    if let Some(auth) = auth_header {
-        if let Some(token) = auth.strip_prefix("Bearer ") {
-            if token == required_key {
-                return true;
-            }
-        }
-    }
-    
-    // Check x-api-key header (Claude)
-    if let Some(key) = api_key_header {
-        if key == required_key {
-            return true;
-        }
-    }
-    
-    // Check x-goog-api-key header (Gemini)
-    if let Some(key) = goog_api_key {
-        if key == required_key {
-            return true;
-        }
-    }
-    
-    // Check query parameter
-    if let Some(key) = query_key {
-        if key == required_key {
-            return true;
-        }
-    }
-    
-    false
+    let bearer = auth_header.and_then(|auth| auth.strip_prefix("Bearer "));
+    [bearer, api_key_header, goog_api_key, query_key]
+        .into_iter()
+        .flatten()
+        .any(|key| crate::auth::constant_time_eq(key, required_key))
 }
 
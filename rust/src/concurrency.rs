@@ -0,0 +1,187 @@
+/*!
+ * Concurrency Limits
+ *
+ * Caps the number of in-flight requests per credential. Requests over the limit wait in a
+ * bounded queue and fail once the queue is full or the wait exceeds the queue timeout.
+ */
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Key of the primary (non-pool) adapter in a `ConcurrencyRegistry`
+pub const PRIMARY_KEY: &str = "primary";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QueueError {
+    #[error("Too many concurrent requests for this credential (queue is full)")]
+    QueueFull,
+    #[error("Timed out after {0:?} waiting for a free slot on this credential")]
+    Timeout(Duration),
+}
+
+/// Observability snapshot of a limiter
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStats {
+    pub max_concurrency: usize,
+    pub in_flight: usize,
+    pub queued: usize,
+    pub max_queue_size: usize,
+    /// Requests that had to wait for a slot
+    pub total_queued: u64,
+    pub rejected: u64,
+    pub timed_out: u64,
+    pub avg_wait_ms: f64,
+    pub max_wait_ms: u64,
+}
+
+/// Max-in-flight limit with a bounded wait queue for one credential
+pub struct ConcurrencyLimiter {
+    semaphore: Arc<Semaphore>,
+    max_concurrency: usize,
+    max_queue_size: usize,
+    queued: AtomicUsize,
+    total_queued: AtomicU64,
+    total_wait_ms: AtomicU64,
+    max_wait_ms: AtomicU64,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max_concurrency: usize, max_queue_size: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrency)),
+            max_concurrency,
+            max_queue_size,
+            queued: AtomicUsize::new(0),
+            total_queued: AtomicU64::new(0),
+            total_wait_ms: AtomicU64::new(0),
+            max_wait_ms: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a slot, waiting in the queue for at most `timeout`. The slot is freed when the
+    /// permit is dropped.
+    pub async fn acquire(&self, timeout: Duration) -> Result<OwnedSemaphorePermit, QueueError> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let position = self.queued.fetch_add(1, Ordering::SeqCst);
+        if position >= self.max_queue_size {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(QueueError::QueueFull);
+        }
+
+        let started = Instant::now();
+        let result = tokio::time::timeout(timeout, self.semaphore.clone().acquire_owned()).await;
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+
+        let waited_ms = started.elapsed().as_millis() as u64;
+        self.total_queued.fetch_add(1, Ordering::Relaxed);
+        self.total_wait_ms.fetch_add(waited_ms, Ordering::Relaxed);
+        self.max_wait_ms.fetch_max(waited_ms, Ordering::Relaxed);
+
+        match result {
+            Ok(Ok(permit)) => {
+                tracing::debug!("Acquired concurrency slot after waiting {}ms", waited_ms);
+                Ok(permit)
+            }
+            // The semaphore is never closed, so this only happens on timeout
+            _ => {
+                self.timed_out.fetch_add(1, Ordering::Relaxed);
+                Err(QueueError::Timeout(timeout))
+            }
+        }
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let total_queued = self.total_queued.load(Ordering::Relaxed);
+        let total_wait_ms = self.total_wait_ms.load(Ordering::Relaxed);
+        QueueStats {
+            max_concurrency: self.max_concurrency,
+            in_flight: self.max_concurrency - self.semaphore.available_permits(),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_queue_size: self.max_queue_size,
+            total_queued,
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            avg_wait_ms: if total_queued == 0 {
+                0.0
+            } else {
+                total_wait_ms as f64 / total_queued as f64
+            },
+            max_wait_ms: self.max_wait_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Limiters keyed by pool member uuid (or `PRIMARY_KEY`), kept across configuration reloads
+#[derive(Default)]
+pub struct ConcurrencyRegistry {
+    limiters: Mutex<HashMap<String, Arc<ConcurrencyLimiter>>>,
+}
+
+impl ConcurrencyRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limiter for `key`, or `None` if the credential is unlimited (`max_concurrency` unset or 0).
+    ///
+    /// A limiter is replaced when its settings change; requests holding permits of the old one
+    /// finish normally.
+    pub fn limiter(
+        &self,
+        key: &str,
+        max_concurrency: Option<u32>,
+        max_queue_size: usize,
+    ) -> Option<Arc<ConcurrencyLimiter>> {
+        let mut limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        let max_concurrency = match max_concurrency {
+            Some(n) if n > 0 => n as usize,
+            _ => {
+                limiters.remove(key);
+                return None;
+            }
+        };
+
+        let limiter = limiters
+            .entry(key.to_string())
+            .and_modify(|existing| {
+                if existing.max_concurrency != max_concurrency || existing.max_queue_size != max_queue_size {
+                    *existing = Arc::new(ConcurrencyLimiter::new(max_concurrency, max_queue_size));
+                }
+            })
+            .or_insert_with(|| Arc::new(ConcurrencyLimiter::new(max_concurrency, max_queue_size)));
+        Some(limiter.clone())
+    }
+
+    /// Drop limiters whose credential no longer exists
+    pub fn retain(&self, keys: &[&str]) {
+        let mut limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        limiters.retain(|key, _| keys.contains(&key.as_str()));
+    }
+
+    /// Stats of all limited credentials
+    pub fn stats(&self) -> HashMap<String, QueueStats> {
+        let limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        limiters
+            .iter()
+            .map(|(key, limiter)| (key.clone(), limiter.stats()))
+            .collect()
+    }
+}
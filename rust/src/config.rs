@@ -1,34 +1,22 @@
 /*!
  * Configuration module
  *
- * Handles loading and managing server configuration from files and command-line arguments.
+ * Handles loading and managing server configuration from files, environment variables and
+ * command-line arguments (see `cli`).
  */
 
+use crate::auth::{constant_time_eq, verify_key_hash, AuthEndpoint, CredentialLocation, LockoutPolicy};
+use crate::cli::{Cli, ConfigArgs};
+use crate::http_client::HttpClientConfig;
+use crate::retry::RetrySettings;
+use crate::secrets::{is_reference, resolve_reference, Secret};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
-
-/// CLI configuration from command-line arguments
-#[derive(Debug, Default)]
-struct CliConfig {
-    config_file: Option<String>,
-    host: Option<String>,
-    port: Option<u16>,
-    api_key: Option<String>,
-    model_provider: Option<String>,
-    openai_api_key: Option<String>,
-    openai_base_url: Option<String>,
-    claude_api_key: Option<String>,
-    claude_base_url: Option<String>,
-    kiro_oauth_creds_file: Option<PathBuf>,
-    kiro_oauth_creds_base64: Option<String>,
-    gemini_oauth_creds_file: Option<PathBuf>,
-    project_id: Option<String>,
-    qwen_oauth_creds_file: Option<PathBuf>,
-    prompt_log_mode: Option<String>,
-}
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Main configuration structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,9 +29,60 @@ pub struct Config {
     #[serde(default = "default_port")]
     pub port: u16,
 
+    /// Addresses to listen on (`host:port` or `unix:/path/to.sock`); `host:port` if empty
+    #[serde(default)]
+    pub listen: Vec<String>,
+
+    /// TLS certificate chain and private key (PEM) for TCP listeners; reloaded when changed
+    #[serde(default)]
+    pub tls_cert_path: Option<PathBuf>,
+    #[serde(default)]
+    pub tls_key_path: Option<PathBuf>,
+
+    /// Allowed CORS origins (`*` for any)
+    #[serde(default = "default_cors_allowed_origins")]
+    pub cors_allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_allowed_methods")]
+    pub cors_allowed_methods: Vec<String>,
+    /// Allowed CORS request headers (`*` for any)
+    #[serde(default = "default_cors_allowed_headers")]
+    pub cors_allowed_headers: Vec<String>,
+
+    /// How long streaming responses may keep running after SIGTERM/SIGINT before they are ended
+    #[serde(default = "default_shutdown_drain_timeout_secs")]
+    pub shutdown_drain_timeout_secs: u64,
+
+    /// Upstream timeouts, connection pool and proxy settings for all providers
+    #[serde(default)]
+    pub http: HttpClientConfig,
+
+    /// Per-provider overrides of `http`, by provider type
+    #[serde(default)]
+    pub provider_http: HashMap<String, HttpClientConfig>,
+
     /// Required API key for authentication
     #[serde(default = "default_api_key")]
-    pub required_api_key: String,
+    pub required_api_key: Secret,
+
+    /// Client key table; when non-empty it replaces `required_api_key`
+    #[serde(default)]
+    pub api_keys: Vec<ClientKeyConfig>,
+
+    /// API key for the `/admin` endpoints (admin API disabled if unset)
+    #[serde(default)]
+    pub admin_api_key: Option<Secret>,
+
+    /// Credential locations accepted per endpoint group (`openai`, `claude`, `gemini`, `admin`);
+    /// groups not listed use `AuthEndpoint::default_locations`
+    #[serde(default)]
+    pub auth_locations: HashMap<String, Vec<CredentialLocation>>,
+    /// Failed authentication attempts from one IP before it is locked out (0 disables lockout)
+    #[serde(default = "default_auth_lockout_max_failures")]
+    pub auth_lockout_max_failures: u32,
+    #[serde(default = "default_auth_lockout_window_secs")]
+    pub auth_lockout_window_secs: u64,
+    #[serde(default = "default_auth_lockout_duration_secs")]
+    pub auth_lockout_duration_secs: u64,
 
     /// Primary model provider
     #[serde(default = "default_model_provider")]
@@ -55,19 +94,19 @@ pub struct Config {
 
     /// OpenAI configuration
     #[serde(default)]
-    pub openai_api_key: Option<String>,
+    pub openai_api_key: Option<Secret>,
     #[serde(default)]
     pub openai_base_url: Option<String>,
 
     /// Claude configuration
     #[serde(default)]
-    pub claude_api_key: Option<String>,
+    pub claude_api_key: Option<Secret>,
     #[serde(default)]
     pub claude_base_url: Option<String>,
 
     /// Gemini OAuth configuration
     #[serde(default)]
-    pub gemini_oauth_creds_base64: Option<String>,
+    pub gemini_oauth_creds_base64: Option<Secret>,
     #[serde(default)]
     pub gemini_oauth_creds_file_path: Option<PathBuf>,
     #[serde(default)]
@@ -75,7 +114,7 @@ pub struct Config {
 
     /// Kiro OAuth configuration
     #[serde(default)]
-    pub kiro_oauth_creds_base64: Option<String>,
+    pub kiro_oauth_creds_base64: Option<Secret>,
     #[serde(default)]
     pub kiro_oauth_creds_file_path: Option<PathBuf>,
 
@@ -97,11 +136,35 @@ pub struct Config {
     #[serde(default = "default_prompt_log_base_name")]
     pub prompt_log_base_name: String,
 
+    /// Check structured (JSON schema) answers against their schema and ask once more on a mismatch;
+    /// non-streaming responses only, streams are passed through unchecked
+    #[serde(default)]
+    pub validate_structured_output: bool,
+    /// `lenient` drops or clamps generation parameters the target model doesn't take; `strict`
+    /// rejects the request
+    #[serde(default = "default_param_mode")]
+    pub param_mode: String,
+
     /// Retry configuration
     #[serde(default = "default_max_retries")]
     pub request_max_retries: u32,
     #[serde(default = "default_base_delay")]
     pub request_base_delay: u64,
+    /// Upper bound of the backoff delay in milliseconds
+    #[serde(default = "default_max_delay")]
+    pub request_max_delay: u64,
+    /// Total time in milliseconds for a request including its retries, until the response headers
+    /// of the last attempt; `http.request_timeout_ms` bounds the whole call and should be longer
+    #[serde(default = "default_request_deadline")]
+    pub request_deadline: u64,
+    /// Retries allowed per request across all providers in the budget window
+    #[serde(default = "default_retry_budget_ratio")]
+    pub retry_budget_ratio: f64,
+    /// Retries always allowed per budget window, regardless of traffic
+    #[serde(default = "default_retry_budget_min_retries")]
+    pub retry_budget_min_retries: u32,
+    #[serde(default = "default_retry_budget_window_secs")]
+    pub retry_budget_window_secs: u64,
 
     /// Cron configuration
     #[serde(default = "default_cron_near_minutes")]
@@ -114,8 +177,123 @@ pub struct Config {
     pub provider_pools_file_path: Option<PathBuf>,
     #[serde(default)]
     pub provider_pools: HashMap<String, Vec<ProviderConfig>>,
+
+    /// Pool member selection strategy, per provider type (see `pool_selection`)
+    #[serde(default = "default_pool_strategy")]
+    pub default_pool_strategy: String,
+    #[serde(default)]
+    pub pool_strategies: HashMap<String, String>,
+
+    /// Keep a conversation on the same pool member (session key from header, `user` or first messages)
+    #[serde(default)]
+    pub sticky_sessions: bool,
+    #[serde(default = "default_session_header")]
+    pub sticky_session_header: String,
+
+    /// Hot reload of config.json and the provider pools file (also triggered by SIGHUP)
+    #[serde(default = "default_hot_reload")]
+    pub hot_reload: bool,
+    #[serde(default = "default_hot_reload_interval")]
+    pub hot_reload_interval_secs: u64,
+
+    /// Max in-flight requests through the primary adapter (unlimited if unset or 0)
+    #[serde(default)]
+    pub max_concurrency: Option<u32>,
+    /// Requests allowed to wait per credential once its limit is reached
+    #[serde(default = "default_max_queue_size")]
+    pub max_queue_size: usize,
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+
+    /// Path the configuration was loaded from
+    #[serde(skip, default = "default_config_file_path")]
+    pub config_file_path: PathBuf,
+
+    /// Layer that set each top-level value (see `value_source`)
+    #[serde(skip, default)]
+    pub value_sources: BTreeMap<String, ConfigLayer>,
+}
+
+/// Configuration layers, from lowest to highest precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigLayer {
+    Default,
+    File,
+    Env,
+    Cli,
 }
 
+impl ConfigLayer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::File => "file",
+            Self::Env => "env",
+            Self::Cli => "cli",
+        }
+    }
+}
+
+/// A client API key with its scopes and limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientKeyConfig {
+    /// The key itself; either this or `key_hash` must be set
+    #[serde(default)]
+    pub key: Secret,
+    /// Salted hash of the key (see `auth::hash_key`), so the config never holds the key
+    #[serde(default)]
+    pub key_hash: Option<String>,
+    /// Name identifying the client in logs and usage statistics
+    pub label: String,
+    /// Provider types the key may use (all if empty)
+    #[serde(default)]
+    pub allowed_providers: Vec<String>,
+    /// Model names the key may use; a trailing `*` matches a prefix (all if empty)
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// Requests per minute
+    #[serde(default)]
+    pub rpm: Option<u32>,
+    /// Tokens per minute
+    #[serde(default)]
+    pub tpm: Option<u64>,
+    /// Tokens per UTC day
+    #[serde(default)]
+    pub daily_token_quota: Option<u64>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ClientKeyConfig {
+    /// Key without scopes or limits (used for `required_api_key`)
+    pub fn unrestricted(label: &str, key: Secret) -> Self {
+        Self {
+            key,
+            key_hash: None,
+            label: label.to_string(),
+            allowed_providers: Vec::new(),
+            allowed_models: Vec::new(),
+            rpm: None,
+            tpm: None,
+            daily_token_quota: None,
+            expires_at: None,
+        }
+    }
+
+    /// Whether a presented key is this client's key
+    pub fn matches(&self, presented: &str) -> bool {
+        match &self.key_hash {
+            Some(hash) => verify_key_hash(presented, hash),
+            None => constant_time_eq(presented, self.key.expose()),
+        }
+    }
+}
+
+/// Pool member credentials that may be written as `env:`/`file:`/`cmd:` references; other
+/// string credentials only get `~` and `${VAR}` expansion
+pub const SECRET_CREDENTIAL_KEYS: [&str; 4] =
+    ["OPENAI_API_KEY", "CLAUDE_API_KEY", "GEMINI_OAUTH_CREDS_BASE64", "KIRO_OAUTH_CREDS_BASE64"];
+
 /// Provider configuration for pool management
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProviderConfig {
@@ -141,6 +319,97 @@ pub struct ProviderConfig {
     
     #[serde(default)]
     pub last_error_time: Option<String>,
+
+    /// Relative share of traffic for the `weighted` strategy
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+
+    /// Maximum requests per UTC day (unlimited if unset)
+    #[serde(default)]
+    pub daily_quota: Option<u64>,
+
+    /// Max in-flight requests through this member (unlimited if unset or 0)
+    #[serde(default)]
+    pub max_concurrency: Option<u32>,
+
+    /// Overrides of the provider's HTTP settings for this member
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpClientConfig>,
+
+    /// Credential values resolved from references and `~`/`${VAR}`, by key. `credentials` keeps
+    /// the values as written so that saving the pools never writes resolved secrets.
+    #[serde(skip)]
+    pub resolved_credentials: HashMap<String, Secret>,
+}
+
+impl ProviderConfig {
+    /// Resolve secret references in `SECRET_CREDENTIAL_KEYS`, expand `*_PATH` keys and `${VAR}`
+    /// in the other string credentials
+    pub fn resolve_credentials(&mut self) -> Result<()> {
+        self.resolved_credentials.clear();
+        for (key, value) in &self.credentials {
+            let Some(text) = value.as_str() else {
+                continue;
+            };
+            let resolved = if key.ends_with("_PATH") {
+                expand_path(text)?
+            } else if SECRET_CREDENTIAL_KEYS.contains(&key.as_str()) {
+                resolve_reference(text).with_context(|| format!("Failed to resolve {}", key))?
+            } else {
+                expand_env_vars(text).with_context(|| format!("Failed to expand {}", key))?
+            };
+            if resolved != text {
+                self.resolved_credentials
+                    .insert(key.clone(), Secret::resolved(text, resolved));
+            }
+        }
+        if let Some(http) = &mut self.http {
+            http.resolve_secrets()?;
+        }
+        Ok(())
+    }
+
+    /// Effective value of a string credential
+    pub fn credential(&self, key: &str) -> Option<&str> {
+        match self.resolved_credentials.get(key) {
+            Some(secret) => Some(secret.expose()),
+            None => self.credentials.get(key).and_then(|v| v.as_str()),
+        }
+    }
+
+    fn credential_secret(&self, key: &str) -> Option<Secret> {
+        let reference = self.credentials.get(key)?.as_str()?;
+        let value = self.credential(key)?;
+        Some(Secret::resolved(reference, value))
+    }
+
+    /// Fields that read from the server itself (`file:`, `cmd:` and `env:` references, `${VAR}`
+    /// interpolation), which the admin API doesn't accept
+    pub fn local_references(&self) -> Vec<String> {
+        fn is_local(text: &str) -> bool {
+            ["file:", "cmd:", "env:"].iter().any(|prefix| text.starts_with(prefix)) || text.contains("${")
+        }
+        fn collect(path: &str, value: &serde_json::Value, found: &mut Vec<String>) {
+            match value {
+                serde_json::Value::String(text) if is_local(text) => found.push(path.to_string()),
+                serde_json::Value::Object(fields) => {
+                    for (key, field) in fields {
+                        collect(&format!("{}.{}", path, key), field, found);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut found = Vec::new();
+        for (key, value) in &self.credentials {
+            collect(key, value, &mut found);
+        }
+        if let Ok(http) = serde_json::to_value(&self.http) {
+            collect("http", &http, &mut found);
+        }
+        found.sort();
+        found
+    }
 }
 
 // Default value functions
@@ -152,8 +421,24 @@ fn default_port() -> u16 {
     3000
 }
 
-fn default_api_key() -> String {
-    "123456".to_string()
+fn default_cors_allowed_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "DELETE", "OPTIONS"].iter().map(|m| m.to_string()).collect()
+}
+
+fn default_cors_allowed_headers() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_shutdown_drain_timeout_secs() -> u64 {
+    30
+}
+
+fn default_api_key() -> Secret {
+    Secret::new("123456")
 }
 
 fn default_model_provider() -> String {
@@ -168,6 +453,10 @@ fn default_system_prompt_mode() -> String {
     "overwrite".to_string()
 }
 
+fn default_param_mode() -> String {
+    "lenient".to_string()
+}
+
 fn default_prompt_log_mode() -> String {
     "none".to_string()
 }
@@ -184,6 +473,26 @@ fn default_base_delay() -> u64 {
     1000
 }
 
+fn default_max_delay() -> u64 {
+    30_000
+}
+
+fn default_request_deadline() -> u64 {
+    120_000
+}
+
+fn default_retry_budget_ratio() -> f64 {
+    0.2
+}
+
+fn default_retry_budget_min_retries() -> u32 {
+    10
+}
+
+fn default_retry_budget_window_secs() -> u64 {
+    10
+}
+
 fn default_cron_near_minutes() -> u64 {
     15
 }
@@ -196,6 +505,50 @@ fn default_healthy() -> bool {
     true
 }
 
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_pool_strategy() -> String {
+    "round-robin".to_string()
+}
+
+fn default_session_header() -> String {
+    "x-session-id".to_string()
+}
+
+fn default_hot_reload() -> bool {
+    true
+}
+
+fn default_hot_reload_interval() -> u64 {
+    2
+}
+
+fn default_max_queue_size() -> usize {
+    100
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    30000
+}
+
+fn default_auth_lockout_max_failures() -> u32 {
+    10
+}
+
+fn default_auth_lockout_window_secs() -> u64 {
+    300
+}
+
+fn default_auth_lockout_duration_secs() -> u64 {
+    900
+}
+
+fn default_config_file_path() -> PathBuf {
+    PathBuf::from("config.json")
+}
+
 impl Config {
     /// Load configuration from config file, environment, and command-line arguments
     pub fn load() -> Result<Self> {
@@ -204,18 +557,34 @@ impl Config {
 
     /// Load configuration with custom arguments (for testing)
     pub fn load_with_args(args: &[String]) -> Result<Self> {
-        // Parse command-line arguments
-        let cli_config = Self::parse_cli_args(args)?;
-        
-        let config_path = cli_config.config_file.as_deref().unwrap_or("config.json");
-        
-        let mut config: Config = if let Ok(content) = fs::read_to_string(config_path) {
-            serde_json::from_str(&content)
-                .context("Failed to parse config.json")?
+        let cli = Cli::try_parse_with_sources(args)?;
+        Self::load_from(cli.config_args())
+    }
+
+    /// Load the layered configuration: defaults, then the config file (JSON, YAML or TOML), then
+    /// `AIC2API_*` environment variables, then command-line flags
+    pub fn load_from(args: &ConfigArgs) -> Result<Self> {
+        let config_path = PathBuf::from(expand_path(&args.config_file.to_string_lossy())?);
+
+        let mut sources = BTreeMap::new();
+        let mut config: Config = if config_path.exists() {
+            let file_values = read_config_file(&config_path)?;
+            if let Some(fields) = file_values.as_object() {
+                sources.extend(fields.keys().map(|key| (key.clone(), ConfigLayer::File)));
+            }
+            serde_json::from_value(file_values)
+                .with_context(|| format!("Failed to parse {}", config_path.display()))?
         } else {
             // Use default configuration if file doesn't exist
             Self::default()
         };
+        config.config_file_path = config_path;
+
+        // Environment variables and command-line flags override the file
+        args.apply(&mut config);
+        sources.extend(args.sources.iter().map(|(key, layer)| (key.clone(), *layer)));
+        config.expand_values()?;
+        config.resolve_secrets()?;
 
         // Load system prompt content if file exists
         if config.system_prompt_file_path.exists() {
@@ -229,138 +598,277 @@ impl Config {
                     .context("Failed to read provider pools file")?;
                 config.provider_pools = serde_json::from_str(&pools_content)
                     .context("Failed to parse provider pools file")?;
+                sources.insert("provider_pools".to_string(), ConfigLayer::File);
+            }
+        }
+        for (provider_type, members) in config.provider_pools.iter_mut() {
+            for member in members {
+                member
+                    .resolve_credentials()
+                    .with_context(|| format!("Failed to resolve credentials of {} ({})", provider_type, member.uuid))?;
             }
         }
-
-        // Merge CLI arguments into config
-        config.merge_cli_args(cli_config);
 
         // Normalize provider configuration
         config.normalize_providers();
+        config.value_sources = sources;
 
         Ok(config)
     }
 
-    /// Parse command-line arguments
-    fn parse_cli_args(args: &[String]) -> Result<CliConfig> {
-        let mut cli_config = CliConfig::default();
-        let mut i = 1; // Skip program name
-        
-        while i < args.len() {
-            match args[i].as_str() {
-                "--host" if i + 1 < args.len() => {
-                    cli_config.host = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--port" if i + 1 < args.len() => {
-                    cli_config.port = Some(args[i + 1].parse()?);
-                    i += 2;
-                }
-                "--api-key" if i + 1 < args.len() => {
-                    cli_config.api_key = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--model-provider" if i + 1 < args.len() => {
-                    cli_config.model_provider = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--openai-api-key" if i + 1 < args.len() => {
-                    cli_config.openai_api_key = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--openai-base-url" if i + 1 < args.len() => {
-                    cli_config.openai_base_url = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--claude-api-key" if i + 1 < args.len() => {
-                    cli_config.claude_api_key = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--claude-base-url" if i + 1 < args.len() => {
-                    cli_config.claude_base_url = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--kiro-oauth-creds-file" if i + 1 < args.len() => {
-                    cli_config.kiro_oauth_creds_file = Some(PathBuf::from(&args[i + 1]));
-                    i += 2;
-                }
-                "--kiro-oauth-creds-base64" if i + 1 < args.len() => {
-                    cli_config.kiro_oauth_creds_base64 = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--gemini-oauth-creds-file" if i + 1 < args.len() => {
-                    cli_config.gemini_oauth_creds_file = Some(PathBuf::from(&args[i + 1]));
-                    i += 2;
-                }
-                "--project-id" if i + 1 < args.len() => {
-                    cli_config.project_id = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--qwen-oauth-creds-file" if i + 1 < args.len() => {
-                    cli_config.qwen_oauth_creds_file = Some(PathBuf::from(&args[i + 1]));
-                    i += 2;
-                }
-                "--config" if i + 1 < args.len() => {
-                    cli_config.config_file = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                "--log-prompts" if i + 1 < args.len() => {
-                    cli_config.prompt_log_mode = Some(args[i + 1].clone());
-                    i += 2;
-                }
-                _ => {
-                    i += 1;
-                }
+    /// Layer that set a top-level value
+    pub fn value_source(&self, key: &str) -> ConfigLayer {
+        self.value_sources.get(key).copied().unwrap_or(ConfigLayer::Default)
+    }
+
+    /// Expand `~` in path fields and `${VAR}` in path and URL fields
+    fn expand_values(&mut self) -> Result<()> {
+        for path in [
+            &mut self.gemini_oauth_creds_file_path,
+            &mut self.kiro_oauth_creds_file_path,
+            &mut self.qwen_oauth_creds_file_path,
+            &mut self.provider_pools_file_path,
+            &mut self.tls_cert_path,
+            &mut self.tls_key_path,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = PathBuf::from(expand_path(&path.to_string_lossy())?);
+        }
+        self.system_prompt_file_path = PathBuf::from(expand_path(&self.system_prompt_file_path.to_string_lossy())?);
+        for addr in &mut self.listen {
+            if let Some(path) = addr.strip_prefix("unix:") {
+                *addr = format!("unix:{}", expand_path(path)?);
             }
         }
-        
-        Ok(cli_config)
+
+        for value in [&mut self.openai_base_url, &mut self.claude_base_url, &mut self.project_id]
+            .into_iter()
+            .flatten()
+        {
+            *value = expand_env_vars(value)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve `env:`, `file:` and `cmd:` references in secret fields (see `secrets`)
+    fn resolve_secrets(&mut self) -> Result<()> {
+        self.required_api_key
+            .resolve()
+            .context("Failed to resolve required_api_key")?;
+        let secrets = [
+            ("admin_api_key", &mut self.admin_api_key),
+            ("openai_api_key", &mut self.openai_api_key),
+            ("claude_api_key", &mut self.claude_api_key),
+            ("gemini_oauth_creds_base64", &mut self.gemini_oauth_creds_base64),
+            ("kiro_oauth_creds_base64", &mut self.kiro_oauth_creds_base64),
+        ];
+        for (name, secret) in secrets {
+            if let Some(secret) = secret {
+                secret.resolve().with_context(|| format!("Failed to resolve {}", name))?;
+            }
+        }
+        for client_key in &mut self.api_keys {
+            client_key
+                .key
+                .resolve()
+                .with_context(|| format!("Failed to resolve API key {}", client_key.label))?;
+        }
+        self.http.resolve_secrets()?;
+        for (provider, http) in &mut self.provider_http {
+            http.resolve_secrets()
+                .with_context(|| format!("Failed to resolve provider_http.{}", provider))?;
+        }
+        Ok(())
     }
 
-    /// Merge CLI arguments into config
-    fn merge_cli_args(&mut self, cli: CliConfig) {
-        if let Some(host) = cli.host {
-            self.host = host;
+    /// Configuration for a single pool member: the member's credential keys
+    /// (e.g. `OPENAI_API_KEY`, `GEMINI_OAUTH_CREDS_FILE_PATH`) override the top-level values,
+    /// and its `http` block overrides the provider's HTTP settings.
+    pub fn for_pool_member(&self, provider_type: &str, member: &ProviderConfig) -> Config {
+        let mut config = self.clone();
+        if let Some(http) = &member.http {
+            let merged = self.http_client_for(provider_type).merged(Some(http));
+            config.provider_http.insert(provider_type.to_string(), merged);
+        }
+        let string = |key: &str| member.credential(key).map(str::to_string);
+        let secret = |key: &str| member.credential_secret(key);
+
+        if let Some(v) = secret("OPENAI_API_KEY") {
+            config.openai_api_key = Some(v);
+        }
+        if let Some(v) = string("OPENAI_BASE_URL") {
+            config.openai_base_url = Some(v);
+        }
+        if let Some(v) = secret("CLAUDE_API_KEY") {
+            config.claude_api_key = Some(v);
+        }
+        if let Some(v) = string("CLAUDE_BASE_URL") {
+            config.claude_base_url = Some(v);
+        }
+        if let Some(v) = secret("GEMINI_OAUTH_CREDS_BASE64") {
+            config.gemini_oauth_creds_base64 = Some(v);
+        }
+        if let Some(v) = string("GEMINI_OAUTH_CREDS_FILE_PATH") {
+            config.gemini_oauth_creds_file_path = Some(PathBuf::from(v));
+        }
+        if let Some(v) = string("PROJECT_ID") {
+            config.project_id = Some(v);
+        }
+        if let Some(v) = secret("KIRO_OAUTH_CREDS_BASE64") {
+            config.kiro_oauth_creds_base64 = Some(v);
+        }
+        if let Some(v) = string("KIRO_OAUTH_CREDS_FILE_PATH") {
+            config.kiro_oauth_creds_file_path = Some(PathBuf::from(v));
+        }
+        if let Some(v) = string("QWEN_OAUTH_CREDS_FILE_PATH") {
+            config.qwen_oauth_creds_file_path = Some(PathBuf::from(v));
         }
-        if let Some(port) = cli.port {
-            self.port = port;
+
+        config
+    }
+
+    /// HTTP settings for a provider type: `http` with `provider_http.<provider>` applied
+    pub fn http_client_for(&self, provider: &str) -> HttpClientConfig {
+        self.http.merged(self.provider_http.get(provider))
+    }
+
+    pub fn retry_settings(&self) -> RetrySettings {
+        RetrySettings {
+            max_retries: self.request_max_retries,
+            base_delay: Duration::from_millis(self.request_base_delay),
+            max_delay: Duration::from_millis(self.request_max_delay),
+            deadline: Duration::from_millis(self.request_deadline),
+            budget_ratio: self.retry_budget_ratio,
+            budget_min_retries: self.retry_budget_min_retries,
+            budget_window: Duration::from_secs(self.retry_budget_window_secs),
         }
-        if let Some(key) = cli.api_key {
-            self.required_api_key = key;
+    }
+
+    pub fn param_policy(&self) -> crate::ir::ParamPolicy {
+        crate::ir::ParamPolicy::parse(&self.param_mode).unwrap_or_default()
+    }
+
+    /// Validate values that serde cannot check on its own
+    pub fn validate(&self) -> Result<()> {
+        for provider in std::iter::once(&self.model_provider).chain(&self.default_model_providers) {
+            if crate::common::ModelProvider::parse(provider).is_none() {
+                anyhow::bail!("Invalid model provider: {}", provider);
+            }
         }
-        if let Some(provider) = cli.model_provider {
-            self.model_provider = provider;
+
+        let strategies = std::iter::once(("default", &self.default_pool_strategy))
+            .chain(self.pool_strategies.iter().map(|(k, v)| (k.as_str(), v)));
+        for (pool, strategy) in strategies {
+            if crate::pool_selection::SelectionStrategyKind::parse(strategy).is_none() {
+                anyhow::bail!("Invalid pool strategy for {}: {}", pool, strategy);
+            }
         }
-        if let Some(key) = cli.openai_api_key {
-            self.openai_api_key = Some(key);
+
+        for (provider_type, members) in &self.provider_pools {
+            if crate::common::ModelProvider::parse(provider_type).is_none() {
+                anyhow::bail!("Invalid provider type in pools: {}", provider_type);
+            }
+            let mut seen = std::collections::HashSet::new();
+            for member in members {
+                if !seen.insert(&member.uuid) {
+                    anyhow::bail!("Duplicate pool member uuid in {}: {}", provider_type, member.uuid);
+                }
+                if let Some(http) = &member.http {
+                    http.validate()
+                        .with_context(|| format!("Invalid http settings for {} ({})", provider_type, member.uuid))?;
+                }
+            }
         }
-        if let Some(url) = cli.openai_base_url {
-            self.openai_base_url = Some(url);
+
+        if crate::ir::ParamPolicy::parse(&self.param_mode).is_none() {
+            anyhow::bail!("Invalid param mode: {}", self.param_mode);
         }
-        if let Some(key) = cli.claude_api_key {
-            self.claude_api_key = Some(key);
+
+        if !(self.retry_budget_ratio.is_finite() && self.retry_budget_ratio >= 0.0) {
+            anyhow::bail!("retry_budget_ratio must be a non-negative number");
         }
-        if let Some(url) = cli.claude_base_url {
-            self.claude_base_url = Some(url);
+        if self.retry_budget_window_secs == 0 {
+            anyhow::bail!("retry_budget_window_secs must be greater than 0");
         }
-        if let Some(file) = cli.kiro_oauth_creds_file {
-            self.kiro_oauth_creds_file_path = Some(file);
+
+        self.http.validate().context("Invalid http settings")?;
+        for (provider, http) in &self.provider_http {
+            if crate::common::ModelProvider::parse(provider).is_none() {
+                anyhow::bail!("Invalid provider type in provider_http: {}", provider);
+            }
+            http.validate()
+                .with_context(|| format!("Invalid provider_http settings for {}", provider))?;
         }
-        if let Some(base64) = cli.kiro_oauth_creds_base64 {
-            self.kiro_oauth_creds_base64 = Some(base64);
+
+        // Listen addresses and CORS settings are only used at startup; check them up front
+        crate::transport::listen_addrs(self)?;
+        let _ = crate::transport::cors_layer(self)?;
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            anyhow::bail!("tls_cert_path and tls_key_path must be set together");
         }
-        if let Some(file) = cli.gemini_oauth_creds_file {
-            self.gemini_oauth_creds_file_path = Some(file);
+
+        for (endpoint, locations) in &self.auth_locations {
+            if AuthEndpoint::parse(endpoint).is_none() {
+                anyhow::bail!("Invalid endpoint group in auth_locations: {}", endpoint);
+            }
+            if locations.is_empty() {
+                anyhow::bail!("auth_locations for {} must not be empty", endpoint);
+            }
         }
-        if let Some(id) = cli.project_id {
-            self.project_id = Some(id);
+
+        let mut labels = std::collections::HashSet::new();
+        for client_key in &self.api_keys {
+            match &client_key.key_hash {
+                Some(_) if !client_key.key.reference().is_empty() => {
+                    anyhow::bail!("API key {} sets both key and key_hash", client_key.label);
+                }
+                Some(hash) if !crate::auth::is_valid_key_hash(hash) => {
+                    anyhow::bail!("Invalid key_hash for API key {}", client_key.label);
+                }
+                None if client_key.key.is_empty() => {
+                    anyhow::bail!("API key {} is empty", client_key.label);
+                }
+                _ => {}
+            }
+            if !labels.insert(&client_key.label) {
+                anyhow::bail!("Duplicate API key label: {}", client_key.label);
+            }
+            for provider in &client_key.allowed_providers {
+                if crate::common::ModelProvider::parse(provider).is_none() {
+                    anyhow::bail!("Invalid provider in API key {}: {}", client_key.label, provider);
+                }
+            }
         }
-        if let Some(file) = cli.qwen_oauth_creds_file {
-            self.qwen_oauth_creds_file_path = Some(file);
+
+        Ok(())
+    }
+
+    /// Credential locations accepted by an endpoint group
+    pub fn credential_locations(&self, endpoint: AuthEndpoint) -> Vec<CredentialLocation> {
+        self.auth_locations
+            .get(endpoint.as_str())
+            .cloned()
+            .unwrap_or_else(|| endpoint.default_locations())
+    }
+
+    pub fn lockout_policy(&self) -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: self.auth_lockout_max_failures,
+            window: Duration::from_secs(self.auth_lockout_window_secs),
+            duration: Duration::from_secs(self.auth_lockout_duration_secs),
         }
-        if let Some(mode) = cli.prompt_log_mode {
-            self.prompt_log_mode = mode;
+    }
+
+    /// Effective configuration as JSON with secret values masked
+    pub fn redacted(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("system_prompt_content");
         }
+        redact_secrets(&mut value);
+        value
     }
 
     /// Normalize and validate provider configuration
@@ -380,7 +888,22 @@ impl Default for Config {
         Self {
             host: default_host(),
             port: default_port(),
+            listen: Vec::new(),
+            tls_cert_path: None,
+            tls_key_path: None,
+            cors_allowed_origins: default_cors_allowed_origins(),
+            cors_allowed_methods: default_cors_allowed_methods(),
+            cors_allowed_headers: default_cors_allowed_headers(),
+            shutdown_drain_timeout_secs: default_shutdown_drain_timeout_secs(),
+            http: HttpClientConfig::default(),
+            provider_http: HashMap::new(),
             required_api_key: default_api_key(),
+            api_keys: Vec::new(),
+            admin_api_key: None,
+            auth_locations: HashMap::new(),
+            auth_lockout_max_failures: default_auth_lockout_max_failures(),
+            auth_lockout_window_secs: default_auth_lockout_window_secs(),
+            auth_lockout_duration_secs: default_auth_lockout_duration_secs(),
             model_provider: default_model_provider(),
             default_model_providers: vec![],
             openai_api_key: None,
@@ -398,13 +921,99 @@ impl Default for Config {
             system_prompt_content: None,
             prompt_log_mode: default_prompt_log_mode(),
             prompt_log_base_name: default_prompt_log_base_name(),
+            validate_structured_output: false,
+            param_mode: default_param_mode(),
             request_max_retries: default_max_retries(),
             request_base_delay: default_base_delay(),
+            request_max_delay: default_max_delay(),
+            request_deadline: default_request_deadline(),
+            retry_budget_ratio: default_retry_budget_ratio(),
+            retry_budget_min_retries: default_retry_budget_min_retries(),
+            retry_budget_window_secs: default_retry_budget_window_secs(),
             cron_near_minutes: default_cron_near_minutes(),
             cron_refresh_token: default_cron_refresh_token(),
             provider_pools_file_path: None,
             provider_pools: HashMap::new(),
+            default_pool_strategy: default_pool_strategy(),
+            pool_strategies: HashMap::new(),
+            sticky_sessions: false,
+            sticky_session_header: default_session_header(),
+            hot_reload: default_hot_reload(),
+            hot_reload_interval_secs: default_hot_reload_interval(),
+            max_concurrency: None,
+            max_queue_size: default_max_queue_size(),
+            queue_timeout_ms: default_queue_timeout_ms(),
+            config_file_path: default_config_file_path(),
+            value_sources: BTreeMap::new(),
+        }
+    }
+}
+
+/// Read a config file as JSON, YAML or TOML depending on its extension (JSON if unknown)
+fn read_config_file(path: &Path) -> Result<serde_json::Value> {
+    let format = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => config::FileFormat::Yaml,
+        Some("toml") => config::FileFormat::Toml,
+        _ => config::FileFormat::Json,
+    };
+    config::Config::builder()
+        .add_source(config::File::from(path).format(format))
+        .build()
+        .and_then(|c| c.try_deserialize())
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Expand a leading `~` to the home directory, then `${VAR}` references
+pub fn expand_path(value: &str) -> Result<String> {
+    let value = expand_env_vars(value)?;
+    match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = dirs::home_dir().context("Cannot expand ~: home directory unknown")?;
+            Ok(format!("{}{}", home.display(), rest))
         }
+        _ => Ok(value),
+    }
+}
+
+/// Replace `${VAR}` with the value of environment variable `VAR`; unset variables are an error
+pub fn expand_env_vars(value: &str) -> Result<String> {
+    if !value.contains("${") {
+        return Ok(value.to_string());
+    }
+
+    let pattern = regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}")?;
+    let mut missing = None;
+    let expanded = pattern.replace_all(value, |caps: &regex::Captures| {
+        std::env::var(&caps[1]).unwrap_or_else(|_| {
+            missing = Some(caps[1].to_string());
+            String::new()
+        })
+    });
+    match missing {
+        Some(name) => anyhow::bail!("Environment variable {} is not set", name),
+        None => Ok(expanded.into_owned()),
     }
 }
 
+/// Mask string values of secret-looking keys (API keys, tokens, base64 credentials), recursively.
+/// Secret references such as `env:NAME` are kept since they reveal nothing.
+pub fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                let secret = ["key", "token", "base64", "secret", "password", "proxy"]
+                    .iter()
+                    .any(|marker| key.contains(marker));
+                let reference = value.as_str().map(is_reference).unwrap_or(false);
+                if secret && value.is_string() && !reference {
+                    *value = serde_json::Value::String("***".to_string());
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
@@ -1,13 +1,14 @@
 /*!
  * Format Conversion Module
  *
- * Handles conversion between different AI API formats (OpenAI, Claude, Gemini).
+ * Handles conversion between different AI API formats (OpenAI, Claude, Gemini, Kiro).
+ * Every direction parses the source into the IR (`crate::ir`) and emits the target from it.
  */
 
 use crate::common::*;
+use crate::ir;
 use anyhow::Result;
 use serde_json::Value;
-use uuid::Uuid;
 
 /// Conversion type
 #[derive(Debug, Clone, Copy)]
@@ -18,7 +19,11 @@ pub enum ConversionType {
     ModelList,
 }
 
-/// Convert data between different API formats
+/// Convert data between different API formats.
+///
+/// `model` overrides the model named in the output. A stream chunk converts to zero or more
+/// chunks, returned as an array; it is converted as if it were the first chunk of its stream,
+/// so whole streams should go through `ir::StreamConverter`, which keeps state between chunks.
 pub fn convert_data(
     data: Value,
     conversion_type: ConversionType,
@@ -31,200 +36,48 @@ pub fn convert_data(
         return Ok(data);
     }
 
-    match (conversion_type, to_protocol, from_protocol) {
-        // OpenAI conversions
-        (ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Gemini) => {
-            to_openai_request_from_gemini(data)
+    match conversion_type {
+        ConversionType::Request => {
+            let mut request = ir::parse_request(from_protocol, &data)?;
+            if let Some(model) = model {
+                request.model = Some(model.to_string());
+            }
+            ir::emit_request(to_protocol, &request)
+        }
+        ConversionType::Response => {
+            let mut response = ir::parse_response(from_protocol, &data)?;
+            if let Some(model) = model {
+                response.model = Some(model.to_string());
+            }
+            ir::emit_response(to_protocol, &response)
+        }
+        ConversionType::StreamChunk => {
+            let mut converter = ir::StreamConverter::new(from_protocol, to_protocol, model)?;
+            Ok(Value::Array(converter.convert(&data)?))
+        }
+        ConversionType::ModelList => {
+            let models = ir::parse_models(from_protocol, &data)?;
+            ir::emit_models(to_protocol, &models)
         }
-        (ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Claude) => {
-            to_openai_request_from_claude(data)
-        }
-        (ConversionType::Response, ModelProtocol::OpenAI, ModelProtocol::Gemini) => {
-            to_openai_response_from_gemini(data, model)
-        }
-        (ConversionType::Response, ModelProtocol::OpenAI, ModelProtocol::Claude) => {
-            to_openai_response_from_claude(data, model)
-        }
-        (ConversionType::StreamChunk, ModelProtocol::OpenAI, ModelProtocol::Gemini) => {
-            to_openai_stream_chunk_from_gemini(data, model)
-        }
-        (ConversionType::StreamChunk, ModelProtocol::OpenAI, ModelProtocol::Claude) => {
-            to_openai_stream_chunk_from_claude(data, model)
-        }
-        (ConversionType::ModelList, ModelProtocol::OpenAI, ModelProtocol::Gemini) => {
-            to_openai_model_list_from_gemini(data)
-        }
-        (ConversionType::ModelList, ModelProtocol::OpenAI, ModelProtocol::Claude) => {
-            to_openai_model_list_from_claude(data)
-        }
-
-        // Claude conversions
-        (ConversionType::Request, ModelProtocol::Claude, ModelProtocol::OpenAI) => {
-            to_claude_request_from_openai(data)
-        }
-        (ConversionType::Request, ModelProtocol::Claude, ModelProtocol::Gemini) => {
-            to_claude_request_from_gemini(data)
-        }
-        (ConversionType::Response, ModelProtocol::Claude, ModelProtocol::OpenAI) => {
-            to_claude_response_from_openai(data, model)
-        }
-        (ConversionType::Response, ModelProtocol::Claude, ModelProtocol::Gemini) => {
-            to_claude_response_from_gemini(data, model)
-        }
-        (ConversionType::StreamChunk, ModelProtocol::Claude, ModelProtocol::OpenAI) => {
-            to_claude_stream_chunk_from_openai(data, model)
-        }
-        (ConversionType::StreamChunk, ModelProtocol::Claude, ModelProtocol::Gemini) => {
-            to_claude_stream_chunk_from_gemini(data, model)
-        }
-        (ConversionType::ModelList, ModelProtocol::Claude, ModelProtocol::OpenAI) => {
-            to_claude_model_list_from_openai(data)
-        }
-        (ConversionType::ModelList, ModelProtocol::Claude, ModelProtocol::Gemini) => {
-            to_claude_model_list_from_gemini(data)
-        }
-
-        // Gemini conversions
-        (ConversionType::Request, ModelProtocol::Gemini, ModelProtocol::OpenAI) => {
-            to_gemini_request_from_openai(data)
-        }
-        (ConversionType::Request, ModelProtocol::Gemini, ModelProtocol::Claude) => {
-            to_gemini_request_from_claude(data)
-        }
-
-        _ => anyhow::bail!(
-            "Unsupported conversion: {:?} from {:?} to {:?}",
-            conversion_type,
-            from_protocol,
-            to_protocol
-        ),
     }
 }
 
-// Conversion functions using detailed implementations
-fn to_openai_request_from_gemini(data: Value) -> Result<Value> {
-    // Gemini to OpenAI request is reverse of OpenAI to Gemini
-    // For now, pass through
-    Ok(data)
-}
-
-fn to_openai_request_from_claude(data: Value) -> Result<Value> {
-    // Claude to OpenAI request is reverse of OpenAI to Claude
-    // For now, pass through
-    Ok(data)
-}
-
-fn to_openai_response_from_gemini(data: Value, model: Option<&str>) -> Result<Value> {
-    crate::convert_detailed::gemini_response_to_openai(data, model.unwrap_or("gemini-2.5-flash"))
-}
-
-fn to_openai_response_from_claude(data: Value, model: Option<&str>) -> Result<Value> {
-    crate::convert_detailed::claude_response_to_openai(data, model.unwrap_or("claude-3-opus"))
-}
-
-fn to_openai_stream_chunk_from_gemini(_data: Value, model: Option<&str>) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "id": format!("chatcmpl-{}", Uuid::new_v4()),
-        "object": "chat.completion.chunk",
-        "created": chrono::Utc::now().timestamp(),
-        "model": model.unwrap_or("unknown"),
-        "choices": []
-    }))
-}
-
-fn to_openai_stream_chunk_from_claude(_data: Value, model: Option<&str>) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "id": format!("chatcmpl-{}", Uuid::new_v4()),
-        "object": "chat.completion.chunk",
-        "created": chrono::Utc::now().timestamp(),
-        "model": model.unwrap_or("unknown"),
-        "choices": []
-    }))
-}
-
-fn to_openai_model_list_from_gemini(_data: Value) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "object": "list",
-        "data": []
-    }))
-}
-
-fn to_openai_model_list_from_claude(_data: Value) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "object": "list",
-        "data": []
-    }))
-}
-
-fn to_claude_request_from_openai(data: Value) -> Result<Value> {
-    crate::convert_detailed::openai_request_to_claude(data)
-}
-
-fn to_claude_request_from_gemini(data: Value) -> Result<Value> {
-    // Gemini to Claude: convert via intermediate format if needed
-    // For now, pass through
-    Ok(data)
-}
-
-fn to_claude_response_from_openai(_data: Value, model: Option<&str>) -> Result<Value> {
-    // OpenAI to Claude response is reverse
-    // For now, return basic structure
-    Ok(serde_json::json!({
-        "id": format!("msg_{}", Uuid::new_v4()),
-        "type": "message",
-        "role": "assistant",
-        "content": [],
-        "model": model.unwrap_or("claude-3-opus"),
-        "stop_reason": "end_turn",
-        "usage": {}
-    }))
-}
-
-fn to_claude_response_from_gemini(data: Value, model: Option<&str>) -> Result<Value> {
-    crate::convert_detailed::gemini_response_to_claude(data, model.unwrap_or("claude-3-opus"))
-}
-
-fn to_claude_stream_chunk_from_openai(_data: Value, _model: Option<&str>) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "type": "content_block_delta",
-        "index": 0,
-        "delta": {}
-    }))
-}
-
-fn to_claude_stream_chunk_from_gemini(_data: Value, _model: Option<&str>) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "type": "content_block_delta",
-        "index": 0,
-        "delta": {}
-    }))
-}
-
-fn to_claude_model_list_from_openai(_data: Value) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "models": []
-    }))
-}
-
-fn to_claude_model_list_from_gemini(_data: Value) -> Result<Value> {
-    // TODO: Implement conversion
-    Ok(serde_json::json!({
-        "models": []
-    }))
-}
-
-fn to_gemini_request_from_openai(data: Value) -> Result<Value> {
-    crate::convert_detailed::openai_request_to_gemini(data)
-}
-
-fn to_gemini_request_from_claude(data: Value) -> Result<Value> {
-    crate::convert_detailed::claude_request_to_gemini(data)
+/// Convert a request for the upstream, with its generation parameters fitted to the target model
+/// under `policy`. A request already in the target protocol is passed through as it is unless
+/// its parameters had to be adjusted, in which case it is emitted again from the IR.
+pub fn convert_request(
+    data: Value,
+    from_protocol: ModelProtocol,
+    to_protocol: ModelProtocol,
+    model: Option<&str>,
+    policy: ir::ParamPolicy,
+) -> Result<Value> {
+    let mut request = ir::parse_request(from_protocol, &data)?;
+    if let Some(model) = model {
+        request.model = Some(model.to_string());
+    }
+    if from_protocol == to_protocol && ir::capabilities::fit(&request, to_protocol, policy)? == request {
+        return Ok(data);
+    }
+    ir::emit_request_with(to_protocol, &request, policy)
 }
-
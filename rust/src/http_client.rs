@@ -0,0 +1,200 @@
+/*!
+ * Upstream HTTP Client Settings
+ *
+ * Timeouts, connection pool and proxy settings for the clients that talk to the providers.
+ * Settings are layered: the top-level `http` block, then `provider_http.<provider>`, then the
+ * `http` block of a pool member; unset fields fall through to the next layer down and finally
+ * to the defaults below.
+ *
+ * The client itself has no total timeout, since that would also cut off long streams. Instead,
+ * non-streaming requests get `request_timeout_ms`, and streams get a deadline for their first
+ * chunk plus a maximum gap between chunks.
+ */
+
+use crate::secrets::Secret;
+use anyhow::{Context, Result};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 300_000;
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_FIRST_BYTE_TIMEOUT_MS: u64 = 120_000;
+const DEFAULT_IDLE_TIMEOUT_MS: u64 = 90_000;
+const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 10;
+
+/// Proxy value that disables proxying, including `HTTP(S)_PROXY` from the environment
+const NO_PROXY: &str = "none";
+
+/// HTTP client settings; every field is optional so layers can be merged
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HttpClientConfig {
+    /// Total time for a non-streaming request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+    /// Time until the first chunk of a stream arrives
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_byte_timeout_ms: Option<u64>,
+    /// Longest allowed gap between two chunks of a stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_idle_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// `http://`, `https://`, `socks5://` or `socks5h://` proxy URL (credentials allowed), or
+    /// `none` to ignore `HTTP(S)_PROXY`; may be a secret reference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<Secret>,
+}
+
+impl HttpClientConfig {
+    /// These settings with the fields set in `overrides` replaced
+    pub fn merged(&self, overrides: Option<&HttpClientConfig>) -> HttpClientConfig {
+        let Some(overrides) = overrides else {
+            return self.clone();
+        };
+        HttpClientConfig {
+            request_timeout_ms: overrides.request_timeout_ms.or(self.request_timeout_ms),
+            connect_timeout_ms: overrides.connect_timeout_ms.or(self.connect_timeout_ms),
+            first_byte_timeout_ms: overrides.first_byte_timeout_ms.or(self.first_byte_timeout_ms),
+            idle_timeout_ms: overrides.idle_timeout_ms.or(self.idle_timeout_ms),
+            pool_idle_timeout_secs: overrides.pool_idle_timeout_secs.or(self.pool_idle_timeout_secs),
+            pool_max_idle_per_host: overrides.pool_max_idle_per_host.or(self.pool_max_idle_per_host),
+            proxy: overrides.proxy.clone().or_else(|| self.proxy.clone()),
+        }
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms.unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS))
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms.unwrap_or(DEFAULT_CONNECT_TIMEOUT_MS))
+    }
+
+    pub fn first_byte_timeout(&self) -> Duration {
+        Duration::from_millis(self.first_byte_timeout_ms.unwrap_or(DEFAULT_FIRST_BYTE_TIMEOUT_MS))
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.idle_timeout_ms.unwrap_or(DEFAULT_IDLE_TIMEOUT_MS))
+    }
+
+    /// Resolve a secret reference in `proxy`
+    pub fn resolve_secrets(&mut self) -> Result<()> {
+        if let Some(proxy) = &mut self.proxy {
+            proxy.resolve().context("Failed to resolve proxy")?;
+        }
+        Ok(())
+    }
+
+    /// Check values that serde cannot check on its own
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("request_timeout_ms", self.request_timeout_ms),
+            ("connect_timeout_ms", self.connect_timeout_ms),
+            ("first_byte_timeout_ms", self.first_byte_timeout_ms),
+            ("idle_timeout_ms", self.idle_timeout_ms),
+        ] {
+            if value == Some(0) {
+                anyhow::bail!("{} must be greater than 0", name);
+            }
+        }
+        if let Some(proxy) = self.proxy.as_ref().map(Secret::expose) {
+            if proxy != NO_PROXY {
+                let url = url::Url::parse(proxy).with_context(|| format!("Invalid proxy URL: {}", redact_url(proxy)))?;
+                if !["http", "https", "socks5", "socks5h"].contains(&url.scheme()) {
+                    anyhow::bail!("Unsupported proxy scheme: {}", url.scheme());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build a client with these connection pool, connect timeout and proxy settings.
+    ///
+    /// The idle timeout is also applied to every socket read, which bounds requests that
+    /// bypass the adapter timeouts (e.g. token refreshes).
+    pub fn build_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout())
+            .read_timeout(self.idle_timeout().max(self.first_byte_timeout()))
+            .pool_idle_timeout(Duration::from_secs(
+                self.pool_idle_timeout_secs.unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT_SECS),
+            ))
+            .pool_max_idle_per_host(self.pool_max_idle_per_host.unwrap_or(DEFAULT_POOL_MAX_IDLE_PER_HOST))
+            .tcp_nodelay(true);
+
+        match self.proxy.as_ref().map(Secret::expose) {
+            Some(NO_PROXY) => builder = builder.no_proxy(),
+            Some(proxy) => {
+                let proxy = reqwest::Proxy::all(proxy)
+                    .with_context(|| format!("Invalid proxy URL: {}", redact_url(proxy)))?;
+                builder = builder.proxy(proxy);
+            }
+            None => {}
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// URL with the password replaced, for error messages
+fn redact_url(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(mut parsed) if parsed.password().is_some() => {
+            let _ = parsed.set_password(Some("***"));
+            parsed.to_string()
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// An upstream request that took too long
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum UpstreamTimeout {
+    #[error("Upstream request timed out after {0:?}")]
+    Request(Duration),
+    #[error("Upstream stream sent no data within {0:?}")]
+    FirstByte(Duration),
+    #[error("Upstream stream stalled for more than {0:?} between chunks")]
+    Idle(Duration),
+}
+
+type BoxStream<T> = Pin<Box<dyn Stream<Item = Result<T>> + Send>>;
+
+/// Fail a stream whose first item doesn't arrive by `first_byte_deadline`, or whose following
+/// items are more than `idle` apart
+pub fn with_stream_timeouts<T: Send + 'static>(
+    stream: BoxStream<T>,
+    first_byte_deadline: Instant,
+    first_byte: Duration,
+    idle: Duration,
+) -> BoxStream<T> {
+    Box::pin(async_stream::stream! {
+        let mut stream = stream;
+        let mut deadline = first_byte_deadline;
+        let mut error = UpstreamTimeout::FirstByte(first_byte);
+        loop {
+            match tokio::time::timeout_at(deadline, stream.next()).await {
+                Ok(Some(item)) => {
+                    yield item;
+                    deadline = Instant::now() + idle;
+                    error = UpstreamTimeout::Idle(idle);
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    yield Err(error.into());
+                    break;
+                }
+            }
+        }
+    })
+}
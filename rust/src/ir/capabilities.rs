@@ -0,0 +1,263 @@
+/*!
+ * Model Capabilities
+ *
+ * Output limits and generation parameters each provider's models accept, looked up by model
+ * name prefix. `fit` adapts a request to them before it is emitted: with `ParamPolicy::Lenient`
+ * a parameter the model doesn't take is dropped and a value out of range is clamped, with
+ * `ParamPolicy::Strict` the request is rejected instead.
+ */
+
+use super::*;
+use std::fmt::Display;
+use tracing::debug;
+
+/// What a model accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    /// Most tokens one answer can have; Claude's `max_tokens` defaults to it
+    pub max_output_tokens: u64,
+    /// Highest temperature (the lowest is 0), or `None` if it can't be set
+    pub max_temperature: Option<f64>,
+    pub top_p: bool,
+    pub top_k: bool,
+    /// Most stop sequences per request; 0 if there are none
+    pub max_stop_sequences: usize,
+    pub seed: bool,
+    /// `presence_penalty` and `frequency_penalty`
+    pub penalties: bool,
+    /// Most choices per request (OpenAI `n`, Gemini `candidateCount`)
+    pub max_choices: u32,
+    pub logprobs: bool,
+    /// The length limit is sent as `max_completion_tokens` rather than `max_tokens`
+    pub max_completion_tokens: bool,
+}
+
+const OPENAI: Capabilities = Capabilities {
+    max_output_tokens: 16384,
+    max_temperature: Some(2.0),
+    top_p: true,
+    top_k: false,
+    max_stop_sequences: 4,
+    seed: true,
+    penalties: true,
+    max_choices: 128,
+    logprobs: true,
+    max_completion_tokens: false,
+};
+
+/// o-series and GPT-5 reasoning models take no sampling settings
+const OPENAI_REASONING: Capabilities = Capabilities {
+    max_output_tokens: 100_000,
+    max_temperature: None,
+    top_p: false,
+    penalties: false,
+    logprobs: false,
+    max_completion_tokens: true,
+    ..OPENAI
+};
+
+const CLAUDE: Capabilities = Capabilities {
+    max_output_tokens: 64000,
+    max_temperature: Some(1.0),
+    top_p: true,
+    top_k: true,
+    max_stop_sequences: usize::MAX,
+    seed: false,
+    penalties: false,
+    max_choices: 1,
+    logprobs: false,
+    max_completion_tokens: false,
+};
+
+const GEMINI: Capabilities = Capabilities {
+    max_output_tokens: 8192,
+    max_temperature: Some(2.0),
+    top_p: true,
+    top_k: true,
+    max_stop_sequences: 5,
+    seed: true,
+    penalties: true,
+    max_choices: 8,
+    logprobs: true,
+    max_completion_tokens: false,
+};
+
+/// CodeWhisperer takes no generation settings and no length limit
+const KIRO: Capabilities = Capabilities {
+    max_output_tokens: u64::MAX,
+    max_temperature: None,
+    top_p: false,
+    top_k: false,
+    max_stop_sequences: 0,
+    seed: false,
+    penalties: false,
+    max_choices: 1,
+    logprobs: false,
+    max_completion_tokens: false,
+};
+
+/// Model name prefixes, most specific first; a model matching none gets its protocol's defaults
+const MODELS: &[(ModelProtocol, &str, Capabilities)] = &[
+    (ModelProtocol::OpenAI, "gpt-5", Capabilities { max_output_tokens: 128_000, ..OPENAI_REASONING }),
+    (ModelProtocol::OpenAI, "o1", OPENAI_REASONING),
+    (ModelProtocol::OpenAI, "o3", OPENAI_REASONING),
+    (ModelProtocol::OpenAI, "o4", OPENAI_REASONING),
+    (ModelProtocol::OpenAI, "gpt-4.1", Capabilities { max_output_tokens: 32768, ..OPENAI }),
+    (ModelProtocol::OpenAI, "gpt-4o", OPENAI),
+    (ModelProtocol::OpenAI, "gpt-4-turbo", Capabilities { max_output_tokens: 4096, ..OPENAI }),
+    (ModelProtocol::OpenAI, "gpt-4", Capabilities { max_output_tokens: 8192, ..OPENAI }),
+    (ModelProtocol::OpenAI, "gpt-3.5", Capabilities { max_output_tokens: 4096, ..OPENAI }),
+    (ModelProtocol::Claude, "claude-opus-4-5", CLAUDE),
+    (ModelProtocol::Claude, "claude-opus-4", Capabilities { max_output_tokens: 32000, ..CLAUDE }),
+    (ModelProtocol::Claude, "claude-4-opus", Capabilities { max_output_tokens: 32000, ..CLAUDE }),
+    (ModelProtocol::Claude, "claude-3-7", CLAUDE),
+    (ModelProtocol::Claude, "claude-3-5", Capabilities { max_output_tokens: 8192, ..CLAUDE }),
+    (ModelProtocol::Claude, "claude-3", Capabilities { max_output_tokens: 4096, ..CLAUDE }),
+    (ModelProtocol::Gemini, "gemini-2.5", Capabilities { max_output_tokens: 65536, penalties: false, logprobs: false, ..GEMINI }),
+    (ModelProtocol::Gemini, "gemini-3", Capabilities { max_output_tokens: 65536, penalties: false, logprobs: false, ..GEMINI }),
+];
+
+/// Capabilities of a model spoken to over a protocol
+pub fn lookup(protocol: ModelProtocol, model: &str) -> Capabilities {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    MODELS
+        .iter()
+        .find(|(p, prefix, _)| *p == protocol && model.starts_with(prefix))
+        .map(|(_, _, capabilities)| *capabilities)
+        .unwrap_or(match protocol {
+            ModelProtocol::OpenAI => OPENAI,
+            ModelProtocol::Claude => CLAUDE,
+            ModelProtocol::Gemini => GEMINI,
+            ModelProtocol::Kiro => KIRO,
+        })
+}
+
+/// What to do with a parameter the target model doesn't take
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParamPolicy {
+    /// Drop it, or clamp it into range
+    #[default]
+    Lenient,
+    /// Reject the request
+    Strict,
+}
+
+impl ParamPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "lenient" => Some(Self::Lenient),
+            "strict" => Some(Self::Strict),
+            _ => None,
+        }
+    }
+}
+
+/// The request with its generation parameters fitted to the target model
+pub fn fit(request: &ChatRequest, protocol: ModelProtocol, policy: ParamPolicy) -> Result<ChatRequest, UnsupportedContent> {
+    let mut request = request.clone();
+    let model = request.model.clone().unwrap_or_default();
+    let capabilities = lookup(protocol, &model);
+    let fitter = Fitter { protocol, model: &model, policy };
+    let params = &mut request.params;
+
+    if let Some(max_tokens) = params.max_tokens.filter(|m| *m > capabilities.max_output_tokens) {
+        fitter.out_of_range("max_tokens", max_tokens, capabilities.max_output_tokens)?;
+        params.max_tokens = Some(capabilities.max_output_tokens);
+    }
+    if let Some(temperature) = params.temperature {
+        match capabilities.max_temperature {
+            None => {
+                fitter.unsupported("temperature")?;
+                params.temperature = None;
+            }
+            Some(max) if !(0.0..=max).contains(&temperature) => {
+                fitter.out_of_range("temperature", temperature, max)?;
+                params.temperature = Some(temperature.clamp(0.0, max));
+            }
+            Some(_) => {}
+        }
+    }
+    if params.top_p.is_some() && !capabilities.top_p {
+        fitter.unsupported("top_p")?;
+        params.top_p = None;
+    }
+    if params.top_k.is_some() && !capabilities.top_k {
+        fitter.unsupported("top_k")?;
+        params.top_k = None;
+    }
+    if params.stop.len() > capabilities.max_stop_sequences {
+        if capabilities.max_stop_sequences == 0 {
+            fitter.unsupported("stop sequences")?;
+        } else {
+            fitter.out_of_range("stop sequences", params.stop.len(), capabilities.max_stop_sequences)?;
+        }
+        params.stop.truncate(capabilities.max_stop_sequences);
+    }
+    if params.seed.is_some() && !capabilities.seed {
+        fitter.unsupported("seed")?;
+        params.seed = None;
+    }
+    if !capabilities.penalties {
+        if params.presence_penalty.is_some() {
+            fitter.unsupported("presence_penalty")?;
+            params.presence_penalty = None;
+        }
+        if params.frequency_penalty.is_some() {
+            fitter.unsupported("frequency_penalty")?;
+            params.frequency_penalty = None;
+        }
+    }
+    if let Some(n) = params.n.filter(|n| *n > capabilities.max_choices) {
+        fitter.out_of_range("n", n, capabilities.max_choices)?;
+        params.n = Some(capabilities.max_choices);
+    }
+    if (params.logprobs || params.top_logprobs.is_some()) && !capabilities.logprobs {
+        fitter.unsupported("logprobs")?;
+        params.logprobs = false;
+        params.top_logprobs = None;
+    }
+    Ok(request)
+}
+
+struct Fitter<'a> {
+    protocol: ModelProtocol,
+    model: &'a str,
+    policy: ParamPolicy,
+}
+
+impl Fitter<'_> {
+    fn unsupported(&self, param: &str) -> Result<(), UnsupportedContent> {
+        self.reject_or_log(format!("{} ({})", param, self.model_name()))
+    }
+
+    fn out_of_range(&self, param: &str, value: impl Display, limit: impl Display) -> Result<(), UnsupportedContent> {
+        self.reject_or_log(format!("{} {} (at most {} for {})", param, value, limit, self.model_name()))
+    }
+
+    fn reject_or_log(&self, what: String) -> Result<(), UnsupportedContent> {
+        match self.policy {
+            ParamPolicy::Strict => Err(UnsupportedContent { protocol: protocol_name(self.protocol), what }),
+            ParamPolicy::Lenient => {
+                debug!("Adjusting {} request parameter: {}", protocol_name(self.protocol), what);
+                Ok(())
+            }
+        }
+    }
+
+    fn model_name(&self) -> &str {
+        if self.model.is_empty() {
+            "the default model"
+        } else {
+            self.model
+        }
+    }
+}
+
+fn protocol_name(protocol: ModelProtocol) -> &'static str {
+    match protocol {
+        ModelProtocol::OpenAI => "OpenAI",
+        ModelProtocol::Claude => "Claude",
+        ModelProtocol::Gemini => "Gemini",
+        ModelProtocol::Kiro => "Kiro",
+    }
+}
@@ -0,0 +1,673 @@
+/*!
+ * Claude Messages API
+ *
+ * Parser and emitter between Messages API bodies and the IR, which follows the same shape.
+ */
+
+use super::*;
+use base64::Engine;
+use serde_json::json;
+use uuid::Uuid;
+
+const DEFAULT_MODEL: &str = "claude-3-opus";
+/// Smallest `thinking.budget_tokens` Claude accepts
+const MIN_THINKING_BUDGET: u64 = 1024;
+
+pub fn parse_request(body: &Value) -> Result<ChatRequest> {
+    let mut request = ChatRequest {
+        model: body.get("model").and_then(|m| m.as_str()).map(String::from),
+        stream: body.get("stream").and_then(|s| s.as_bool()).unwrap_or(false),
+        ..Default::default()
+    };
+
+    request.system = match body.get("system") {
+        Some(Value::String(text)) if !text.is_empty() => vec![text.clone()],
+        Some(Value::Array(blocks)) => blocks
+            .iter()
+            .filter_map(|block| block.get("text")?.as_str())
+            .filter(|text| !text.is_empty())
+            .map(String::from)
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    for msg in body.get("messages").and_then(|m| m.as_array()).into_iter().flatten() {
+        let role = match msg.get("role").and_then(|r| r.as_str()) {
+            Some("assistant") => Role::Assistant,
+            _ => Role::User,
+        };
+        request
+            .messages
+            .push(Message::new(role, parse_content(msg.get("content").unwrap_or(&Value::Null))));
+    }
+
+    for tool in body.get("tools").and_then(|t| t.as_array()).into_iter().flatten() {
+        // Server tools such as `web_search_20250305` run at Anthropic and have no equivalent elsewhere
+        if tool.get("type").and_then(|t| t.as_str()).is_some_and(|t| t != "custom") {
+            continue;
+        }
+        let Some(name) = tool.get("name").and_then(|n| n.as_str()) else { continue };
+        request.tools.push(ToolDefinition {
+            name: name.to_string(),
+            description: tool.get("description").and_then(|d| d.as_str()).map(String::from),
+            parameters: tool.get("input_schema").cloned().unwrap_or_else(|| json!({"type": "object"})),
+        });
+    }
+    if let Some(choice) = body.get("tool_choice") {
+        request.tool_choice = match choice.get("type").and_then(|t| t.as_str()) {
+            Some("auto") => Some(ToolChoice::Auto),
+            Some("any") => Some(ToolChoice::Required),
+            Some("none") => Some(ToolChoice::None),
+            Some("tool") => choice.get("name").and_then(|n| n.as_str()).map(|n| ToolChoice::Tool(n.to_string())),
+            _ => None,
+        };
+        if choice.get("disable_parallel_tool_use").and_then(|d| d.as_bool()) == Some(true) {
+            request.parallel_tool_calls = Some(false);
+        }
+    }
+    link_tool_results(&mut request.messages);
+    request.reasoning = body.get("thinking").and_then(|thinking| match thinking.get("type")?.as_str()? {
+        "enabled" => Some(Reasoning::Budget(thinking.get("budget_tokens")?.as_u64()?)),
+        "disabled" => Some(Reasoning::Disabled),
+        _ => None,
+    });
+    // Claude's own structured output setting, for Claude clients of backends without it
+    request.response_format = body.get("output_format").and_then(|format| {
+        (format.get("type")?.as_str()? == "json_schema").then(|| {
+            ResponseFormat::JsonSchema(JsonSchemaFormat {
+                name: "response".to_string(),
+                description: None,
+                schema: format.get("schema").cloned().unwrap_or_else(|| json!({"type": "object"})),
+                strict: true,
+            })
+        })
+    });
+
+    let params = &mut request.params;
+    params.max_tokens = body.get("max_tokens").and_then(|v| v.as_u64());
+    params.temperature = body.get("temperature").and_then(|v| v.as_f64());
+    params.top_p = body.get("top_p").and_then(|v| v.as_f64());
+    params.top_k = body.get("top_k").and_then(|v| v.as_u64());
+    params.stop = body
+        .get("stop_sequences")
+        .and_then(|s| s.as_array())
+        .map(|stops| stops.iter().filter_map(|s| s.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+
+    Ok(request)
+}
+
+pub fn emit_request(request: &ChatRequest) -> Result<Value> {
+    // JSON output is asked for as a call of the output tool
+    let emulated = structured::with_output_tool(request);
+    let request = emulated.as_ref().unwrap_or(request);
+    let mut messages = Vec::new();
+    for message in &request.messages {
+        // Claude checks the signature of thinking sent back, so unsigned reasoning from other
+        // protocols can't be replayed
+        let parts: Vec<ContentPart> = message
+            .content
+            .iter()
+            .filter(|part| !matches!(part, ContentPart::Thinking(t) if t.signature.is_none() && !t.redacted))
+            .cloned()
+            .collect();
+        let content = emit_content(&parts)?;
+        if content.is_empty() {
+            continue;
+        }
+        let role = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        messages.push(json!({"role": role, "content": content}));
+    }
+
+    let params = &request.params;
+    let model = request.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    // `max_tokens` is required; without one the answer may be as long as the model allows
+    let limit = capabilities::lookup(ModelProtocol::Claude, model).max_output_tokens;
+    let mut max_tokens = params.max_tokens.unwrap_or(limit);
+    let thinking = match request.reasoning {
+        Some(Reasoning::Disabled) => Some(json!({"type": "disabled"})),
+        Some(reasoning) => {
+            let budget = reasoning
+                .budget_tokens()
+                .unwrap_or(0)
+                .min(limit.saturating_sub(MIN_THINKING_BUDGET))
+                .max(MIN_THINKING_BUDGET);
+            // The budget counts towards `max_tokens`, which must leave room for the answer
+            max_tokens = max_tokens.max(budget + MIN_THINKING_BUDGET);
+            Some(json!({"type": "enabled", "budget_tokens": budget}))
+        }
+        None => None,
+    };
+    let thinking_enabled = thinking.as_ref().is_some_and(|t| t["type"] == "enabled");
+    let mut body = json!({
+        "model": model,
+        "max_tokens": max_tokens,
+        "messages": messages,
+    });
+    if let Some(thinking) = thinking {
+        body["thinking"] = thinking;
+    }
+    match request.system.as_slice() {
+        [] => {}
+        [text] => body["system"] = json!(text),
+        blocks => {
+            body["system"] = blocks.iter().map(|text| json!({"type": "text", "text": text})).collect();
+        }
+    }
+    if !request.tools.is_empty() {
+        body["tools"] = request
+            .tools
+            .iter()
+            .map(|tool| {
+                let mut definition = json!({"name": tool.name, "input_schema": tool.parameters});
+                if let Some(description) = &tool.description {
+                    definition["description"] = json!(description);
+                }
+                definition
+            })
+            .collect();
+        if let Some(choice) = emit_tool_choice(request) {
+            body["tool_choice"] = choice;
+        }
+    }
+    // Thinking rejects changes to temperature and top_k, and top_p below 0.95
+    if let Some(temperature) = params.temperature.filter(|_| !thinking_enabled) {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = params.top_p.filter(|p| !thinking_enabled || *p >= 0.95) {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(top_k) = params.top_k.filter(|_| !thinking_enabled) {
+        body["top_k"] = json!(top_k);
+    }
+    if !params.stop.is_empty() {
+        body["stop_sequences"] = json!(params.stop);
+    }
+    if request.stream {
+        body["stream"] = json!(true);
+    }
+    Ok(body)
+}
+
+pub fn parse_response(body: &Value) -> Result<ChatResponse> {
+    let content = body
+        .get("content")
+        .ok_or_else(|| anyhow::anyhow!("Claude response has no content"))?;
+    let mut response = ChatResponse {
+        id: body.get("id").and_then(|id| id.as_str()).map(String::from),
+        model: body.get("model").and_then(|m| m.as_str()).map(String::from),
+        content: parse_content(content),
+        stop_reason: body.get("stop_reason").and_then(|r| r.as_str()).map(parse_stop_reason),
+        usage: body.get("usage").map(|usage| parse_usage(usage, Usage::default())).unwrap_or_default(),
+    };
+    structured::unwrap_output_tool(&mut response);
+    Ok(response)
+}
+
+pub fn emit_response(response: &ChatResponse) -> Value {
+    json!({
+        "id": response.id.clone().unwrap_or_else(|| format!("msg_{}", Uuid::new_v4())),
+        "type": "message",
+        "role": "assistant",
+        // Claude responses carry no attachments; any the upstream produced are left out
+        "content": response.content.iter().filter_map(|part| emit_block(part).ok().flatten()).collect::<Vec<_>>(),
+        "model": response.model.as_deref().unwrap_or(DEFAULT_MODEL),
+        "stop_reason": emit_stop_reason(response.stop_reason.unwrap_or(StopReason::EndTurn)),
+        "stop_sequence": null,
+        "usage": emit_usage(&response.usage),
+    })
+}
+
+pub fn parse_models(body: &Value) -> ModelList {
+    let models = body
+        .get("data")
+        .and_then(|d| d.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            Some(ModelEntry {
+                id: model.get("id")?.as_str()?.to_string(),
+                display_name: model.get("display_name").and_then(|n| n.as_str()).map(String::from),
+                owned_by: Some("anthropic".to_string()),
+                created: model
+                    .get("created_at")
+                    .and_then(|c| c.as_str())
+                    .and_then(|c| chrono::DateTime::parse_from_rfc3339(c).ok())
+                    .map(|c| c.timestamp()),
+            })
+        })
+        .collect();
+    ModelList { models }
+}
+
+pub fn emit_models(list: &ModelList) -> Value {
+    let data: Vec<Value> = list
+        .models
+        .iter()
+        .map(|model| {
+            let created = model
+                .created
+                .and_then(|c| chrono::DateTime::from_timestamp(c, 0))
+                .unwrap_or_default();
+            json!({
+                "type": "model",
+                "id": model.id,
+                "display_name": model.display_name.as_deref().unwrap_or(&model.id),
+                "created_at": created.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            })
+        })
+        .collect();
+    json!({
+        "data": data,
+        "has_more": false,
+        "first_id": list.models.first().map(|m| m.id.as_str()),
+        "last_id": list.models.last().map(|m| m.id.as_str()),
+    })
+}
+
+/// Reads Messages API stream events
+#[derive(Default)]
+pub struct ChunkParser {
+    /// Usage so far: `message_start` has the input counts, `message_delta` the output
+    usage: Usage,
+    /// Indexes of blocks that have no IR equivalent; their deltas are dropped
+    skipped: Vec<usize>,
+    output_tool: structured::OutputToolStream,
+}
+
+impl StreamParser for ChunkParser {
+    fn parse(&mut self, chunk: &Value) -> Result<Vec<StreamEvent>> {
+        let index = chunk.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
+        let event = match chunk.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+            "message_start" => {
+                let message = chunk.get("message").unwrap_or(&Value::Null);
+                self.usage = message.get("usage").map(|usage| parse_usage(usage, Usage::default())).unwrap_or_default();
+                StreamEvent::MessageStart {
+                    id: message.get("id").and_then(|id| id.as_str()).map(String::from),
+                    model: message.get("model").and_then(|m| m.as_str()).map(String::from),
+                }
+            }
+            "content_block_start" => {
+                let block = chunk.get("content_block").unwrap_or(&Value::Null);
+                let kind = match block.get("type").and_then(|t| t.as_str()) {
+                    Some("text") => BlockKind::Text,
+                    Some("thinking") => BlockKind::Thinking,
+                    Some("redacted_thinking") => BlockKind::RedactedThinking {
+                        data: block.get("data").and_then(|d| d.as_str()).unwrap_or("").to_string(),
+                    },
+                    Some("tool_use") => BlockKind::ToolCall {
+                        id: block.get("id").and_then(|id| id.as_str()).unwrap_or("").to_string(),
+                        name: block.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string(),
+                    },
+                    _ => {
+                        self.skipped.push(index);
+                        return Ok(Vec::new());
+                    }
+                };
+                let mut events = vec![StreamEvent::BlockStart { index, block: kind }];
+                // Text that arrives with the block start
+                if let Some(text) = block.get("text").and_then(|t| t.as_str()).filter(|t| !t.is_empty()) {
+                    events.push(StreamEvent::TextDelta { index, text: text.to_string() });
+                }
+                if let Some(text) = block.get("thinking").and_then(|t| t.as_str()).filter(|t| !t.is_empty()) {
+                    events.push(StreamEvent::ThinkingDelta { index, text: text.to_string() });
+                }
+                return Ok(events.into_iter().map(|event| self.output_tool.rewrite(event)).collect());
+            }
+            "content_block_delta" if !self.skipped.contains(&index) => {
+                let delta = chunk.get("delta").unwrap_or(&Value::Null);
+                match delta.get("type").and_then(|t| t.as_str()) {
+                    Some("text_delta") => StreamEvent::TextDelta {
+                        index,
+                        text: delta.get("text").and_then(|t| t.as_str()).unwrap_or("").to_string(),
+                    },
+                    Some("input_json_delta") => StreamEvent::ToolArgumentsDelta {
+                        index,
+                        partial_json: delta.get("partial_json").and_then(|j| j.as_str()).unwrap_or("").to_string(),
+                    },
+                    Some("thinking_delta") => StreamEvent::ThinkingDelta {
+                        index,
+                        text: delta.get("thinking").and_then(|t| t.as_str()).unwrap_or("").to_string(),
+                    },
+                    Some("signature_delta") => StreamEvent::SignatureDelta {
+                        index,
+                        signature: delta.get("signature").and_then(|s| s.as_str()).unwrap_or("").to_string(),
+                    },
+                    _ => return Ok(Vec::new()),
+                }
+            }
+            "content_block_stop" if !self.skipped.contains(&index) => StreamEvent::BlockStop { index },
+            "message_delta" => {
+                // Counts here are cumulative; input counts only come with newer API versions
+                if let Some(usage) = chunk.get("usage") {
+                    self.usage = parse_usage(usage, self.usage);
+                }
+                StreamEvent::MessageDelta {
+                    stop_reason: chunk
+                        .get("delta")
+                        .and_then(|d| d.get("stop_reason"))
+                        .and_then(|r| r.as_str())
+                        .map(parse_stop_reason),
+                    usage: chunk.get("usage").map(|_| self.usage),
+                }
+            }
+            "message_stop" => StreamEvent::MessageStop,
+            _ => return Ok(Vec::new()),
+        };
+        Ok(vec![self.output_tool.rewrite(event)])
+    }
+
+    fn finish(&mut self) -> Vec<StreamEvent> {
+        Vec::new()
+    }
+}
+
+/// Writes Messages API stream events
+pub struct ChunkEmitter {
+    id: String,
+    model: String,
+    blocks: BlockIndexes,
+    stop_reason: Option<StopReason>,
+    usage: Usage,
+}
+
+impl ChunkEmitter {
+    pub fn new(model: Option<&str>) -> Self {
+        Self {
+            id: format!("msg_{}", Uuid::new_v4()),
+            model: model.unwrap_or(DEFAULT_MODEL).to_string(),
+            blocks: BlockIndexes::default(),
+            stop_reason: None,
+            usage: Usage::default(),
+        }
+    }
+}
+
+impl StreamEmitter for ChunkEmitter {
+    fn emit(&mut self, event: &StreamEvent) -> Vec<Value> {
+        match event {
+            StreamEvent::MessageStart { id, model } => {
+                if let Some(id) = id {
+                    self.id = id.clone();
+                }
+                if let Some(model) = model {
+                    self.model = model.clone();
+                }
+                vec![json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": self.id,
+                        "type": "message",
+                        "role": "assistant",
+                        "model": self.model,
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": {"input_tokens": 0, "output_tokens": 0},
+                    },
+                })]
+            }
+            StreamEvent::BlockStart { index, block } => {
+                let content_block = match block {
+                    BlockKind::Text => json!({"type": "text", "text": ""}),
+                    BlockKind::Thinking => json!({"type": "thinking", "thinking": ""}),
+                    BlockKind::RedactedThinking { data } => json!({"type": "redacted_thinking", "data": data}),
+                    BlockKind::ToolCall { id, name } => json!({"type": "tool_use", "id": id, "name": name, "input": {}}),
+                };
+                vec![json!({
+                    "type": "content_block_start",
+                    "index": self.blocks.start(*index),
+                    "content_block": content_block,
+                })]
+            }
+            StreamEvent::TextDelta { index, text } => vec![json!({
+                "type": "content_block_delta",
+                "index": self.blocks.get(*index).unwrap_or(*index),
+                "delta": {"type": "text_delta", "text": text},
+            })],
+            StreamEvent::ThinkingDelta { index, text } => vec![json!({
+                "type": "content_block_delta",
+                "index": self.blocks.get(*index).unwrap_or(*index),
+                "delta": {"type": "thinking_delta", "thinking": text},
+            })],
+            StreamEvent::SignatureDelta { index, signature } => vec![json!({
+                "type": "content_block_delta",
+                "index": self.blocks.get(*index).unwrap_or(*index),
+                "delta": {"type": "signature_delta", "signature": signature},
+            })],
+            StreamEvent::ToolArgumentsDelta { index, partial_json } => vec![json!({
+                "type": "content_block_delta",
+                "index": self.blocks.get(*index).unwrap_or(*index),
+                "delta": {"type": "input_json_delta", "partial_json": partial_json},
+            })],
+            StreamEvent::BlockStop { index } => vec![json!({
+                "type": "content_block_stop",
+                "index": self.blocks.get(*index).unwrap_or(*index),
+            })],
+            StreamEvent::MessageDelta { stop_reason, usage } => {
+                // Sent with `message_stop`, once everything is known
+                if stop_reason.is_some() {
+                    self.stop_reason = *stop_reason;
+                }
+                if let Some(usage) = usage {
+                    self.usage = *usage;
+                }
+                Vec::new()
+            }
+            StreamEvent::MessageStop => vec![
+                json!({
+                    "type": "message_delta",
+                    "delta": {
+                        "stop_reason": emit_stop_reason(self.stop_reason.unwrap_or(StopReason::EndTurn)),
+                        "stop_sequence": null,
+                    },
+                    "usage": emit_usage(&self.usage),
+                }),
+                json!({"type": "message_stop"}),
+            ],
+        }
+    }
+}
+
+fn parse_content(content: &Value) -> Vec<ContentPart> {
+    match content {
+        Value::String(text) if !text.is_empty() => vec![ContentPart::Text(text.clone())],
+        Value::Array(blocks) => blocks.iter().filter_map(parse_block).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn parse_block(block: &Value) -> Option<ContentPart> {
+    match block.get("type")?.as_str()? {
+        "text" => {
+            let text = block.get("text")?.as_str().filter(|t| !t.is_empty())?;
+            Some(ContentPart::Text(text.to_string()))
+        }
+        "image" => {
+            let source = block.get("source")?;
+            let mime_type = source.get("media_type").and_then(|m| m.as_str()).unwrap_or("image/jpeg");
+            let source = match source.get("type")?.as_str()? {
+                "base64" => MediaSource::Base64(source.get("data")?.as_str()?.to_string()),
+                "url" => MediaSource::Url(source.get("url")?.as_str()?.to_string()),
+                _ => return None,
+            };
+            Some(ContentPart::Media(Media::new(mime_type, source)))
+        }
+        "document" => {
+            let source = block.get("source")?;
+            let mime_type = source.get("media_type").and_then(|m| m.as_str()).unwrap_or("application/pdf");
+            let source = match source.get("type")?.as_str()? {
+                "base64" => MediaSource::Base64(source.get("data")?.as_str()?.to_string()),
+                "url" => MediaSource::Url(source.get("url")?.as_str()?.to_string()),
+                // Plain-text documents are just text to every other protocol
+                "text" => return Some(ContentPart::Text(source.get("data")?.as_str()?.to_string())),
+                _ => return None,
+            };
+            let mut media = Media::new(mime_type, source);
+            media.name = block.get("title").and_then(|t| t.as_str()).map(String::from);
+            Some(ContentPart::Media(media))
+        }
+        "thinking" => Some(ContentPart::Thinking(Thinking {
+            text: block.get("thinking").and_then(|t| t.as_str()).unwrap_or("").to_string(),
+            // Responses from other protocols carry an empty signature
+            signature: block.get("signature").and_then(|s| s.as_str()).filter(|s| !s.is_empty()).map(String::from),
+            redacted: false,
+        })),
+        "redacted_thinking" => Some(ContentPart::Thinking(Thinking {
+            text: block.get("data")?.as_str()?.to_string(),
+            signature: None,
+            redacted: true,
+        })),
+        "tool_use" => Some(ContentPart::ToolCall(ToolCall {
+            id: block.get("id")?.as_str()?.to_string(),
+            name: block.get("name")?.as_str()?.to_string(),
+            arguments: block.get("input").cloned().unwrap_or_else(|| json!({})),
+        })),
+        "tool_result" => Some(ContentPart::ToolResult(ToolResult {
+            call_id: block.get("tool_use_id")?.as_str()?.to_string(),
+            name: None,
+            content: parse_content(block.get("content").unwrap_or(&Value::Null)),
+            is_error: block.get("is_error").and_then(|e| e.as_bool()).unwrap_or(false),
+        })),
+        _ => None,
+    }
+}
+
+fn emit_content(parts: &[ContentPart]) -> Result<Vec<Value>> {
+    parts.iter().filter_map(|part| emit_block(part).transpose()).collect()
+}
+
+fn emit_block(part: &ContentPart) -> Result<Option<Value>> {
+    Ok(match part {
+        ContentPart::Text(text) if text.is_empty() => None,
+        ContentPart::Text(text) => Some(json!({"type": "text", "text": text})),
+        ContentPart::Media(media) => Some(emit_media(media)?),
+        ContentPart::Thinking(thinking) if thinking.redacted => {
+            Some(json!({"type": "redacted_thinking", "data": thinking.text}))
+        }
+        ContentPart::Thinking(thinking) => Some(json!({
+            "type": "thinking",
+            "thinking": thinking.text,
+            "signature": thinking.signature.as_deref().unwrap_or(""),
+        })),
+        ContentPart::ToolCall(call) => Some(json!({
+            "type": "tool_use",
+            "id": call.id,
+            "name": call.name,
+            "input": arguments_object(&call.arguments),
+        })),
+        ContentPart::ToolResult(result) => {
+            let content = match result.content.as_slice() {
+                [ContentPart::Text(text)] => json!(text),
+                parts => json!(emit_content(parts)?),
+            };
+            let mut block = json!({"type": "tool_result", "tool_use_id": result.call_id, "content": content});
+            if result.is_error {
+                block["is_error"] = json!(true);
+            }
+            Some(block)
+        }
+    })
+}
+
+/// `tool_choice` carries the parallel-calls switch too, so it is needed when either is set
+fn emit_tool_choice(request: &ChatRequest) -> Option<Value> {
+    let single_call = request.parallel_tool_calls == Some(false);
+    let mut choice = match request.tool_choice.as_ref() {
+        Some(ToolChoice::None) => return Some(json!({"type": "none"})),
+        Some(ToolChoice::Auto) => json!({"type": "auto"}),
+        Some(ToolChoice::Required) => json!({"type": "any"}),
+        Some(ToolChoice::Tool(name)) => json!({"type": "tool", "name": name}),
+        None if single_call => json!({"type": "auto"}),
+        None => return None,
+    };
+    if single_call {
+        choice["disable_parallel_tool_use"] = json!(true);
+    }
+    Some(choice)
+}
+
+fn emit_media(media: &Media) -> Result<Value> {
+    let unsupported = || UnsupportedContent::media("Claude", media);
+    let source = match &media.source {
+        MediaSource::Base64(data) => json!({"type": "base64", "media_type": media.mime_type, "data": data}),
+        MediaSource::Url(url) => json!({"type": "url", "url": url}),
+        MediaSource::FileId(_) => return Err(unsupported().into()),
+    };
+    match media.kind() {
+        MediaKind::Image => Ok(json!({"type": "image", "source": source})),
+        MediaKind::Document => {
+            let source = match (&media.source, media.mime_type.as_str()) {
+                (_, "application/pdf") => source,
+                (MediaSource::Base64(data), mime_type) if mime_type.starts_with("text/") => {
+                    let text = base64::engine::general_purpose::STANDARD
+                        .decode(data)
+                        .ok()
+                        .and_then(|bytes| String::from_utf8(bytes).ok())
+                        .ok_or_else(unsupported)?;
+                    json!({"type": "text", "media_type": "text/plain", "data": text})
+                }
+                _ => return Err(unsupported().into()),
+            };
+            let mut block = json!({"type": "document", "source": source});
+            if let Some(name) = &media.name {
+                block["title"] = json!(name);
+            }
+            Ok(block)
+        }
+        MediaKind::Audio | MediaKind::Video => Err(unsupported().into()),
+    }
+}
+
+fn parse_stop_reason(reason: &str) -> StopReason {
+    match reason {
+        "max_tokens" => StopReason::MaxTokens,
+        "stop_sequence" => StopReason::StopSequence,
+        "tool_use" => StopReason::ToolUse,
+        "pause_turn" => StopReason::PauseTurn,
+        "refusal" => StopReason::ContentFilter,
+        "model_context_window_exceeded" => StopReason::MaxTokens,
+        _ => StopReason::EndTurn,
+    }
+}
+
+fn emit_stop_reason(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::EndTurn => "end_turn",
+        StopReason::MaxTokens => "max_tokens",
+        StopReason::StopSequence => "stop_sequence",
+        StopReason::ToolUse => "tool_use",
+        StopReason::PauseTurn => "pause_turn",
+        StopReason::ContentFilter | StopReason::Recitation => "refusal",
+        StopReason::MalformedToolCall => "end_turn",
+    }
+}
+
+/// Read a `usage` object; counts it leaves out keep their value in `base`. Claude's
+/// `input_tokens` excludes cache reads and writes, the IR's includes them.
+fn parse_usage(usage: &Value, base: Usage) -> Usage {
+    let count = |key: &str, default: u64| usage.get(key).and_then(|t| t.as_u64()).unwrap_or(default);
+    let cache_read = count("cache_read_input_tokens", base.cache_read_input_tokens);
+    let cache_creation = count("cache_creation_input_tokens", base.cache_creation_input_tokens);
+    Usage {
+        input_tokens: count("input_tokens", base.uncached_input_tokens()) + cache_read + cache_creation,
+        output_tokens: count("output_tokens", base.output_tokens),
+        cache_read_input_tokens: cache_read,
+        cache_creation_input_tokens: cache_creation,
+        reasoning_tokens: 0,
+    }
+}
+
+fn emit_usage(usage: &Usage) -> Value {
+    let mut body = json!({"input_tokens": usage.uncached_input_tokens(), "output_tokens": usage.output_tokens});
+    if usage.cache_creation_input_tokens > 0 {
+        body["cache_creation_input_tokens"] = json!(usage.cache_creation_input_tokens);
+    }
+    if usage.cache_read_input_tokens > 0 {
+        body["cache_read_input_tokens"] = json!(usage.cache_read_input_tokens);
+    }
+    body
+}
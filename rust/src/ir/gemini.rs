@@ -0,0 +1,732 @@
+/*!
+ * Gemini generateContent
+ *
+ * Parser and emitter between generateContent bodies and the IR. Gemini accepts both camelCase
+ * and snake_case field names, so the parser reads either; the emitter writes camelCase.
+ * Code Assist wraps responses in `{"response": ...}`, which the parser unwraps.
+ */
+
+use super::*;
+use serde_json::json;
+
+pub fn parse_request(body: &Value) -> Result<ChatRequest> {
+    let mut request = ChatRequest {
+        model: body.get("model").and_then(|m| m.as_str()).map(String::from),
+        ..Default::default()
+    };
+
+    if let Some(system) = field(body, &["systemInstruction", "system_instruction"]) {
+        request.system = system
+            .get("parts")
+            .and_then(|p| p.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|part| part.get("text")?.as_str())
+            .filter(|text| !text.is_empty())
+            .map(String::from)
+            .collect();
+    }
+
+    // IDs derived from the position in the conversation stay the same when it is resent
+    let mut ids = CallIds { sequential: true, ..Default::default() };
+    for content in body.get("contents").and_then(|c| c.as_array()).into_iter().flatten() {
+        let role = match content.get("role").and_then(|r| r.as_str()) {
+            Some("model") => Role::Assistant,
+            _ => Role::User,
+        };
+        request.messages.push(Message::new(role, parse_parts(content, &mut ids)));
+    }
+
+    for tool in body.get("tools").and_then(|t| t.as_array()).into_iter().flatten() {
+        let declarations = field(tool, &["functionDeclarations", "function_declarations"]).and_then(|d| d.as_array());
+        for declaration in declarations.into_iter().flatten() {
+            let Some(name) = declaration.get("name").and_then(|n| n.as_str()) else { continue };
+            request.tools.push(ToolDefinition {
+                name: name.to_string(),
+                description: declaration.get("description").and_then(|d| d.as_str()).map(String::from),
+                parameters: declaration.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object"})),
+            });
+        }
+    }
+    let calling = field(body, &["toolConfig", "tool_config"])
+        .and_then(|config| field(config, &["functionCallingConfig", "function_calling_config"]));
+    if let Some(calling) = calling {
+        let allowed: Vec<&str> = field(calling, &["allowedFunctionNames", "allowed_function_names"])
+            .and_then(|names| names.as_array())
+            .map(|names| names.iter().filter_map(|n| n.as_str()).collect())
+            .unwrap_or_default();
+        request.tool_choice = match (calling.get("mode").and_then(|m| m.as_str()), allowed.as_slice()) {
+            (Some("ANY"), [name]) => Some(ToolChoice::Tool(name.to_string())),
+            (Some("ANY"), _) => Some(ToolChoice::Required),
+            (Some("NONE"), _) => Some(ToolChoice::None),
+            (Some("AUTO"), _) => Some(ToolChoice::Auto),
+            _ => None,
+        };
+    }
+
+    if let Some(config) = field(body, &["generationConfig", "generation_config"]) {
+        let params = &mut request.params;
+        params.max_tokens = field(config, &["maxOutputTokens", "max_output_tokens"]).and_then(|v| v.as_u64());
+        params.temperature = config.get("temperature").and_then(|v| v.as_f64());
+        params.top_p = field(config, &["topP", "top_p"]).and_then(|v| v.as_f64());
+        params.top_k = field(config, &["topK", "top_k"]).and_then(|v| v.as_u64());
+        params.stop = field(config, &["stopSequences", "stop_sequences"])
+            .and_then(|s| s.as_array())
+            .map(|stops| stops.iter().filter_map(|s| s.as_str().map(String::from)).collect())
+            .unwrap_or_default();
+        params.seed = config.get("seed").and_then(|v| v.as_i64());
+        params.presence_penalty = field(config, &["presencePenalty", "presence_penalty"]).and_then(|v| v.as_f64());
+        params.frequency_penalty = field(config, &["frequencyPenalty", "frequency_penalty"]).and_then(|v| v.as_f64());
+        params.n = field(config, &["candidateCount", "candidate_count"]).and_then(|v| v.as_u64()).map(|n| n as u32);
+        params.logprobs = field(config, &["responseLogprobs", "response_logprobs"]).and_then(|v| v.as_bool()).unwrap_or(false);
+        params.top_logprobs = config.get("logprobs").and_then(|v| v.as_u64()).map(|n| n as u32);
+        if let Some(thinking) = field(config, &["thinkingConfig", "thinking_config"]) {
+            let budget = field(thinking, &["thinkingBudget", "thinking_budget"]).and_then(|b| b.as_i64());
+            let level = field(thinking, &["thinkingLevel", "thinking_level"]).and_then(|l| l.as_str());
+            request.reasoning = match (budget, level) {
+                (Some(0), _) => Some(Reasoning::Disabled),
+                // -1 lets the model decide
+                (Some(-1), _) => Some(Reasoning::Effort(ReasoningEffort::Medium)),
+                (Some(budget), _) if budget > 0 => Some(Reasoning::Budget(budget as u64)),
+                (_, Some(level)) => ReasoningEffort::parse(&level.to_lowercase()).map(Reasoning::Effort),
+                _ => None,
+            };
+        }
+        let mime_type = field(config, &["responseMimeType", "response_mime_type"]).and_then(|m| m.as_str());
+        if mime_type == Some("application/json") {
+            let schema = field(config, &["responseJsonSchema", "response_json_schema", "responseSchema", "response_schema"]);
+            request.response_format = Some(match schema {
+                Some(schema) => ResponseFormat::JsonSchema(JsonSchemaFormat {
+                    name: "response".to_string(),
+                    description: None,
+                    schema: schema.clone(),
+                    strict: true,
+                }),
+                None => ResponseFormat::JsonObject,
+            });
+        }
+    }
+
+    Ok(request)
+}
+
+pub fn emit_request(request: &ChatRequest) -> Result<Value> {
+    // Gemini wants alternating turns, so consecutive messages of one role are merged
+    let mut contents: Vec<(Role, Vec<Value>)> = Vec::new();
+    for message in &request.messages {
+        let parts = emit_parts(&message.content, false)?;
+        if parts.is_empty() {
+            continue;
+        }
+        match contents.last_mut() {
+            Some((role, merged)) if *role == message.role => merged.extend(parts),
+            _ => contents.push((message.role, parts)),
+        }
+    }
+    let contents: Vec<Value> = contents
+        .into_iter()
+        .map(|(role, parts)| json!({"role": emit_role(role), "parts": parts}))
+        .collect();
+
+    let mut body = json!({ "contents": contents });
+    if !request.system.is_empty() {
+        let parts: Vec<Value> = request.system.iter().map(|text| json!({"text": text})).collect();
+        body["systemInstruction"] = json!({ "parts": parts });
+    }
+    if !request.tools.is_empty() {
+        let declarations: Vec<Value> = request
+            .tools
+            .iter()
+            .map(|tool| {
+                let mut declaration = json!({ "name": tool.name });
+                let parameters = schema::sanitize_for_gemini(&tool.parameters, &tool.name);
+                // Gemini rejects an object schema without properties; a tool without arguments has none
+                if parameters.get("properties").is_some() || parameters.get("type").is_some_and(|t| t != "object") {
+                    declaration["parameters"] = parameters;
+                }
+                if let Some(description) = &tool.description {
+                    declaration["description"] = json!(description);
+                }
+                declaration
+            })
+            .collect();
+        body["tools"] = json!([{ "functionDeclarations": declarations }]);
+        // Gemini has no switch for parallel calls, so `parallel_tool_calls` is dropped
+        if let Some(choice) = &request.tool_choice {
+            let calling = match choice {
+                ToolChoice::Auto => json!({"mode": "AUTO"}),
+                ToolChoice::None => json!({"mode": "NONE"}),
+                ToolChoice::Required => json!({"mode": "ANY"}),
+                ToolChoice::Tool(name) => json!({"mode": "ANY", "allowedFunctionNames": [name]}),
+            };
+            body["toolConfig"] = json!({ "functionCallingConfig": calling });
+        }
+    }
+
+    let params = &request.params;
+    let mut config = serde_json::Map::new();
+    if let Some(max_tokens) = params.max_tokens {
+        config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+    }
+    if let Some(temperature) = params.temperature {
+        config.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(top_p) = params.top_p {
+        config.insert("topP".to_string(), json!(top_p));
+    }
+    if let Some(top_k) = params.top_k {
+        config.insert("topK".to_string(), json!(top_k));
+    }
+    if !params.stop.is_empty() {
+        config.insert("stopSequences".to_string(), json!(params.stop));
+    }
+    if let Some(seed) = params.seed {
+        config.insert("seed".to_string(), json!(seed));
+    }
+    if let Some(penalty) = params.presence_penalty {
+        config.insert("presencePenalty".to_string(), json!(penalty));
+    }
+    if let Some(penalty) = params.frequency_penalty {
+        config.insert("frequencyPenalty".to_string(), json!(penalty));
+    }
+    if let Some(n) = params.n {
+        config.insert("candidateCount".to_string(), json!(n));
+    }
+    if params.logprobs || params.top_logprobs.is_some() {
+        config.insert("responseLogprobs".to_string(), json!(true));
+    }
+    if let Some(top_logprobs) = params.top_logprobs {
+        config.insert("logprobs".to_string(), json!(top_logprobs));
+    }
+    if let Some(reasoning) = request.reasoning {
+        let thinking = match reasoning.budget_tokens() {
+            Some(budget) => json!({"thinkingBudget": budget, "includeThoughts": true}),
+            None => json!({"thinkingBudget": 0}),
+        };
+        config.insert("thinkingConfig".to_string(), thinking);
+    }
+    if let Some(format) = &request.response_format {
+        config.insert("responseMimeType".to_string(), json!("application/json"));
+        if let ResponseFormat::JsonSchema(format) = format {
+            let schema = schema::sanitize_for_gemini(&format.schema, &format.name);
+            config.insert("responseSchema".to_string(), schema);
+        }
+    }
+    if !config.is_empty() {
+        body["generationConfig"] = Value::Object(config);
+    }
+    Ok(body)
+}
+
+pub fn parse_response(body: &Value) -> Result<ChatResponse> {
+    let body = unwrap_response(body);
+    let candidate = body.get("candidates").and_then(|c| c.get(0));
+    let content = candidate
+        .and_then(|c| c.get("content"))
+        .map(|content| parse_parts(content, &mut CallIds::default()))
+        .unwrap_or_default();
+    let has_calls = content.iter().any(|part| matches!(part, ContentPart::ToolCall(_)));
+    let stop_reason = candidate
+        .and_then(|c| field(c, &["finishReason", "finish_reason"]))
+        .and_then(|r| r.as_str())
+        .map(|reason| parse_finish_reason(reason, has_calls))
+        .or_else(|| prompt_blocked(body).then_some(StopReason::ContentFilter));
+
+    Ok(ChatResponse {
+        id: field(body, &["responseId", "response_id"]).and_then(|id| id.as_str()).map(String::from),
+        model: field(body, &["modelVersion", "model_version"]).and_then(|m| m.as_str()).map(String::from),
+        content,
+        stop_reason,
+        usage: field(body, &["usageMetadata", "usage_metadata"]).map(parse_usage).unwrap_or_default(),
+    })
+}
+
+pub fn emit_response(response: &ChatResponse) -> Value {
+    let mut body = json!({
+        "candidates": [{
+            // Content Gemini can't carry is left out of responses
+            "content": {
+                "role": "model",
+                "parts": emit_parts(&response.content, true).unwrap_or_default(),
+            },
+            "finishReason": emit_finish_reason(response.stop_reason.unwrap_or(StopReason::EndTurn)),
+            "index": 0,
+        }],
+        "usageMetadata": emit_usage(&response.usage),
+    });
+    if let Some(model) = &response.model {
+        body["modelVersion"] = json!(model);
+    }
+    body
+}
+
+pub fn parse_models(body: &Value) -> ModelList {
+    let models = body
+        .get("models")
+        .and_then(|m| m.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            let name = model.get("name")?.as_str()?;
+            Some(ModelEntry {
+                id: name.strip_prefix("models/").unwrap_or(name).to_string(),
+                display_name: model.get("displayName").and_then(|n| n.as_str()).map(String::from),
+                owned_by: Some("google".to_string()),
+                created: None,
+            })
+        })
+        .collect();
+    ModelList { models }
+}
+
+pub fn emit_models(list: &ModelList) -> Value {
+    let models: Vec<Value> = list
+        .models
+        .iter()
+        .map(|model| {
+            json!({
+                "name": format!("models/{}", model.id),
+                "displayName": model.display_name.as_deref().unwrap_or(&model.id),
+                "supportedGenerationMethods": ["generateContent", "streamGenerateContent"],
+            })
+        })
+        .collect();
+    json!({ "models": models })
+}
+
+/// Reads streamGenerateContent responses
+#[derive(Default)]
+pub struct ChunkParser {
+    started: bool,
+    finished: bool,
+    blocks: OpenBlocks,
+    has_calls: bool,
+    usage: Option<Usage>,
+    ids: CallIds,
+}
+
+impl StreamParser for ChunkParser {
+    fn parse(&mut self, chunk: &Value) -> Result<Vec<StreamEvent>> {
+        let chunk = unwrap_response(chunk);
+        let mut events = Vec::new();
+        if !self.started {
+            self.started = true;
+            events.push(StreamEvent::MessageStart {
+                id: field(chunk, &["responseId", "response_id"]).and_then(|id| id.as_str()).map(String::from),
+                model: field(chunk, &["modelVersion", "model_version"]).and_then(|m| m.as_str()).map(String::from),
+            });
+        }
+        // Usage is reported in every chunk; the last one is the total
+        if let Some(usage) = field(chunk, &["usageMetadata", "usage_metadata"]) {
+            self.usage = Some(parse_usage(usage));
+        }
+
+        let candidate = chunk.get("candidates").and_then(|c| c.get(0));
+        let parts = candidate.and_then(|c| c.get("content")).map(|content| parse_parts(content, &mut self.ids));
+        for part in parts.unwrap_or_default() {
+            match part {
+                ContentPart::Text(text) => {
+                    let index = self.blocks.text(&mut events);
+                    events.push(StreamEvent::TextDelta { index, text });
+                }
+                ContentPart::Thinking(thinking) => {
+                    let index = self.blocks.thinking(&mut events);
+                    if !thinking.text.is_empty() {
+                        events.push(StreamEvent::ThinkingDelta { index, text: thinking.text });
+                    }
+                    if let Some(signature) = thinking.signature {
+                        events.push(StreamEvent::SignatureDelta { index, signature });
+                    }
+                }
+                ContentPart::ToolCall(call) => {
+                    // Gemini sends each call whole
+                    self.blocks.close(&mut events);
+                    self.has_calls = true;
+                    let index = self.blocks.allocate();
+                    events.push(StreamEvent::BlockStart {
+                        index,
+                        block: BlockKind::ToolCall { id: call.id, name: call.name },
+                    });
+                    events.push(StreamEvent::ToolArgumentsDelta {
+                        index,
+                        partial_json: arguments_json(&call.arguments),
+                    });
+                    events.push(StreamEvent::BlockStop { index });
+                }
+                _ => {}
+            }
+        }
+
+        let reason = candidate
+            .and_then(|c| field(c, &["finishReason", "finish_reason"]))
+            .and_then(|r| r.as_str())
+            .map(|reason| parse_finish_reason(reason, self.has_calls))
+            .or_else(|| prompt_blocked(chunk).then_some(StopReason::ContentFilter));
+        if let Some(reason) = reason {
+            self.blocks.close(&mut events);
+            self.finished = true;
+            events.push(StreamEvent::MessageDelta { stop_reason: Some(reason), usage: self.usage });
+        }
+        Ok(events)
+    }
+
+    fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        self.blocks.close(&mut events);
+        if !self.started {
+            return events;
+        }
+        if !self.finished {
+            events.push(StreamEvent::MessageDelta { stop_reason: None, usage: self.usage });
+        }
+        events.push(StreamEvent::MessageStop);
+        events
+    }
+}
+
+/// Writes streamGenerateContent responses
+pub struct ChunkEmitter {
+    model: Option<String>,
+    blocks: BlockIndexes,
+    /// Tool calls being received, sent whole when their block stops
+    tool_calls: Vec<(usize, String, String)>,
+    /// Thought signature waiting for the next part, which carries it
+    signature: Option<String>,
+}
+
+impl ChunkEmitter {
+    pub fn new(model: Option<&str>) -> Self {
+        Self {
+            model: model.map(String::from),
+            blocks: BlockIndexes::default(),
+            tool_calls: Vec::new(),
+            signature: None,
+        }
+    }
+
+    fn chunk(&mut self, mut parts: Vec<Value>) -> Value {
+        if let (Some(first), Some(signature)) = (parts.first_mut(), self.signature.take()) {
+            first["thoughtSignature"] = json!(signature);
+        }
+        let mut chunk = json!({
+            "candidates": [{"content": {"role": "model", "parts": parts}, "index": 0}],
+        });
+        if let Some(model) = &self.model {
+            chunk["modelVersion"] = json!(model);
+        }
+        chunk
+    }
+}
+
+impl StreamEmitter for ChunkEmitter {
+    fn emit(&mut self, event: &StreamEvent) -> Vec<Value> {
+        match event {
+            StreamEvent::MessageStart { model, .. } => {
+                if self.model.is_none() {
+                    self.model = model.clone();
+                }
+                Vec::new()
+            }
+            StreamEvent::BlockStart { index, block } => {
+                let block_index = self.blocks.start(*index);
+                if let BlockKind::ToolCall { name, .. } = block {
+                    self.tool_calls.push((block_index, name.clone(), String::new()));
+                }
+                Vec::new()
+            }
+            StreamEvent::TextDelta { text, .. } => vec![self.chunk(vec![json!({"text": text})])],
+            StreamEvent::ThinkingDelta { text, .. } => vec![self.chunk(vec![json!({"text": text, "thought": true})])],
+            StreamEvent::SignatureDelta { signature, .. } => {
+                self.signature = Some(signature.clone());
+                Vec::new()
+            }
+            StreamEvent::ToolArgumentsDelta { index, partial_json } => {
+                let block_index = self.blocks.get(*index);
+                if let Some((_, _, arguments)) = self.tool_calls.iter_mut().find(|(b, _, _)| Some(*b) == block_index) {
+                    arguments.push_str(partial_json);
+                }
+                Vec::new()
+            }
+            StreamEvent::BlockStop { index } => {
+                let block_index = self.blocks.get(*index);
+                let Some(position) = self.tool_calls.iter().position(|(b, _, _)| Some(*b) == block_index) else {
+                    return Vec::new();
+                };
+                let (_, name, arguments) = self.tool_calls.remove(position);
+                let args = arguments_object(&parse_arguments(&arguments));
+                vec![self.chunk(vec![json!({"functionCall": {"name": name, "args": args}})])]
+            }
+            StreamEvent::MessageDelta { stop_reason, usage } => {
+                let mut chunk = match stop_reason {
+                    Some(reason) => {
+                        let mut chunk = self.chunk(vec![json!({"text": ""})]);
+                        chunk["candidates"][0]["finishReason"] = json!(emit_finish_reason(*reason));
+                        chunk
+                    }
+                    None if usage.is_some() => json!({}),
+                    None => return Vec::new(),
+                };
+                if let Some(usage) = usage {
+                    chunk["usageMetadata"] = emit_usage(usage);
+                }
+                vec![chunk]
+            }
+            // A signature nothing followed goes out on an empty part
+            StreamEvent::MessageStop if self.signature.is_some() => vec![self.chunk(vec![json!({"text": ""})])],
+            StreamEvent::MessageStop => Vec::new(),
+        }
+    }
+}
+
+fn unwrap_response(body: &Value) -> &Value {
+    match body.get("response") {
+        Some(inner) if body.get("candidates").is_none() => inner,
+        _ => body,
+    }
+}
+
+/// Gives function calls IDs, which Gemini calls don't carry, and pairs responses with them
+#[derive(Debug, Default)]
+struct CallIds {
+    /// `call_0`, `call_1`, ... instead of random IDs
+    sequential: bool,
+    next: usize,
+    /// Calls without a response yet, oldest first
+    pending: Vec<(String, String)>,
+}
+
+impl CallIds {
+    fn fresh(&mut self) -> String {
+        self.next += 1;
+        if self.sequential {
+            format!("call_{}", self.next - 1)
+        } else {
+            synthesize_call_id()
+        }
+    }
+
+    fn call(&mut self, name: &str, id: Option<&str>) -> String {
+        let id = id.map(String::from).unwrap_or_else(|| self.fresh());
+        self.pending.push((name.to_string(), id.clone()));
+        id
+    }
+
+    /// ID of the call a response answers: its own if it has one, else the oldest open call of that name
+    fn response(&mut self, name: &str, id: Option<&str>) -> String {
+        let position = match id {
+            Some(id) => self.pending.iter().position(|(_, pending)| pending == id),
+            None => self.pending.iter().position(|(pending, _)| pending == name),
+        };
+        match (position, id) {
+            (Some(position), _) => self.pending.remove(position).1,
+            (None, Some(id)) => id.to_string(),
+            (None, None) => self.fresh(),
+        }
+    }
+}
+
+/// Thought parts become thinking; a signature on any other part becomes an empty thinking part
+/// before it, so it can be put back on that part
+fn parse_parts(content: &Value, ids: &mut CallIds) -> Vec<ContentPart> {
+    let mut parsed = Vec::new();
+    for part in content.get("parts").and_then(|p| p.as_array()).into_iter().flatten() {
+        let signature = field(part, &["thoughtSignature", "thought_signature"]).and_then(|s| s.as_str()).map(String::from);
+        if part.get("thought").and_then(|t| t.as_bool()) == Some(true) {
+            let text = part.get("text").and_then(|t| t.as_str()).unwrap_or("").to_string();
+            parsed.push(ContentPart::Thinking(Thinking { text, signature, redacted: false }));
+            continue;
+        }
+        if signature.is_some() {
+            parsed.push(ContentPart::Thinking(Thinking { signature, ..Default::default() }));
+        }
+        parsed.extend(parse_part(part, ids));
+    }
+    parsed
+}
+
+fn parse_part(part: &Value, ids: &mut CallIds) -> Option<ContentPart> {
+    if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+        return (!text.is_empty()).then(|| ContentPart::Text(text.to_string()));
+    }
+    if let Some(inline) = field(part, &["inlineData", "inline_data"]) {
+        return Some(ContentPart::Media(Media::new(
+            field(inline, &["mimeType", "mime_type"])?.as_str()?,
+            MediaSource::Base64(inline.get("data")?.as_str()?.to_string()),
+        )));
+    }
+    if let Some(file) = field(part, &["fileData", "file_data"]) {
+        return Some(ContentPart::Media(Media::new(
+            field(file, &["mimeType", "mime_type"]).and_then(|m| m.as_str()).unwrap_or("image/jpeg"),
+            MediaSource::Url(field(file, &["fileUri", "file_uri"])?.as_str()?.to_string()),
+        )));
+    }
+    if let Some(call) = field(part, &["functionCall", "function_call"]) {
+        let name = call.get("name")?.as_str()?;
+        return Some(ContentPart::ToolCall(ToolCall {
+            id: ids.call(name, call.get("id").and_then(|id| id.as_str())),
+            name: name.to_string(),
+            arguments: call.get("args").cloned().unwrap_or_else(|| json!({})),
+        }));
+    }
+    if let Some(response) = field(part, &["functionResponse", "function_response"]) {
+        let name = response.get("name")?.as_str()?;
+        let output = response.get("response").cloned().unwrap_or(Value::Null);
+        let error = output.get("error").filter(|e| !e.is_null());
+        let text = match error.or_else(|| ["content", "output", "result"].iter().find_map(|key| output.get(*key))) {
+            Some(Value::String(text)) => text.clone(),
+            Some(other) if error.is_some() => other.to_string(),
+            _ => output.to_string(),
+        };
+        return Some(ContentPart::ToolResult(ToolResult {
+            call_id: ids.response(name, response.get("id").and_then(|id| id.as_str())),
+            name: Some(name.to_string()),
+            content: vec![ContentPart::Text(text)],
+            is_error: error.is_some(),
+        }));
+    }
+    None
+}
+
+/// With `skip_unsupported`, parts Gemini can't carry are left out instead of failing
+fn emit_parts(parts: &[ContentPart], skip_unsupported: bool) -> Result<Vec<Value>> {
+    let mut emitted = Vec::new();
+    // A bare signature belongs on the part after it
+    let mut signature = None;
+    for part in parts {
+        if let ContentPart::Thinking(thinking) = part {
+            if thinking.text.is_empty() && !thinking.redacted {
+                signature = thinking.signature.clone();
+                continue;
+            }
+        }
+        let mut values = match emit_part(part) {
+            Ok(values) => values,
+            Err(_) if skip_unsupported => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(first) = values.first_mut() {
+            if let Some(signature) = signature.take() {
+                first["thoughtSignature"] = json!(signature);
+            }
+        }
+        emitted.extend(values);
+    }
+    if let Some(signature) = signature {
+        emitted.push(json!({"text": "", "thoughtSignature": signature}));
+    }
+    Ok(emitted)
+}
+
+/// Call IDs are left out: Gemini pairs calls and responses by name and order
+fn emit_part(part: &ContentPart) -> Result<Vec<Value>> {
+    Ok(match part {
+        ContentPart::Text(text) if text.is_empty() => Vec::new(),
+        ContentPart::Text(text) => vec![json!({"text": text})],
+        ContentPart::Media(media) => vec![emit_media(media)?],
+        // Claude's encrypted reasoning means nothing to Gemini
+        ContentPart::Thinking(thinking) if thinking.redacted => Vec::new(),
+        ContentPart::Thinking(thinking) => {
+            let mut part = json!({"text": thinking.text, "thought": true});
+            if let Some(signature) = &thinking.signature {
+                part["thoughtSignature"] = json!(signature);
+            }
+            vec![part]
+        }
+        ContentPart::ToolCall(call) => vec![json!({
+            "functionCall": {"name": call.name, "args": arguments_object(&call.arguments)},
+        })],
+        ContentPart::ToolResult(result) => {
+            let text = text_of(&result.content);
+            let response = if result.is_error { json!({"error": text}) } else { json!({"content": text}) };
+            let mut emitted = vec![json!({
+                "functionResponse": {
+                    "name": result.name.as_deref().unwrap_or("unknown"),
+                    "response": response,
+                },
+            })];
+            // Images a tool returned follow its response as parts of the same turn
+            for part in &result.content {
+                if let ContentPart::Media(media) = part {
+                    emitted.push(emit_media(media)?);
+                }
+            }
+            emitted
+        }
+    })
+}
+
+/// Gemini reads every kind of media inline or from a URI, but not another provider's file IDs
+fn emit_media(media: &Media) -> Result<Value> {
+    Ok(match &media.source {
+        MediaSource::Base64(data) => json!({"inlineData": {"mimeType": media.mime_type, "data": data}}),
+        MediaSource::Url(url) => json!({"fileData": {"mimeType": media.mime_type, "fileUri": url}}),
+        MediaSource::FileId(_) => return Err(UnsupportedContent::media("Gemini", media).into()),
+    })
+}
+
+fn emit_role(role: Role) -> &'static str {
+    match role {
+        Role::User => "user",
+        Role::Assistant => "model",
+    }
+}
+
+fn parse_finish_reason(reason: &str, has_calls: bool) -> StopReason {
+    match reason {
+        "STOP" if has_calls => StopReason::ToolUse,
+        "MAX_TOKENS" => StopReason::MaxTokens,
+        "SAFETY" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" | "IMAGE_PROHIBITED_CONTENT" => {
+            StopReason::ContentFilter
+        }
+        "RECITATION" | "IMAGE_RECITATION" => StopReason::Recitation,
+        "MALFORMED_FUNCTION_CALL" | "UNEXPECTED_TOOL_CALL" => StopReason::MalformedToolCall,
+        _ => StopReason::EndTurn,
+    }
+}
+
+fn emit_finish_reason(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::MaxTokens => "MAX_TOKENS",
+        StopReason::ContentFilter => "SAFETY",
+        StopReason::Recitation => "RECITATION",
+        StopReason::MalformedToolCall => "MALFORMED_FUNCTION_CALL",
+        StopReason::EndTurn | StopReason::StopSequence | StopReason::ToolUse | StopReason::PauseTurn => "STOP",
+    }
+}
+
+/// A prompt Gemini refused to answer has no candidates, only `promptFeedback.blockReason`
+fn prompt_blocked(body: &Value) -> bool {
+    field(body, &["promptFeedback", "prompt_feedback"])
+        .and_then(|feedback| field(feedback, &["blockReason", "block_reason"]))
+        .is_some()
+}
+
+/// Read `usageMetadata`. Gemini counts thoughts apart from the candidates and tool use prompts
+/// apart from the prompt; the IR counts them as output and input.
+fn parse_usage(usage: &Value) -> Usage {
+    let count = |keys: &[&str]| field(usage, keys).and_then(|t| t.as_u64()).unwrap_or(0);
+    let thoughts = count(&["thoughtsTokenCount", "thoughts_token_count"]);
+    Usage {
+        input_tokens: count(&["promptTokenCount", "prompt_token_count"])
+            + count(&["toolUsePromptTokenCount", "tool_use_prompt_token_count"]),
+        output_tokens: count(&["candidatesTokenCount", "candidates_token_count"]) + thoughts,
+        cache_read_input_tokens: count(&["cachedContentTokenCount", "cached_content_token_count"]),
+        cache_creation_input_tokens: 0,
+        reasoning_tokens: thoughts,
+    }
+}
+
+fn emit_usage(usage: &Usage) -> Value {
+    let mut body = json!({
+        "promptTokenCount": usage.input_tokens,
+        "candidatesTokenCount": usage.output_tokens.saturating_sub(usage.reasoning_tokens),
+        "totalTokenCount": usage.input_tokens + usage.output_tokens,
+    });
+    if usage.cache_read_input_tokens > 0 {
+        body["cachedContentTokenCount"] = json!(usage.cache_read_input_tokens);
+    }
+    if usage.reasoning_tokens > 0 {
+        body["thoughtsTokenCount"] = json!(usage.reasoning_tokens);
+    }
+    body
+}
@@ -0,0 +1,681 @@
+/*!
+ * Kiro CodeWhisperer
+ *
+ * Parser and emitter for CodeWhisperer `generateAssistantResponse` requests, and a parser for
+ * its responses. CodeWhisperer is only ever spoken to upstream: the response is an AWS event
+ * stream that is read whole and parsed into a complete response, which callers replay as a
+ * stream where needed.
+ */
+
+use super::*;
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+const CHAT_TRIGGER_TYPE_MANUAL: &str = "MANUAL";
+const ORIGIN_AI_EDITOR: &str = "AI_EDITOR";
+const BYTES_PER_TOKEN: usize = 4;
+/// Largest `generateAssistantResponse` body CodeWhisperer accepts; older turns are dropped to fit
+const MAX_REQUEST_BYTES: usize = 600_000;
+/// Longest tool description CodeWhisperer accepts
+const MAX_TOOL_DESCRIPTION_CHARS: usize = 10_240;
+/// The model's side of the system prompt turn
+const SYSTEM_PROMPT_REPLY: &str = "Understood. I will follow these instructions.";
+const UNPARSABLE_RESPONSE: &str =
+    "⚠️ Unable to parse response from Kiro API. Please check server logs with RUST_LOG=debug.";
+
+/// CodeWhisperer model ID for a Claude model name
+pub fn map_model(model: &str) -> &'static str {
+    match model {
+        "claude-sonnet-4-20250514" => "CLAUDE_SONNET_4_20250514_V1_0",
+        "claude-sonnet-4-5-20250929" => "CLAUDE_SONNET_4_5_20250929_V1_0",
+        "claude-3-7-sonnet-20250219" => "CLAUDE_3_7_SONNET_20250219_V1_0",
+        "claude-3-5-sonnet-20241022" => "CLAUDE_3_5_SONNET_20241022_V1_0",
+        "claude-3-5-haiku-20241022" => "CLAUDE_SONNET_4_5_20250929_V1_0",
+        "amazonq-claude-sonnet-4-20250514" => "CLAUDE_SONNET_4_20250514_V1_0",
+        "amazonq-claude-sonnet-4-5-20250929" => "CLAUDE_SONNET_4_5_20250929_V1_0",
+        "amazonq-claude-3-7-sonnet-20250219" => "CLAUDE_3_7_SONNET_20250219_V1_0",
+        _ => "CLAUDE_SONNET_4_5_20250929_V1_0",
+    }
+}
+
+pub fn parse_request(body: &Value) -> Result<ChatRequest> {
+    let state = body
+        .get("conversationState")
+        .ok_or_else(|| anyhow::anyhow!("CodeWhisperer request has no conversationState"))?;
+    let mut request = ChatRequest::default();
+
+    if let Some(system) = body
+        .get("conversationStateMetadata")
+        .and_then(|m| m.get("systemPrompt"))
+        .and_then(|s| s.as_str())
+    {
+        request.system.push(system.to_string());
+    }
+
+    let mut history = state.get("history").and_then(|h| h.as_array()).map(Vec::as_slice).unwrap_or_default();
+    // The system prompt turn and its reply
+    let system_turn = history.first().and_then(|e| e.get("userInputMessage")).and_then(|u| u.get("content"));
+    if request.system.first().is_some_and(|system| system_turn.and_then(|c| c.as_str()) == Some(system)) {
+        history = history.get(2..).unwrap_or_default();
+    }
+    for entry in history.iter().chain(state.get("currentMessage")) {
+        if let Some(user) = entry.get("userInputMessage") {
+            if request.model.is_none() {
+                request.model = user.get("modelId").and_then(|m| m.as_str()).map(String::from);
+            }
+            let context = user.get("userInputMessageContext").unwrap_or(&Value::Null);
+            let mut parts: Vec<ContentPart> = context
+                .get("toolResults")
+                .and_then(|r| r.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(parse_tool_result)
+                .collect();
+            if let Some(text) = user.get("content").and_then(|c| c.as_str()).filter(|t| !t.is_empty()) {
+                parts.push(ContentPart::Text(text.to_string()));
+            }
+            for image in user.get("images").and_then(|i| i.as_array()).into_iter().flatten() {
+                let format = image.get("format").and_then(|f| f.as_str()).unwrap_or("png");
+                if let Some(bytes) = image.get("source").and_then(|s| s.get("bytes")).and_then(|b| b.as_str()) {
+                    let mime_type = format!("image/{}", format);
+                    parts.push(ContentPart::Media(Media::new(&mime_type, MediaSource::Base64(bytes.to_string()))));
+                }
+            }
+            if request.tools.is_empty() {
+                request.tools = context
+                    .get("tools")
+                    .and_then(|t| t.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|tool| {
+                        let spec = tool.get("toolSpecification")?;
+                        Some(ToolDefinition {
+                            name: spec.get("name")?.as_str()?.to_string(),
+                            description: spec.get("description").and_then(|d| d.as_str()).map(String::from),
+                            parameters: spec
+                                .get("inputSchema")
+                                .and_then(|s| s.get("json"))
+                                .cloned()
+                                .unwrap_or_else(|| json!({"type": "object"})),
+                        })
+                    })
+                    .collect();
+            }
+            request.messages.push(Message::new(Role::User, parts));
+        } else if let Some(assistant) = entry.get("assistantResponseMessage") {
+            let mut parts = Vec::new();
+            if let Some(text) = assistant.get("content").and_then(|c| c.as_str()).filter(|t| !t.is_empty()) {
+                parts.push(ContentPart::Text(text.to_string()));
+            }
+            for tool_use in assistant.get("toolUses").and_then(|t| t.as_array()).into_iter().flatten() {
+                parts.push(ContentPart::ToolCall(ToolCall {
+                    id: tool_use.get("toolUseId").and_then(|id| id.as_str()).unwrap_or("").to_string(),
+                    name: tool_use.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string(),
+                    arguments: tool_use.get("input").cloned().unwrap_or_else(|| json!({})),
+                }));
+            }
+            request.messages.push(Message::new(Role::Assistant, parts));
+        }
+    }
+    link_tool_results(&mut request.messages);
+
+    Ok(request)
+}
+
+/// Build a `generateAssistantResponse` body; the caller adds `profileArn`
+pub fn emit_request(request: &ChatRequest) -> Result<Value> {
+    // JSON output is asked for as a call of the output tool
+    let emulated = structured::with_output_tool(request);
+    let request = emulated.as_ref().unwrap_or(request);
+    if request.messages.is_empty() {
+        anyhow::bail!("No user messages found");
+    }
+
+    let model_id = map_model(request.model.as_deref().unwrap_or(DEFAULT_MODEL));
+    let tools = emit_tools(&request.tools);
+    let system_prompt = request
+        .system
+        .iter()
+        .cloned()
+        .chain(tool_choice_instructions(request))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    // CodeWhisperer has no system role: the prompt is a turn of its own ahead of the
+    // conversation, so it survives trimming and leaves the first user message as it was
+    let mut system_turn = Vec::new();
+    if !system_prompt.is_empty() {
+        system_turn.push(json!({
+            "userInputMessage": {"content": system_prompt, "modelId": model_id, "origin": ORIGIN_AI_EDITOR}
+        }));
+        system_turn.push(json!({"assistantResponseMessage": {"content": SYSTEM_PROMPT_REPLY}}));
+    }
+
+    let mut messages = fit_history(&request.messages, &system_turn, model_id, &tools)?;
+    unlink_orphaned_results(&mut messages);
+    let (current, history) = messages.split_last().expect("messages is not empty");
+    let mut entries = system_turn;
+    for message in history {
+        entries.push(emit_history_entry(message, model_id)?);
+    }
+    Ok(envelope(entries, emit_current_message(current, model_id, &tools)?, &system_prompt))
+}
+
+fn envelope(history: Vec<Value>, current_message: Value, system_prompt: &str) -> Value {
+    json!({
+        "conversationState": {
+            "chatTriggerType": CHAT_TRIGGER_TYPE_MANUAL,
+            "conversationId": Uuid::new_v4().to_string(),
+            "history": history,
+            "currentMessage": current_message,
+        },
+        "conversationStateMetadata": {
+            "systemPrompt": if system_prompt.is_empty() { Value::Null } else { json!(system_prompt) },
+        },
+    })
+}
+
+/// History entries carry no tool definitions; those go with the current message only
+fn emit_history_entry(message: &Message, model_id: &str) -> Result<Value> {
+    Ok(match message.role {
+        Role::User => json!({"userInputMessage": emit_user_message(message, model_id, &[], false)?}),
+        Role::Assistant => json!({"assistantResponseMessage": emit_assistant_message(message, false)}),
+    })
+}
+
+fn emit_current_message(message: &Message, model_id: &str, tools: &[Value]) -> Result<Value> {
+    Ok(match message.role {
+        Role::User => json!({"userInputMessage": emit_user_message(message, model_id, tools, true)?}),
+        Role::Assistant => json!({"assistantResponseMessage": emit_assistant_message(message, true)}),
+    })
+}
+
+/// CodeWhisperer has no `tool_choice` or `parallel_tool_calls`; they are asked for in the
+/// system prompt instead
+fn tool_choice_instructions(request: &ChatRequest) -> Vec<String> {
+    if request.tools.is_empty() {
+        return Vec::new();
+    }
+    let mut instructions = Vec::new();
+    match &request.tool_choice {
+        Some(ToolChoice::None) => instructions.push("Do not call any tools; answer with text only.".to_string()),
+        Some(ToolChoice::Required) => {
+            instructions.push("You must call at least one of the available tools in your response.".to_string())
+        }
+        // The output tool comes with its own instruction
+        Some(ToolChoice::Tool(name)) if name != structured::OUTPUT_TOOL => {
+            instructions.push(format!("You must call the {} tool in your response.", name))
+        }
+        _ => {}
+    }
+    if request.parallel_tool_calls == Some(false) {
+        instructions.push("Call at most one tool per response.".to_string());
+    }
+    instructions
+}
+
+/// The messages to send, without the oldest ones if the body would exceed
+/// `MAX_REQUEST_BYTES`. The history always starts with a user message; the current message is
+/// never dropped.
+fn fit_history(messages: &[Message], system_turn: &[Value], model_id: &str, tools: &[Value]) -> Result<Vec<Message>> {
+    let (current, history) = messages.split_last().expect("messages is not empty");
+    let fixed = envelope(system_turn.to_vec(), emit_current_message(current, model_id, tools)?, "")
+        .to_string()
+        .len();
+    let sizes = history
+        .iter()
+        .map(|message| Ok(emit_history_entry(message, model_id)?.to_string().len() + 1))
+        .collect::<Result<Vec<usize>>>()?;
+    let mut total = fixed + sizes.iter().sum::<usize>();
+    let mut start = 0;
+    while total > MAX_REQUEST_BYTES && start < history.len() {
+        total -= sizes[start];
+        start += 1;
+        while start < history.len() && history[start].role == Role::Assistant {
+            total -= sizes[start];
+            start += 1;
+        }
+    }
+    if start > 0 {
+        warn!(
+            "Dropped the {} oldest of {} messages to fit the CodeWhisperer request limit ({} bytes)",
+            start,
+            messages.len(),
+            MAX_REQUEST_BYTES
+        );
+    }
+    if total > MAX_REQUEST_BYTES {
+        warn!("CodeWhisperer request is {} bytes even without history", total);
+    }
+    Ok(messages[start..].to_vec())
+}
+
+/// CodeWhisperer rejects tool results without a matching earlier tool use, as left behind when
+/// the call was trimmed away; they are sent as text instead
+fn unlink_orphaned_results(messages: &mut [Message]) {
+    let mut calls = std::collections::HashSet::new();
+    for message in messages {
+        for part in &mut message.content {
+            match part {
+                ContentPart::ToolCall(call) => {
+                    calls.insert(call.id.clone());
+                }
+                ContentPart::ToolResult(result) if !calls.contains(&result.call_id) => {
+                    let name = result.name.as_deref().unwrap_or(&result.call_id);
+                    *part = ContentPart::Text(format!("[{} result]\n{}\n", name, text_of(&result.content)));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Parse a CodeWhisperer event stream into a complete response
+pub fn parse_response(response_text: &str) -> Result<ChatResponse> {
+    let (mut text, mut tool_calls) = parse_events(response_text);
+
+    // Some models write tool calls into the text: [Called functionName with args: {...}]
+    let bracket_tool_calls = parse_bracket_tool_calls(&text);
+    if !bracket_tool_calls.is_empty() {
+        info!("Found {} bracket-style tool calls in content", bracket_tool_calls.len());
+        for call in &bracket_tool_calls {
+            let pattern = format!(
+                r"\[Called\s+{}\s+with\s+args:\s*\{{[^}}]*(?:\{{[^}}]*\}}[^}}]*)*\}}\]",
+                regex::escape(&call.name)
+            );
+            if let Ok(re) = regex::Regex::new(&pattern) {
+                text = re.replace_all(&text, "").to_string();
+            }
+        }
+        text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        tool_calls.extend(bracket_tool_calls);
+    }
+
+    if text.is_empty() && tool_calls.is_empty() {
+        error!("Could not parse any content from CodeWhisperer response!");
+        error!("Full response: {}", &response_text[..floor_char_boundary(response_text, 2000)]);
+        text = UNPARSABLE_RESPONSE.to_string();
+    }
+
+    let mut content = Vec::new();
+    if !text.is_empty() {
+        content.push(ContentPart::Text(text));
+    }
+    let stop_reason = if tool_calls.is_empty() {
+        StopReason::EndTurn
+    } else {
+        StopReason::ToolUse
+    };
+    content.extend(tool_calls.into_iter().map(ContentPart::ToolCall));
+
+    // CodeWhisperer reports no usage; callers that have the request fill in the input
+    let output_tokens = estimate_tokens(&content);
+    let mut response = ChatResponse {
+        id: None,
+        model: None,
+        content,
+        stop_reason: Some(stop_reason),
+        usage: Usage { output_tokens, ..Default::default() },
+    };
+    structured::unwrap_output_tool(&mut response);
+    Ok(response)
+}
+
+/// Estimated input tokens of a request, for the usage CodeWhisperer doesn't report
+pub fn estimate_input_tokens(request: &ChatRequest) -> u64 {
+    let system: usize = request.system.iter().map(|text| text.len()).sum();
+    let tools: usize = request
+        .tools
+        .iter()
+        .map(|tool| tool.name.len() + tool.description.as_ref().map_or(0, |d| d.len()) + tool.parameters.to_string().len())
+        .sum();
+    let messages: u64 = request.messages.iter().map(|message| estimate_tokens(&message.content)).sum();
+    ((system + tools) / BYTES_PER_TOKEN) as u64 + messages
+}
+
+/// Estimated tokens of some content, at four bytes per token; attachments aren't counted
+fn estimate_tokens(parts: &[ContentPart]) -> u64 {
+    let bytes: usize = parts
+        .iter()
+        .map(|part| match part {
+            ContentPart::Text(text) => text.len(),
+            ContentPart::Thinking(thinking) => thinking.text.len(),
+            ContentPart::ToolCall(call) => call.name.len() + arguments_json(&call.arguments).len(),
+            ContentPart::ToolResult(result) => estimate_tokens(&result.content) as usize * BYTES_PER_TOKEN,
+            ContentPart::Media(_) => 0,
+        })
+        .sum();
+    (bytes / BYTES_PER_TOKEN) as u64
+}
+
+fn emit_user_message(message: &Message, model_id: &str, tools: &[Value], current: bool) -> Result<Value> {
+    let mut text = String::new();
+    let mut images = Vec::new();
+    let mut tool_results = Vec::new();
+    let mut tools_used = Vec::new();
+    for part in &message.content {
+        match part {
+            ContentPart::Text(part_text) => text.push_str(part_text),
+            ContentPart::Media(media) => images.push(emit_image(media)?),
+            ContentPart::ToolResult(result) => {
+                tool_results.push(emit_tool_result(result));
+                // Tool results hold text only; their images go with the message
+                for part in &result.content {
+                    if let ContentPart::Media(media) = part {
+                        images.push(emit_image(media)?);
+                    }
+                }
+            }
+            ContentPart::ToolCall(call) => tools_used.push(emit_tool_use(call)),
+            // CodeWhisperer has no reasoning input
+            ContentPart::Thinking(_) => {}
+        }
+    }
+    if current && text.is_empty() {
+        text = "Continue".to_string();
+    }
+
+    let mut context = json!({
+        "tools": if tools.is_empty() { Value::Null } else { json!(tools) },
+        "toolsUsed": if tools_used.is_empty() { Value::Null } else { json!(tools_used) },
+    });
+    if !tool_results.is_empty() {
+        context["toolResults"] = json!(tool_results);
+    }
+    let mut user = json!({
+        "content": text,
+        "modelId": model_id,
+        "origin": ORIGIN_AI_EDITOR,
+        "userInputMessageContext": context,
+    });
+    if !images.is_empty() {
+        user["images"] = json!(images);
+    }
+    Ok(user)
+}
+
+/// CodeWhisperer takes inline images only
+fn emit_image(media: &Media) -> Result<Value> {
+    let format = match media.mime_type.as_str() {
+        "image/png" => "png",
+        "image/jpeg" | "image/jpg" => "jpeg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => return Err(UnsupportedContent::media("Kiro", media).into()),
+    };
+    let MediaSource::Base64(data) = &media.source else {
+        return Err(UnsupportedContent::media("Kiro", media).into());
+    };
+    Ok(json!({"format": format, "source": {"bytes": data}}))
+}
+
+fn emit_assistant_message(message: &Message, current: bool) -> Value {
+    let mut text = message.text();
+    if current && text.is_empty() {
+        text = "Continue".to_string();
+    }
+    let mut tool_uses = Vec::new();
+    let mut tool_results = Vec::new();
+    for part in &message.content {
+        match part {
+            ContentPart::ToolCall(call) => tool_uses.push(emit_tool_use(call)),
+            ContentPart::ToolResult(result) => tool_results.push(emit_tool_result(result)),
+            _ => {}
+        }
+    }
+    json!({
+        "content": text,
+        "toolUses": if tool_uses.is_empty() { Value::Null } else { json!(tool_uses) },
+        "toolResults": if tool_results.is_empty() { Value::Null } else { json!(tool_results) },
+    })
+}
+
+fn emit_tools(tools: &[ToolDefinition]) -> Vec<Value> {
+    tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "toolSpecification": {
+                    "name": tool.name,
+                    "description": tool
+                        .description
+                        .as_deref()
+                        .unwrap_or("")
+                        .chars()
+                        .take(MAX_TOOL_DESCRIPTION_CHARS)
+                        .collect::<String>(),
+                    "inputSchema": {"json": tool.parameters},
+                }
+            })
+        })
+        .collect()
+}
+
+fn emit_tool_use(call: &ToolCall) -> Value {
+    json!({
+        "input": arguments_object(&call.arguments),
+        "name": call.name,
+        "toolUseId": call.id,
+    })
+}
+
+fn emit_tool_result(result: &ToolResult) -> Value {
+    let mut content: Vec<Value> = result
+        .content
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text(text) => Some(json!({"text": text})),
+            _ => None,
+        })
+        .collect();
+    if content.is_empty() {
+        content.push(json!({"text": ""}));
+    }
+    json!({
+        "content": content,
+        "status": if result.is_error { "error" } else { "success" },
+        "toolUseId": result.call_id,
+    })
+}
+
+fn parse_tool_result(result: &Value) -> Option<ContentPart> {
+    let content = result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("text")?.as_str())
+        .filter(|text| !text.is_empty())
+        .map(|text| ContentPart::Text(text.to_string()))
+        .collect();
+    Some(ContentPart::ToolResult(ToolResult {
+        call_id: result.get("toolUseId")?.as_str()?.to_string(),
+        name: None,
+        content,
+        is_error: result.get("status").and_then(|s| s.as_str()) == Some("error"),
+    }))
+}
+
+/// Text and tool calls from the JSON payloads of the event stream
+fn parse_events(response_text: &str) -> (String, Vec<ToolCall>) {
+    let mut content = String::with_capacity(response_text.len() / 2);
+    let mut tool_calls = Vec::new();
+    // Tool call being received: id, name and the argument JSON so far
+    let mut current: Option<(String, String, String)> = None;
+
+    info!("Starting to parse CodeWhisperer response, length: {}", response_text.len());
+
+    // Each payload follows an `event` header; the binary framing around it is skipped
+    let event_positions: Vec<usize> = response_text.match_indices("event{").map(|(pos, _)| pos).collect();
+    debug!("Found {} event blocks", event_positions.len());
+
+    for (i, &position) in event_positions.iter().enumerate() {
+        let start = position + "event".len();
+        let end = event_positions.get(i + 1).copied().unwrap_or(response_text.len());
+        let slice = &response_text[start..end];
+        let Some(json_end) = find_json_end(slice) else { continue };
+        let Ok(event) = serde_json::from_str::<Value>(&slice[..=json_end]) else { continue };
+
+        if let (Some(name), Some(tool_use_id)) = (
+            event.get("name").and_then(|n| n.as_str()),
+            event.get("toolUseId").and_then(|id| id.as_str()),
+        ) {
+            let call = current.get_or_insert_with(|| (tool_use_id.to_string(), name.to_string(), String::new()));
+            if let Some(input) = event.get("input").and_then(|i| i.as_str()) {
+                call.2.push_str(input);
+            }
+            if event.get("stop").and_then(|s| s.as_bool()).unwrap_or(false) {
+                if let Some(call) = current.take() {
+                    tool_calls.push(finish_tool_call(call));
+                }
+            }
+        } else if event.get("followupPrompt").is_none() {
+            if let Some(text) = event.get("content").and_then(|c| c.as_str()) {
+                content.push_str(&text.replace("\\n", "\n"));
+            }
+        }
+    }
+
+    if let Some(call) = current {
+        tool_calls.push(finish_tool_call(call));
+    }
+    info!(
+        "Parsed {} event blocks, content: {} chars, tool_calls: {}",
+        event_positions.len(),
+        content.len(),
+        tool_calls.len()
+    );
+    (content, tool_calls)
+}
+
+fn finish_tool_call((id, name, input): (String, String, String)) -> ToolCall {
+    let arguments = parse_arguments(&input);
+    if arguments.is_string() {
+        warn!("Tool call {} has arguments that are not valid JSON", name);
+    }
+    ToolCall { id, name, arguments }
+}
+
+/// Position of the brace closing the JSON object that starts `text`
+fn find_json_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escape_next = false;
+    for (i, &ch) in text.as_bytes().iter().enumerate() {
+        if escape_next {
+            escape_next = false;
+            continue;
+        }
+        match ch {
+            b'\\' if in_string => escape_next = true,
+            b'"' => in_string = !in_string,
+            b'{' if !in_string => depth += 1,
+            b'}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Tool calls written into the text as `[Called functionName with args: {...}]`
+fn parse_bracket_tool_calls(text: &str) -> Vec<ToolCall> {
+    if !text.contains("[Called") {
+        return Vec::new();
+    }
+    let call_positions: Vec<usize> = text.match_indices("[Called").map(|(pos, _)| pos).collect();
+    call_positions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &start)| {
+            let end = call_positions.get(i + 1).copied().unwrap_or(text.len());
+            let segment = &text[start..end];
+            let call_text = match find_matching_bracket(segment) {
+                Some(bracket_end) => &segment[..=bracket_end],
+                None => &segment[..=segment.rfind(']')?],
+            };
+            parse_single_tool_call(call_text)
+        })
+        .collect()
+}
+
+/// Position of the bracket closing the one that starts `text`
+fn find_matching_bracket(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.first() != Some(&b'[') {
+        return None;
+    }
+    let mut depth = 1;
+    let mut in_string = false;
+    let mut escape_next = false;
+    for (i, &ch) in bytes.iter().enumerate().skip(1) {
+        if escape_next {
+            escape_next = false;
+            continue;
+        }
+        match ch {
+            b'\\' if in_string => escape_next = true,
+            b'"' => in_string = !in_string,
+            b'[' if !in_string => depth += 1,
+            b']' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_single_tool_call(call_text: &str) -> Option<ToolCall> {
+    let name_pattern = regex::Regex::new(r"(?i)\[Called\s+(\w+)\s+with\s+args:").ok()?;
+    let name = name_pattern.captures(call_text)?.get(1)?.as_str().trim().to_string();
+
+    let marker = "with args:";
+    let args_start = call_text.to_lowercase().find(marker)? + marker.len();
+    let args_end = call_text.rfind(']')?;
+    if args_end <= args_start {
+        return None;
+    }
+
+    // Repair the loose JSON models tend to write: trailing commas, unquoted keys and values
+    let mut repaired = call_text[args_start..args_end].trim().to_string();
+    repaired = regex::Regex::new(r",\s*([}\]])").ok()?.replace_all(&repaired, "$1").to_string();
+    repaired = regex::Regex::new(r#"([{,]\s*)([a-zA-Z0-9_]+?)\s*:"#)
+        .ok()?
+        .replace_all(&repaired, r#"$1"$2":"#)
+        .to_string();
+    repaired = regex::Regex::new(r":\s*([a-zA-Z0-9_]+)([,\}\]])")
+        .ok()?
+        .replace_all(&repaired, r#":"$1"$2"#)
+        .to_string();
+
+    match serde_json::from_str::<Value>(&repaired) {
+        Ok(arguments) if arguments.is_object() => Some(ToolCall {
+            id: format!("call_{}", &Uuid::new_v4().simple().to_string()[..8]),
+            name,
+            arguments,
+        }),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Failed to parse tool call arguments: {}", e);
+            None
+        }
+    }
+}
+
+fn floor_char_boundary(text: &str, max: usize) -> usize {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    end
+}
@@ -0,0 +1,735 @@
+/*!
+ * Protocol-Neutral Intermediate Representation
+ *
+ * Every conversion parses the source protocol into these types and emits the target protocol
+ * from them, so a protocol needs one parser and one emitter instead of a converter per pair:
+ *
+ * - `openai`: Chat Completions
+ * - `claude`: Messages API
+ * - `gemini`: generateContent
+ * - `kiro`: CodeWhisperer `generateAssistantResponse`, only ever spoken to upstream
+ *
+ * `schema` rewrites tool schemas into the subset Gemini accepts; `structured` emulates JSON output
+ * for protocols without a native mode and checks the output against its schema; `capabilities`
+ * fits generation parameters to what the target model accepts.
+ *
+ * Where the protocols differ the IR follows the Claude shape: a conversation is a list of user
+ * and assistant messages made of content parts, tool results are parts of the user message that
+ * follows the tool calls, and a stream is a sequence of content blocks that start, receive
+ * deltas and stop. Stream parsers and emitters keep per-stream state, since one chunk of one
+ * protocol can map to several (or no) chunks of another.
+ */
+
+pub mod capabilities;
+pub mod claude;
+pub mod gemini;
+pub mod kiro;
+pub mod openai;
+pub mod schema;
+pub mod structured;
+
+use crate::common::ModelProtocol;
+use anyhow::Result;
+use serde_json::Value;
+use thiserror::Error;
+
+pub use capabilities::ParamPolicy;
+
+/// Who sent a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// A chat request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatRequest {
+    pub model: Option<String>,
+    /// System prompt blocks, in order
+    pub system: Vec<String>,
+    pub messages: Vec<Message>,
+    pub tools: Vec<ToolDefinition>,
+    pub tool_choice: Option<ToolChoice>,
+    /// `Some(false)` asks for at most one tool call per turn
+    pub parallel_tool_calls: Option<bool>,
+    /// Extended thinking; `None` leaves it to the model's default
+    pub reasoning: Option<Reasoning>,
+    /// JSON output in place of free text
+    pub response_format: Option<ResponseFormat>,
+    pub params: GenerationParams,
+    pub stream: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub role: Role,
+    pub content: Vec<ContentPart>,
+}
+
+impl Message {
+    pub fn new(role: Role, content: Vec<ContentPart>) -> Self {
+        Self { role, content }
+    }
+
+    /// The text parts of the message, concatenated
+    pub fn text(&self) -> String {
+        text_of(&self.content)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentPart {
+    Text(String),
+    /// Model reasoning; never part of the visible text
+    Thinking(Thinking),
+    Media(Media),
+    ToolCall(ToolCall),
+    ToolResult(ToolResult),
+}
+
+/// A reasoning block, kept with its signature so it can be sent back on the next turn
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Thinking {
+    /// The reasoning, or the encrypted data of a redacted block; empty for a bare signature
+    pub text: String,
+    /// Opaque signature the upstream issued for this block and checks when it is sent back
+    pub signature: Option<String>,
+    /// Claude `redacted_thinking`
+    pub redacted: bool,
+}
+
+/// An attachment: an image, a document such as a PDF, or audio
+#[derive(Debug, Clone, PartialEq)]
+pub struct Media {
+    pub mime_type: String,
+    pub source: MediaSource,
+    /// File name or title, where the protocol carries one
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaSource {
+    Base64(String),
+    Url(String),
+    /// A file uploaded to the client's provider; no other provider can read it
+    FileId(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Document,
+    Audio,
+    Video,
+}
+
+impl Media {
+    pub fn new(mime_type: &str, source: MediaSource) -> Self {
+        Self {
+            mime_type: mime_type.to_string(),
+            source,
+            name: None,
+        }
+    }
+
+    /// Parse an OpenAI-style URL, which may be a `data:` URL; `mime_type` is used for plain URLs
+    pub fn from_url(url: &str, mime_type: &str) -> Self {
+        if let Some((header, data)) = url.strip_prefix("data:").and_then(|rest| rest.split_once(',')) {
+            let mime_type = header.split(';').next().filter(|m| !m.is_empty()).unwrap_or(mime_type);
+            return Self::new(mime_type, MediaSource::Base64(data.to_string()));
+        }
+        Self::new(mime_type, MediaSource::Url(url.to_string()))
+    }
+
+    /// The media as an OpenAI-style URL (`data:` URL for inline data); file IDs have none
+    pub fn to_url(&self) -> Option<String> {
+        match &self.source {
+            MediaSource::Base64(data) => Some(format!("data:{};base64,{}", self.mime_type, data)),
+            MediaSource::Url(url) => Some(url.clone()),
+            MediaSource::FileId(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> MediaKind {
+        match self.mime_type.split('/').next() {
+            Some("image") => MediaKind::Image,
+            Some("audio") => MediaKind::Audio,
+            Some("video") => MediaKind::Video,
+            _ => MediaKind::Document,
+        }
+    }
+}
+
+/// Content a request can't be converted with, because the target protocol can't carry it;
+/// the client gets a 400 instead of a request that silently lost an attachment
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{protocol} requests can't carry {what}")]
+pub struct UnsupportedContent {
+    pub protocol: &'static str,
+    pub what: String,
+}
+
+impl UnsupportedContent {
+    pub fn media(protocol: &'static str, media: &Media) -> Self {
+        let kind = match media.kind() {
+            MediaKind::Image => "images",
+            MediaKind::Document => "documents",
+            MediaKind::Audio => "audio",
+            MediaKind::Video => "video",
+        };
+        let source = match media.source {
+            MediaSource::Base64(_) => "inline",
+            MediaSource::Url(_) => "given by URL",
+            MediaSource::FileId(_) => "given by file ID",
+        };
+        Self {
+            protocol,
+            what: format!("{} {} ({})", kind, source, media.mime_type),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// Parsed arguments; a string if the model produced arguments that are not valid JSON
+    pub arguments: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolResult {
+    pub call_id: String,
+    /// Name of the called tool, where the protocol carries it
+    pub name: Option<String>,
+    pub content: Vec<ContentPart>,
+    pub is_error: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    /// JSON Schema of the arguments
+    pub parameters: Value,
+}
+
+/// Whether and which tools the model must call
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolChoice {
+    Auto,
+    None,
+    /// At least one tool call, of any tool
+    Required,
+    /// A call of this tool
+    Tool(String),
+}
+
+/// Structured output the answer must be
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseFormat {
+    /// Any JSON object
+    JsonObject,
+    JsonSchema(JsonSchemaFormat),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub description: Option<String>,
+    pub schema: Value,
+    /// OpenAI's `strict`: the output must match the schema exactly
+    pub strict: bool,
+}
+
+impl ResponseFormat {
+    /// Schema the output must match; an object of any shape for `JsonObject`
+    pub fn schema(&self) -> Value {
+        match self {
+            Self::JsonObject => serde_json::json!({"type": "object"}),
+            Self::JsonSchema(format) => format.schema.clone(),
+        }
+    }
+}
+
+/// How much the model should think before answering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reasoning {
+    Disabled,
+    Effort(ReasoningEffort),
+    /// Thinking token budget
+    Budget(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReasoningEffort {
+    Minimal,
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    /// Thinking budget for protocols that take a token count
+    pub fn budget_tokens(self) -> u64 {
+        match self {
+            Self::Minimal => 1024,
+            Self::Low => 2048,
+            Self::Medium => 8192,
+            Self::High => 24576,
+        }
+    }
+
+    /// Effort for a token budget, for protocols that take a level
+    pub fn from_budget(budget: u64) -> Self {
+        match budget {
+            // Never `Minimal`, which only some models accept
+            0..=4096 => Self::Low,
+            4097..=16384 => Self::Medium,
+            _ => Self::High,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Minimal => "minimal",
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+
+    pub fn parse(effort: &str) -> Option<Self> {
+        match effort {
+            "minimal" => Some(Self::Minimal),
+            "low" => Some(Self::Low),
+            "medium" => Some(Self::Medium),
+            "high" => Some(Self::High),
+            _ => None,
+        }
+    }
+}
+
+impl Reasoning {
+    /// Thinking budget, if thinking is on
+    pub fn budget_tokens(self) -> Option<u64> {
+        match self {
+            Self::Disabled => None,
+            Self::Effort(effort) => Some(effort.budget_tokens()),
+            Self::Budget(budget) => Some(budget),
+        }
+    }
+}
+
+/// Sampling and length settings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenerationParams {
+    pub max_tokens: Option<u64>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<u64>,
+    pub stop: Vec<String>,
+    pub seed: Option<i64>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+    /// Number of choices to generate; responses only carry the first
+    pub n: Option<u32>,
+    pub logprobs: bool,
+    pub top_logprobs: Option<u32>,
+}
+
+/// A complete (non-streaming) response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatResponse {
+    pub id: Option<String>,
+    pub model: Option<String>,
+    pub content: Vec<ContentPart>,
+    pub stop_reason: Option<StopReason>,
+    pub usage: Usage,
+}
+
+impl ChatResponse {
+    /// The text parts of the response, concatenated
+    pub fn text(&self) -> String {
+        text_of(&self.content)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    EndTurn,
+    MaxTokens,
+    StopSequence,
+    ToolUse,
+    /// Claude paused a long-running server tool turn; sending the answer back continues it
+    PauseTurn,
+    /// Blocked by a safety filter, or refused
+    ContentFilter,
+    /// Stopped for reproducing copyrighted material (Gemini)
+    Recitation,
+    /// The model produced a tool call that doesn't parse (Gemini)
+    MalformedToolCall,
+}
+
+/// Token counts. Input includes cached input and cache writes, and output includes reasoning,
+/// whichever way the protocol counts them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Input read from the prompt cache
+    pub cache_read_input_tokens: u64,
+    /// Input written to the prompt cache
+    pub cache_creation_input_tokens: u64,
+    /// Output spent on reasoning
+    pub reasoning_tokens: u64,
+}
+
+impl Usage {
+    /// Input neither read from nor written to the cache
+    pub fn uncached_input_tokens(&self) -> u64 {
+        self.input_tokens
+            .saturating_sub(self.cache_read_input_tokens)
+            .saturating_sub(self.cache_creation_input_tokens)
+    }
+}
+
+/// One step of a streamed response
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    MessageStart { id: Option<String>, model: Option<String> },
+    BlockStart { index: usize, block: BlockKind },
+    TextDelta { index: usize, text: String },
+    ThinkingDelta { index: usize, text: String },
+    /// Signature of a thinking block, sent before the block stops
+    SignatureDelta { index: usize, signature: String },
+    ToolArgumentsDelta { index: usize, partial_json: String },
+    BlockStop { index: usize },
+    /// Final stop reason and usage; may arrive in several parts
+    MessageDelta { stop_reason: Option<StopReason>, usage: Option<Usage> },
+    MessageStop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockKind {
+    Text,
+    Thinking,
+    /// A redacted thinking block, which arrives whole
+    RedactedThinking { data: String },
+    ToolCall { id: String, name: String },
+}
+
+/// Turns the chunks of one upstream stream into events
+pub trait StreamParser: Send {
+    fn parse(&mut self, chunk: &Value) -> Result<Vec<StreamEvent>>;
+
+    /// Events still owed once the stream has ended
+    fn finish(&mut self) -> Vec<StreamEvent>;
+}
+
+/// Turns events into the chunks of one client stream
+pub trait StreamEmitter: Send {
+    fn emit(&mut self, event: &StreamEvent) -> Vec<Value>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelList {
+    pub models: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelEntry {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub owned_by: Option<String>,
+    pub created: Option<i64>,
+}
+
+pub fn parse_request(protocol: ModelProtocol, body: &Value) -> Result<ChatRequest> {
+    match protocol {
+        ModelProtocol::OpenAI => openai::parse_request(body),
+        ModelProtocol::Claude => claude::parse_request(body),
+        ModelProtocol::Gemini => gemini::parse_request(body),
+        ModelProtocol::Kiro => kiro::parse_request(body),
+    }
+}
+
+/// Emit a request; fails with `UnsupportedContent` for content the protocol can't carry.
+/// Parameters the target model doesn't take are dropped or clamped.
+pub fn emit_request(protocol: ModelProtocol, request: &ChatRequest) -> Result<Value> {
+    emit_request_with(protocol, request, ParamPolicy::Lenient)
+}
+
+/// Emit a request with its parameters fitted to the target model under `policy`
+pub fn emit_request_with(protocol: ModelProtocol, request: &ChatRequest, policy: ParamPolicy) -> Result<Value> {
+    let request = &capabilities::fit(request, protocol, policy)?;
+    match protocol {
+        ModelProtocol::OpenAI => openai::emit_request(request),
+        ModelProtocol::Claude => claude::emit_request(request),
+        ModelProtocol::Gemini => gemini::emit_request(request),
+        ModelProtocol::Kiro => kiro::emit_request(request),
+    }
+}
+
+/// Parse a response; a CodeWhisperer response is the raw event stream as a JSON string
+pub fn parse_response(protocol: ModelProtocol, body: &Value) -> Result<ChatResponse> {
+    match protocol {
+        ModelProtocol::OpenAI => openai::parse_response(body),
+        ModelProtocol::Claude => claude::parse_response(body),
+        ModelProtocol::Gemini => gemini::parse_response(body),
+        ModelProtocol::Kiro => match body.as_str() {
+            Some(raw) => kiro::parse_response(raw),
+            None => anyhow::bail!("CodeWhisperer responses must be given as the raw event stream"),
+        },
+    }
+}
+
+pub fn emit_response(protocol: ModelProtocol, response: &ChatResponse) -> Result<Value> {
+    match protocol {
+        ModelProtocol::OpenAI => Ok(openai::emit_response(response)),
+        ModelProtocol::Claude => Ok(claude::emit_response(response)),
+        ModelProtocol::Gemini => Ok(gemini::emit_response(response)),
+        ModelProtocol::Kiro => anyhow::bail!("CodeWhisperer is only used upstream; responses can't be emitted in it"),
+    }
+}
+
+pub fn parse_models(protocol: ModelProtocol, body: &Value) -> Result<ModelList> {
+    match protocol {
+        ModelProtocol::OpenAI => Ok(openai::parse_models(body)),
+        ModelProtocol::Claude => Ok(claude::parse_models(body)),
+        ModelProtocol::Gemini => Ok(gemini::parse_models(body)),
+        ModelProtocol::Kiro => anyhow::bail!("CodeWhisperer has no model list format"),
+    }
+}
+
+pub fn emit_models(protocol: ModelProtocol, models: &ModelList) -> Result<Value> {
+    match protocol {
+        ModelProtocol::OpenAI => Ok(openai::emit_models(models)),
+        ModelProtocol::Claude => Ok(claude::emit_models(models)),
+        ModelProtocol::Gemini => Ok(gemini::emit_models(models)),
+        ModelProtocol::Kiro => anyhow::bail!("CodeWhisperer has no model list format"),
+    }
+}
+
+pub fn stream_parser(protocol: ModelProtocol) -> Result<Box<dyn StreamParser>> {
+    match protocol {
+        ModelProtocol::OpenAI => Ok(Box::<openai::ChunkParser>::default()),
+        ModelProtocol::Claude => Ok(Box::<claude::ChunkParser>::default()),
+        ModelProtocol::Gemini => Ok(Box::<gemini::ChunkParser>::default()),
+        ModelProtocol::Kiro => anyhow::bail!("CodeWhisperer streams are read whole, see kiro::parse_response"),
+    }
+}
+
+/// Emitter for a client stream; `model` is reported when the upstream doesn't name one
+pub fn stream_emitter(protocol: ModelProtocol, model: Option<&str>) -> Result<Box<dyn StreamEmitter>> {
+    match protocol {
+        ModelProtocol::OpenAI => Ok(Box::new(openai::ChunkEmitter::new(model))),
+        ModelProtocol::Claude => Ok(Box::new(claude::ChunkEmitter::new(model))),
+        ModelProtocol::Gemini => Ok(Box::new(gemini::ChunkEmitter::new(model))),
+        ModelProtocol::Kiro => anyhow::bail!("CodeWhisperer is only used upstream; streams can't be emitted in it"),
+    }
+}
+
+/// Converts one stream chunk by chunk from one protocol to another
+pub struct StreamConverter {
+    parser: Box<dyn StreamParser>,
+    emitter: Box<dyn StreamEmitter>,
+}
+
+impl StreamConverter {
+    pub fn new(from: ModelProtocol, to: ModelProtocol, model: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            parser: stream_parser(from)?,
+            emitter: stream_emitter(to, model)?,
+        })
+    }
+
+    /// Target chunks for one source chunk
+    pub fn convert(&mut self, chunk: &Value) -> Result<Vec<Value>> {
+        let events = self.parser.parse(chunk)?;
+        Ok(events.iter().flat_map(|event| self.emitter.emit(event)).collect())
+    }
+
+    /// Target chunks still owed once the source stream has ended
+    pub fn finish(&mut self) -> Vec<Value> {
+        let events = self.parser.finish();
+        events.iter().flat_map(|event| self.emitter.emit(event)).collect()
+    }
+}
+
+/// Replay a complete response as a stream
+pub fn response_events(response: &ChatResponse) -> Vec<StreamEvent> {
+    let mut events = vec![StreamEvent::MessageStart {
+        id: response.id.clone(),
+        model: response.model.clone(),
+    }];
+    let blocks = response.content.iter().filter(|part| {
+        matches!(part, ContentPart::Text(_) | ContentPart::Thinking(_) | ContentPart::ToolCall(_))
+    });
+    for (index, part) in blocks.enumerate() {
+        match part {
+            ContentPart::Text(text) => {
+                events.push(StreamEvent::BlockStart { index, block: BlockKind::Text });
+                events.push(StreamEvent::TextDelta { index, text: text.clone() });
+            }
+            ContentPart::Thinking(thinking) if thinking.redacted => {
+                let block = BlockKind::RedactedThinking { data: thinking.text.clone() };
+                events.push(StreamEvent::BlockStart { index, block });
+            }
+            ContentPart::Thinking(thinking) => {
+                events.push(StreamEvent::BlockStart { index, block: BlockKind::Thinking });
+                if !thinking.text.is_empty() {
+                    events.push(StreamEvent::ThinkingDelta { index, text: thinking.text.clone() });
+                }
+                if let Some(signature) = &thinking.signature {
+                    events.push(StreamEvent::SignatureDelta { index, signature: signature.clone() });
+                }
+            }
+            ContentPart::ToolCall(call) => {
+                let block = BlockKind::ToolCall {
+                    id: call.id.clone(),
+                    name: call.name.clone(),
+                };
+                events.push(StreamEvent::BlockStart { index, block });
+                events.push(StreamEvent::ToolArgumentsDelta {
+                    index,
+                    partial_json: arguments_json(&call.arguments),
+                });
+            }
+            _ => continue,
+        }
+        events.push(StreamEvent::BlockStop { index });
+    }
+    events.push(StreamEvent::MessageDelta {
+        stop_reason: response.stop_reason,
+        usage: Some(response.usage),
+    });
+    events.push(StreamEvent::MessageStop);
+    events
+}
+
+/// Concatenated text parts
+pub fn text_of(parts: &[ContentPart]) -> String {
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Tool arguments as a JSON string, keeping invalid arguments as the model produced them
+pub fn arguments_json(arguments: &Value) -> String {
+    match arguments {
+        Value::String(raw) => raw.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Parse a tool-arguments string, keeping it as a string if it isn't valid JSON
+pub fn parse_arguments(raw: &str) -> Value {
+    if raw.trim().is_empty() {
+        return Value::Object(Default::default());
+    }
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+/// Tool arguments as a JSON object, for protocols that require one
+pub fn arguments_object(arguments: &Value) -> Value {
+    match arguments {
+        Value::Object(_) => arguments.clone(),
+        _ => Value::Object(Default::default()),
+    }
+}
+
+/// Maps block indexes of the source stream to sequential indexes of the target stream
+#[derive(Debug, Default)]
+pub(crate) struct BlockIndexes {
+    blocks: Vec<(usize, usize)>,
+}
+
+impl BlockIndexes {
+    pub(crate) fn start(&mut self, source: usize) -> usize {
+        let target = self.blocks.len();
+        self.blocks.push((source, target));
+        target
+    }
+
+    /// Target index of a block; kept after the block stops, for late argument deltas
+    pub(crate) fn get(&self, source: usize) -> Option<usize> {
+        self.blocks.iter().rev().find(|(s, _)| *s == source).map(|(_, t)| *t)
+    }
+}
+
+/// Open blocks of a stream being parsed, for protocols without explicit block boundaries
+#[derive(Debug, Default)]
+pub(crate) struct OpenBlocks {
+    next: usize,
+    text: Option<usize>,
+    thinking: Option<usize>,
+}
+
+impl OpenBlocks {
+    /// Index of the open text block, starting one (and stopping a thinking block) if needed
+    pub(crate) fn text(&mut self, events: &mut Vec<StreamEvent>) -> usize {
+        if let Some(index) = self.text {
+            return index;
+        }
+        self.close(events);
+        let index = self.allocate();
+        events.push(StreamEvent::BlockStart { index, block: BlockKind::Text });
+        self.text = Some(index);
+        index
+    }
+
+    /// Index of the open thinking block, starting one (and stopping a text block) if needed
+    pub(crate) fn thinking(&mut self, events: &mut Vec<StreamEvent>) -> usize {
+        if let Some(index) = self.thinking {
+            return index;
+        }
+        self.close(events);
+        let index = self.allocate();
+        events.push(StreamEvent::BlockStart { index, block: BlockKind::Thinking });
+        self.thinking = Some(index);
+        index
+    }
+
+    pub(crate) fn allocate(&mut self) -> usize {
+        let index = self.next;
+        self.next += 1;
+        index
+    }
+
+    /// Stop the open text or thinking block, if any
+    pub(crate) fn close(&mut self, events: &mut Vec<StreamEvent>) {
+        for index in [self.text.take(), self.thinking.take()].into_iter().flatten() {
+            events.push(StreamEvent::BlockStop { index });
+        }
+    }
+}
+
+/// A fresh tool call ID, for protocols whose calls carry none
+pub fn synthesize_call_id() -> String {
+    format!("call_{}", &uuid::Uuid::new_v4().simple().to_string()[..24])
+}
+
+/// Fill in the tool name of results that only carry the call ID, from the calls before them
+pub(crate) fn link_tool_results(messages: &mut [Message]) {
+    let mut names: Vec<(String, String)> = Vec::new();
+    for part in messages.iter_mut().flat_map(|message| message.content.iter_mut()) {
+        match part {
+            ContentPart::ToolCall(call) => names.push((call.id.clone(), call.name.clone())),
+            ContentPart::ToolResult(result) if result.name.is_none() => {
+                result.name = names.iter().rev().find(|(id, _)| *id == result.call_id).map(|(_, name)| name.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// First non-null value of `keys`, for protocols that accept camelCase and snake_case names
+pub(crate) fn field<'a>(value: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+    keys.iter().find_map(|key| value.get(*key).filter(|v| !v.is_null()))
+}
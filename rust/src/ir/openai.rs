@@ -0,0 +1,815 @@
+/*!
+ * OpenAI Chat Completions
+ *
+ * Parser and emitter between Chat Completions bodies and the IR. Tool results are separate
+ * `tool` messages in OpenAI and parts of a user message in the IR.
+ */
+
+use super::*;
+use serde_json::json;
+use uuid::Uuid;
+
+pub fn parse_request(body: &Value) -> Result<ChatRequest> {
+    let mut request = ChatRequest {
+        model: body.get("model").and_then(|m| m.as_str()).map(String::from),
+        stream: body.get("stream").and_then(|s| s.as_bool()).unwrap_or(false),
+        ..Default::default()
+    };
+
+    for msg in body.get("messages").and_then(|m| m.as_array()).into_iter().flatten() {
+        let content = msg.get("content").unwrap_or(&Value::Null);
+        match msg.get("role").and_then(|r| r.as_str()).unwrap_or("user") {
+            "system" | "developer" => {
+                let text = text_of(&parse_content(content));
+                if !text.is_empty() {
+                    request.system.push(text);
+                }
+            }
+            "tool" => {
+                let result = ContentPart::ToolResult(ToolResult {
+                    call_id: msg.get("tool_call_id").and_then(|id| id.as_str()).unwrap_or("").to_string(),
+                    name: msg.get("name").and_then(|n| n.as_str()).map(String::from),
+                    content: parse_content(content),
+                    is_error: false,
+                });
+                // Results of parallel calls share one user message
+                match request.messages.last_mut() {
+                    Some(last) if last.role == Role::User && only_tool_results(&last.content) => {
+                        last.content.push(result)
+                    }
+                    _ => request.messages.push(Message::new(Role::User, vec![result])),
+                }
+            }
+            "assistant" => {
+                let mut parts = parse_reasoning(msg);
+                parts.extend(parse_content(content));
+                for call in msg.get("tool_calls").and_then(|c| c.as_array()).into_iter().flatten() {
+                    let function = call.get("function").unwrap_or(&Value::Null);
+                    parts.push(ContentPart::ToolCall(ToolCall {
+                        id: call.get("id").and_then(|id| id.as_str()).unwrap_or("").to_string(),
+                        name: function.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string(),
+                        arguments: parse_arguments(function.get("arguments").and_then(|a| a.as_str()).unwrap_or("")),
+                    }));
+                }
+                request.messages.push(Message::new(Role::Assistant, parts));
+            }
+            _ => request.messages.push(Message::new(Role::User, parse_content(content))),
+        }
+    }
+
+    for tool in body.get("tools").and_then(|t| t.as_array()).into_iter().flatten() {
+        let Some(function) = tool.get("function") else { continue };
+        let Some(name) = function.get("name").and_then(|n| n.as_str()) else { continue };
+        request.tools.push(ToolDefinition {
+            name: name.to_string(),
+            description: function.get("description").and_then(|d| d.as_str()).map(String::from),
+            parameters: function.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object"})),
+        });
+    }
+    request.tool_choice = body.get("tool_choice").and_then(parse_tool_choice);
+    request.parallel_tool_calls = body.get("parallel_tool_calls").and_then(|p| p.as_bool());
+    request.reasoning = match body.get("reasoning_effort").and_then(|e| e.as_str()) {
+        Some("none") => Some(Reasoning::Disabled),
+        Some(effort) => ReasoningEffort::parse(effort).map(Reasoning::Effort),
+        None => None,
+    };
+    request.response_format = body.get("response_format").and_then(parse_response_format);
+    link_tool_results(&mut request.messages);
+
+    let params = &mut request.params;
+    params.max_tokens = field(body, &["max_completion_tokens", "max_tokens"]).and_then(|v| v.as_u64());
+    params.temperature = body.get("temperature").and_then(|v| v.as_f64());
+    params.top_p = body.get("top_p").and_then(|v| v.as_f64());
+    params.stop = match body.get("stop") {
+        Some(Value::String(stop)) => vec![stop.clone()],
+        Some(Value::Array(stops)) => stops.iter().filter_map(|s| s.as_str().map(String::from)).collect(),
+        _ => Vec::new(),
+    };
+    params.seed = body.get("seed").and_then(|v| v.as_i64());
+    params.presence_penalty = body.get("presence_penalty").and_then(|v| v.as_f64());
+    params.frequency_penalty = body.get("frequency_penalty").and_then(|v| v.as_f64());
+    params.n = body.get("n").and_then(|v| v.as_u64()).map(|n| n as u32);
+    params.logprobs = body.get("logprobs").and_then(|v| v.as_bool()).unwrap_or(false);
+    params.top_logprobs = body.get("top_logprobs").and_then(|v| v.as_u64()).map(|n| n as u32);
+
+    Ok(request)
+}
+
+pub fn emit_request(request: &ChatRequest) -> Result<Value> {
+    let mut messages: Vec<Value> = request
+        .system
+        .iter()
+        .map(|text| json!({"role": "system", "content": text}))
+        .collect();
+
+    for message in &request.messages {
+        match message.role {
+            Role::User => {
+                let mut rest = Vec::new();
+                for part in &message.content {
+                    match part {
+                        ContentPart::ToolResult(result) => {
+                            messages.push(json!({
+                                "role": "tool",
+                                "tool_call_id": result.call_id,
+                                "content": text_of(&result.content),
+                            }));
+                            // Tool messages can only hold text, so images follow in a user message
+                            let media: Vec<ContentPart> = result
+                                .content
+                                .iter()
+                                .filter(|part| matches!(part, ContentPart::Media(_)))
+                                .cloned()
+                                .collect();
+                            if !media.is_empty() {
+                                rest.push(ContentPart::Text(format!("Images returned by tool call {}:", result.call_id)));
+                                rest.extend(media);
+                            }
+                        }
+                        other => rest.push(other.clone()),
+                    }
+                }
+                if !rest.is_empty() {
+                    messages.push(json!({"role": "user", "content": emit_content(&rest)?}));
+                }
+            }
+            Role::Assistant => {
+                let text = message.text();
+                let mut msg = json!({
+                    "role": "assistant",
+                    "content": if text.is_empty() { Value::Null } else { json!(text) },
+                });
+                let calls: Vec<Value> = message
+                    .content
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::ToolCall(call) => Some(emit_tool_call(call)),
+                        _ => None,
+                    })
+                    .collect();
+                if !calls.is_empty() {
+                    msg["tool_calls"] = json!(calls);
+                }
+                emit_reasoning(&message.content, &mut msg);
+                messages.push(msg);
+            }
+        }
+    }
+
+    let mut body = json!({ "messages": messages });
+    if let Some(model) = &request.model {
+        body["model"] = json!(model);
+    }
+    if !request.tools.is_empty() {
+        body["tools"] = request
+            .tools
+            .iter()
+            .map(|tool| {
+                let mut function = json!({"name": tool.name, "parameters": tool.parameters});
+                if let Some(description) = &tool.description {
+                    function["description"] = json!(description);
+                }
+                json!({"type": "function", "function": function})
+            })
+            .collect();
+        if let Some(choice) = &request.tool_choice {
+            body["tool_choice"] = emit_tool_choice(choice);
+        }
+        if let Some(parallel) = request.parallel_tool_calls {
+            body["parallel_tool_calls"] = json!(parallel);
+        }
+    }
+    // OpenAI can't turn reasoning off, so `Disabled` is left out
+    let effort = match request.reasoning {
+        Some(Reasoning::Effort(effort)) => Some(effort),
+        Some(Reasoning::Budget(budget)) => Some(ReasoningEffort::from_budget(budget)),
+        _ => None,
+    };
+    if let Some(effort) = effort {
+        body["reasoning_effort"] = json!(effort.as_str());
+    }
+    if let Some(format) = &request.response_format {
+        body["response_format"] = emit_response_format(format);
+    }
+    let params = &request.params;
+    if let Some(max_tokens) = params.max_tokens {
+        let model = request.model.as_deref().unwrap_or_default();
+        // Reasoning models reject `max_tokens`
+        let key = match capabilities::lookup(ModelProtocol::OpenAI, model).max_completion_tokens {
+            true => "max_completion_tokens",
+            false => "max_tokens",
+        };
+        body[key] = json!(max_tokens);
+    }
+    if let Some(temperature) = params.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if !params.stop.is_empty() {
+        body["stop"] = json!(params.stop);
+    }
+    if let Some(seed) = params.seed {
+        body["seed"] = json!(seed);
+    }
+    if let Some(penalty) = params.presence_penalty {
+        body["presence_penalty"] = json!(penalty);
+    }
+    if let Some(penalty) = params.frequency_penalty {
+        body["frequency_penalty"] = json!(penalty);
+    }
+    if let Some(n) = params.n {
+        body["n"] = json!(n);
+    }
+    if params.logprobs || params.top_logprobs.is_some() {
+        body["logprobs"] = json!(true);
+    }
+    if let Some(top_logprobs) = params.top_logprobs {
+        body["top_logprobs"] = json!(top_logprobs);
+    }
+    if request.stream {
+        body["stream"] = json!(true);
+    }
+    Ok(body)
+}
+
+pub fn parse_response(body: &Value) -> Result<ChatResponse> {
+    let choice = body
+        .get("choices")
+        .and_then(|c| c.get(0))
+        .ok_or_else(|| anyhow::anyhow!("OpenAI response has no choices"))?;
+    let message = choice.get("message").unwrap_or(&Value::Null);
+
+    let mut content = parse_reasoning(message);
+    content.extend(parse_content(message.get("content").unwrap_or(&Value::Null)));
+    for call in message.get("tool_calls").and_then(|c| c.as_array()).into_iter().flatten() {
+        let function = call.get("function").unwrap_or(&Value::Null);
+        content.push(ContentPart::ToolCall(ToolCall {
+            id: call.get("id").and_then(|id| id.as_str()).unwrap_or("").to_string(),
+            name: function.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string(),
+            arguments: parse_arguments(function.get("arguments").and_then(|a| a.as_str()).unwrap_or("")),
+        }));
+    }
+
+    Ok(ChatResponse {
+        id: body.get("id").and_then(|id| id.as_str()).map(String::from),
+        model: body.get("model").and_then(|m| m.as_str()).map(String::from),
+        content,
+        stop_reason: choice.get("finish_reason").and_then(|r| r.as_str()).map(parse_finish_reason),
+        usage: body.get("usage").map(parse_usage).unwrap_or_default(),
+    })
+}
+
+pub fn emit_response(response: &ChatResponse) -> Value {
+    let text = response.text();
+    let mut message = json!({
+        "role": "assistant",
+        "content": if text.is_empty() && has_tool_calls(&response.content) { Value::Null } else { json!(text) },
+    });
+    let calls: Vec<Value> = response
+        .content
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::ToolCall(call) => Some(emit_tool_call(call)),
+            _ => None,
+        })
+        .collect();
+    if !calls.is_empty() {
+        message["tool_calls"] = json!(calls);
+    }
+    emit_reasoning(&response.content, &mut message);
+
+    json!({
+        "id": response.id.clone().unwrap_or_else(|| format!("chatcmpl-{}", Uuid::new_v4())),
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": response.model.as_deref().unwrap_or("unknown"),
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": emit_finish_reason(response.stop_reason),
+        }],
+        "usage": emit_usage(&response.usage),
+    })
+}
+
+pub fn parse_models(body: &Value) -> ModelList {
+    let models = body
+        .get("data")
+        .and_then(|d| d.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            Some(ModelEntry {
+                id: model.get("id")?.as_str()?.to_string(),
+                display_name: None,
+                owned_by: model.get("owned_by").and_then(|o| o.as_str()).map(String::from),
+                created: model.get("created").and_then(|c| c.as_i64()),
+            })
+        })
+        .collect();
+    ModelList { models }
+}
+
+pub fn emit_models(list: &ModelList) -> Value {
+    let data: Vec<Value> = list
+        .models
+        .iter()
+        .map(|model| {
+            json!({
+                "id": model.id,
+                "object": "model",
+                "created": model.created.unwrap_or(0),
+                "owned_by": model.owned_by.as_deref().unwrap_or("system"),
+            })
+        })
+        .collect();
+    json!({ "object": "list", "data": data })
+}
+
+/// Reads `chat.completion.chunk` objects
+#[derive(Default)]
+pub struct ChunkParser {
+    started: bool,
+    blocks: OpenBlocks,
+    /// OpenAI tool call index to IR block index, and whether the block is still open
+    tool_calls: Vec<(u64, usize, bool)>,
+}
+
+impl ChunkParser {
+    fn close_tool_calls(&mut self, events: &mut Vec<StreamEvent>) {
+        for (_, index, open) in &mut self.tool_calls {
+            if std::mem::take(open) {
+                events.push(StreamEvent::BlockStop { index: *index });
+            }
+        }
+    }
+}
+
+impl StreamParser for ChunkParser {
+    fn parse(&mut self, chunk: &Value) -> Result<Vec<StreamEvent>> {
+        let mut events = Vec::new();
+        if !self.started {
+            self.started = true;
+            events.push(StreamEvent::MessageStart {
+                id: chunk.get("id").and_then(|id| id.as_str()).map(String::from),
+                model: chunk.get("model").and_then(|m| m.as_str()).map(String::from),
+            });
+        }
+        let usage = chunk.get("usage").filter(|u| !u.is_null()).map(parse_usage);
+
+        let Some(choice) = chunk.get("choices").and_then(|c| c.get(0)) else {
+            // Usage arrives in a chunk of its own when `stream_options.include_usage` is set
+            if usage.is_some() {
+                events.push(StreamEvent::MessageDelta { stop_reason: None, usage });
+            }
+            return Ok(events);
+        };
+        let delta = choice.get("delta").unwrap_or(&Value::Null);
+
+        if let Some(text) = field(delta, &["reasoning_content", "reasoning"]).and_then(|r| r.as_str()).filter(|t| !t.is_empty()) {
+            self.close_tool_calls(&mut events);
+            let index = self.blocks.thinking(&mut events);
+            events.push(StreamEvent::ThinkingDelta { index, text: text.to_string() });
+        }
+        // Only signatures and redacted blocks are read here: the text repeats `reasoning_content`
+        for block in delta.get("thinking_blocks").and_then(|b| b.as_array()).into_iter().flatten() {
+            if block.get("type").and_then(|t| t.as_str()) == Some("redacted_thinking") {
+                self.blocks.close(&mut events);
+                self.close_tool_calls(&mut events);
+                let index = self.blocks.allocate();
+                let data = block.get("data").and_then(|d| d.as_str()).unwrap_or("").to_string();
+                events.push(StreamEvent::BlockStart { index, block: BlockKind::RedactedThinking { data } });
+                events.push(StreamEvent::BlockStop { index });
+            } else if let Some(signature) = block.get("signature").and_then(|s| s.as_str()) {
+                let index = self.blocks.thinking(&mut events);
+                events.push(StreamEvent::SignatureDelta { index, signature: signature.to_string() });
+            }
+        }
+
+        if let Some(text) = delta.get("content").and_then(|c| c.as_str()).filter(|t| !t.is_empty()) {
+            self.close_tool_calls(&mut events);
+            let index = self.blocks.text(&mut events);
+            events.push(StreamEvent::TextDelta { index, text: text.to_string() });
+        }
+
+        for call in delta.get("tool_calls").and_then(|c| c.as_array()).into_iter().flatten() {
+            let position = call.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+            let function = call.get("function").unwrap_or(&Value::Null);
+            let known = self.tool_calls.iter().rev().find(|(p, ..)| *p == position).map(|(_, index, _)| *index);
+            // A chunk with an ID starts a call; without one it continues the call at its position,
+            // even after a parallel call has started since
+            let index = match (known, call.get("id").and_then(|id| id.as_str())) {
+                (Some(index), None) => index,
+                (_, id) => {
+                    self.blocks.close(&mut events);
+                    self.close_tool_calls(&mut events);
+                    let index = self.blocks.allocate();
+                    self.tool_calls.push((position, index, true));
+                    events.push(StreamEvent::BlockStart {
+                        index,
+                        block: BlockKind::ToolCall {
+                            id: id.unwrap_or("").to_string(),
+                            name: function.get("name").and_then(|n| n.as_str()).unwrap_or("").to_string(),
+                        },
+                    });
+                    index
+                }
+            };
+            if let Some(arguments) = function.get("arguments").and_then(|a| a.as_str()).filter(|a| !a.is_empty()) {
+                events.push(StreamEvent::ToolArgumentsDelta {
+                    index,
+                    partial_json: arguments.to_string(),
+                });
+            }
+        }
+
+        if let Some(reason) = choice.get("finish_reason").and_then(|r| r.as_str()) {
+            self.blocks.close(&mut events);
+            self.close_tool_calls(&mut events);
+            events.push(StreamEvent::MessageDelta {
+                stop_reason: Some(parse_finish_reason(reason)),
+                usage,
+            });
+        } else if usage.is_some() {
+            events.push(StreamEvent::MessageDelta { stop_reason: None, usage });
+        }
+        Ok(events)
+    }
+
+    fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        self.blocks.close(&mut events);
+        self.close_tool_calls(&mut events);
+        if self.started {
+            events.push(StreamEvent::MessageStop);
+        }
+        events
+    }
+}
+
+/// Writes `chat.completion.chunk` objects
+pub struct ChunkEmitter {
+    id: String,
+    model: String,
+    created: i64,
+    blocks: BlockIndexes,
+    /// IR block index to OpenAI tool call index
+    tool_calls: Vec<(usize, usize)>,
+}
+
+impl ChunkEmitter {
+    pub fn new(model: Option<&str>) -> Self {
+        Self {
+            id: format!("chatcmpl-{}", Uuid::new_v4()),
+            model: model.unwrap_or("unknown").to_string(),
+            created: chrono::Utc::now().timestamp(),
+            blocks: BlockIndexes::default(),
+            tool_calls: Vec::new(),
+        }
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Option<&str>) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+    }
+
+    fn tool_call_index(&self, block: usize) -> Option<usize> {
+        self.tool_calls.iter().find(|(b, _)| *b == block).map(|(_, i)| *i)
+    }
+}
+
+impl StreamEmitter for ChunkEmitter {
+    fn emit(&mut self, event: &StreamEvent) -> Vec<Value> {
+        match event {
+            StreamEvent::MessageStart { id, .. } => {
+                if let Some(id) = id {
+                    self.id = id.clone();
+                }
+                vec![self.chunk(json!({"role": "assistant", "content": ""}), None)]
+            }
+            StreamEvent::BlockStart { index, block } => {
+                let block_index = self.blocks.start(*index);
+                match block {
+                    BlockKind::Text | BlockKind::Thinking => Vec::new(),
+                    BlockKind::RedactedThinking { data } => vec![self.chunk(
+                        json!({"thinking_blocks": [{"type": "redacted_thinking", "data": data}]}),
+                        None,
+                    )],
+                    BlockKind::ToolCall { id, name } => {
+                        let position = self.tool_calls.len();
+                        self.tool_calls.push((block_index, position));
+                        vec![self.chunk(
+                            json!({"tool_calls": [{
+                                "index": position,
+                                "id": id,
+                                "type": "function",
+                                "function": {"name": name, "arguments": ""},
+                            }]}),
+                            None,
+                        )]
+                    }
+                }
+            }
+            StreamEvent::TextDelta { text, .. } => vec![self.chunk(json!({"content": text}), None)],
+            StreamEvent::ThinkingDelta { text, .. } => vec![self.chunk(json!({"reasoning_content": text}), None)],
+            StreamEvent::SignatureDelta { signature, .. } => vec![self.chunk(
+                json!({"thinking_blocks": [{"type": "thinking", "thinking": "", "signature": signature}]}),
+                None,
+            )],
+            StreamEvent::ToolArgumentsDelta { index, partial_json } => {
+                let Some(position) = self.blocks.get(*index).and_then(|b| self.tool_call_index(b)) else {
+                    return Vec::new();
+                };
+                vec![self.chunk(
+                    json!({"tool_calls": [{"index": position, "function": {"arguments": partial_json}}]}),
+                    None,
+                )]
+            }
+            StreamEvent::BlockStop { .. } => Vec::new(),
+            StreamEvent::MessageDelta { stop_reason, usage } => {
+                let mut chunk = match stop_reason {
+                    Some(reason) => self.chunk(json!({}), Some(emit_finish_reason(Some(*reason)))),
+                    None if usage.is_some() => {
+                        let mut chunk = self.chunk(json!({}), None);
+                        chunk["choices"] = json!([]);
+                        chunk
+                    }
+                    None => return Vec::new(),
+                };
+                if let Some(usage) = usage {
+                    chunk["usage"] = emit_usage(usage);
+                }
+                vec![chunk]
+            }
+            StreamEvent::MessageStop => Vec::new(),
+        }
+    }
+}
+
+fn parse_content(content: &Value) -> Vec<ContentPart> {
+    match content {
+        Value::String(text) if !text.is_empty() => vec![ContentPart::Text(text.clone())],
+        Value::Array(items) => items
+            .iter()
+            .filter_map(|item| match item.get("type").and_then(|t| t.as_str())? {
+                "text" => {
+                    let text = item.get("text")?.as_str().filter(|t| !t.is_empty())?;
+                    Some(ContentPart::Text(text.to_string()))
+                }
+                "image_url" => {
+                    let image_url = item.get("image_url")?;
+                    let url = image_url.as_str().or_else(|| image_url.get("url")?.as_str())?;
+                    Some(ContentPart::Media(Media::from_url(url, "image/jpeg")))
+                }
+                "input_audio" => {
+                    let audio = item.get("input_audio")?;
+                    let mime_type = match audio.get("format").and_then(|f| f.as_str()).unwrap_or("wav") {
+                        "mp3" => "audio/mpeg".to_string(),
+                        format => format!("audio/{}", format),
+                    };
+                    let data = audio.get("data")?.as_str()?;
+                    Some(ContentPart::Media(Media::new(&mime_type, MediaSource::Base64(data.to_string()))))
+                }
+                "file" => {
+                    let file = item.get("file")?;
+                    let mut media = match (file.get("file_data"), file.get("file_id")) {
+                        (Some(data), _) => Media::from_url(data.as_str()?, "application/pdf"),
+                        (None, Some(id)) => Media::new("application/pdf", MediaSource::FileId(id.as_str()?.to_string())),
+                        (None, None) => return None,
+                    };
+                    media.name = file.get("filename").and_then(|n| n.as_str()).map(String::from);
+                    Some(ContentPart::Media(media))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn emit_content(parts: &[ContentPart]) -> Result<Value> {
+    if let [ContentPart::Text(text)] = parts {
+        return Ok(json!(text));
+    }
+    let mut items = Vec::new();
+    for part in parts {
+        match part {
+            ContentPart::Text(text) => items.push(json!({"type": "text", "text": text})),
+            ContentPart::Media(media) => items.push(emit_media(media)?),
+            _ => {}
+        }
+    }
+    Ok(json!(items))
+}
+
+fn emit_media(media: &Media) -> Result<Value> {
+    let unsupported = || UnsupportedContent::media("OpenAI", media);
+    match (media.kind(), &media.source) {
+        (MediaKind::Image, MediaSource::Base64(_) | MediaSource::Url(_)) => {
+            Ok(json!({"type": "image_url", "image_url": {"url": media.to_url()}}))
+        }
+        (MediaKind::Audio, MediaSource::Base64(data)) => {
+            let format = match media.mime_type.as_str() {
+                "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+                "audio/mpeg" | "audio/mp3" => "mp3",
+                _ => return Err(unsupported().into()),
+            };
+            Ok(json!({"type": "input_audio", "input_audio": {"data": data, "format": format}}))
+        }
+        (MediaKind::Document, MediaSource::Base64(_)) => {
+            let filename = media.name.as_deref().unwrap_or("document.pdf");
+            Ok(json!({"type": "file", "file": {"filename": filename, "file_data": media.to_url()}}))
+        }
+        (MediaKind::Document, MediaSource::FileId(id)) => Ok(json!({"type": "file", "file": {"file_id": id}})),
+        _ => Err(unsupported().into()),
+    }
+}
+
+fn emit_tool_call(call: &ToolCall) -> Value {
+    json!({
+        "id": call.id,
+        "type": "function",
+        "function": {"name": call.name, "arguments": arguments_json(&call.arguments)},
+    })
+}
+
+/// Reasoning of a message: `thinking_blocks`, which keep signatures, or else `reasoning_content`
+/// (`reasoning` on some servers)
+fn parse_reasoning(message: &Value) -> Vec<ContentPart> {
+    if let Some(blocks) = message.get("thinking_blocks").and_then(|b| b.as_array()) {
+        return blocks
+            .iter()
+            .map(|block| {
+                let redacted = block.get("type").and_then(|t| t.as_str()) == Some("redacted_thinking");
+                let text = if redacted { block.get("data") } else { block.get("thinking") };
+                ContentPart::Thinking(Thinking {
+                    text: text.and_then(|t| t.as_str()).unwrap_or("").to_string(),
+                    signature: block.get("signature").and_then(|s| s.as_str()).map(String::from),
+                    redacted,
+                })
+            })
+            .collect();
+    }
+    field(message, &["reasoning_content", "reasoning"])
+        .and_then(|r| r.as_str())
+        .filter(|r| !r.is_empty())
+        .map(|text| {
+            vec![ContentPart::Thinking(Thinking {
+                text: text.to_string(),
+                ..Default::default()
+            })]
+        })
+        .unwrap_or_default()
+}
+
+/// `reasoning_content`, plus `thinking_blocks` when there are signatures or redacted blocks to keep
+fn emit_reasoning(parts: &[ContentPart], message: &mut Value) {
+    let thinking: Vec<&Thinking> = parts
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::Thinking(thinking) => Some(thinking),
+            _ => None,
+        })
+        .collect();
+    let text: String = thinking.iter().filter(|t| !t.redacted).map(|t| t.text.as_str()).collect();
+    if !text.is_empty() {
+        message["reasoning_content"] = json!(text);
+    }
+    if thinking.iter().any(|t| t.signature.is_some() || t.redacted) {
+        let blocks: Vec<Value> = thinking
+            .iter()
+            .map(|t| {
+                if t.redacted {
+                    return json!({"type": "redacted_thinking", "data": t.text});
+                }
+                let mut block = json!({"type": "thinking", "thinking": t.text});
+                if let Some(signature) = &t.signature {
+                    block["signature"] = json!(signature);
+                }
+                block
+            })
+            .collect();
+        message["thinking_blocks"] = json!(blocks);
+    }
+}
+
+fn parse_response_format(format: &Value) -> Option<ResponseFormat> {
+    match format.get("type")?.as_str()? {
+        "json_object" => Some(ResponseFormat::JsonObject),
+        "json_schema" => {
+            let spec = format.get("json_schema")?;
+            Some(ResponseFormat::JsonSchema(JsonSchemaFormat {
+                name: spec.get("name").and_then(|n| n.as_str()).unwrap_or("response").to_string(),
+                description: spec.get("description").and_then(|d| d.as_str()).map(String::from),
+                schema: spec.get("schema").cloned().unwrap_or_else(|| json!({"type": "object"})),
+                strict: spec.get("strict").and_then(|s| s.as_bool()).unwrap_or(false),
+            }))
+        }
+        _ => None,
+    }
+}
+
+fn emit_response_format(format: &ResponseFormat) -> Value {
+    match format {
+        ResponseFormat::JsonObject => json!({"type": "json_object"}),
+        ResponseFormat::JsonSchema(format) => {
+            let mut spec = json!({"name": format.name, "schema": format.schema, "strict": format.strict});
+            if let Some(description) = &format.description {
+                spec["description"] = json!(description);
+            }
+            json!({"type": "json_schema", "json_schema": spec})
+        }
+    }
+}
+
+fn parse_tool_choice(choice: &Value) -> Option<ToolChoice> {
+    match choice {
+        Value::String(mode) => match mode.as_str() {
+            "auto" => Some(ToolChoice::Auto),
+            "none" => Some(ToolChoice::None),
+            "required" => Some(ToolChoice::Required),
+            _ => None,
+        },
+        Value::Object(_) => {
+            let name = choice.get("function")?.get("name")?.as_str()?;
+            Some(ToolChoice::Tool(name.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn emit_tool_choice(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!("auto"),
+        ToolChoice::None => json!("none"),
+        ToolChoice::Required => json!("required"),
+        ToolChoice::Tool(name) => json!({"type": "function", "function": {"name": name}}),
+    }
+}
+
+fn only_tool_results(parts: &[ContentPart]) -> bool {
+    parts.iter().all(|part| matches!(part, ContentPart::ToolResult(_)))
+}
+
+fn has_tool_calls(parts: &[ContentPart]) -> bool {
+    parts.iter().any(|part| matches!(part, ContentPart::ToolCall(_)))
+}
+
+fn parse_finish_reason(reason: &str) -> StopReason {
+    match reason {
+        "length" => StopReason::MaxTokens,
+        "tool_calls" | "function_call" => StopReason::ToolUse,
+        "content_filter" => StopReason::ContentFilter,
+        _ => StopReason::EndTurn,
+    }
+}
+
+fn emit_finish_reason(reason: Option<StopReason>) -> &'static str {
+    match reason {
+        Some(StopReason::MaxTokens) => "length",
+        Some(StopReason::ToolUse) => "tool_calls",
+        Some(StopReason::ContentFilter | StopReason::Recitation) => "content_filter",
+        // Stop sequences, paused turns and malformed calls have no finish reason of their own
+        _ => "stop",
+    }
+}
+
+fn parse_usage(usage: &Value) -> Usage {
+    let count = |value: Option<&Value>| value.and_then(|t| t.as_u64()).unwrap_or(0);
+    Usage {
+        input_tokens: count(usage.get("prompt_tokens")),
+        output_tokens: count(usage.get("completion_tokens")),
+        // DeepSeek reports cache hits as `prompt_cache_hit_tokens`
+        cache_read_input_tokens: count(
+            usage.pointer("/prompt_tokens_details/cached_tokens").or_else(|| usage.get("prompt_cache_hit_tokens")),
+        ),
+        cache_creation_input_tokens: count(usage.get("cache_creation_input_tokens")),
+        reasoning_tokens: count(usage.pointer("/completion_tokens_details/reasoning_tokens")),
+    }
+}
+
+fn emit_usage(usage: &Usage) -> Value {
+    let mut body = json!({
+        "prompt_tokens": usage.input_tokens,
+        "completion_tokens": usage.output_tokens,
+        "total_tokens": usage.input_tokens + usage.output_tokens,
+    });
+    if usage.cache_read_input_tokens > 0 {
+        body["prompt_tokens_details"] = json!({"cached_tokens": usage.cache_read_input_tokens});
+    }
+    // OpenAI has no cache writes; LiteLLM reports Claude's this way
+    if usage.cache_creation_input_tokens > 0 {
+        body["cache_creation_input_tokens"] = json!(usage.cache_creation_input_tokens);
+    }
+    if usage.reasoning_tokens > 0 {
+        body["completion_tokens_details"] = json!({"reasoning_tokens": usage.reasoning_tokens});
+    }
+    body
+}
@@ -0,0 +1,341 @@
+/*!
+ * Gemini Schema Sanitizer
+ *
+ * Gemini function declarations take an OpenAPI 3.0 subset of JSON Schema and reject the rest
+ * with an opaque 400, while OpenAI and Claude tools (MCP tools especially) use full JSON Schema.
+ * This pass rewrites a schema into the subset:
+ *
+ * - `$ref` is inlined from `$defs`/`definitions`; recursive references become a plain object
+ * - `null` in `anyOf`/`oneOf`/`type`/`enum` becomes `nullable`, and a union left with one
+ *   variant is merged into its parent; `oneOf` becomes `anyOf` and `allOf` is merged
+ * - `const` becomes a one-value `enum`; enums are string-only, so other values are stringified
+ * - `format` is kept only where Gemini knows it, `required` only for listed properties, and
+ *   every other keyword (`additionalProperties`, `$schema`, ...) is dropped
+ *
+ * Each change is logged at debug level with the path of the field it applies to.
+ */
+
+use serde_json::{json, Map, Value};
+use tracing::debug;
+
+/// Keywords Gemini accepts as they are
+const PASSTHROUGH_KEYWORDS: &[&str] = &[
+    "title",
+    "description",
+    "default",
+    "example",
+    "minItems",
+    "maxItems",
+    "minProperties",
+    "maxProperties",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "minimum",
+    "maximum",
+    "propertyOrdering",
+];
+
+/// Keywords handled by the sanitizer itself
+const HANDLED_KEYWORDS: &[&str] = &[
+    "$ref",
+    "$defs",
+    "definitions",
+    "type",
+    "nullable",
+    "anyOf",
+    "oneOf",
+    "allOf",
+    "enum",
+    "const",
+    "format",
+    "properties",
+    "required",
+    "items",
+];
+
+/// Rewrite a JSON Schema into the subset Gemini accepts; `name` prefixes the logged field paths
+pub fn sanitize_for_gemini(schema: &Value, name: &str) -> Value {
+    let mut sanitizer = Sanitizer {
+        root: schema,
+        resolving: Vec::new(),
+    };
+    sanitizer.sanitize(schema, name)
+}
+
+struct Sanitizer<'a> {
+    root: &'a Value,
+    /// References being inlined, to stop at recursion
+    resolving: Vec<String>,
+}
+
+impl Sanitizer<'_> {
+    fn sanitize(&mut self, schema: &Value, path: &str) -> Value {
+        let Some(object) = schema.as_object() else {
+            // `true` (or `{}`) accepts anything
+            if schema != &Value::Bool(true) {
+                log_change(path, "non-object schema replaced by an unconstrained one");
+            }
+            return json!({});
+        };
+
+        if let Some(reference) = object.get("$ref").and_then(|r| r.as_str()) {
+            return self.inline(reference, object, path);
+        }
+
+        let mut out = Map::new();
+        let mut nullable = object
+            .get("nullable")
+            .and_then(|n| n.as_bool())
+            .unwrap_or(false);
+
+        match object.get("type") {
+            Some(Value::String(kind)) if kind == "null" => {
+                log_change(path, "type null replaced by nullable");
+                nullable = true;
+            }
+            Some(Value::String(kind)) => {
+                out.insert("type".to_string(), json!(kind));
+            }
+            Some(Value::Array(kinds)) => {
+                let kinds: Vec<&str> = kinds.iter().filter_map(|k| k.as_str()).collect();
+                nullable |= kinds.contains(&"null");
+                let kinds: Vec<&str> = kinds.into_iter().filter(|k| *k != "null").collect();
+                match kinds.as_slice() {
+                    [] => {}
+                    [kind] => {
+                        out.insert("type".to_string(), json!(kind));
+                    }
+                    kinds => {
+                        log_change(path, "type list replaced by anyOf");
+                        let variants: Vec<Value> =
+                            kinds.iter().map(|kind| json!({"type": kind})).collect();
+                        out.insert("anyOf".to_string(), json!(variants));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let union = object
+            .get("anyOf")
+            .map(|u| ("anyOf", u))
+            .or_else(|| object.get("oneOf").map(|u| ("oneOf", u)));
+        if let Some((keyword, Value::Array(variants))) = union {
+            if keyword == "oneOf" {
+                log_change(path, "oneOf replaced by anyOf");
+            }
+            let mut kept = Vec::new();
+            for (i, variant) in variants.iter().enumerate() {
+                if variant.get("type").and_then(|t| t.as_str()) == Some("null") {
+                    nullable = true;
+                    continue;
+                }
+                kept.push(self.sanitize(variant, &format!("{}.{}[{}]", path, keyword, i)));
+            }
+            match kept.len() {
+                0 => {}
+                1 => {
+                    log_change(
+                        path,
+                        &format!("single-variant {} merged into its parent", keyword),
+                    );
+                    merge_into(&mut out, kept.remove(0));
+                }
+                _ => {
+                    out.insert("anyOf".to_string(), json!(kept));
+                }
+            }
+        }
+
+        let values = match (object.get("enum"), object.get("const")) {
+            (Some(Value::Array(values)), _) => Some(values.clone()),
+            (None, Some(value)) => {
+                log_change(path, "const replaced by a one-value enum");
+                Some(vec![value.clone()])
+            }
+            _ => None,
+        };
+        if let Some(values) = values {
+            nullable |= values.iter().any(|v| v.is_null());
+            let mut stringified = false;
+            let values: Vec<String> = values
+                .iter()
+                .filter(|v| !v.is_null())
+                .map(|v| match v {
+                    Value::String(s) => s.clone(),
+                    other => {
+                        stringified = true;
+                        other.to_string()
+                    }
+                })
+                .collect();
+            if stringified {
+                log_change(path, "non-string enum values converted to strings");
+            }
+            if out.get("type").and_then(|t| t.as_str()) != Some("string") {
+                if out.contains_key("type") {
+                    log_change(path, "enum type changed to string");
+                }
+                out.insert("type".to_string(), json!("string"));
+            }
+            out.insert("enum".to_string(), json!(values));
+        }
+
+        if let Some(format) = object.get("format").and_then(|f| f.as_str()) {
+            let supported: &[&str] = match out.get("type").and_then(|t| t.as_str()) {
+                Some("string") => &["enum", "date-time"],
+                Some("number") => &["float", "double"],
+                Some("integer") => &["int32", "int64"],
+                _ => &[],
+            };
+            if supported.contains(&format) {
+                out.insert("format".to_string(), json!(format));
+            } else {
+                log_change(path, &format!("unsupported format {:?} removed", format));
+            }
+        }
+
+        if let Some(Value::Object(properties)) = object.get("properties") {
+            let properties: Map<String, Value> = properties
+                .iter()
+                .map(|(key, property)| {
+                    (
+                        key.clone(),
+                        self.sanitize(property, &format!("{}.{}", path, key)),
+                    )
+                })
+                .collect();
+            // Gemini rejects an empty property map
+            if !properties.is_empty() {
+                out.insert("properties".to_string(), Value::Object(properties));
+            }
+        }
+
+        // Merged before `required` is checked, since the parts may add properties
+        if let Some(Value::Array(parts)) = object.get("allOf") {
+            for (i, part) in parts.iter().enumerate() {
+                let part = self.sanitize(part, &format!("{}.allOf[{}]", path, i));
+                merge_into(&mut out, part);
+            }
+        }
+
+        if let Some(Value::Array(required)) = object.get("required") {
+            let properties = out.get("properties").and_then(|p| p.as_object());
+            let (known, unknown): (Vec<&Value>, Vec<&Value>) = required.iter().partition(|name| {
+                name.as_str()
+                    .is_some_and(|n| properties.is_some_and(|p| p.contains_key(n)))
+            });
+            if !unknown.is_empty() {
+                log_change(
+                    path,
+                    &format!(
+                        "required names without a property removed: {}",
+                        json!(unknown)
+                    ),
+                );
+            }
+            if !known.is_empty() {
+                merge_into(&mut out, json!({ "required": known }));
+            }
+        }
+
+        match object.get("items") {
+            Some(Value::Array(items)) => {
+                log_change(path, "tuple items replaced by the first item schema");
+                if let Some(first) = items.first() {
+                    let first = self.sanitize(first, &format!("{}.items", path));
+                    out.insert("items".to_string(), first);
+                }
+            }
+            Some(items) => {
+                let items = self.sanitize(items, &format!("{}.items", path));
+                out.insert("items".to_string(), items);
+            }
+            None => {}
+        }
+
+        for (key, value) in object {
+            if PASSTHROUGH_KEYWORDS.contains(&key.as_str()) {
+                out.insert(key.clone(), value.clone());
+            } else if !HANDLED_KEYWORDS.contains(&key.as_str()) {
+                log_change(path, &format!("unsupported keyword {} removed", key));
+            }
+        }
+
+        if nullable {
+            out.insert("nullable".to_string(), json!(true));
+        }
+        Value::Object(out)
+    }
+
+    /// Inline a `$ref`; keywords next to it (such as `description`) take precedence
+    fn inline(&mut self, reference: &str, object: &Map<String, Value>, path: &str) -> Value {
+        if self.resolving.iter().any(|r| r == reference) {
+            log_change(
+                path,
+                &format!("recursive reference {} replaced by an object", reference),
+            );
+            return json!({"type": "object"});
+        }
+        let Some(target) = resolve(self.root, reference) else {
+            log_change(
+                path,
+                &format!("unresolvable reference {} replaced by an object", reference),
+            );
+            return json!({"type": "object"});
+        };
+        self.resolving.push(reference.to_string());
+        let mut inlined = self.sanitize(target, path);
+        self.resolving.pop();
+
+        let siblings: Map<String, Value> = object
+            .iter()
+            .filter(|(k, _)| *k != "$ref")
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if !siblings.is_empty() {
+            let siblings = self.sanitize(&Value::Object(siblings), path);
+            if let (Value::Object(inlined), Value::Object(siblings)) = (&mut inlined, siblings) {
+                inlined.extend(siblings);
+            }
+        }
+        inlined
+    }
+}
+
+/// Look up a local reference such as `#/$defs/Item`
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+/// Merge a sanitized schema into another: properties and required are combined, other keywords
+/// are added where missing
+fn merge_into(out: &mut Map<String, Value>, schema: Value) {
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    for (key, value) in schema {
+        match (key.as_str(), out.get_mut(&key), value) {
+            ("properties", Some(Value::Object(existing)), Value::Object(added)) => {
+                existing.extend(added)
+            }
+            ("required", Some(Value::Array(existing)), Value::Array(added)) => {
+                for name in added {
+                    if !existing.contains(&name) {
+                        existing.push(name);
+                    }
+                }
+            }
+            (_, Some(_), _) => {}
+            (_, None, value) => {
+                out.insert(key, value);
+            }
+        }
+    }
+}
+
+fn log_change(path: &str, message: &str) {
+    debug!("Gemini schema {}: {}", path, message);
+}
@@ -0,0 +1,298 @@
+/*!
+ * Structured Output
+ *
+ * OpenAI `response_format` and Gemini `responseMimeType`/`responseSchema` make the model answer
+ * with JSON. Claude and Kiro are sent no such setting: their emitters declare an extra tool,
+ * `json_response`, whose input schema is the requested one, and have the model call it. Their
+ * parsers turn that call back into a JSON text answer, so clients never see the tool.
+ *
+ * `validate` checks an answer against the requested schema, for callers that ask again when the
+ * model gets it wrong.
+ */
+
+use super::*;
+use serde_json::json;
+
+/// Tool that carries the answer for protocols without a JSON mode
+pub const OUTPUT_TOOL: &str = "json_response";
+
+/// How deep `$ref`s may nest while validating, which stops reference cycles
+const MAX_DEPTH: usize = 64;
+
+/// The request with the output tool declared and called in place of its `response_format`, or
+/// `None` if it has no format
+pub(crate) fn with_output_tool(request: &ChatRequest) -> Option<ChatRequest> {
+    let format = request.response_format.as_ref()?;
+    let mut request = request.clone();
+    let description = match format {
+        ResponseFormat::JsonObject => "Give the final answer as a JSON object".to_string(),
+        ResponseFormat::JsonSchema(format) => match &format.description {
+            Some(description) => format!("Give the final answer ({}): {}", format.name, description),
+            None => format!("Give the final answer ({})", format.name),
+        },
+    };
+    let has_own_tools = !request.tools.is_empty();
+    request.tools.push(ToolDefinition {
+        name: OUTPUT_TOOL.to_string(),
+        description: Some(description),
+        parameters: format.schema(),
+    });
+    request.system.push(format!(
+        "Give your final answer by calling the {} tool with the answer as its input, not as text.",
+        OUTPUT_TOOL
+    ));
+    let thinking = request.reasoning.and_then(|r| r.budget_tokens()).is_some();
+    request.tool_choice = match request.tool_choice.take() {
+        // The client's own forced call comes first; the answer follows on a later turn
+        Some(ToolChoice::Tool(name)) => Some(ToolChoice::Tool(name)),
+        // Claude can't force tool calls while thinking, so the system prompt has to do
+        _ if thinking => Some(ToolChoice::Auto),
+        Some(ToolChoice::Auto | ToolChoice::Required) | None if has_own_tools => Some(ToolChoice::Required),
+        _ => Some(ToolChoice::Tool(OUTPUT_TOOL.to_string())),
+    };
+    request.response_format = None;
+    Some(request)
+}
+
+/// Turn a call of the output tool back into the answer. The tool input is the whole answer, so
+/// text around it is dropped, and the turn ends rather than waiting for a tool result.
+pub(crate) fn unwrap_output_tool(response: &mut ChatResponse) {
+    let Some(position) = response
+        .content
+        .iter()
+        .position(|part| matches!(part, ContentPart::ToolCall(call) if call.name == OUTPUT_TOOL))
+    else {
+        return;
+    };
+    let ContentPart::ToolCall(call) = response.content.remove(position) else { unreachable!() };
+    response.content.retain(|part| !matches!(part, ContentPart::Text(_)));
+    response.content.insert(0, ContentPart::Text(arguments_json(&call.arguments)));
+    let other_calls = response.content.iter().any(|part| matches!(part, ContentPart::ToolCall(_)));
+    if !other_calls && response.stop_reason == Some(StopReason::ToolUse) {
+        response.stop_reason = Some(StopReason::EndTurn);
+    }
+}
+
+/// Rewrites the output tool call of a stream into a text block
+#[derive(Debug, Default)]
+pub(crate) struct OutputToolStream {
+    /// Stream indexes of output tool blocks
+    blocks: Vec<usize>,
+    other_calls: bool,
+}
+
+impl OutputToolStream {
+    pub(crate) fn rewrite(&mut self, event: StreamEvent) -> StreamEvent {
+        match event {
+            StreamEvent::BlockStart { index, block: BlockKind::ToolCall { name, .. } } if name == OUTPUT_TOOL => {
+                self.blocks.push(index);
+                StreamEvent::BlockStart { index, block: BlockKind::Text }
+            }
+            StreamEvent::BlockStart { block: BlockKind::ToolCall { .. }, .. } => {
+                self.other_calls = true;
+                event
+            }
+            StreamEvent::ToolArgumentsDelta { index, partial_json } if self.blocks.contains(&index) => {
+                StreamEvent::TextDelta { index, text: partial_json }
+            }
+            StreamEvent::MessageDelta { stop_reason: Some(StopReason::ToolUse), usage }
+                if !self.blocks.is_empty() && !self.other_calls =>
+            {
+                StreamEvent::MessageDelta { stop_reason: Some(StopReason::EndTurn), usage }
+            }
+            event => event,
+        }
+    }
+}
+
+/// Why an answer doesn't match its format
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidOutput {
+    #[error("the answer is not JSON: {0}")]
+    NotJson(String),
+    #[error("{path}: {message}")]
+    Mismatch { path: String, message: String },
+}
+
+/// Parse an answer and check it against the schema of its format. A Markdown code fence around
+/// the JSON is allowed.
+pub fn validate(format: &ResponseFormat, text: &str) -> Result<Value, InvalidOutput> {
+    let value: Value = serde_json::from_str(strip_fence(text)).map_err(|e| InvalidOutput::NotJson(e.to_string()))?;
+    let schema = format.schema();
+    check(&schema, &schema, &value, "$", 0)?;
+    Ok(value)
+}
+
+/// What to tell the model when its answer didn't match
+pub fn retry_prompt(error: &InvalidOutput) -> String {
+    format!(
+        "Your answer did not match the required JSON schema ({}). Answer again with only JSON that matches it.",
+        error
+    )
+}
+
+fn strip_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(inner) = text.strip_prefix("```").and_then(|t| t.strip_suffix("```")) else {
+        return text;
+    };
+    // Drop the language tag on the opening line
+    inner.split_once('\n').map_or(inner, |(_, body)| body).trim()
+}
+
+fn mismatch(path: &str, message: impl Into<String>) -> InvalidOutput {
+    InvalidOutput::Mismatch { path: path.to_string(), message: message.into() }
+}
+
+/// Check a value against the JSON Schema keywords that describe data shape; annotations and
+/// keywords this doesn't know are ignored
+fn check(root: &Value, schema: &Value, value: &Value, path: &str, depth: usize) -> Result<(), InvalidOutput> {
+    let object = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(mismatch(path, "no value is allowed here")),
+        Value::Object(object) => object,
+        _ => return Ok(()),
+    };
+    if depth > MAX_DEPTH {
+        return Err(mismatch(path, "schema references nest too deeply"));
+    }
+
+    if let Some(reference) = object.get("$ref").and_then(|r| r.as_str()) {
+        let target = reference
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer))
+            .ok_or_else(|| mismatch(path, format!("unresolvable reference {}", reference)))?;
+        check(root, target, value, path, depth + 1)?;
+    }
+
+    if value.is_null() && object.get("nullable").and_then(|n| n.as_bool()) == Some(true) {
+        return Ok(());
+    }
+    let kinds: Vec<&str> = match object.get("type") {
+        Some(Value::String(kind)) => vec![kind.as_str()],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(|k| k.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    if !kinds.is_empty() && !kinds.iter().any(|kind| is_type(value, kind)) {
+        return Err(mismatch(path, format!("expected {}, got {}", kinds.join(" or "), type_name(value))));
+    }
+
+    if let Some(Value::Array(values)) = object.get("enum") {
+        if !values.contains(value) {
+            return Err(mismatch(path, format!("{} is not one of {}", value, json!(values))));
+        }
+    }
+    if let Some(expected) = object.get("const") {
+        if expected != value {
+            return Err(mismatch(path, format!("expected {}", expected)));
+        }
+    }
+
+    if let Some(Value::Array(parts)) = object.get("allOf") {
+        for part in parts {
+            check(root, part, value, path, depth + 1)?;
+        }
+    }
+    for keyword in ["anyOf", "oneOf"] {
+        if let Some(Value::Array(variants)) = object.get(keyword) {
+            let matching = variants.iter().filter(|v| check(root, v, value, path, depth + 1).is_ok()).count();
+            if matching == 0 || (keyword == "oneOf" && matching > 1) {
+                return Err(mismatch(path, format!("matches {} of the {} {} variants", matching, variants.len(), keyword)));
+            }
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            let properties = object.get("properties").and_then(|p| p.as_object());
+            for name in object.get("required").and_then(|r| r.as_array()).into_iter().flatten() {
+                if let Some(name) = name.as_str().filter(|n| !fields.contains_key(*n)) {
+                    return Err(mismatch(path, format!("missing required property {:?}", name)));
+                }
+            }
+            for (name, field) in fields {
+                let field_path = format!("{}.{}", path, name);
+                match (properties.and_then(|p| p.get(name)), object.get("additionalProperties")) {
+                    (Some(property), _) => check(root, property, field, &field_path, depth + 1)?,
+                    (None, Some(Value::Bool(false))) => {
+                        return Err(mismatch(path, format!("unexpected property {:?}", name)));
+                    }
+                    (None, Some(additional)) => check(root, additional, field, &field_path, depth + 1)?,
+                    (None, None) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            check_bounds(object, "minItems", "maxItems", items.len(), "items", path)?;
+            if let Some(schema) = object.get("items").filter(|i| !i.is_array()) {
+                for (i, item) in items.iter().enumerate() {
+                    check(root, schema, item, &format!("{}[{}]", path, i), depth + 1)?;
+                }
+            }
+        }
+        Value::String(text) => {
+            check_bounds(object, "minLength", "maxLength", text.chars().count(), "characters", path)?;
+            let pattern = object.get("pattern").and_then(|p| p.as_str()).and_then(|p| regex::Regex::new(p).ok());
+            if let Some(pattern) = pattern {
+                if !pattern.is_match(text) {
+                    return Err(mismatch(path, format!("does not match the pattern {}", pattern)));
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or(0.0);
+            let limit = |keyword: &str| object.get(keyword).and_then(|l| l.as_f64());
+            let too_small = limit("minimum").is_some_and(|min| number < min)
+                || limit("exclusiveMinimum").is_some_and(|min| number <= min);
+            let too_large = limit("maximum").is_some_and(|max| number > max)
+                || limit("exclusiveMaximum").is_some_and(|max| number >= max);
+            if too_small || too_large {
+                return Err(mismatch(path, format!("{} is out of range", number)));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn check_bounds(
+    schema: &serde_json::Map<String, Value>,
+    min: &str,
+    max: &str,
+    count: usize,
+    unit: &str,
+    path: &str,
+) -> Result<(), InvalidOutput> {
+    let count = count as u64;
+    if let Some(min) = schema.get(min).and_then(|m| m.as_u64()).filter(|min| count < *min) {
+        return Err(mismatch(path, format!("has {} {}, fewer than {}", count, unit, min)));
+    }
+    if let Some(max) = schema.get(max).and_then(|m| m.as_u64()).filter(|max| count > *max) {
+        return Err(mismatch(path, format!("has {} {}, more than {}", count, unit, max)));
+    }
+    Ok(())
+}
+
+fn is_type(value: &Value, kind: &str) -> bool {
+    match kind {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -4,11 +4,27 @@
  * Core library modules for the AI API proxy server.
  */
 
+pub mod adapter;
+pub mod auth;
+pub mod cancellation;
+pub mod cli;
+pub mod client_keys;
 pub mod common;
+pub mod concurrency;
+pub mod config;
 pub mod convert;
 pub mod convert_detailed;
+pub mod http_client;
+pub mod ir;
 pub mod logger;
+pub mod pool_manager;
+pub mod pool_selection;
+pub mod providers;
+pub mod retry;
+pub mod secrets;
+pub mod shutdown;
 pub mod system_prompt;
+pub mod transport;
 
 // Re-export commonly used types
 pub use common::{ModelProtocol, ModelProvider};
@@ -8,37 +8,83 @@
  * License: GPL-3.0
  */
 
+pub mod admin;
+pub mod auth;
+pub mod cancellation;
+pub mod concurrency;
+pub mod cli;
+pub mod client_keys;
+pub mod commands;
 pub mod config;
 pub mod server;
 pub mod common;
 pub mod adapter;
 pub mod convert;
 pub mod convert_detailed;
+pub mod http_client;
+pub mod ir;
 pub mod providers;
 pub mod pool_manager;
+pub mod pool_selection;
+pub mod reload;
+pub mod retry;
+pub mod secrets;
+pub mod shutdown;
 pub mod strategies;
 pub mod system_prompt;
+pub mod transport;
 pub mod logger;
 
 use anyhow::Result;
+use cli::{Cli, Command, ConfigCommand, ConfigArgs, CredsCommand, KeysCommand};
 use tracing::{info, error};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "aiclient2api_rust=info,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let cli = Cli::parse_with_sources();
 
+    let serve_args = match cli.command {
+        None => cli.config,
+        Some(Command::Serve(args)) => args,
+        Some(command) => {
+            // Tool commands print their results on stdout; keep log output off it
+            init_tracing(true);
+            let result = match command {
+                Command::Config(ConfigCommand::Check(args)) => commands::config_check(&args),
+                Command::Models(args) => commands::list_models(&args).await,
+                Command::Creds(CredsCommand::Status(args)) => commands::creds_status(&args),
+                Command::Keys(KeysCommand::Hash(args)) => commands::hash_key(&args),
+                Command::Serve(_) => unreachable!(),
+            };
+            if let Err(e) = result {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    };
+
+    init_tracing(false);
+    serve(serve_args).await
+}
+
+fn init_tracing(to_stderr: bool) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "aiclient2api_rust=info,tower_http=debug".into());
+    let registry = tracing_subscriber::registry().with(filter);
+    if to_stderr {
+        registry.with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr)).init();
+    } else {
+        registry.with(tracing_subscriber::fmt::layer()).init();
+    }
+}
+
+async fn serve(args: ConfigArgs) -> Result<()> {
     info!("Starting AIClient-2-API Rust Server...");
 
     // Load configuration
-    let config = match config::Config::load() {
+    let config = match config::Config::load_from(&args) {
         Ok(cfg) => cfg,
         Err(e) => {
             error!("Failed to load configuration: {}", e);
@@ -59,4 +105,3 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
-
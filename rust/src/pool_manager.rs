@@ -4,67 +4,505 @@
  * Manages pools of API service providers with health checking and load balancing.
  */
 
-use crate::config::ProviderConfig;
-use anyhow::Result;
+use crate::config::{Config, ProviderConfig};
+use crate::pool_selection::{
+    create_strategy, rendezvous_pick, MemberStats, SelectionStrategy, SelectionStrategyKind,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+
+/// Smoothing factor for the per-member latency EWMA
+const LATENCY_EWMA_ALPHA: f64 = 0.3;
 
 pub struct ProviderPoolManager {
     pools: Arc<RwLock<HashMap<String, Vec<ProviderStatus>>>>,
-    round_robin_index: Arc<RwLock<HashMap<String, usize>>>,
+    strategies: Arc<RwLock<HashMap<String, Arc<dyn SelectionStrategy>>>>,
+    configured_strategies: Arc<RwLock<HashMap<String, SelectionStrategyKind>>>,
+    default_strategy: Arc<RwLock<SelectionStrategyKind>>,
     max_error_count: u32,
+    /// Tasks recording the outcome of dropped leases, awaited by `settle_leases`
+    lease_reports: std::sync::Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Pool member as reported by the admin API: persisted fields plus runtime statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberStatus {
+    #[serde(flatten)]
+    pub config: ProviderConfig,
+    pub in_flight: u32,
+    pub latency_ewma_ms: Option<f64>,
+    pub daily_usage: u64,
+    pub remaining_quota: Option<u64>,
 }
 
 struct ProviderStatus {
     config: ProviderConfig,
     is_healthy: bool,
+    in_flight: u32,
+    latency_ewma_ms: Option<f64>,
+    daily_usage: u64,
+    usage_day: NaiveDate,
+}
+
+impl ProviderStatus {
+    fn new(config: ProviderConfig) -> Self {
+        Self {
+            is_healthy: config.is_healthy,
+            config,
+            in_flight: 0,
+            latency_ewma_ms: None,
+            daily_usage: 0,
+            usage_day: Utc::now().date_naive(),
+        }
+    }
+
+    /// Reset the daily counter when the UTC day rolls over
+    fn roll_usage_day(&mut self, today: NaiveDate) {
+        if self.usage_day != today {
+            self.usage_day = today;
+            self.daily_usage = 0;
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.is_healthy && self.remaining_quota() != Some(0)
+    }
+
+    /// Account for a request about to be sent through this member
+    fn mark_selected(&mut self, now: DateTime<Utc>) {
+        self.in_flight += 1;
+        self.daily_usage += 1;
+        self.config.usage_count += 1;
+        self.config.last_used = Some(now.to_rfc3339());
+    }
+
+    /// Whether a new request would start immediately instead of queueing
+    fn has_capacity(&self) -> bool {
+        match self.config.max_concurrency {
+            Some(limit) if limit > 0 => self.in_flight < limit,
+            _ => true,
+        }
+    }
+
+    fn remaining_quota(&self) -> Option<u64> {
+        self.config
+            .daily_quota
+            .map(|quota| quota.saturating_sub(self.daily_usage))
+    }
+
+    /// Persisted view of the member with the live health flag
+    fn current_config(&self) -> ProviderConfig {
+        ProviderConfig {
+            is_healthy: self.is_healthy,
+            ..self.config.clone()
+        }
+    }
+
+    fn stats(&self) -> MemberStats {
+        MemberStats {
+            uuid: self.config.uuid.clone(),
+            weight: self.config.weight,
+            last_used: self
+                .config
+                .last_used
+                .as_deref()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .map(|t| t.with_timezone(&Utc)),
+            in_flight: self.in_flight,
+            latency_ewma_ms: self.latency_ewma_ms,
+            remaining_quota: self.remaining_quota(),
+        }
+    }
 }
 
 impl ProviderPoolManager {
     pub fn new(pools: HashMap<String, Vec<ProviderConfig>>) -> Self {
         let mut status_pools = HashMap::new();
-        
+
         for (provider_type, configs) in pools {
             let statuses: Vec<ProviderStatus> = configs
                 .into_iter()
-                .map(|config| ProviderStatus {
-                    is_healthy: config.is_healthy,
-                    config,
-                })
+                .map(ProviderStatus::new)
                 .collect();
             status_pools.insert(provider_type, statuses);
         }
 
         Self {
             pools: Arc::new(RwLock::new(status_pools)),
-            round_robin_index: Arc::new(RwLock::new(HashMap::new())),
+            strategies: Arc::new(RwLock::new(HashMap::new())),
+            configured_strategies: Arc::new(RwLock::new(HashMap::new())),
+            default_strategy: Arc::new(RwLock::new(SelectionStrategyKind::RoundRobin)),
             max_error_count: 3,
+            lease_reports: std::sync::Mutex::new(Vec::new()),
         }
     }
 
-    pub async fn select_provider(&self, provider_type: &str) -> Option<ProviderConfig> {
-        let pools = self.pools.read().await;
-        let pool = pools.get(provider_type)?;
-        
-        let healthy_providers: Vec<&ProviderStatus> = pool
+    /// Build a pool manager from the loaded configuration, including per-pool strategies
+    pub async fn from_config(config: &Config) -> Result<Self> {
+        let manager = Self::new(config.provider_pools.clone());
+        manager.apply_strategies(config).await?;
+        Ok(manager)
+    }
+
+    /// Apply the strategy settings of a (re)loaded configuration.
+    ///
+    /// Pools whose strategy kind is unchanged keep their strategy instance and its state.
+    pub async fn apply_strategies(&self, config: &Config) -> Result<()> {
+        let default = SelectionStrategyKind::parse(&config.default_pool_strategy)
+            .ok_or_else(|| anyhow::anyhow!("Invalid pool strategy: {}", config.default_pool_strategy))?;
+
+        let mut configured = HashMap::new();
+        for (provider_type, name) in &config.pool_strategies {
+            let kind = SelectionStrategyKind::parse(name)
+                .ok_or_else(|| anyhow::anyhow!("Invalid pool strategy for {}: {}", provider_type, name))?;
+            configured.insert(provider_type.clone(), kind);
+        }
+
+        *self.default_strategy.write().await = default;
+        let mut strategies = self.strategies.write().await;
+        strategies.retain(|provider_type, strategy| {
+            strategy.name() == configured.get(provider_type).unwrap_or(&default).as_str()
+        });
+        *self.configured_strategies.write().await = configured;
+
+        Ok(())
+    }
+
+    /// Replace the selection strategy of one pool
+    pub async fn set_strategy(&self, provider_type: &str, kind: SelectionStrategyKind) {
+        self.configured_strategies
+            .write()
+            .await
+            .insert(provider_type.to_string(), kind);
+        self.strategies
+            .write()
+            .await
+            .insert(provider_type.to_string(), Arc::from(create_strategy(kind)));
+    }
+
+    async fn strategy_for(&self, provider_type: &str) -> Arc<dyn SelectionStrategy> {
+        if let Some(strategy) = self.strategies.read().await.get(provider_type) {
+            return strategy.clone();
+        }
+
+        let kind = match self.configured_strategies.read().await.get(provider_type) {
+            Some(kind) => *kind,
+            None => *self.default_strategy.read().await,
+        };
+        let mut strategies = self.strategies.write().await;
+        strategies
+            .entry(provider_type.to_string())
+            .or_insert_with(|| Arc::from(create_strategy(kind)))
+            .clone()
+    }
+
+    /// Replace the pool members with a freshly loaded set, matching members by `uuid`.
+    ///
+    /// Members that still exist keep their runtime state (health, in-flight count, latency,
+    /// usage and error counters) and take the new static settings (credentials, weight, quota).
+    /// A member whose credentials or HTTP settings changed starts over with its configured
+    /// health, since it may have been disabled for the settings that were just fixed.
+    pub async fn reconcile(&self, new_pools: HashMap<String, Vec<ProviderConfig>>) {
+        let mut pools = self.pools.write().await;
+        let mut old_pools = std::mem::take(&mut *pools);
+
+        for (provider_type, configs) in new_pools {
+            let mut old_members = old_pools.remove(&provider_type).unwrap_or_default();
+            let members = configs
+                .into_iter()
+                .map(|config| {
+                    match old_members.iter().position(|m| m.config.uuid == config.uuid) {
+                        Some(index) => {
+                            let mut status = old_members.swap_remove(index);
+                            if status.config.credentials != config.credentials || status.config.http != config.http {
+                                status.is_healthy = config.is_healthy;
+                            }
+                            status.config = ProviderConfig {
+                                is_healthy: status.is_healthy,
+                                last_used: status.config.last_used.take(),
+                                usage_count: status.config.usage_count,
+                                error_count: status.config.error_count,
+                                last_error_time: status.config.last_error_time.take(),
+                                ..config
+                            };
+                            status
+                        }
+                        None => ProviderStatus::new(config),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            for removed in &old_members {
+                tracing::info!("Removed provider {} ({}) from pool", provider_type, removed.config.uuid);
+            }
+            pools.insert(provider_type, members);
+        }
+
+        for (provider_type, removed) in old_pools {
+            tracing::info!("Removed pool {} ({} members)", provider_type, removed.len());
+        }
+    }
+
+    /// Snapshot of the current pool members
+    pub async fn members(&self, provider_type: &str) -> Vec<ProviderConfig> {
+        self.pools
+            .read()
+            .await
+            .get(provider_type)
+            .map(|pool| pool.iter().map(ProviderStatus::current_config).collect())
+            .unwrap_or_default()
+    }
+
+    /// Snapshot of all pools in the shape of `provider_pools.json`
+    pub async fn snapshot(&self) -> HashMap<String, Vec<ProviderConfig>> {
+        self.pools
+            .read()
+            .await
             .iter()
-            .filter(|p| p.is_healthy)
-            .collect();
+            .map(|(provider_type, pool)| {
+                (
+                    provider_type.clone(),
+                    pool.iter().map(ProviderStatus::current_config).collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// All members with runtime statistics
+    pub async fn status(&self) -> HashMap<String, Vec<MemberStatus>> {
+        let mut pools = self.pools.write().await;
+        let today = Utc::now().date_naive();
+        pools
+            .iter_mut()
+            .map(|(provider_type, pool)| {
+                let members = pool
+                    .iter_mut()
+                    .map(|p| {
+                        p.roll_usage_day(today);
+                        MemberStatus {
+                            config: p.current_config(),
+                            in_flight: p.in_flight,
+                            latency_ewma_ms: p.latency_ewma_ms,
+                            daily_usage: p.daily_usage,
+                            remaining_quota: p.remaining_quota(),
+                        }
+                    })
+                    .collect();
+                (provider_type.clone(), members)
+            })
+            .collect()
+    }
+
+    /// Whether the pool contains a member with this uuid
+    pub async fn contains(&self, provider_type: &str, uuid: &str) -> bool {
+        self.pools
+            .read()
+            .await
+            .get(provider_type)
+            .map(|pool| pool.iter().any(|p| p.config.uuid == uuid))
+            .unwrap_or(false)
+    }
+
+    /// Reset usage and error counters of a member. Returns false if it does not exist.
+    pub async fn reset_counters(&self, provider_type: &str, uuid: &str) -> bool {
+        let mut pools = self.pools.write().await;
+        let Some(provider) = pools
+            .get_mut(provider_type)
+            .and_then(|pool| pool.iter_mut().find(|p| p.config.uuid == uuid))
+        else {
+            return false;
+        };
 
-        if healthy_providers.is_empty() {
+        provider.config.usage_count = 0;
+        provider.config.error_count = 0;
+        provider.config.last_error_time = None;
+        provider.daily_usage = 0;
+        provider.latency_ewma_ms = None;
+        true
+    }
+
+    /// Write the current pools to the pools file
+    pub async fn save_to_file(&self, path: &Path) -> Result<()> {
+        write_pools_file(path, &self.snapshot().await).await
+    }
+
+    /// Whether a non-empty pool exists for the provider type
+    pub async fn has_pool(&self, provider_type: &str) -> bool {
+        self.pools
+            .read()
+            .await
+            .get(provider_type)
+            .map(|pool| !pool.is_empty())
+            .unwrap_or(false)
+    }
+
+    /// Select a healthy pool member using the pool's strategy.
+    ///
+    /// The selected member is counted as in flight until `record_result` is called for it.
+    pub async fn select_provider(&self, provider_type: &str) -> Option<ProviderConfig> {
+        let strategy = self.strategy_for(provider_type).await;
+
+        let mut pools = self.pools.write().await;
+        let pool = pools.get_mut(provider_type)?;
+        let now = Utc::now();
+        let today = now.date_naive();
+
+        let mut candidate_indices = Vec::new();
+        let mut candidates = Vec::new();
+        for (index, provider) in pool.iter_mut().enumerate() {
+            provider.roll_usage_day(today);
+            if provider.is_available() {
+                candidate_indices.push(index);
+                candidates.push(provider.stats());
+            }
+        }
+
+        if candidates.is_empty() {
             return None;
         }
 
-        let mut indices = self.round_robin_index.write().await;
-        let current_index = indices.entry(provider_type.to_string()).or_insert(0);
-        
-        let selected = &healthy_providers[*current_index % healthy_providers.len()];
-        *current_index = (*current_index + 1) % healthy_providers.len();
+        // Prefer members with a free concurrency slot; queue on a busy one only if all are busy
+        let free: Vec<usize> = (0..candidates.len())
+            .filter(|&i| pool[candidate_indices[i]].has_capacity())
+            .collect();
+        if !free.is_empty() && free.len() < candidates.len() {
+            candidate_indices = free.iter().map(|&i| candidate_indices[i]).collect();
+            candidates = free.iter().map(|&i| candidates[i].clone()).collect();
+        }
+
+        let chosen = strategy.select(&candidates)?;
+        let selected = &mut pool[*candidate_indices.get(chosen)?];
+        selected.mark_selected(now);
 
+        tracing::debug!(
+            "Selected provider {} ({}) via {} strategy",
+            provider_type,
+            selected.config.uuid,
+            strategy.name()
+        );
+
+        Some(selected.config.clone())
+    }
+
+    /// Select a pool member for a conversation.
+    ///
+    /// With a session key the member is chosen deterministically so multi-turn conversations keep
+    /// the same credential (and warm upstream caches). If that member is unhealthy or out of
+    /// quota, or there is no key, the pool's normal strategy is used instead.
+    pub async fn select_provider_for_session(
+        &self,
+        provider_type: &str,
+        session_key: Option<&str>,
+    ) -> Option<ProviderConfig> {
+        if let Some(key) = session_key {
+            if let Some(config) = self.select_sticky_provider(provider_type, key).await {
+                return Some(config);
+            }
+            tracing::debug!(
+                "Sticky member for session unavailable in {}, falling back to strategy",
+                provider_type
+            );
+        }
+        self.select_provider(provider_type).await
+    }
+
+    async fn select_sticky_provider(&self, provider_type: &str, session_key: &str) -> Option<ProviderConfig> {
+        let mut pools = self.pools.write().await;
+        let pool = pools.get_mut(provider_type)?;
+        let now = Utc::now();
+
+        let index = rendezvous_pick(session_key, pool.iter().map(|p| p.config.uuid.as_str()))?;
+        let selected = &mut pool[index];
+        selected.roll_usage_day(now.date_naive());
+        if !selected.is_available() {
+            return None;
+        }
+
+        selected.mark_selected(now);
+        tracing::debug!("Selected sticky provider {} ({})", provider_type, selected.config.uuid);
         Some(selected.config.clone())
     }
 
+    /// Track a selected member until the call finishes; the lease records the outcome when dropped.
+    pub fn lease(self: &Arc<Self>, provider_type: &str, uuid: &str) -> PoolLease {
+        PoolLease {
+            manager: self.clone(),
+            provider_type: provider_type.to_string(),
+            uuid: uuid.to_string(),
+            started: std::time::Instant::now(),
+            outcome: None,
+        }
+    }
+
+    /// Wait until the outcomes of all dropped leases are recorded, e.g. before saving the pools
+    /// on shutdown
+    pub async fn settle_leases(&self) {
+        loop {
+            let pending = std::mem::take(&mut *self.lease_reports.lock().unwrap_or_else(|e| e.into_inner()));
+            if pending.is_empty() {
+                return;
+            }
+            for report in pending {
+                let _ = report.await;
+            }
+        }
+    }
+
+    /// Release an in-flight slot without recording a result (e.g. the call never happened)
+    pub async fn release(&self, provider_type: &str, uuid: &str) {
+        let mut pools = self.pools.write().await;
+        if let Some(provider) = pools
+            .get_mut(provider_type)
+            .and_then(|pool| pool.iter_mut().find(|p| p.config.uuid == uuid))
+        {
+            provider.in_flight = provider.in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Record the outcome of a call made with a member returned by `select_provider`.
+    ///
+    /// Successful calls feed the latency average and reset the error count; a member is marked
+    /// unhealthy after `max_error_count` consecutive failures.
+    pub async fn record_result(&self, provider_type: &str, uuid: &str, latency: Duration, success: bool) {
+        let mut pools = self.pools.write().await;
+        let Some(provider) = pools
+            .get_mut(provider_type)
+            .and_then(|pool| pool.iter_mut().find(|p| p.config.uuid == uuid))
+        else {
+            return;
+        };
+
+        provider.in_flight = provider.in_flight.saturating_sub(1);
+
+        if success {
+            let sample = latency.as_secs_f64() * 1000.0;
+            provider.latency_ewma_ms = Some(match provider.latency_ewma_ms {
+                Some(avg) => LATENCY_EWMA_ALPHA * sample + (1.0 - LATENCY_EWMA_ALPHA) * avg,
+                None => sample,
+            });
+            provider.config.error_count = 0;
+        } else {
+            provider.config.error_count += 1;
+            provider.config.last_error_time = Some(Utc::now().to_rfc3339());
+            if provider.config.error_count >= self.max_error_count && provider.is_healthy {
+                provider.is_healthy = false;
+                tracing::warn!(
+                    "Marked provider {} ({}) as unhealthy after {} errors",
+                    provider_type,
+                    uuid,
+                    provider.config.error_count
+                );
+            }
+        }
+    }
+
     pub async fn mark_provider_unhealthy(&self, provider_type: &str, uuid: &str) {
         let mut pools = self.pools.write().await;
         if let Some(pool) = pools.get_mut(provider_type) {
@@ -105,3 +543,64 @@ impl ProviderPoolManager {
     }
 }
 
+/// In-flight marker for a pool member returned by `select_provider`.
+///
+/// Dropping the lease reports the call to the pool manager: with an outcome set via `succeed`
+/// or `fail` it feeds health and latency tracking, otherwise it only frees the in-flight slot.
+pub struct PoolLease {
+    manager: Arc<ProviderPoolManager>,
+    provider_type: String,
+    uuid: String,
+    started: std::time::Instant,
+    outcome: Option<bool>,
+}
+
+impl PoolLease {
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    pub fn succeed(&mut self) {
+        self.outcome = Some(true);
+    }
+
+    pub fn fail(&mut self) {
+        self.outcome = Some(false);
+    }
+}
+
+impl Drop for PoolLease {
+    fn drop(&mut self) {
+        let manager = self.manager.clone();
+        let provider_type = std::mem::take(&mut self.provider_type);
+        let uuid = std::mem::take(&mut self.uuid);
+        let latency = self.started.elapsed();
+        let outcome = self.outcome;
+
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let reporter = manager.clone();
+            let report = handle.spawn(async move {
+                match outcome {
+                    Some(success) => reporter.record_result(&provider_type, &uuid, latency, success).await,
+                    None => reporter.release(&provider_type, &uuid).await,
+                }
+            });
+            let mut reports = manager.lease_reports.lock().unwrap_or_else(|e| e.into_inner());
+            reports.retain(|report| !report.is_finished());
+            reports.push(report);
+        }
+    }
+}
+
+/// Write pools in the `provider_pools.json` format, atomically via a temporary file
+pub async fn write_pools_file(path: &Path, pools: &HashMap<String, Vec<ProviderConfig>>) -> Result<()> {
+    let content = serde_json::to_string_pretty(pools)?;
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, content)
+        .await
+        .context("Failed to write provider pools file")?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .context("Failed to replace provider pools file")?;
+    Ok(())
+}
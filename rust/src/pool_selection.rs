@@ -0,0 +1,288 @@
+/*!
+ * Pool Selection Strategies
+ *
+ * Pluggable strategies that decide which healthy pool member serves the next request.
+ */
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Runtime view of a pool member handed to selection strategies
+#[derive(Debug, Clone)]
+pub struct MemberStats {
+    pub uuid: String,
+    pub weight: u32,
+    pub last_used: Option<DateTime<Utc>>,
+    pub in_flight: u32,
+    pub latency_ewma_ms: Option<f64>,
+    /// Remaining requests in today's budget (`None` means unlimited)
+    pub remaining_quota: Option<u64>,
+}
+
+/// Strategy interface used by `ProviderPoolManager::select_provider`
+pub trait SelectionStrategy: Send + Sync {
+    /// Strategy identifier as used in configuration
+    fn name(&self) -> &'static str;
+
+    /// Pick one of the candidates and return its index, or `None` if none is usable.
+    /// Candidates are always healthy and never empty.
+    fn select(&self, candidates: &[MemberStats]) -> Option<usize>;
+}
+
+/// Known selection strategies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionStrategyKind {
+    RoundRobin,
+    Weighted,
+    LeastRecentlyUsed,
+    LeastInFlight,
+    LowestLatency,
+    QuotaAware,
+}
+
+impl SelectionStrategyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => "round-robin",
+            Self::Weighted => "weighted",
+            Self::LeastRecentlyUsed => "least-recently-used",
+            Self::LeastInFlight => "least-in-flight",
+            Self::LowestLatency => "lowest-latency",
+            Self::QuotaAware => "quota-aware",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "round-robin" => Some(Self::RoundRobin),
+            "weighted" => Some(Self::Weighted),
+            "least-recently-used" | "lru" => Some(Self::LeastRecentlyUsed),
+            "least-in-flight" => Some(Self::LeastInFlight),
+            "lowest-latency" => Some(Self::LowestLatency),
+            "quota-aware" => Some(Self::QuotaAware),
+            _ => None,
+        }
+    }
+}
+
+/// Create a fresh strategy instance. Each pool gets its own so stateful strategies don't interfere.
+pub fn create_strategy(kind: SelectionStrategyKind) -> Box<dyn SelectionStrategy> {
+    match kind {
+        SelectionStrategyKind::RoundRobin => Box::new(RoundRobinStrategy::default()),
+        SelectionStrategyKind::Weighted => Box::new(WeightedStrategy::default()),
+        SelectionStrategyKind::LeastRecentlyUsed => Box::new(LeastRecentlyUsedStrategy),
+        SelectionStrategyKind::LeastInFlight => Box::new(LeastInFlightStrategy),
+        SelectionStrategyKind::LowestLatency => Box::new(LowestLatencyStrategy),
+        SelectionStrategyKind::QuotaAware => Box::new(QuotaAwareStrategy),
+    }
+}
+
+/// Cycles through the healthy members in order
+#[derive(Default)]
+pub struct RoundRobinStrategy {
+    next: AtomicUsize,
+}
+
+impl SelectionStrategy for RoundRobinStrategy {
+    fn name(&self) -> &'static str {
+        SelectionStrategyKind::RoundRobin.as_str()
+    }
+
+    fn select(&self, candidates: &[MemberStats]) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(self.next.fetch_add(1, Ordering::Relaxed) % candidates.len())
+    }
+}
+
+/// Smooth weighted round-robin (as in nginx): members receive traffic proportional to `weight`
+/// without bursts towards the heaviest member.
+#[derive(Default)]
+pub struct WeightedStrategy {
+    current: Mutex<HashMap<String, i64>>,
+}
+
+impl SelectionStrategy for WeightedStrategy {
+    fn name(&self) -> &'static str {
+        SelectionStrategyKind::Weighted.as_str()
+    }
+
+    fn select(&self, candidates: &[MemberStats]) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        // Forget members that left the pool
+        current.retain(|uuid, _| candidates.iter().any(|c| &c.uuid == uuid));
+
+        let total: i64 = candidates.iter().map(|c| c.weight as i64).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut best: Option<(usize, i64)> = None;
+        for (index, candidate) in candidates.iter().enumerate() {
+            let value = current.entry(candidate.uuid.clone()).or_insert(0);
+            *value += candidate.weight as i64;
+            if candidate.weight > 0 && best.map(|(_, v)| *value > v).unwrap_or(true) {
+                best = Some((index, *value));
+            }
+        }
+
+        let (index, _) = best?;
+        if let Some(value) = current.get_mut(&candidates[index].uuid) {
+            *value -= total;
+        }
+        Some(index)
+    }
+}
+
+/// Picks the member that has gone unused the longest (never-used members first)
+pub struct LeastRecentlyUsedStrategy;
+
+impl SelectionStrategy for LeastRecentlyUsedStrategy {
+    fn name(&self) -> &'static str {
+        SelectionStrategyKind::LeastRecentlyUsed.as_str()
+    }
+
+    fn select(&self, candidates: &[MemberStats]) -> Option<usize> {
+        least_recently_used(candidates, 0..candidates.len())
+    }
+}
+
+/// Picks the member with the fewest requests currently in flight
+pub struct LeastInFlightStrategy;
+
+impl SelectionStrategy for LeastInFlightStrategy {
+    fn name(&self) -> &'static str {
+        SelectionStrategyKind::LeastInFlight.as_str()
+    }
+
+    fn select(&self, candidates: &[MemberStats]) -> Option<usize> {
+        let min = candidates.iter().map(|c| c.in_flight).min()?;
+        least_recently_used(
+            candidates,
+            (0..candidates.len()).filter(|&i| candidates[i].in_flight == min),
+        )
+    }
+}
+
+/// Picks the member with the lowest observed latency (EWMA). Members without samples are
+/// tried first so every member gets measured.
+pub struct LowestLatencyStrategy;
+
+impl SelectionStrategy for LowestLatencyStrategy {
+    fn name(&self) -> &'static str {
+        SelectionStrategyKind::LowestLatency.as_str()
+    }
+
+    fn select(&self, candidates: &[MemberStats]) -> Option<usize> {
+        let unmeasured: Vec<usize> = (0..candidates.len())
+            .filter(|&i| candidates[i].latency_ewma_ms.is_none())
+            .collect();
+        if !unmeasured.is_empty() {
+            return least_recently_used(candidates, unmeasured.into_iter());
+        }
+
+        candidates
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let a = a.latency_ewma_ms.unwrap_or(f64::MAX);
+                let b = b.latency_ewma_ms.unwrap_or(f64::MAX);
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|(i, _)| i)
+    }
+}
+
+/// Picks the member with the most remaining daily budget; unlimited members count as the largest
+/// budget. Exhausted members are never picked.
+pub struct QuotaAwareStrategy;
+
+impl SelectionStrategy for QuotaAwareStrategy {
+    fn name(&self) -> &'static str {
+        SelectionStrategyKind::QuotaAware.as_str()
+    }
+
+    fn select(&self, candidates: &[MemberStats]) -> Option<usize> {
+        let remaining = |c: &MemberStats| c.remaining_quota.unwrap_or(u64::MAX);
+        let max = candidates.iter().map(remaining).max()?;
+        if max == 0 {
+            return None;
+        }
+        least_recently_used(
+            candidates,
+            (0..candidates.len()).filter(|&i| remaining(&candidates[i]) == max),
+        )
+    }
+}
+
+/// Tie-breaker shared by several strategies: oldest `last_used` wins, first index on equal times
+fn least_recently_used(
+    candidates: &[MemberStats],
+    indices: impl Iterator<Item = usize>,
+) -> Option<usize> {
+    indices.min_by_key(|&i| candidates[i].last_used)
+}
+
+/// Derive a session key for sticky pool selection.
+///
+/// Sources in order of preference: the client-supplied session header, the request's `user`
+/// field (OpenAI) or `metadata.user_id` (Claude), and finally a hash of the system prompt and
+/// first user message, which stays constant across the turns of one conversation. OpenAI puts
+/// the system prompt in `system`/`developer` messages; those count as the system prompt, so
+/// conversations that only share it still get keys of their own.
+pub fn derive_session_key(header_value: Option<&str>, request: &Value) -> Option<String> {
+    if let Some(key) = header_value.map(str::trim).filter(|k| !k.is_empty()) {
+        return Some(format!("header:{}", key));
+    }
+
+    let user = request
+        .get("user")
+        .or_else(|| request.get("metadata").and_then(|m| m.get("user_id")))
+        .and_then(|u| u.as_str())
+        .filter(|u| !u.is_empty());
+    if let Some(user) = user {
+        return Some(format!("user:{}", user));
+    }
+
+    let messages = request
+        .get("messages")
+        .or_else(|| request.get("contents"))
+        .and_then(|m| m.as_array())?;
+    let role = |message: &Value| message.get("role").and_then(|r| r.as_str()).map(str::to_string);
+    let is_system = |message: &&Value| matches!(role(message).as_deref(), Some("system" | "developer"));
+    // Gemini contents may leave the role out for user turns
+    let first_user_message = messages
+        .iter()
+        .find(|message| matches!(role(message).as_deref(), Some("user") | None))?;
+    let system_messages: Vec<Value> = messages.iter().filter(is_system).cloned().collect();
+    let system = request
+        .get("system")
+        .or_else(|| request.get("systemInstruction"))
+        .or_else(|| request.get("system_instruction"))
+        .cloned()
+        .or_else(|| (!system_messages.is_empty()).then_some(Value::Array(system_messages)))
+        .unwrap_or(Value::Null);
+
+    let digest = md5::compute(format!("{}\n{}", system, first_user_message));
+    Some(format!("prefix:{:x}", digest))
+}
+
+/// Rendezvous (highest random weight) hashing: the same key always maps to the same member,
+/// and removing a member only moves the sessions that were pinned to it.
+pub fn rendezvous_pick<'a>(session_key: &str, uuids: impl Iterator<Item = &'a str>) -> Option<usize> {
+    uuids
+        .enumerate()
+        .max_by_key(|(_, uuid)| {
+            let digest = md5::compute(format!("{}|{}", session_key, uuid));
+            u64::from_be_bytes(digest.0[..8].try_into().unwrap_or_default())
+        })
+        .map(|(index, _)| index)
+}
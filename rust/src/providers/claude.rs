@@ -4,10 +4,11 @@
 
 use crate::adapter::ApiServiceAdapter;
 use crate::common::*;
+use crate::http_client::HttpClientConfig;
+use crate::retry::RetryPolicy;
 use anyhow::Result;
 use async_stream::stream;
 use async_trait::async_trait;
-use futures::future::BoxFuture;
 use futures::Stream;
 use reqwest::Client;
 use serde_json::json;
@@ -30,19 +31,17 @@ pub struct ClaudeApiService {
     client: Client,
     api_key: String,
     base_url: String,
-    max_retries: u32,
-    base_delay: u64,
+    retry: RetryPolicy,
 }
 
 impl ClaudeApiService {
-    pub fn new(api_key: String, base_url: Option<String>, max_retries: u32, base_delay: u64) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(60))  // 减少到60秒
-            .connect_timeout(std::time::Duration::from_secs(10))
-            .pool_idle_timeout(std::time::Duration::from_secs(90))
-            .pool_max_idle_per_host(10)
-            .tcp_nodelay(true)
-            .build()?;
+    pub fn new(
+        api_key: String,
+        base_url: Option<String>,
+        retry: RetryPolicy,
+        http: &HttpClientConfig,
+    ) -> Result<Self> {
+        let client = http.build_client()?;
 
         let base_url = base_url.unwrap_or_else(|| "https://api.anthropic.com".to_string());
 
@@ -50,47 +49,25 @@ impl ClaudeApiService {
             client,
             api_key,
             base_url,
-            max_retries,
-            base_delay,
+            retry,
         })
     }
 
-    fn call_api_with_retry<'a>(
-        &'a self,
-        endpoint: &'a str,
-        body: serde_json::Value,
-        retry_count: u32,
-    ) -> BoxFuture<'a, Result<serde_json::Value>> {
-        Box::pin(async move {
+    /// POST to `endpoint`, retrying per the retry policy
+    async fn send(&self, endpoint: &str, body: &serde_json::Value) -> Result<reqwest::Response> {
         let url = format!("{}{}", self.base_url, endpoint);
-
-        let response = self.client
-            .post(&url)
-            .header("x-api-key", &self.api_key)
-            .header("Content-Type", "application/json")
-            .header("anthropic-version", "2023-06-01")
-            .json(&body)
-            .send()
-            .await?;
-
-        let status = response.status();
-
-        if status.is_success() {
-            let result: serde_json::Value = response.json().await?;
-            return Ok(result);
-        }
-
-        // Handle retryable errors
-        if (status.as_u16() == 429 || status.is_server_error()) && retry_count < self.max_retries {
-            let delay = self.base_delay * 2_u64.pow(retry_count);
-            warn!("Request failed with status {}, retrying in {}ms...", status, delay);
-            tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
-            return self.call_api_with_retry(endpoint, body, retry_count + 1).await;
-        }
-
-        let error_text = response.text().await?;
-        anyhow::bail!("API call failed ({}): {}", status, error_text)
-        })
+        self.retry
+            .send(|| async {
+                Ok(self.client
+                    .post(&url)
+                    .header("x-api-key", &self.api_key)
+                    .header("Content-Type", "application/json")
+                    .header("anthropic-version", "2023-06-01")
+                    .json(body)
+                    .send()
+                    .await?)
+            })
+            .await
     }
 }
 
@@ -102,7 +79,8 @@ impl ApiServiceAdapter for ClaudeApiService {
         request_body: serde_json::Value,
     ) -> Result<serde_json::Value> {
         debug!("Claude generate_content");
-        self.call_api_with_retry("/v1/messages", request_body, 0).await
+        let response = self.send("/v1/messages", &request_body).await?;
+        Ok(response.json().await?)
     }
 
     async fn generate_content_stream(
@@ -117,21 +95,7 @@ impl ApiServiceAdapter for ClaudeApiService {
             obj.insert("stream".to_string(), json!(true));
         }
 
-        let url = format!("{}/v1/messages", self.base_url);
-        let response = self.client
-            .post(&url)
-            .header("x-api-key", &self.api_key)
-            .header("Content-Type", "application/json")
-            .header("anthropic-version", "2023-06-01")
-            .json(&request_body)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("Stream API call failed: {}", error_text);
-        }
-
+        let response = self.send("/v1/messages", &request_body).await?;
         let byte_stream = response.bytes_stream();
         
         let stream = stream! {
@@ -149,8 +113,8 @@ impl ApiServiceAdapter for ClaudeApiService {
                             
                             let mut data = String::new();
                             for line in event_block.lines() {
-                                if line.starts_with("data: ") {
-                                    data = line[6..].to_string();
+                                if let Some(rest) = line.strip_prefix("data: ") {
+                                    data = rest.to_string();
                                     break;
                                 }
                             }
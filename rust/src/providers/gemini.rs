@@ -4,12 +4,13 @@
 
 use crate::adapter::ApiServiceAdapter;
 use crate::common::*;
+use crate::http_client::HttpClientConfig;
+use crate::retry::RetryPolicy;
 use anyhow::{Context, Result};
 use async_stream::stream;
 use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine as _};
 use chrono::Utc;
-use futures::future::BoxFuture;
 use futures::Stream;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
@@ -42,8 +43,7 @@ pub struct GeminiApiService {
     credentials_path: PathBuf,
     project_id: Arc<RwLock<Option<String>>>,
     available_models: Vec<String>,
-    max_retries: u32,
-    base_delay: u64,
+    retry: RetryPolicy,
 }
 
 impl GeminiApiService {
@@ -51,16 +51,10 @@ impl GeminiApiService {
         oauth_creds_base64: Option<String>,
         oauth_creds_file: Option<PathBuf>,
         project_id: Option<String>,
-        max_retries: u32,
-        base_delay: u64,
+        retry: RetryPolicy,
+        http: &HttpClientConfig,
     ) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(60))  // 减少到60秒
-            .connect_timeout(std::time::Duration::from_secs(10))
-            .pool_idle_timeout(std::time::Duration::from_secs(90))
-            .pool_max_idle_per_host(10)
-            .tcp_nodelay(true)
-            .build()?;
+        let client = http.build_client()?;
 
         // Determine credentials path
         let credentials_path = oauth_creds_file.unwrap_or_else(|| {
@@ -77,14 +71,13 @@ impl GeminiApiService {
             Self::load_credentials_from_file(&credentials_path).await?
         };
 
-        let mut service = Self {
+        let service = Self {
             client,
             credentials: Arc::new(RwLock::new(credentials)),
             credentials_path,
             project_id: Arc::new(RwLock::new(project_id)),
             available_models: GEMINI_MODELS.iter().map(|s| s.to_string()).collect(),
-            max_retries,
-            base_delay,
+            retry,
         };
 
         // Discover project ID if not provided
@@ -231,17 +224,8 @@ impl GeminiApiService {
         Ok(project_id.to_string())
     }
 
+    /// Call a Code Assist method, retrying per the retry policy
     async fn call_api(&self, method: &str, body: serde_json::Value) -> Result<serde_json::Value> {
-        self.call_api_with_retry(method, body, 0).await
-    }
-
-    fn call_api_with_retry<'a>(
-        &'a self,
-        method: &'a str,
-        body: serde_json::Value,
-        retry_count: u32,
-    ) -> BoxFuture<'a, Result<serde_json::Value>> {
-        Box::pin(async move {
         // Check and refresh token if needed
         {
             let creds = self.credentials.read().await;
@@ -260,33 +244,19 @@ impl GeminiApiService {
             CODE_ASSIST_ENDPOINT, CODE_ASSIST_API_VERSION, project_id, method
         );
 
-        let creds = self.credentials.read().await;
-        let response = self.client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", creds.access_token))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
+        let response = self.retry
+            .send(|| async {
+                let access_token = self.credentials.read().await.access_token.clone();
+                Ok(self.client
+                    .post(&url)
+                    .header("Authorization", format!("Bearer {}", access_token))
+                    .header("Content-Type", "application/json")
+                    .json(&body)
+                    .send()
+                    .await?)
+            })
             .await?;
-
-        let status = response.status();
-        
-        if status.is_success() {
-            let result: serde_json::Value = response.json().await?;
-            return Ok(result);
-        }
-
-        // Handle retryable errors
-        if (status.as_u16() == 429 || status.is_server_error()) && retry_count < self.max_retries {
-            let delay = self.base_delay * 2_u64.pow(retry_count);
-            warn!("Request failed with status {}, retrying in {}ms...", status, delay);
-            tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
-            return self.call_api_with_retry(method, body, retry_count + 1).await;
-        }
-
-        let error_text = response.text().await?;
-        anyhow::bail!("API call failed ({}): {}", status, error_text)
-        })
+        Ok(response.json().await?)
     }
 }
 
@@ -359,5 +329,9 @@ impl ApiServiceAdapter for GeminiApiService {
         }
         Ok(())
     }
+
+    async fn refresh_token_forced(&self) -> Result<()> {
+        self.refresh_access_token().await
+    }
 }
 
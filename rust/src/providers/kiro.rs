@@ -7,13 +7,14 @@
 
 use crate::adapter::ApiServiceAdapter;
 use crate::common::*;
+use crate::http_client::HttpClientConfig;
+use crate::ir::{self, StreamEmitter};
+use crate::retry::{upstream_status, RetryPolicy};
 use anyhow::{Context, Result};
-use async_stream::stream;
 use async_trait::async_trait;
 use base64::Engine;
-use futures::future::BoxFuture;
 use futures::Stream;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::path::PathBuf;
@@ -24,8 +25,7 @@ use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 use lru::LruCache;
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+use ring::digest::{digest, SHA256};
 
 const CLAUDE_MODELS: &[&str] = &[
     "claude-sonnet-4-20250514",
@@ -38,24 +38,6 @@ const CLAUDE_MODELS: &[&str] = &[
     "amazonq-claude-3-7-sonnet-20250219",
 ];
 
-const CHAT_TRIGGER_TYPE_MANUAL: &str = "MANUAL";
-const ORIGIN_AI_EDITOR: &str = "AI_EDITOR";
-
-// Model mapping from Claude to CodeWhisperer format
-fn map_model_to_codewhisperer(model: &str) -> &'static str {
-    match model {
-        "claude-sonnet-4-20250514" => "CLAUDE_SONNET_4_20250514_V1_0",
-        "claude-sonnet-4-5-20250929" => "CLAUDE_SONNET_4_5_20250929_V1_0",
-        "claude-3-7-sonnet-20250219" => "CLAUDE_3_7_SONNET_20250219_V1_0",
-        "claude-3-5-sonnet-20241022" => "CLAUDE_3_5_SONNET_20241022_V1_0",
-        "claude-3-5-haiku-20241022" => "CLAUDE_SONNET_4_5_20250929_V1_0",
-        "amazonq-claude-sonnet-4-20250514" => "CLAUDE_SONNET_4_20250514_V1_0",
-        "amazonq-claude-sonnet-4-5-20250929" => "CLAUDE_SONNET_4_5_20250929_V1_0",
-        "amazonq-claude-3-7-sonnet-20250219" => "CLAUDE_3_7_SONNET_20250219_V1_0",
-        _ => "CLAUDE_SONNET_4_5_20250929_V1_0", // Default (changed to 4.5)
-    }
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct KiroOAuthCredentials {
@@ -76,26 +58,21 @@ pub struct KiroApiService {
     client: Client,
     credentials: Arc<RwLock<KiroOAuthCredentials>>,
     credentials_path: PathBuf,
-    max_retries: u32,
-    base_delay: u64,
+    param_policy: ir::ParamPolicy,
+    retry: RetryPolicy,
     region: String,
-    request_cache: Arc<RwLock<lru::LruCache<u64, serde_json::Value>>>,
+    request_cache: Arc<RwLock<lru::LruCache<[u8; 32], serde_json::Value>>>,
 }
 
 impl KiroApiService {
     pub async fn new(
         oauth_creds_base64: Option<String>,
         oauth_creds_file: Option<PathBuf>,
-        max_retries: u32,
-        base_delay: u64,
+        param_policy: ir::ParamPolicy,
+        retry: RetryPolicy,
+        http: &HttpClientConfig,
     ) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))  // 减少到30秒
-            .connect_timeout(std::time::Duration::from_secs(5))  // 连接超时5秒
-            .pool_idle_timeout(std::time::Duration::from_secs(90))
-            .pool_max_idle_per_host(10)
-            .tcp_nodelay(true)  // 禁用 Nagle 算法，减少延迟
-            .build()?;
+        let client = http.build_client()?;
 
         let credentials_path = oauth_creds_file.unwrap_or_else(|| {
             dirs::home_dir()
@@ -133,8 +110,8 @@ impl KiroApiService {
             client,
             credentials: Arc::new(RwLock::new(credentials)),
             credentials_path,
-            max_retries,
-            base_delay,
+            param_policy,
+            retry,
             region,
             request_cache,
         })
@@ -153,358 +130,50 @@ impl KiroApiService {
         }
     }
     
-    /// Calculate hash for request caching
-    fn calculate_request_hash(&self, request: &serde_json::Value) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        request.to_string().hash(&mut hasher);
-        hasher.finish()
+    /// SHA-256 of the request, so different requests can't share a cache entry
+    fn calculate_request_hash(&self, request: &serde_json::Value) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(digest(&SHA256, request.to_string().as_bytes()).as_ref());
+        hash
     }
     
     /// Convert Claude messages format to CodeWhisperer request format with caching
     async fn build_codewhisperer_request(&self, claude_request: &serde_json::Value) -> Result<serde_json::Value> {
         // 检查缓存
         let hash = self.calculate_request_hash(claude_request);
-        {
-            let cache = self.request_cache.read().await;
-            if let Some(cached) = cache.peek(&hash) {
+        let cached = self.request_cache.read().await.peek(&hash).cloned();
+        let mut codewhisperer_request = match cached {
+            Some(cached) => {
                 debug!("Using cached request conversion");
-                return Ok(cached.clone());
-            }
-        }
-        
-        // 如果缓存未命中，执行转换
-        let result = self.build_codewhisperer_request_uncached(claude_request).await?;
-        
-        // 缓存结果
-        {
-            let mut cache = self.request_cache.write().await;
-            cache.put(hash, result.clone());
-        }
-        
-        Ok(result)
-    }
-    
-    /// Convert Claude messages format to CodeWhisperer request format (uncached)
-    async fn build_codewhisperer_request_uncached(&self, claude_request: &serde_json::Value) -> Result<serde_json::Value> {
-        let messages = claude_request.get("messages")
-            .and_then(|v| v.as_array())
-            .ok_or_else(|| anyhow::anyhow!("Missing or invalid messages field"))?;
-
-        if messages.is_empty() {
-            anyhow::bail!("No user messages found");
-        }
-
-        let model = claude_request.get("model")
-            .and_then(|v| v.as_str())
-            .unwrap_or("claude-sonnet-4-20250514");
-
-        let codewhisperer_model = map_model_to_codewhisperer(model);
-        let conversation_id = Uuid::new_v4().to_string();
-
-        let tools_context = self.build_tools_context(claude_request.get("tools"));
-        let system_prompt = claude_request.get("system")
-            .map(|v| self.extract_content_text(v))
-            .unwrap_or_default();
-
-        let mut history = Vec::new();
-        let mut start_index = 0;
-
-        // Handle system prompt
-        if !system_prompt.is_empty() {
-            if messages.first().and_then(|m| m.get("role").and_then(|r| r.as_str())) == Some("user") {
-                let first_user_content = self.extract_content_text(&messages[0]);
-                history.push(json!({
-                    "userInputMessage": {
-                        "content": format!("{}\n\n{}", system_prompt, first_user_content),
-                        "modelId": codewhisperer_model,
-                        "origin": ORIGIN_AI_EDITOR,
-                    }
-                }));
-                start_index = 1;
-            } else {
-                history.push(json!({
-                    "userInputMessage": {
-                        "content": system_prompt,
-                        "modelId": codewhisperer_model,
-                        "origin": ORIGIN_AI_EDITOR,
-                    }
-                }));
-            }
-        }
-
-        // Process all but the last message into history
-        for i in start_index..messages.len().saturating_sub(1) {
-            let message = &messages[i];
-            let role = message.get("role").and_then(|r| r.as_str()).unwrap_or("");
-
-            match role {
-                "user" => {
-                    let (content, images, tool_results, tools_used) = self.extract_user_message_details(message);
-                    let mut user_msg = json!({
-                        "content": content,
-                        "modelId": codewhisperer_model,
-                        "origin": ORIGIN_AI_EDITOR,
-                        "userInputMessageContext": {}
-                    });
-
-                    if !images.is_empty() {
-                        user_msg["images"] = json!(images);
-                    }
-
-                    if let Some(ctx) = user_msg.get_mut("userInputMessageContext") {
-                        if !tool_results.is_empty() {
-                            ctx["toolResults"] = json!(tool_results);
-                        }
-                        ctx["tools"] = if !tools_context.is_empty() { json!(tools_context) } else { serde_json::Value::Null };
-                        ctx["toolsUsed"] = if !tools_used.is_empty() { json!(tools_used) } else { serde_json::Value::Null };
-                    }
-
-                    history.push(json!({ "userInputMessage": user_msg }));
-                }
-                "assistant" => {
-                    let (content, tool_uses, tool_results) = self.extract_assistant_message_details(message);
-                    let mut assistant_msg = json!({
-                        "content": content,
-                        "toolUses": if tool_uses.is_empty() { serde_json::Value::Null } else { json!(tool_uses) },
-                        "toolResults": if tool_results.is_empty() { serde_json::Value::Null } else { json!(tool_results) }
-                    });
-
-                    history.push(json!({
-                        "assistantResponseMessage": assistant_msg
-                    }));
-                }
-                _ => {}
+                cached
             }
-        }
-
-        // Build current message from the last message in the array
-        let current_message = messages.last().unwrap();
-        let role = current_message.get("role").and_then(|r| r.as_str()).unwrap_or("");
-        let mut conversation_state = json!({
-            "chatTriggerType": CHAT_TRIGGER_TYPE_MANUAL,
-            "conversationId": conversation_id,
-            "history": history,
-            "currentMessage": {}
-        });
-
-        match role {
-            "user" => {
-                let (content, images, tool_results, tools_used) = self.extract_user_message_details(current_message);
-                let mut current = json!({
-                    "content": if content.is_empty() { "Continue".to_string() } else { content },
-                    "modelId": codewhisperer_model,
-                    "origin": ORIGIN_AI_EDITOR,
-                    "userInputMessageContext": {}
-                });
-
-                if !images.is_empty() {
-                    current["images"] = json!(images);
-                }
-
-                if let Some(ctx) = current.get_mut("userInputMessageContext") {
-                    if !tool_results.is_empty() {
-                        ctx["toolResults"] = json!(tool_results);
-                    }
-                    ctx["tools"] = if !tools_context.is_empty() { json!(tools_context) } else { serde_json::Value::Null };
-                    ctx["toolsUsed"] = if !tools_used.is_empty() { json!(tools_used) } else { serde_json::Value::Null };
-                }
-
-                conversation_state["currentMessage"]["userInputMessage"] = current;
-            }
-            "assistant" => {
-                let (content, tool_uses, tool_results) = self.extract_assistant_message_details(current_message);
-                conversation_state["currentMessage"]["assistantResponseMessage"] = json!({
-                    "content": if content.is_empty() { "Continue".to_string() } else { content },
-                    "toolUses": if tool_uses.is_empty() { serde_json::Value::Null } else { json!(tool_uses) },
-                    "toolResults": if tool_results.is_empty() { serde_json::Value::Null } else { json!(tool_results) }
-                });
-            }
-            _ => {}
-        }
-
-        let mut request = json!({
-            "conversationState": conversation_state,
-            "conversationStateMetadata": {
-                "systemPrompt": if system_prompt.is_empty() { serde_json::Value::Null } else { json!(system_prompt) }
+            None => {
+                // 如果缓存未命中，执行转换并缓存结果
+                let result = self.build_codewhisperer_request_uncached(claude_request)?;
+                self.request_cache.write().await.put(hash, result.clone());
+                result
             }
-        });
+        };
 
+        // Only the conversion is cached; every call is a conversation of its own, for whoever
+        // the credentials are at the time
+        codewhisperer_request["conversationState"]["conversationId"] = json!(Uuid::new_v4().to_string());
         if let Some(profile_arn) = self.credentials.read().await.profile_arn.clone() {
-            request["profileArn"] = json!(profile_arn);
+            codewhisperer_request["profileArn"] = json!(profile_arn);
         }
 
-        Ok(request)
+        Ok(codewhisperer_request)
     }
-
-    fn extract_content_text(&self, value: &serde_json::Value) -> String {
-        if value.is_null() {
-            return String::new();
-        }
-
-        if let Some(obj) = value.as_object() {
-            if let Some(array) = obj.get("content").and_then(|c| c.as_array()) {
-                return array.iter()
-                    .filter_map(|part| {
-                        if part.get("type").and_then(|t| t.as_str()) == Some("text") {
-                            part.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join("");
-            }
-            if let Some(text) = obj.get("content").and_then(|t| t.as_str()) {
-                return text.to_string();
-            }
-        }
-
-        value.as_str().unwrap_or("").to_string()
-    }
-
-    fn extract_user_message_details(&self, message: &serde_json::Value) -> (String, Vec<serde_json::Value>, Vec<serde_json::Value>, Vec<serde_json::Value>) {
-        let mut content = String::new();
-        let mut images = Vec::new();
-        let mut tool_results = Vec::new();
-        let mut tools_used = Vec::new();
-
-        if let Some(parts) = message.get("content").and_then(|c| c.as_array()) {
-            for part in parts {
-                match part.get("type").and_then(|t| t.as_str()).unwrap_or("") {
-                    "text" => {
-                        if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
-                            content.push_str(text);
-                        }
-                    }
-                    "tool_result" => {
-                        if let Some(tool_use_id) = part.get("tool_use_id").and_then(|id| id.as_str()) {
-                            let tool_content = part.get("content").cloned().unwrap_or(json!([]));
-                            let text_content = self.extract_tool_result_content(&tool_content);
-                            tool_results.push(json!({
-                                "content": text_content,
-                                "status": "success",
-                                "toolUseId": tool_use_id
-                            }));
-                        }
-                    }
-                    "tool_use" => {
-                        if let Some(name) = part.get("name").and_then(|n| n.as_str()) {
-                            let input = part.get("input").cloned().unwrap_or(json!({}));
-                            if let Some(id) = part.get("id").and_then(|i| i.as_str()) {
-                                tools_used.push(json!({
-                                    "input": input,
-                                    "name": name,
-                                    "toolUseId": id
-                                }));
-                            }
-                        }
-                    }
-                    "image" => {
-                        if let Some(source) = part.get("source") {
-                            let media_type = source.get("media_type").and_then(|m| m.as_str()).unwrap_or("image/png");
-                            let format = media_type.split('/').nth(1).unwrap_or("png");
-                            let data = source.get("data");
-                            images.push(json!({
-                                "format": format,
-                                "source": {
-                                    "bytes": data
-                                }
-                            }));
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        } else {
-            content = self.extract_content_text(message);
-        }
-
-        (content, images, tool_results, tools_used)
-    }
-
-    fn extract_tool_result_content(&self, value: &serde_json::Value) -> Vec<serde_json::Value> {
-        if let Some(array) = value.as_array() {
-            array.iter().filter_map(|item| {
-                if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
-                    Some(json!({ "text": text }))
-                } else if let Some(item_type) = item.get("type").and_then(|t| t.as_str()) {
-                    match item_type {
-                        "text" => item.get("text").map(|t| json!({ "text": t.as_str().unwrap_or("") })),
-                        _ => Some(json!({ "text": self.extract_content_text(item) }))
-                    }
-                } else {
-                    Some(json!({ "text": self.extract_content_text(item) }))
-                }
-            }).collect()
-        } else if let Some(text) = value.as_str() {
-            vec![json!({ "text": text })]
-        } else {
-            // For any other type, convert to text
-            vec![json!({ "text": self.extract_content_text(value) })]
-        }
-    }
-
-    fn extract_assistant_message_details(&self, message: &serde_json::Value) -> (String, Vec<serde_json::Value>, Vec<serde_json::Value>) {
-        let mut content = String::new();
-        let mut tool_uses = Vec::new();
-        let mut tool_results = Vec::new();
-
-        if let Some(parts) = message.get("content").and_then(|c| c.as_array()) {
-            for part in parts {
-                match part.get("type").and_then(|t| t.as_str()).unwrap_or("") {
-                    "text" => {
-                        if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
-                            content.push_str(text);
-                        }
-                    }
-                    "tool_use" => {
-                        let input = part.get("input").cloned().unwrap_or(json!({}));
-                        let name = part.get("name").and_then(|n| n.as_str()).unwrap_or("");
-                        let id = part.get("id").and_then(|i| i.as_str()).unwrap_or("");
-                        tool_uses.push(json!({
-                            "input": input,
-                            "name": name,
-                            "toolUseId": id
-                        }));
-                    }
-                    "tool_result" => {
-                        if let Some(tool_use_id) = part.get("tool_use_id").and_then(|id| id.as_str()) {
-                            let tool_content = part.get("content").cloned().unwrap_or(json!([]));
-                            let text_content = self.extract_tool_result_content(&tool_content);
-                            tool_results.push(json!({
-                                "content": text_content,
-                                "status": "success",
-                                "toolUseId": tool_use_id
-                            }));
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        } else {
-            content = self.extract_content_text(message);
-        }
-
-        (content, tool_uses, tool_results)
-    }
-
-    fn build_tools_context(&self, tools: Option<&serde_json::Value>) -> Vec<serde_json::Value> {
-        tools
-            .and_then(|v| v.as_array())
-            .map(|tools| {
-                tools.iter().filter_map(|tool| {
-                    let name = tool.get("name")?.as_str()?;
-                    let input_schema = tool.get("input_schema").cloned().unwrap_or(json!({}));
-                    Some(json!({
-                        "toolSpecification": {
-                            "name": name,
-                            "description": tool.get("description").and_then(|d| d.as_str()).unwrap_or(""),
-                            "inputSchema": { "json": input_schema }
-                        }
-                    }))
-                }).collect::<Vec<_>>()
-            })
-            .unwrap_or_default()
+    
+    /// Convert Claude messages format to CodeWhisperer request format (uncached), without the
+    /// per-call `conversationId` and `profileArn`
+    fn build_codewhisperer_request_uncached(&self, claude_request: &serde_json::Value) -> Result<serde_json::Value> {
+        let request = ir::claude::parse_request(claude_request)?;
+        let mut codewhisperer_request = ir::emit_request_with(ModelProtocol::Kiro, &request, self.param_policy)?;
+        if let Some(state) = codewhisperer_request["conversationState"].as_object_mut() {
+            state.remove("conversationId");
+        }
+        Ok(codewhisperer_request)
     }
 
     fn load_credentials_from_base64(base64_str: &str) -> Result<KiroOAuthCredentials> {
@@ -622,324 +291,24 @@ impl KiroApiService {
         }
     }
     
-    /// Find the end position of a JSON object in a string slice
-    /// Returns the position of the matching closing brace
-    fn find_json_end(&self, text: &str) -> Option<usize> {
-        let mut depth = 0;
-        let mut in_string = false;
-        let mut escape_next = false;
-        let bytes = text.as_bytes();
-        
-        for (i, &ch) in bytes.iter().enumerate() {
-            if escape_next {
-                escape_next = false;
-                continue;
-            }
-            
-            match ch {
-                b'\\' if in_string => escape_next = true,
-                b'"' if !escape_next => in_string = !in_string,
-                b'{' if !in_string => depth += 1,
-                b'}' if !in_string => {
-                    depth -= 1;
-                    if depth == 0 {
-                        return Some(i);
-                    }
-                }
-                _ => {}
-            }
-        }
-        None
-    }
-    
-    /// Parse CodeWhisperer event stream response to extract text content and tool calls
-    /// Optimized version with better performance
-    fn parse_codewhisperer_response(&self, response_text: &str) -> Result<(String, Vec<serde_json::Value>)> {
-        let mut full_content = String::with_capacity(response_text.len() / 2);  // 预分配容量
-        let mut tool_calls: Vec<serde_json::Value> = Vec::new();
-        let mut current_tool_call: Option<serde_json::Value> = None;
-        
-        let response_len = response_text.len();
-        info!("Starting to parse CodeWhisperer response, length: {}", response_len);
-        
-        // 使用更高效的搜索方法
-        let event_prefix = "event{";
-        let mut pos = 0;
-        let response_bytes = response_text.as_bytes();
-        let prefix_bytes = event_prefix.as_bytes();
-        
-        // 快速查找所有事件位置
-        let mut event_positions = Vec::with_capacity(response_len / 100);  // 预估事件数量
-        while pos < response_len - prefix_bytes.len() {
-            if &response_bytes[pos..pos + prefix_bytes.len()] == prefix_bytes {
-                event_positions.push(pos);
-                pos += prefix_bytes.len();
-            } else {
-                pos += 1;
-            }
-        }
-        
-        let found_events = event_positions.len();
-        debug!("Found {} event blocks", found_events);
-        
-        // 处理每个事件块
-        for i in 0..found_events {
-            let event_start = event_positions[i] + 5;  // Skip "event"
-            let event_end = if i + 1 < found_events {
-                event_positions[i + 1]
-            } else {
-                response_len
-            };
-            
-            if event_start >= event_end {
-                continue;
-            }
-            
-            // 使用更高效的 JSON 解析策略
-            let event_slice = &response_text[event_start..event_end];
-            if let Some(json_end) = self.find_json_end(event_slice) {
-                let json_str = &event_slice[..=json_end];
-                if let Ok(event_data) = serde_json::from_str::<serde_json::Value>(json_str) {
-                    // 成功解析 - 检查事件类型
-                    if let (Some(name), Some(tool_use_id)) = (event_data.get("name").and_then(|n| n.as_str()), 
-                                                               event_data.get("toolUseId").and_then(|id| id.as_str())) {
-                        if current_tool_call.is_none() {
-                            current_tool_call = Some(json!({
-                                "id": tool_use_id,
-                                "type": "tool_use",
-                                "name": name,
-                                "input": ""
-                            }));
-                        }
-                        
-                        // 累加输入
-                        if let Some(input) = event_data.get("input").and_then(|i| i.as_str()) {
-                            if let Some(ref mut tool_call) = current_tool_call {
-                                let current_input = tool_call["input"].as_str().unwrap_or("");
-                                tool_call["input"] = json!(format!("{}{}", current_input, input));
-                            }
-                        }
-                        
-                        // 检查工具调用是否完成
-                        if event_data.get("stop").and_then(|s| s.as_bool()).unwrap_or(false) {
-                            if let Some(tool_call) = current_tool_call.take() {
-                                tool_calls.push(tool_call);
-                            }
-                        }
-                    }
-                    // 检查内容事件
-                    else if event_data.get("followupPrompt").is_none() {
-                        if let Some(text) = event_data.get("content").and_then(|c| c.as_str()) {
-                            // 使用更高效的替换方法
-                            if text.contains("\\n") {
-                                full_content.push_str(&text.replace("\\n", "\n"));
-                            } else {
-                                full_content.push_str(text);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        
-        // If there's a pending tool call, add it
-        if let Some(tool_call) = current_tool_call {
-            tool_calls.push(tool_call);
-        }
-        
-        info!("Parsed {} event blocks, content: {} chars, tool_calls: {}", 
-              found_events, full_content.len(), tool_calls.len());
-        
-        // Parse bracket-style tool calls from content: [Called functionName with args: {...}]
-        let bracket_tool_calls = self.parse_bracket_tool_calls(&full_content);
-        if !bracket_tool_calls.is_empty() {
-            info!("Found {} bracket-style tool calls in content", bracket_tool_calls.len());
-            
-            // Add bracket tool calls to the list
-            tool_calls.extend(bracket_tool_calls.clone());
-            
-            // Remove tool call syntax from content
-            for tool_call in &bracket_tool_calls {
-                if let Some(name) = tool_call.get("name").and_then(|n| n.as_str()) {
-                    // Remove [Called functionName with args: {...}] pattern
-                    let pattern = format!(r"\[Called\s+{}\s+with\s+args:\s*\{{[^}}]*(?:\{{[^}}]*\}}[^}}]*)*\}}\]", regex::escape(name));
-                    if let Ok(re) = regex::Regex::new(&pattern) {
-                        full_content = re.replace_all(&full_content, "").to_string();
-                    }
-                }
-            }
-            // Clean up extra whitespace
-            full_content = full_content.split_whitespace().collect::<Vec<_>>().join(" ");
-        }
-        
-        if full_content.is_empty() && tool_calls.is_empty() {
-            error!("Could not parse any content from CodeWhisperer response!");
-            error!("Full response: {}", &response_text[..response_text.len().min(2000)]);
-            // Return a helpful error message instead of empty
-            full_content = "⚠️ Unable to parse response from Kiro API. Please check server logs with RUST_LOG=debug.".to_string();
-        }
-        
-        Ok((full_content, tool_calls))
-    }
-    
-    /// Parse bracket-style tool calls: [Called functionName with args: {...}]
-    fn parse_bracket_tool_calls(&self, text: &str) -> Vec<serde_json::Value> {
-        if !text.contains("[Called") {
-            return Vec::new();
-        }
-        
-        let mut tool_calls = Vec::new();
-        
-        // Find all [Called positions
-        let mut call_positions = Vec::new();
-                let mut start = 0;
-        while let Some(pos) = text[start..].find("[Called") {
-            call_positions.push(start + pos);
-            start += pos + 1;
-        }
-        
-        for (i, &start_pos) in call_positions.iter().enumerate() {
-            let end_search_limit = if i + 1 < call_positions.len() {
-                call_positions[i + 1]
-            } else {
-                text.len()
-            };
-            
-            let segment = &text[start_pos..end_search_limit];
-            
-            // Find matching bracket
-            let bracket_end = self.find_matching_bracket(segment, 0);
-            
-            let tool_call_text = if bracket_end != -1 {
-                &segment[..=(bracket_end as usize)]
-            } else {
-                // Fallback: find last ']'
-                if let Some(last_bracket) = segment.rfind(']') {
-                    &segment[..=last_bracket]
-                } else {
-                    continue;
-                }
-            };
-            
-            if let Some(parsed) = self.parse_single_tool_call(tool_call_text) {
-                tool_calls.push(parsed);
-            }
-        }
-        
-        tool_calls
-    }
-    
-    /// Find matching closing bracket
-    fn find_matching_bracket(&self, text: &str, start_pos: usize) -> i32 {
-        let bytes = text.as_bytes();
-        if start_pos >= bytes.len() || bytes[start_pos] != b'[' {
-            return -1;
-        }
-        
-        let mut bracket_count = 1;
-        let mut in_string = false;
-        let mut escape_next = false;
-        
-        for i in (start_pos + 1)..bytes.len() {
-            let ch = bytes[i];
-            
-            if escape_next {
-                escape_next = false;
-                continue;
-            }
-            
-            if ch == b'\\' && in_string {
-                escape_next = true;
-                continue;
-            }
-            
-            if ch == b'"' && !escape_next {
-                in_string = !in_string;
-                continue;
-            }
-            
-            if !in_string {
-                if ch == b'[' {
-                    bracket_count += 1;
-                } else if ch == b']' {
-                    bracket_count -= 1;
-                    if bracket_count == 0 {
-                        return i as i32;
-                    }
-                }
-            }
-        }
-        
-        -1
-    }
-    
-    /// Parse single tool call from text like: [Called functionName with args: {...}]
-    fn parse_single_tool_call(&self, tool_call_text: &str) -> Option<serde_json::Value> {
-        // Pattern: [Called functionName with args:
-        let name_pattern = regex::Regex::new(r"(?i)\[Called\s+(\w+)\s+with\s+args:").ok()?;
-        let name_match = name_pattern.captures(tool_call_text)?;
-        let function_name = name_match.get(1)?.as_str().trim();
-        
-        let args_start_marker = "with args:";
-        let args_start_pos = tool_call_text.to_lowercase().find(&args_start_marker.to_lowercase())?;
-        let args_start = args_start_pos + args_start_marker.len();
-        let args_end = tool_call_text.rfind(']')?;
-        
-        if args_end <= args_start {
-            return None;
-        }
-        
-        let json_candidate = tool_call_text[args_start..args_end].trim();
-        
-        // Try to repair and parse JSON
-        let mut repaired_json = json_candidate.to_string();
-        // Remove trailing comma
-        repaired_json = regex::Regex::new(r",\s*([}\]])").ok()?.replace_all(&repaired_json, "$1").to_string();
-        // Add quotes to unquoted keys
-        repaired_json = regex::Regex::new(r#"([{,]\s*)([a-zA-Z0-9_]+?)\s*:"#).ok()?.replace_all(&repaired_json, r#"$1"$2":"#).to_string();
-        // Quote unquoted values
-        repaired_json = regex::Regex::new(r":\s*([a-zA-Z0-9_]+)(?=[,\}\]])").ok()?.replace_all(&repaired_json, r#":"$1""#).to_string();
-        
-        match serde_json::from_str::<serde_json::Value>(&repaired_json) {
-            Ok(arguments_obj) => {
-                if !arguments_obj.is_object() {
-                    return None;
-                }
-                
-                let tool_call_id = format!("call_{}", Uuid::new_v4().to_string().replace("-", "")[..8].to_string());
-                Some(json!({
-                    "id": tool_call_id,
-                    "type": "tool_use",
-                    "name": function_name,
-                    "input": arguments_obj
-                }))
-            }
-            Err(e) => {
-                warn!("Failed to parse tool call arguments: {}", e);
-                None
-            }
-        }
+    /// Send a CodeWhisperer request, retrying per the retry policy
+    async fn send(&self, url: &str, codewhisperer_request: &serde_json::Value) -> Result<reqwest::Response> {
+        self.retry
+            .send(|| async {
+                let access_token = self.credentials.read().await.access_token.clone();
+                Ok(self.client
+                    .post(url)
+                    .header("Authorization", format!("Bearer {}", access_token))
+                    .header("Content-Type", "application/json")
+                    .header("amz-sdk-invocation-id", Uuid::new_v4().to_string())
+                    .json(codewhisperer_request)
+                    .send()
+                    .await?)
+            })
+            .await
     }
 
-    fn call_api_with_retry<'a>(
-        &'a self,
-        endpoint: &'a str,
-        body: serde_json::Value,
-        retry_count: u32,
-    ) -> BoxFuture<'a, Result<serde_json::Value>> {
-        Box::pin(async move {
-        self.call_api_with_retry_and_refresh(endpoint, body, retry_count, false).await
-        })
-    }
-    
-    fn call_api_with_retry_and_refresh<'a>(
-        &'a self,
-        endpoint: &'a str,
-        body: serde_json::Value,
-        retry_count: u32,
-        is_retry_after_refresh: bool,
-    ) -> BoxFuture<'a, Result<serde_json::Value>> {
-        Box::pin(async move {
+    async fn call_api(&self, endpoint: &str, body: &serde_json::Value) -> Result<ir::ChatResponse> {
         // Check token expiration before making request
         {
             let creds = self.credentials.read().await;
@@ -954,165 +323,60 @@ impl KiroApiService {
         }
 
         let url = self.get_api_url(endpoint);
-        let creds = self.credentials.read().await;
 
         info!("Calling Kiro API: {}", url);
         let request_start = std::time::Instant::now();
         
         // Convert Claude request to CodeWhisperer format
         let convert_start = std::time::Instant::now();
-        let codewhisperer_request = self.build_codewhisperer_request(&body).await?;
+        let codewhisperer_request = self.build_codewhisperer_request(body).await?;
         let convert_duration = convert_start.elapsed();
         debug!("Request conversion took: {:?}", convert_duration);
         debug!("CodeWhisperer request: {}", serde_json::to_string_pretty(&codewhisperer_request)?);
 
         let api_call_start = std::time::Instant::now();
-        let response = self.client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", creds.access_token))
-            .header("Content-Type", "application/json")
-            .header("amz-sdk-invocation-id", Uuid::new_v4().to_string())
-            .json(&codewhisperer_request)
-            .send()
-            .await?;
-        
-        let api_call_duration = api_call_start.elapsed();
-        info!("API call took: {:?}", api_call_duration);
-
-        let status = response.status();
-
-        if status.is_success() {
-            // CodeWhisperer returns event stream format, not JSON
-            let response_text = response.text().await?;
-            info!("Raw response length: {} bytes", response_text.len());
-            debug!("Raw response: {}", &response_text[..response_text.len().min(500)]);
-            
-            // Parse the event stream to extract the actual content and tool calls
-            let parse_start = std::time::Instant::now();
-            let (content, tool_calls) = self.parse_codewhisperer_response(&response_text)?;
-            let parse_duration = parse_start.elapsed();
-            info!("Response parsing took: {:?}", parse_duration);
-            info!("Parsed content length: {}, tool_calls count: {}", content.len(), tool_calls.len());
-            debug!("Parsed content: {}", &content);
-            
-            // Build content array with both text and tool_use blocks
-            let mut content_array = Vec::new();
-            
-            // Add tool calls first (if any)
-            for tool_call in tool_calls {
-                content_array.push(tool_call);
-            }
-            
-            // Add text content (if any)
-            if !content.is_empty() {
-                content_array.push(json!({
-                    "type": "text",
-                    "text": content
-                }));
-            }
-            
-            // Ensure we have at least some content
-            if content_array.is_empty() {
-                error!("No content parsed from CodeWhisperer response!");
-                // Return a helpful error message instead of empty content
-                // This prevents Cline from showing "unparsable response" error
-                content_array.push(json!({
-                    "type": "text",
-                    "text": "⚠️ Unable to parse response from Kiro API. The response may be in an unexpected format. Please check the server logs with RUST_LOG=debug for details."
-                }));
-            }
-            
-            // Determine stop_reason based on content
-            let stop_reason = if content_array.iter().any(|c| c.get("type").and_then(|t| t.as_str()) == Some("tool_use")) {
-                "tool_use"
-            } else {
-                "end_turn"
-            };
-            
-            // Calculate total output tokens
-            let output_tokens: usize = content_array.iter().map(|item| {
-                if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
-                    text.len() / 4
-                } else if let Some(input) = item.get("input") {
-                    serde_json::to_string(input).unwrap_or_default().len() / 4
-                } else {
-                    0
-                }
-            }).sum();
-            
-            // Convert to Claude response format
-            let result = json!({
-                "id": format!("msg_{}", Uuid::new_v4()),
-                "type": "message",
-                "role": "assistant",
-                "content": content_array,
-                "model": "claude-sonnet-4-20250514",
-                "stop_reason": stop_reason,
-                "stop_sequence": null,
-                "usage": {
-                    "input_tokens": 0,
-                    "output_tokens": output_tokens
-                }
-            });
-            
-            let total_duration = request_start.elapsed();
-            info!("Total request processing took: {:?}", total_duration);
-            info!("Returning Claude response with {} content blocks", content_array.len());
-            debug!("Response structure: {}", serde_json::to_string_pretty(&result).unwrap_or_default());
-            
-            // Log the actual content for debugging
-            for (i, block) in content_array.iter().enumerate() {
-                if let Some(text) = block.get("text").and_then(|t| t.as_str()) {
-                    debug!("Content block {}: type=text, length={}, preview={}", 
-                           i, text.len(), &text[..text.len().min(100)]);
-                } else if let Some(tool_name) = block.get("name").and_then(|n| n.as_str()) {
-                    debug!("Content block {}: type=tool_use, name={}", i, tool_name);
+        let response = match self.send(&url, &codewhisperer_request).await {
+            // Handle 403 Forbidden - token might be invalid
+            Err(e) if upstream_status(&e) == Some(StatusCode::FORBIDDEN) => {
+                info!("Received 403 Forbidden. Attempting token refresh and retrying...");
+                if let Err(refresh_error) = self.refresh_access_token().await {
+                    error!("Token refresh failed during 403 retry: {}", refresh_error);
+                    return Err(e.context(format!("Token refresh after 403 Forbidden failed: {}", refresh_error)));
                 }
+                info!("Token refreshed successfully, retrying request...");
+                self.send(&url, &codewhisperer_request).await?
             }
-            
-            return Ok(result);
-        }
+            result => result?,
+        };
         
-        // Handle 403 Forbidden - token might be invalid
-        if status.as_u16() == 403 && !is_retry_after_refresh {
-            info!("Received 403 Forbidden. Attempting token refresh and retrying...");
-            drop(creds); // Release the read lock before refreshing
-            
-            match self.refresh_access_token().await {
-                Ok(_) => {
-                    info!("Token refreshed successfully, retrying request...");
-                    return self.call_api_with_retry_and_refresh(endpoint, body, retry_count, true).await;
-                }
-                Err(e) => {
-                    error!("Token refresh failed during 403 retry: {}", e);
-                    let error_text = response.text().await?;
-                    anyhow::bail!("API call failed (403 Forbidden) and token refresh failed: {}", error_text);
-                }
-            }
-        }
+        let api_call_duration = api_call_start.elapsed();
+        info!("API call took: {:?}", api_call_duration);
 
-        if (status.as_u16() == 429 || status.is_server_error()) && retry_count < self.max_retries {
-            // 检查 Retry-After 头
-            let retry_after_secs = response.headers()
-                .get("Retry-After")
-                .and_then(|v| v.to_str().ok())
-                .and_then(|s| s.parse::<u64>().ok());
-            
-            let delay = if let Some(secs) = retry_after_secs {
-                secs * 1000  // 转换为毫秒
-            } else {
-                // 使用线性退避而非指数退避，减少重试延迟
-                self.base_delay * (retry_count + 1) as u64
-            };
-            
-            warn!("Request failed with status {}, retrying in {}ms...", status, delay);
-            tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
-            return self.call_api_with_retry(endpoint, body, retry_count + 1).await;
-        }
+        // CodeWhisperer returns event stream format, not JSON
+        let response_text = response.text().await?;
+        info!("Raw response length: {} bytes", response_text.len());
+        debug!("Raw response: {}", &response_text[..response_text.len().min(500)]);
+        
+        // Parse the event stream to extract the actual content and tool calls
+        let parse_start = std::time::Instant::now();
+        let mut result = ir::kiro::parse_response(&response_text)?;
+        result.usage.input_tokens = ir::kiro::estimate_input_tokens(&ir::claude::parse_request(body)?);
+        let parse_duration = parse_start.elapsed();
+        info!("Response parsing took: {:?}", parse_duration);
+        result.id = Some(format!("msg_{}", Uuid::new_v4()));
+        result.model = Some(
+            body.get("model")
+                .and_then(|m| m.as_str())
+                .unwrap_or("claude-sonnet-4-20250514")
+                .to_string(),
+        );
+
+        let total_duration = request_start.elapsed();
+        info!("Total request processing took: {:?}", total_duration);
+        info!("Returning Claude response with {} content blocks", result.content.len());
+        debug!("Parsed response: {:?}", result);
 
-        let error_text = response.text().await?;
-        anyhow::bail!("API call failed ({}): {}", status, error_text)
-        })
+        Ok(result)
     }
 }
 
@@ -1124,7 +388,8 @@ impl ApiServiceAdapter for KiroApiService {
         request_body: serde_json::Value,
     ) -> Result<serde_json::Value> {
         debug!("Kiro generate_content");
-        self.call_api_with_retry("/v1/messages", request_body, 0).await
+        let response = self.call_api("/v1/messages", &request_body).await?;
+        Ok(ir::claude::emit_response(&response))
     }
 
     async fn generate_content_stream(
@@ -1135,148 +400,19 @@ impl ApiServiceAdapter for KiroApiService {
         debug!("Kiro generate_content_stream");
 
         // Note: Kiro/CodeWhisperer doesn't support true streaming
-        // We'll get the full response and simulate streaming
-        
-        // Get the full response first
-        let full_response = self.call_api_with_retry("/v1/messages", request_body, 0).await?;
-        
-        // Extract the content array from the response
-        let content_array = full_response
-            .get("content")
-            .and_then(|c| c.as_array())
-            .cloned()
-            .unwrap_or_default();
-            
-        let model = full_response
-            .get("model")
-            .and_then(|m| m.as_str())
-            .unwrap_or("claude-sonnet-4-20250514")
-            .to_string();
-            
-        let stop_reason = full_response
-            .get("stop_reason")
-            .and_then(|s| s.as_str())
-            .unwrap_or("end_turn")
-            .to_string();
-        
-        // Create a simulated stream that returns Claude-formatted events
-        let message_id = format!("msg_{}", Uuid::new_v4());
-        
-        let stream = stream! {
-            // 1. Send message_start event
-            yield Ok(json!({
-                "type": "message_start",
-                "message": {
-                    "id": message_id.clone(),
-                    "type": "message",
-                    "role": "assistant",
-                    "model": model.clone(),
-                    "content": [],
-                    "stop_reason": null,
-                    "stop_sequence": null,
-                    "usage": {
-                        "input_tokens": 0,
-                        "output_tokens": 0
-                    }
-                }
-            }));
-            
-            // Estimate total output tokens
-            let mut total_output_tokens = 0;
-            
-            // 2-4. Send events for each content block (tool_use or text)
-            for (index, content_block) in content_array.iter().enumerate() {
-                let content_type = content_block.get("type").and_then(|t| t.as_str()).unwrap_or("text");
-                
-                if content_type == "tool_use" {
-                    // Handle tool_use block
-                    let tool_id = content_block.get("id").and_then(|i| i.as_str()).unwrap_or("");
-                    let tool_name = content_block.get("name").and_then(|n| n.as_str()).unwrap_or("");
-                    // Keep input as string to match Node.js behavior
-                    let tool_input = content_block.get("input").and_then(|i| i.as_str()).unwrap_or("").to_string();
-                    
-                    // Send content_block_start for tool_use
-            yield Ok(json!({
-                "type": "content_block_start",
-                        "index": index,
-                        "content_block": {
-                            "type": "tool_use",
-                            "id": tool_id,
-                            "name": tool_name,
-                            "input": {}
-                        }
-                    }));
-                    
-                    // Send content_block_delta with tool input (as string)
-                    yield Ok(json!({
-                        "type": "content_block_delta",
-                        "index": index,
-                        "delta": {
-                            "type": "input_json_delta",
-                            "partial_json": tool_input
-                        }
-                    }));
-                    
-                    // Send content_block_stop
-                    yield Ok(json!({
-                        "type": "content_block_stop",
-                        "index": index
-                    }));
-                    
-                    total_output_tokens += serde_json::to_string(&tool_input).unwrap_or_default().len() / 4;
-                } else {
-                    // Handle text block
-                    let text = content_block.get("text").and_then(|t| t.as_str()).unwrap_or("");
-                    
-                    // Send content_block_start for text
-                    yield Ok(json!({
-                        "type": "content_block_start",
-                        "index": index,
-                "content_block": {
-                    "type": "text",
-                    "text": ""
-                }
-            }));
-            
-                    // Send content_block_delta with text
-            yield Ok(json!({
-                "type": "content_block_delta",
-                        "index": index,
-                "delta": {
-                    "type": "text_delta",
-                            "text": text
-                }
-            }));
-            
-                    // Send content_block_stop
-            yield Ok(json!({
-                "type": "content_block_stop",
-                        "index": index
-            }));
-                    
-                    total_output_tokens += text.len() / 4;
-                }
-            }
-            
-            // 5. Send message_delta event
-            yield Ok(json!({
-                "type": "message_delta",
-                "delta": {
-                    "stop_reason": stop_reason,
-                    "stop_sequence": null
-                },
-                "usage": {
-                    "output_tokens": total_output_tokens
-                }
-            }));
-            
-            // 6. Send message_stop event
-            yield Ok(json!({
-                "type": "message_stop"
-            }));
-        };
+        // The full response is read first and replayed as Claude stream events
+        let response = self.call_api("/v1/messages", &request_body).await?;
+        let mut emitter = ir::claude::ChunkEmitter::new(response.model.as_deref());
+        let events: Vec<serde_json::Value> = ir::response_events(&response)
+            .iter()
+            .flat_map(|event| emitter.emit(event))
+            .collect();
 
-        Ok(Box::pin(stream))
+        Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+    }
+
+    fn buffers_stream(&self) -> bool {
+        true
     }
 
     async fn list_models(&self) -> Result<ModelListResponse> {
@@ -1309,5 +445,9 @@ impl ApiServiceAdapter for KiroApiService {
         }
         Ok(())
     }
+
+    async fn refresh_token_forced(&self) -> Result<()> {
+        self.refresh_access_token().await
+    }
 }
 
@@ -7,10 +7,11 @@
 
 use crate::adapter::ApiServiceAdapter;
 use crate::common::*;
+use crate::http_client::HttpClientConfig;
+use crate::retry::RetryPolicy;
 use anyhow::{Context, Result};
 use async_stream::stream;
 use async_trait::async_trait;
-use futures::future::BoxFuture;
 use futures::Stream;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
@@ -40,23 +41,16 @@ pub struct QwenApiService {
     client: Client,
     credentials: Arc<RwLock<QwenOAuthCredentials>>,
     credentials_path: PathBuf,
-    max_retries: u32,
-    base_delay: u64,
+    retry: RetryPolicy,
 }
 
 impl QwenApiService {
     pub async fn new(
         oauth_creds_file: Option<PathBuf>,
-        max_retries: u32,
-        base_delay: u64,
+        retry: RetryPolicy,
+        http: &HttpClientConfig,
     ) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(60))  // 减少到60秒
-            .connect_timeout(std::time::Duration::from_secs(10))
-            .pool_idle_timeout(std::time::Duration::from_secs(90))
-            .pool_max_idle_per_host(10)
-            .tcp_nodelay(true)
-            .build()?;
+        let client = http.build_client()?;
 
         let credentials_path = oauth_creds_file.unwrap_or_else(|| {
             dirs::home_dir()
@@ -72,8 +66,7 @@ impl QwenApiService {
             client,
             credentials: Arc::new(RwLock::new(credentials)),
             credentials_path,
-            max_retries,
-            base_delay,
+            retry,
         })
     }
 
@@ -97,17 +90,15 @@ impl QwenApiService {
 
     async fn refresh_access_token(&self) -> Result<()> {
         info!("Refreshing Qwen access token...");
-        warn!("Qwen token refresh not fully implemented - requires Qwen OAuth flow");
+        warn!(
+            "Qwen token refresh not fully implemented - requires Qwen OAuth flow; update {} by hand",
+            self.credentials_path.display()
+        );
         Ok(())
     }
 
-    fn call_api_with_retry<'a>(
-        &'a self,
-        endpoint: &'a str,
-        body: serde_json::Value,
-        retry_count: u32,
-    ) -> BoxFuture<'a, Result<serde_json::Value>> {
-        Box::pin(async move {
+    /// POST to `endpoint`, retrying per the retry policy
+    async fn send(&self, endpoint: &str, body: &serde_json::Value) -> Result<reqwest::Response> {
         {
             let creds = self.credentials.read().await;
             if self.is_token_expired(&creds) {
@@ -117,33 +108,18 @@ impl QwenApiService {
         }
 
         let url = format!("{}{}", QWEN_API_BASE, endpoint);
-        let creds = self.credentials.read().await;
-
-        let response = self.client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", creds.access_token))
-            .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
-            .await?;
-
-        let status = response.status();
-
-        if status.is_success() {
-            let result: serde_json::Value = response.json().await?;
-            return Ok(result);
-        }
-
-        if (status.as_u16() == 429 || status.is_server_error()) && retry_count < self.max_retries {
-            let delay = self.base_delay * 2_u64.pow(retry_count);
-            warn!("Request failed with status {}, retrying in {}ms...", status, delay);
-            tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
-            return self.call_api_with_retry(endpoint, body, retry_count + 1).await;
-        }
-
-        let error_text = response.text().await?;
-        anyhow::bail!("API call failed ({}): {}", status, error_text)
-        })
+        self.retry
+            .send(|| async {
+                let access_token = self.credentials.read().await.access_token.clone();
+                Ok(self.client
+                    .post(&url)
+                    .header("Authorization", format!("Bearer {}", access_token))
+                    .header("Content-Type", "application/json")
+                    .json(body)
+                    .send()
+                    .await?)
+            })
+            .await
     }
 }
 
@@ -155,7 +131,8 @@ impl ApiServiceAdapter for QwenApiService {
         request_body: serde_json::Value,
     ) -> Result<serde_json::Value> {
         debug!("Qwen generate_content");
-        self.call_api_with_retry("/chat/completions", request_body, 0).await
+        let response = self.send("/chat/completions", &request_body).await?;
+        Ok(response.json().await?)
     }
 
     async fn generate_content_stream(
@@ -169,22 +146,7 @@ impl ApiServiceAdapter for QwenApiService {
             obj.insert("stream".to_string(), json!(true));
         }
 
-        let creds = self.credentials.read().await;
-        let url = format!("{}/chat/completions", QWEN_API_BASE);
-        
-        let response = self.client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", creds.access_token))
-            .header("Content-Type", "application/json")
-            .json(&request_body)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            anyhow::bail!("Stream API call failed: {}", error_text);
-        }
-
+        let response = self.send("/chat/completions", &request_body).await?;
         let byte_stream = response.bytes_stream();
         
         let stream = stream! {
@@ -200,8 +162,7 @@ impl ApiServiceAdapter for QwenApiService {
                             let line = buffer[..newline_pos].trim().to_string();
                             buffer = buffer[newline_pos + 1..].to_string();
                             
-                            if line.starts_with("data: ") {
-                                let json_data = &line[6..];
+                            if let Some(json_data) = line.strip_prefix("data: ") {
                                 if json_data == "[DONE]" {
                                     return;
                                 }
@@ -254,5 +215,9 @@ impl ApiServiceAdapter for QwenApiService {
         }
         Ok(())
     }
+
+    async fn refresh_token_forced(&self) -> Result<()> {
+        self.refresh_access_token().await
+    }
 }
 
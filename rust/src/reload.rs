@@ -0,0 +1,279 @@
+/*!
+ * Runtime State and Hot Reload
+ *
+ * Holds the adapters built from the current configuration and swaps them atomically when
+ * config.json or the provider pools file changes, or when the process receives SIGHUP.
+ */
+
+use crate::adapter::{create_adapter, ApiServiceAdapter};
+use crate::auth::AuthFailureTracker;
+use crate::common::ModelProvider;
+use crate::client_keys::ClientKeyRegistry;
+use crate::concurrency::{ConcurrencyRegistry, PRIMARY_KEY};
+use crate::config::{Config, ProviderConfig};
+use crate::pool_manager::ProviderPoolManager;
+use crate::retry::RetryBudget;
+use crate::secrets::Secret;
+use anyhow::Result;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{error, info, warn};
+
+/// Everything a request needs that is derived from the configuration.
+///
+/// Handlers take an `Arc` snapshot, so requests that are already running keep using the
+/// adapters they started with while a reload swaps in new ones.
+pub struct Runtime {
+    pub config: Config,
+    pub adapter: Arc<dyn ApiServiceAdapter>,
+    /// Adapters of pool members, keyed by member uuid
+    pub pool_adapters: HashMap<String, Arc<dyn ApiServiceAdapter>>,
+}
+
+pub struct RuntimeHandle {
+    current: RwLock<Arc<Runtime>>,
+    pub pool_manager: Arc<ProviderPoolManager>,
+    /// Per-credential concurrency limiters, kept across reloads so queued requests survive
+    pub concurrency: ConcurrencyRegistry,
+    /// Usage counters of client API keys, kept across reloads
+    pub client_keys: ClientKeyRegistry,
+    /// Failed authentication attempts per client IP, kept across reloads
+    pub auth_failures: AuthFailureTracker,
+    /// Retries made across all adapters, kept across reloads
+    pub retry_budget: Arc<RetryBudget>,
+    args: Vec<String>,
+    reload_lock: Mutex<()>,
+}
+
+impl RuntimeHandle {
+    /// Build the initial runtime. `args` are the command-line arguments used again on reload.
+    pub async fn new(config: Config, args: Vec<String>) -> Result<Self> {
+        config.validate()?;
+        let pool_manager = Arc::new(ProviderPoolManager::from_config(&config).await?);
+        let retry_budget = Arc::new(RetryBudget::new());
+        let runtime = build_runtime(config, None, &retry_budget).await?;
+        mark_members_without_adapter(&runtime, &pool_manager).await;
+
+        Ok(Self {
+            current: RwLock::new(Arc::new(runtime)),
+            pool_manager,
+            concurrency: ConcurrencyRegistry::new(),
+            client_keys: ClientKeyRegistry::new(),
+            auth_failures: AuthFailureTracker::new(),
+            retry_budget,
+            args,
+            reload_lock: Mutex::new(()),
+        })
+    }
+
+    /// Snapshot of the current runtime
+    pub async fn current(&self) -> Arc<Runtime> {
+        self.current.read().await.clone()
+    }
+
+    /// Re-read config.json and the pools file and swap in the result.
+    ///
+    /// The old runtime stays active if loading or validation fails.
+    pub async fn reload(&self) -> Result<()> {
+        let _guard = self.reload_lock.lock().await;
+        info!("Reloading configuration...");
+
+        // Resolving `cmd:` and `file:` secrets blocks
+        let args = self.args.clone();
+        let config = tokio::task::spawn_blocking(move || Config::load_with_args(&args)).await??;
+        config.validate()?;
+
+        let previous = self.current().await;
+        let runtime = build_runtime(config, Some(&previous), &self.retry_budget).await?;
+
+        self.pool_manager.apply_strategies(&runtime.config).await?;
+        self.pool_manager
+            .reconcile(runtime.config.provider_pools.clone())
+            .await;
+        mark_members_without_adapter(&runtime, &self.pool_manager).await;
+        restore_members_with_new_adapter(&previous, &runtime, &self.pool_manager).await;
+
+        let mut credential_keys: Vec<&str> = runtime.pool_adapters.keys().map(String::as_str).collect();
+        credential_keys.push(PRIMARY_KEY);
+        self.concurrency.retain(&credential_keys);
+
+        *self.current.write().await = Arc::new(runtime);
+        info!("Configuration reloaded successfully");
+        Ok(())
+    }
+
+    async fn reload_logged(&self) {
+        if let Err(e) = self.reload().await {
+            error!("Configuration reload failed, keeping previous configuration: {:#}", e);
+        }
+    }
+}
+
+/// Build adapters for a configuration, reusing the ones from `previous` whose settings did not change
+async fn build_runtime(
+    config: Config,
+    previous: Option<&Runtime>,
+    retry_budget: &Arc<RetryBudget>,
+) -> Result<Runtime> {
+    let provider = ModelProvider::parse(&config.model_provider)
+        .ok_or_else(|| anyhow::anyhow!("Invalid model provider: {}", config.model_provider))?;
+
+    let adapter = match previous {
+        Some(prev) if adapter_settings(&prev.config) == adapter_settings(&config) => prev.adapter.clone(),
+        _ => Arc::from(create_adapter(provider, &config, retry_budget).await?),
+    };
+
+    let mut pool_adapters = HashMap::new();
+    for (provider_type, members) in &config.provider_pools {
+        let Some(provider) = ModelProvider::parse(provider_type) else {
+            warn!("Skipping pool with unknown provider type: {}", provider_type);
+            continue;
+        };
+
+        for member in members {
+            if let Some(existing) = previous.and_then(|prev| reusable_member_adapter(prev, &config, provider_type, member)) {
+                pool_adapters.insert(member.uuid.clone(), existing);
+                continue;
+            }
+
+            match create_adapter(provider.clone(), &config.for_pool_member(provider_type, member), retry_budget).await {
+                Ok(adapter) => {
+                    pool_adapters.insert(member.uuid.clone(), Arc::from(adapter));
+                }
+                Err(e) => {
+                    warn!("Failed to create adapter for {} ({}): {}", provider_type, member.uuid, e);
+                }
+            }
+        }
+    }
+
+    Ok(Runtime {
+        config,
+        adapter,
+        pool_adapters,
+    })
+}
+
+fn reusable_member_adapter(
+    previous: &Runtime,
+    config: &Config,
+    provider_type: &str,
+    member: &ProviderConfig,
+) -> Option<Arc<dyn ApiServiceAdapter>> {
+    let old_member = previous
+        .config
+        .provider_pools
+        .get(provider_type)?
+        .iter()
+        .find(|m| m.uuid == member.uuid)?;
+
+    let unchanged = old_member.credentials == member.credentials
+        && old_member.resolved_credentials == member.resolved_credentials
+        && previous.config.retry_settings() == config.retry_settings()
+        && previous.config.for_pool_member(provider_type, old_member).http_client_for(provider_type)
+            == config.for_pool_member(provider_type, member).http_client_for(provider_type);
+    if unchanged {
+        previous.pool_adapters.get(&member.uuid).cloned()
+    } else {
+        None
+    }
+}
+
+/// Settings that require the primary adapter to be rebuilt when they change
+fn adapter_settings(config: &Config) -> serde_json::Value {
+    // Compare resolved secrets so a rotated `file:`/`cmd:` secret rebuilds the adapter
+    let secret = |s: &Option<Secret>| s.as_ref().map(|s| s.expose().to_string());
+    json!({
+        "model_provider": config.model_provider,
+        "openai_api_key": secret(&config.openai_api_key),
+        "openai_base_url": config.openai_base_url,
+        "claude_api_key": secret(&config.claude_api_key),
+        "claude_base_url": config.claude_base_url,
+        "gemini_oauth_creds_base64": secret(&config.gemini_oauth_creds_base64),
+        "gemini_oauth_creds_file_path": config.gemini_oauth_creds_file_path,
+        "project_id": config.project_id,
+        "kiro_oauth_creds_base64": secret(&config.kiro_oauth_creds_base64),
+        "kiro_oauth_creds_file_path": config.kiro_oauth_creds_file_path,
+        "qwen_oauth_creds_file_path": config.qwen_oauth_creds_file_path,
+        "request_max_retries": config.request_max_retries,
+        "request_base_delay": config.request_base_delay,
+        "request_max_delay": config.request_max_delay,
+        "request_deadline": config.request_deadline,
+        "retry_budget_ratio": config.retry_budget_ratio,
+        "retry_budget_min_retries": config.retry_budget_min_retries,
+        "retry_budget_window_secs": config.retry_budget_window_secs,
+        "http": config.http_client_for(&config.model_provider),
+        "http_proxy": secret(&config.http_client_for(&config.model_provider).proxy),
+    })
+}
+
+/// Members whose adapter could not be created must not be selected
+async fn mark_members_without_adapter(runtime: &Runtime, pool_manager: &ProviderPoolManager) {
+    for (provider_type, members) in &runtime.config.provider_pools {
+        for member in members {
+            if !runtime.pool_adapters.contains_key(&member.uuid) {
+                pool_manager.mark_provider_unhealthy(provider_type, &member.uuid).await;
+            }
+        }
+    }
+}
+
+/// Members that had no adapter before and have one now get their configured health back
+async fn restore_members_with_new_adapter(previous: &Runtime, runtime: &Runtime, pool_manager: &ProviderPoolManager) {
+    for (provider_type, members) in &runtime.config.provider_pools {
+        for member in members {
+            let adapter_built =
+                runtime.pool_adapters.contains_key(&member.uuid) && !previous.pool_adapters.contains_key(&member.uuid);
+            if adapter_built && member.is_healthy {
+                pool_manager.mark_provider_healthy(provider_type, &member.uuid).await;
+            }
+        }
+    }
+}
+
+/// Watch config.json and the pools file for changes and listen for SIGHUP
+pub fn spawn_reload_watchers(handle: Arc<RuntimeHandle>) {
+    let watcher = handle.clone();
+    tokio::spawn(async move {
+        let mut last_seen = watched_mtimes(&watcher.current().await.config);
+        loop {
+            let runtime = watcher.current().await;
+            let interval = runtime.config.hot_reload_interval_secs.max(1);
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+            if runtime.config.hot_reload && watched_mtimes(&runtime.config) != last_seen {
+                info!("Configuration file change detected");
+                watcher.reload_logged().await;
+            }
+            last_seen = watched_mtimes(&watcher.current().await.config);
+        }
+    });
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to install SIGHUP handler: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP");
+            handle.reload_logged().await;
+        }
+    });
+}
+
+fn watched_mtimes(config: &Config) -> Vec<Option<SystemTime>> {
+    let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    vec![
+        mtime(&config.config_file_path),
+        config.provider_pools_file_path.as_deref().and_then(mtime),
+    ]
+}
@@ -0,0 +1,257 @@
+/*!
+ * Upstream Retry Policy
+ *
+ * One retry loop shared by all providers:
+ *
+ * - connection errors, timeouts, 408, 429 and 5xx responses are retried; other statuses are not
+ * - the delay is exponential backoff with full jitter (a random time between 0 and
+ *   `base_delay * 2^attempt`, capped at `max_delay`), unless the server says how long to wait
+ *   with `Retry-After` or a Google `RetryInfo` error detail
+ * - the per-request deadline covers every attempt: no retry is started that would end after it,
+ *   and an attempt still waiting for its response headers when it passes is cut off
+ * - a global budget allows retries for only a fraction of recent requests, so retries can't
+ *   multiply the load on an upstream that is already failing
+ *
+ * Streaming requests are retried the same way until the response headers arrive; once a stream
+ * has started it is never retried.
+ *
+ * The deadline ends with the response headers. The provider's `request_timeout` is the limit for
+ * the whole call, reading the response body included, so it should be the longer of the two; when
+ * it is shorter it cuts the retries off first.
+ */
+
+use anyhow::Result;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::warn;
+
+const GOOGLE_RETRY_INFO: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+/// An upstream attempt that was still waiting for its response when the request deadline passed
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Upstream request passed its {0:?} deadline")]
+pub struct DeadlineExceeded(pub Duration);
+
+/// Retry settings taken from the configuration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetrySettings {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Total time for a request including all retries
+    pub deadline: Duration,
+    /// Retries allowed per request in the budget window, on top of `budget_min_retries`
+    pub budget_ratio: f64,
+    pub budget_min_retries: u32,
+    pub budget_window: Duration,
+}
+
+/// A non-success response from the upstream API
+#[derive(Debug, Error)]
+#[error("API call failed ({status}): {body}")]
+pub struct UpstreamStatusError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+/// HTTP status of the upstream response behind an error, if any
+pub fn upstream_status(error: &anyhow::Error) -> Option<StatusCode> {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<UpstreamStatusError>())
+        .map(|e| e.status)
+}
+
+/// Statuses worth retrying: timeouts, rate limits and server errors (incl. 529 overloaded)
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// Whether an upstream error may succeed when the request is sent again
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        if let Some(e) = e.downcast_ref::<UpstreamStatusError>() {
+            is_retryable_status(e.status)
+        } else if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            // Failures to connect or send the request; not body decoding errors
+            e.is_connect() || e.is_timeout() || (e.is_request() && !e.is_builder())
+        } else {
+            false
+        }
+    })
+}
+
+/// Delay from a `Retry-After` header (seconds or an HTTP date)
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let remaining = date.signed_duration_since(chrono::Utc::now());
+    Some(remaining.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Delay from a Google `RetryInfo` detail in an error body (`"retryDelay": "1.5s"`)
+pub fn google_retry_delay(body: &str) -> Option<Duration> {
+    let body: serde_json::Value = serde_json::from_str(body).ok()?;
+    let error = match &body {
+        // Some Google endpoints wrap the error in an array
+        serde_json::Value::Array(items) => items.first()?.get("error")?,
+        _ => body.get("error")?,
+    };
+    error
+        .get("details")?
+        .as_array()?
+        .iter()
+        .filter(|detail| detail.get("@type").and_then(|t| t.as_str()) == Some(GOOGLE_RETRY_INFO))
+        .find_map(|detail| detail.get("retryDelay")?.as_str()?.strip_suffix('s')?.parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+/// Retries made across all providers in the recent window, kept across reloads
+#[derive(Default)]
+pub struct RetryBudget {
+    window: Mutex<BudgetWindow>,
+}
+
+#[derive(Default)]
+struct BudgetWindow {
+    requests: VecDeque<Instant>,
+    retries: VecDeque<Instant>,
+}
+
+impl BudgetWindow {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        for events in [&mut self.requests, &mut self.retries] {
+            while events.front().is_some_and(|t| now.duration_since(*t) >= window) {
+                events.pop_front();
+            }
+        }
+    }
+}
+
+impl RetryBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a new request (not a retry) towards the budget
+    pub fn record_request(&self, settings: &RetrySettings) {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        window.prune(now, settings.budget_window);
+        window.requests.push_back(now);
+    }
+
+    /// Take one retry from the budget; false if it is used up
+    pub fn try_acquire(&self, settings: &RetrySettings) -> bool {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        window.prune(now, settings.budget_window);
+        let allowed = settings.budget_min_retries as usize
+            + (window.requests.len() as f64 * settings.budget_ratio) as usize;
+        if window.retries.len() >= allowed {
+            return false;
+        }
+        window.retries.push_back(now);
+        true
+    }
+}
+
+/// Retry settings plus the shared budget, held by each adapter
+#[derive(Clone)]
+pub struct RetryPolicy {
+    settings: RetrySettings,
+    budget: Arc<RetryBudget>,
+}
+
+impl RetryPolicy {
+    pub fn new(settings: RetrySettings, budget: Arc<RetryBudget>) -> Self {
+        Self { settings, budget }
+    }
+
+    pub fn settings(&self) -> &RetrySettings {
+        &self.settings
+    }
+
+    /// Full-jitter backoff before retry number `attempt + 1`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .settings
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.settings.max_delay);
+        let mut bytes = [0u8; 8];
+        if SystemRandom::new().fill(&mut bytes).is_err() {
+            return ceiling;
+        }
+        ceiling.mul_f64(u64::from_le_bytes(bytes) as f64 / u64::MAX as f64)
+    }
+
+    /// Send a request until it succeeds or the error is final.
+    ///
+    /// `send` is called once per attempt. Non-success responses are returned as
+    /// `UpstreamStatusError` with the response body.
+    pub async fn send<F, Fut>(&self, mut send: F) -> Result<reqwest::Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<reqwest::Response>>,
+    {
+        let settings = &self.settings;
+        let start = Instant::now();
+        let deadline = start + settings.deadline;
+        self.budget.record_request(settings);
+
+        let mut attempt = 0;
+        loop {
+            let Ok(result) = tokio::time::timeout_at(deadline, send()).await else {
+                return Err(DeadlineExceeded(settings.deadline).into());
+            };
+            let (error, hint) = match result {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let header_hint = retry_after(response.headers());
+                    let body = response.text().await.unwrap_or_default();
+                    let hint = header_hint.or_else(|| google_retry_delay(&body));
+                    (anyhow::Error::from(UpstreamStatusError { status, body }), hint)
+                }
+                Err(e) => (e, None),
+            };
+
+            if attempt >= settings.max_retries || !is_retryable(&error) {
+                return Err(error);
+            }
+            let delay = hint.unwrap_or_else(|| self.backoff(attempt));
+            if start.elapsed() + delay > settings.deadline {
+                warn!("Not retrying upstream error, the next attempt would pass the deadline: {}", error);
+                return Err(error);
+            }
+            if !self.budget.try_acquire(settings) {
+                warn!("Not retrying upstream error, retry budget exhausted: {}", error);
+                return Err(error);
+            }
+
+            attempt += 1;
+            warn!(
+                "Upstream request failed ({}), retry {}/{} in {}ms",
+                error,
+                attempt,
+                settings.max_retries,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
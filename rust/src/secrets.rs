@@ -0,0 +1,151 @@
+/*!
+ * Secret References
+ *
+ * Secret config values may be written as references that are resolved when the configuration
+ * is loaded (and again on every reload):
+ *
+ * - `env:NAME` - value of environment variable `NAME`
+ * - `file:/run/secrets/x` - contents of a file (trailing newline removed, `~` expanded)
+ * - `cmd:pass show openai` - stdout of a shell command
+ *
+ * Anything else is a literal, in which `${VAR}` references are expanded. A `Secret` keeps the
+ * text as written so that serializing the config writes the reference back, never the
+ * resolved value, and its `Debug` output never contains the resolved value either.
+ */
+
+use crate::config::{expand_env_vars, expand_path};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// A secret config value: the reference as written plus its resolved value
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Secret {
+    reference: String,
+    value: String,
+}
+
+impl Secret {
+    /// Unresolved secret; until `resolve` is called the value is the text as written
+    pub fn new(reference: impl Into<String>) -> Self {
+        let reference = reference.into();
+        Self {
+            value: reference.clone(),
+            reference,
+        }
+    }
+
+    /// Secret whose value was resolved elsewhere
+    pub fn resolved(reference: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            reference: reference.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Resolve the reference (see module docs)
+    pub fn resolve(&mut self) -> Result<()> {
+        self.value = resolve_reference(&self.reference)?;
+        Ok(())
+    }
+
+    /// The resolved value
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+
+    /// The text as written in the configuration
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(reference: String) -> Self {
+        Self::new(reference)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(reference: &str) -> Self {
+        Self::new(reference)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if is_reference(&self.reference) {
+            write!(f, "Secret({:?})", self.reference)
+        } else {
+            write!(f, "Secret(***)")
+        }
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.reference)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// Whether a config value is a reference rather than the secret itself (safe to display)
+pub fn is_reference(value: &str) -> bool {
+    ["env:", "file:", "cmd:"].iter().any(|prefix| value.starts_with(prefix)) || value.contains("${")
+}
+
+/// Resolve `env:`, `file:` and `cmd:` references; literals only get `${VAR}` expansion
+pub fn resolve_reference(reference: &str) -> Result<String> {
+    if let Some(name) = reference.strip_prefix("env:") {
+        let name = name.trim();
+        return std::env::var(name).with_context(|| format!("Environment variable {} is not set", name));
+    }
+
+    if let Some(path) = reference.strip_prefix("file:") {
+        let path = expand_path(path.trim())?;
+        let content =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read secret file {}", path))?;
+        return Ok(content.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    if let Some(command) = reference.strip_prefix("cmd:") {
+        let output = shell_command(command.trim())
+            .output()
+            .with_context(|| format!("Failed to run secret command `{}`", command.trim()))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Secret command `{}` failed with {}: {}",
+                command.trim(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8(output.stdout).context("Secret command printed invalid UTF-8")?;
+        return Ok(stdout.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    expand_env_vars(reference)
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> std::process::Command {
+    let mut cmd = std::process::Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> std::process::Command {
+    let mut cmd = std::process::Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
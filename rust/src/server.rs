@@ -2,57 +2,80 @@
  * HTTP server implementation
  */
 
-use crate::adapter::{create_adapter, ApiServiceAdapter};
-use crate::common::*;
-use crate::config::Config;
+use crate::adapter::ApiServiceAdapter;
+use crate::admin::admin_routes;
+use crate::auth::{constant_time_eq, presented_keys, AuthEndpoint};
+use crate::cancellation::CancelGuard;
+use crate::concurrency::{QueueError, PRIMARY_KEY};
+use crate::client_keys::{KeyRejection, TokenUsage};
+use crate::common::{ModelProtocol, ModelProvider};
+use crate::config::{ClientKeyConfig, Config};
+use crate::convert::{convert_data, convert_request, ConversionType};
+use crate::ir::{self, structured, ContentPart, Message, ParamPolicy, ResponseFormat, Role, UnsupportedContent};
+use crate::pool_manager::PoolLease;
+use crate::pool_selection::derive_session_key;
+use crate::reload::{spawn_reload_watchers, Runtime, RuntimeHandle};
+use crate::shutdown::{spawn_signal_handler, Shutdown};
+use crate::transport::{cors_layer, listen_addrs, serve, spawn_cert_watcher, ListenAddr, Listener, TlsCertificates};
 use anyhow::Result;
 use axum::{
-    body::Body,
-    extract::{Path, Query, State},
-    http::{HeaderMap, Method, StatusCode},
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Path, Query, State},
+    http::{request::Parts, HeaderMap, StatusCode},
     response::{IntoResponse, Response, Sse},
     response::sse::Event,
     routing::{get, post},
     Json, Router,
 };
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tower_http::cors::{Any, CorsLayer};
-use tracing::{error, info};
+use std::time::Duration;
+use tokio::sync::OwnedSemaphorePermit;
+use tracing::{error, info, warn};
 
 /// Application state
 pub struct AppState {
-    pub config: Config,
-    pub adapter: Box<dyn ApiServiceAdapter>,
+    pub runtime: Arc<RuntimeHandle>,
+    pub shutdown: Arc<Shutdown>,
+    /// Serializes admin changes to the pools file, from snapshot to reload or rollback
+    pub pools_file_lock: tokio::sync::Mutex<()>,
 }
 
 /// Start the HTTP server
 pub async fn start_server(config: Config) -> Result<()> {
-    let host = config.host.clone();
-    let port = config.port;
-    let addr = format!("{}:{}", host, port);
+    let addrs = listen_addrs(&config)?;
+    let cors = cors_layer(&config)?;
+    let tls = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => {
+            let certs = TlsCertificates::load(cert, key)?;
+            spawn_cert_watcher(certs.clone(), Duration::from_secs(config.hot_reload_interval_secs.max(1)));
+            Some(certs.acceptor()?)
+        }
+        _ => None,
+    };
+
+    let config_drain_timeout = config.shutdown_drain_timeout_secs;
 
-    // Create adapter
-    let provider = ModelProvider::parse(&config.model_provider)
-        .ok_or_else(|| anyhow::anyhow!("Invalid model provider: {}", config.model_provider))?;
-    let adapter = create_adapter(provider, &config).await?;
+    // Create adapters and pools; reloads re-parse the same command line
+    let runtime = Arc::new(RuntimeHandle::new(config, std::env::args().collect()).await?);
+    spawn_reload_watchers(runtime.clone());
+
+    let shutdown = Arc::new(Shutdown::new());
+    spawn_signal_handler(shutdown.clone());
+    let drain_timeout = Duration::from_secs(config_drain_timeout);
 
     // Create application state
-    let state = Arc::new(AppState { 
-        config: config.clone(),
-        adapter,
+    let state = Arc::new(AppState {
+        runtime: runtime.clone(),
+        shutdown: shutdown.clone(),
+        pools_file_lock: tokio::sync::Mutex::new(()),
     });
-    let state_clone = state.clone();
-
-    // Build CORS layer
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS])
-        .allow_headers(Any);
+    let state_clone = state.current_runtime().await;
 
     // Build application router
     let app = Router::new()
@@ -68,111 +91,287 @@ pub async fn start_server(config: Config) -> Result<()> {
         .route("/:provider/v1/chat/completions", post(openai_chat_handler))
         .route("/:provider/v1/models", get(openai_models_handler))
         .route("/:provider/v1/messages", post(claude_messages_handler))
+        .merge(admin_routes())
         .with_state(state)
         .layer(cors);
 
-    // Create TCP listener
-    let listener = TcpListener::bind(&addr).await?;
+    // Bind all listeners before serving so a bad address fails startup
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in &addrs {
+        listeners.push(Listener::bind(addr).await?);
+    }
 
     info!("--- Unified API Server Configuration ---");
-    info!("  Host: {}", host);
-    info!("  Port: {}", port);
+    for addr in &addrs {
+        let scheme = match (addr, &tls) {
+            (ListenAddr::Tcp(_), Some(_)) => "https",
+            _ => "http",
+        };
+        info!("  Listening: {} ({})", addr, scheme);
+    }
     info!("  Primary Model Provider: {}", state_clone.config.model_provider);
+    info!("  Pool members: {}", state_clone.pool_adapters.len());
     info!("------------------------------------------");
-    info!("\nUnified API Server running on http://{}", addr);
     info!("Supports multiple API formats:");
     info!("  • OpenAI-compatible: /v1/chat/completions, /v1/models");
     info!("  • Gemini-compatible: /v1beta/models, /v1beta/models/{{model}}:generateContent");
     info!("  • Claude-compatible: /v1/messages");
     info!("  • Health check: /health");
+    info!("  • Pool admin: /admin/pools");
 
-    // Start serving
-    axum::serve(listener, app).await?;
-
+    // Start serving; returns once shutdown was requested and connections are drained
+    serve(listeners, app, tls, shutdown, drain_timeout).await?;
+    flush_state(&runtime).await;
     Ok(())
 }
 
+/// Persist pool state and flush log output before exiting
+async fn flush_state(runtime: &RuntimeHandle) {
+    // Dropped pool leases record their outcome on spawned tasks; wait for them to finish
+    runtime.pool_manager.settle_leases().await;
+
+    if let Some(path) = runtime.current().await.config.provider_pools_file_path.clone() {
+        match runtime.pool_manager.save_to_file(&path).await {
+            Ok(()) => info!("Saved provider pool state to {}", path.display()),
+            Err(e) => error!("Failed to save provider pool state: {:#}", e),
+        }
+    }
+
+    info!("Shutdown complete");
+    use std::io::Write;
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+}
+
+impl AppState {
+    async fn current_runtime(&self) -> Arc<Runtime> {
+        self.runtime.current().await
+    }
+
+    /// Pick the adapter for a request: a pool member of the primary provider if a pool is
+    /// configured (sticky per session when enabled), otherwise the primary adapter.
+    ///
+    /// Waits for a free slot if the chosen credential has a concurrency limit; the returned
+    /// permit must be held until the upstream call (or stream) is finished.
+    async fn select_adapter(
+        &self,
+        runtime: &Runtime,
+        headers: &HeaderMap,
+        body: &Value,
+    ) -> Result<(Arc<dyn ApiServiceAdapter>, Option<PoolLease>, Option<OwnedSemaphorePermit>), AppError> {
+        let config = &runtime.config;
+        let (adapter, lease, limit_key, max_concurrency) = match self.select_pool_member(runtime, headers, body).await {
+            Some((adapter, lease, max_concurrency)) => {
+                let key = lease.uuid().to_string();
+                (adapter, Some(lease), key, max_concurrency)
+            }
+            None => (runtime.adapter.clone(), None, PRIMARY_KEY.to_string(), config.max_concurrency),
+        };
+
+        let Some(limiter) = self
+            .runtime
+            .concurrency
+            .limiter(&limit_key, max_concurrency, config.max_queue_size)
+        else {
+            return Ok((adapter, lease, None));
+        };
+
+        match limiter.acquire(Duration::from_millis(config.queue_timeout_ms)).await {
+            Ok(permit) => Ok((adapter, lease, Some(permit))),
+            Err(e) => {
+                warn!("Concurrency limit reached for {}: {}", limit_key, e);
+                Err(match e {
+                    QueueError::QueueFull => AppError::TooManyRequests(e.to_string()),
+                    QueueError::Timeout(_) => AppError::ServiceUnavailable(e.to_string()),
+                })
+            }
+        }
+    }
+
+    async fn select_pool_member(
+        &self,
+        runtime: &Runtime,
+        headers: &HeaderMap,
+        body: &Value,
+    ) -> Option<(Arc<dyn ApiServiceAdapter>, PoolLease, Option<u32>)> {
+        let pool_manager = &self.runtime.pool_manager;
+        let provider_type = runtime.config.model_provider.as_str();
+        if !pool_manager.has_pool(provider_type).await {
+            return None;
+        }
+
+        let session_key = if runtime.config.sticky_sessions {
+            let header = headers
+                .get(runtime.config.sticky_session_header.as_str())
+                .and_then(|v| v.to_str().ok());
+            derive_session_key(header, body)
+        } else {
+            None
+        };
+
+        let Some(member) = pool_manager
+            .select_provider_for_session(provider_type, session_key.as_deref())
+            .await
+        else {
+            warn!("No healthy pool member for {}, using primary adapter", provider_type);
+            return None;
+        };
+
+        let lease = pool_manager.lease(provider_type, &member.uuid);
+        let adapter = runtime.pool_adapters.get(&member.uuid)?.clone();
+        Some((adapter, lease, member.max_concurrency))
+    }
+}
+
+/// IP address of the connected peer, if the listener provides one
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip())))
+    }
+}
+
+/// Check the credentials a request presents in the locations `endpoint` accepts. `find` maps
+/// the presented keys to the authenticated identity.
+///
+/// Failures are logged and counted per client IP; an IP with too many recent failures is
+/// rejected with 429 until its lockout expires, even if its key is valid.
+pub fn authenticate<T>(
+    state: &AppState,
+    config: &Config,
+    endpoint: AuthEndpoint,
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+    find: impl FnOnce(&[&str]) -> Option<T>,
+) -> Result<T, AppError> {
+    let failures = &state.runtime.auth_failures;
+    if let Some(remaining) = peer.and_then(|ip| failures.locked_for(ip)) {
+        return Err(AppError::TooManyRequests(format!(
+            "Too many failed authentication attempts, try again in {}s",
+            remaining.as_secs() + 1
+        )));
+    }
+
+    let keys = presented_keys(headers, params, &config.credential_locations(endpoint));
+    if let Some(identity) = find(&keys) {
+        if let Some(ip) = peer {
+            failures.record_success(ip);
+        }
+        return Ok(identity);
+    }
+
+    let client = peer.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown client".to_string());
+    let policy = config.lockout_policy();
+    let (count, locked) = peer.map_or((1, false), |ip| failures.record_failure(ip, &policy));
+    warn!(
+        "Authentication failed on {} endpoint from {} ({} key(s) presented, {} recent failures)",
+        endpoint.as_str(),
+        client,
+        keys.len(),
+        count
+    );
+    if locked {
+        warn!("Locking out {} for {}s after repeated authentication failures", client, policy.duration.as_secs());
+    }
+    Err(AppError::Unauthorized)
+}
+
+/// Identify the client by its API key: an entry of `api_keys`, or `required_api_key` (labelled
+/// "default") when no key table is configured
+fn authorize(
+    state: &AppState,
+    runtime: &Runtime,
+    endpoint: AuthEndpoint,
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+) -> Result<ClientKeyConfig, AppError> {
+    let config = &runtime.config;
+    authenticate(state, config, endpoint, peer, headers, params, |keys| {
+        if config.api_keys.is_empty() {
+            let required = config.required_api_key.expose();
+            keys.iter()
+                .any(|key| constant_time_eq(key, required))
+                .then(|| ClientKeyConfig::unrestricted("default", config.required_api_key.clone()))
+        } else {
+            config
+                .api_keys
+                .iter()
+                .find(|client_key| keys.iter().any(|key| client_key.matches(key)))
+                .cloned()
+        }
+    })
+}
+
+/// Enforce the client key's scopes and limits for a request to `model` on the primary provider
+fn admit(state: &AppState, runtime: &Runtime, client: &ClientKeyConfig, model: &str) -> Result<(), AppError> {
+    let provider = runtime.config.model_provider.as_str();
+    state.runtime.client_keys.admit(client, provider, model).map_err(|e| {
+        warn!("Rejected request from client {} for {}/{}: {}", client.label, provider, model, e);
+        match e {
+            KeyRejection::RateLimited(_) | KeyRejection::QuotaExhausted(_) => AppError::TooManyRequests(e.to_string()),
+            _ => AppError::Forbidden(e.to_string()),
+        }
+    })
+}
+
 /// Health check handler
 async fn health_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    Json(json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now().to_rfc3339(),
-        "provider": state.config.model_provider
-    }))
+    let runtime = state.current_runtime().await;
+    // Tell load balancers to stop routing here while connections drain
+    let (status, health) = if state.shutdown.is_triggered() {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting_down")
+    } else {
+        (StatusCode::OK, "healthy")
+    };
+    (
+        status,
+        Json(json!({
+            "status": health,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "provider": runtime.config.model_provider
+        })),
+    )
 }
 
 /// OpenAI chat completions handler
 async fn openai_chat_handler(
     State(state): State<Arc<AppState>>,
-    provider_path: Option<Path<String>>,
+    _provider_path: Option<Path<String>>,
+    ClientIp(peer): ClientIp,
     headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
     Json(body): Json<Value>,
 ) -> Result<Response, AppError> {
     // Check authorization
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let api_key_header = headers.get("x-api-key").and_then(|v| v.to_str().ok());
-    let goog_api_key = headers.get("x-goog-api-key").and_then(|v| v.to_str().ok());
-    let query_key = params.get("key").map(|s| s.as_str());
-
-    if !is_authorized(
-        auth_header,
-        api_key_header,
-        goog_api_key,
-        query_key,
-        &state.config.required_api_key,
-    ) {
-        return Err(AppError::Unauthorized);
-    }
+    let runtime = state.current_runtime().await;
+    let client = authorize(&state, &runtime, AuthEndpoint::OpenAi, peer, &headers, &params)?;
+    let model = body.get("model").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    admit(&state, &runtime, &client, &model)?;
 
-    info!("Received OpenAI chat request");
+    info!("Received OpenAI chat request from client {}", client.label);
 
-    // TODO: Implement actual request handling
-    Ok(Json(json!({
-        "id": "chatcmpl-123",
-        "object": "chat.completion",
-        "created": chrono::Utc::now().timestamp(),
-        "model": "test-model",
-        "choices": [{
-            "index": 0,
-            "message": {
-                "role": "assistant",
-                "content": "This is a placeholder response from Rust implementation"
-            },
-            "finish_reason": "stop"
-        }],
-        "usage": {
-            "prompt_tokens": 0,
-            "completion_tokens": 0,
-            "total_tokens": 0
-        }
-    }))
-    .into_response())
+    let stream = body.get("stream").and_then(|v| v.as_bool()).unwrap_or(false);
+    generate(&state, &runtime, &client, &headers, ModelProtocol::OpenAI, &model, stream, body).await
 }
 
 /// OpenAI models list handler
 async fn openai_models_handler(
     State(state): State<Arc<AppState>>,
+    ClientIp(peer): ClientIp,
     headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<Response, AppError> {
     // Check authorization
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let api_key_header = headers.get("x-api-key").and_then(|v| v.to_str().ok());
-    let goog_api_key = headers.get("x-goog-api-key").and_then(|v| v.to_str().ok());
-    let query_key = params.get("key").map(|s| s.as_str());
-
-    if !is_authorized(
-        auth_header,
-        api_key_header,
-        goog_api_key,
-        query_key,
-        &state.config.required_api_key,
-    ) {
-        return Err(AppError::Unauthorized);
-    }
+    let runtime = state.current_runtime().await;
+    let client = authorize(&state, &runtime, AuthEndpoint::OpenAi, peer, &headers, &params)?;
 
-    info!("Received OpenAI models list request");
+    info!("Received OpenAI models list request from client {}", client.label);
 
     // TODO: Implement actual model listing
     Ok(Json(json!({
@@ -185,114 +384,318 @@ async fn openai_models_handler(
 /// Claude messages handler
 async fn claude_messages_handler(
     State(state): State<Arc<AppState>>,
+    ClientIp(peer): ClientIp,
     headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
     Json(body): Json<Value>,
 ) -> Result<Response, AppError> {
     // Check authorization
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let api_key_header = headers.get("x-api-key").and_then(|v| v.to_str().ok());
-    let goog_api_key = headers.get("x-goog-api-key").and_then(|v| v.to_str().ok());
-    let query_key = params.get("key").map(|s| s.as_str());
-
-    if !is_authorized(
-        auth_header,
-        api_key_header,
-        goog_api_key,
-        query_key,
-        &state.config.required_api_key,
-    ) {
-        return Err(AppError::Unauthorized);
-    }
+    let runtime = state.current_runtime().await;
+    let client = authorize(&state, &runtime, AuthEndpoint::Claude, peer, &headers, &params)?;
 
-    info!("Received Claude messages request");
+    info!("Received Claude messages request from client {}", client.label);
 
     // Extract model from request  
     let model = body.get("model")
         .and_then(|v| v.as_str())
         .unwrap_or("claude-3-5-sonnet-20241022")
         .to_string();
+    admit(&state, &runtime, &client, &model)?;
 
     // Check if streaming is requested
     let stream = body.get("stream")
         .and_then(|v| v.as_bool())
         .unwrap_or(false);
 
+    generate(&state, &runtime, &client, &headers, ModelProtocol::Claude, &model, stream, body).await
+}
+
+/// Serve a generation request in the client's protocol: pick a credential, convert the request
+/// for the provider and its answer (or stream) back, and record the outcome against the lease
+/// and the client key
+#[allow(clippy::too_many_arguments)]
+async fn generate(
+    state: &AppState,
+    runtime: &Runtime,
+    client: &ClientKeyConfig,
+    headers: &HeaderMap,
+    protocol: ModelProtocol,
+    model: &str,
+    stream: bool,
+    body: Value,
+) -> Result<Response, AppError> {
+    let provider = ModelProvider::parse(&runtime.config.model_provider)
+        .ok_or_else(|| anyhow::anyhow!("Unknown model provider: {}", runtime.config.model_provider))?
+        .protocol();
+    let policy = runtime.config.param_policy();
+    let request = convert_request(body.clone(), protocol, provider, Some(model), policy)?;
+    let cancel_guard = client_cancel_guard(state, client, model);
+
     if stream {
         // Handle streaming response
-        info!("Streaming response requested for Claude messages");
-        
-        match state.adapter.generate_content_stream(&model, body).await {
+        info!("Streaming response requested for {} request", protocol.as_str());
+        if runtime.config.validate_structured_output {
+            info!("Structured output is only validated for non-streaming responses; passing the stream through");
+        }
+
+        let (adapter, mut lease, permit) = state.select_adapter(runtime, headers, &body).await?;
+        match adapter.generate_content_stream(model, request).await {
             Ok(stream) => {
-                // Convert the stream to SSE format
-                // Claude API uses simple SSE format with only 'data:' lines
-                let sse_stream = stream.map(|result| {
-                    match result {
-                        Ok(chunk) => {
-                            // Format as SSE event with event type based on chunk type
-                            let data = serde_json::to_string(&chunk).unwrap_or_default();
-                            let event_type = chunk.get("type").and_then(|t| t.as_str()).unwrap_or("message");
-                            Ok::<_, Infallible>(Event::default().event(event_type).data(data))
-                        }
-                        Err(e) => {
-                            error!("Stream error: {}", e);
-                            // Send error event
-                            let error_data = json!({
-                                "type": "error",
-                                "error": {
-                                    "message": e.to_string()
-                                }
-                            });
-                            Ok(Event::default().event("error").data(serde_json::to_string(&error_data).unwrap_or_default()))
-                        }
-                    }
-                });
-                
-                Ok(Sse::new(sse_stream).into_response())
+                let stream = convert_stream(stream, provider, protocol, model)?;
+                let stream = record_stream_usage(stream, state.runtime.clone(), client.label.clone());
+                let stream = state.shutdown.guard_stream(with_lease(stream, lease, permit));
+                let stream = cancel_guard.guard_stream(stream);
+                Ok(sse_response(protocol, stream))
             }
             Err(e) => {
                 error!("Failed to start streaming: {}", e);
-                Err(AppError::InternalError(e))
+                cancel_guard.complete();
+                if let Some(lease) = lease.as_mut() {
+                    if !e.is::<UnsupportedContent>() {
+                        lease.fail();
+                    }
+                }
+                Err(e.into())
             }
         }
     } else {
         // Handle non-streaming response
-        match state.adapter.generate_content(&model, body).await {
+        let format = runtime
+            .config
+            .validate_structured_output
+            .then(|| ir::parse_request(protocol, &body).ok()?.response_format)
+            .flatten();
+        let result = match format {
+            Some(format) => {
+                generate_checked(state, runtime, headers, model, protocol, provider, policy, &body, request, &format).await
+            }
+            None => generate_once(state, runtime, headers, &body, model, request).await,
+        };
+        cancel_guard.complete();
+
+        let result = result.and_then(|response| {
+            convert_data(response, ConversionType::Response, provider, protocol, Some(model)).map_err(AppError::from)
+        });
+        match result {
             Ok(response) => {
-                info!("Claude messages request completed successfully");
+                let mut usage = TokenUsage::default();
+                usage.observe(&response);
+                state.runtime.client_keys.record_tokens(&client.label, usage.total());
+                info!(
+                    "{} request from client {} completed successfully ({} tokens)",
+                    protocol.as_str(),
+                    client.label,
+                    usage.total()
+                );
                 Ok(Json(response).into_response())
             }
             Err(e) => {
-                error!("Claude messages request failed: {}", e);
-                Err(AppError::InternalError(e))
+                error!("{} request failed: {:?}", protocol.as_str(), e);
+                Err(e)
             }
         }
     }
 }
 
+/// One non-streaming upstream call on a credential picked for `body`, holding its own pool lease
+/// and concurrency permit and recording its outcome
+async fn generate_once(
+    state: &AppState,
+    runtime: &Runtime,
+    headers: &HeaderMap,
+    body: &Value,
+    model: &str,
+    request: Value,
+) -> Result<Value, AppError> {
+    let (adapter, mut lease, _permit) = state.select_adapter(runtime, headers, body).await?;
+    let result = adapter.generate_content(model, request).await;
+    if let Some(lease) = lease.as_mut() {
+        match &result {
+            Ok(_) => lease.succeed(),
+            // The request never reached the provider
+            Err(e) if e.is::<UnsupportedContent>() => {}
+            Err(_) => lease.fail(),
+        }
+    }
+    Ok(result?)
+}
+
+/// Convert a provider stream chunk by chunk into the client's protocol
+fn convert_stream(
+    stream: Pin<Box<dyn Stream<Item = Result<Value>> + Send>>,
+    from: ModelProtocol,
+    to: ModelProtocol,
+    model: &str,
+) -> Result<Pin<Box<dyn Stream<Item = Result<Value>> + Send>>> {
+    if from == to {
+        return Ok(stream);
+    }
+
+    let mut converter = ir::StreamConverter::new(from, to, Some(model))?;
+    Ok(Box::pin(async_stream::stream! {
+        let mut stream = stream;
+        while let Some(item) = stream.next().await {
+            match item.and_then(|chunk| converter.convert(&chunk)) {
+                Ok(chunks) => {
+                    for chunk in chunks {
+                        yield Ok(chunk);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        for chunk in converter.finish() {
+            yield Ok(chunk);
+        }
+    }))
+}
+
+/// Send a response stream as server-sent events the way the client's protocol does: Claude
+/// names each event after its type, OpenAI ends with `[DONE]`, Gemini sends data lines only
+fn sse_response(protocol: ModelProtocol, stream: Pin<Box<dyn Stream<Item = Result<Value>> + Send>>) -> Response {
+    let events = stream.map(move |result| {
+        let chunk = result.unwrap_or_else(|e| {
+            error!("Stream error: {}", e);
+            json!({"type": "error", "error": {"message": e.to_string()}})
+        });
+        let data = serde_json::to_string(&chunk).unwrap_or_default();
+        let event = Event::default().data(data);
+        match protocol {
+            ModelProtocol::Claude => {
+                let event_type = chunk.get("type").and_then(|t| t.as_str()).unwrap_or("message");
+                Ok::<_, Infallible>(event.event(event_type))
+            }
+            _ => Ok(event),
+        }
+    });
+
+    if protocol == ModelProtocol::OpenAI {
+        let done = futures::stream::once(async { Ok(Event::default().data("[DONE]")) });
+        Sse::new(events.chain(done)).into_response()
+    } else {
+        Sse::new(events).into_response()
+    }
+}
+
+/// Generate a structured answer and check it against its schema, asking once more with the
+/// mismatch explained if it doesn't match. The second answer is returned either way.
+///
+/// The second request is the client's with the answer and the mismatch appended, converted the
+/// way the first one was; it is a call of its own, with its own lease and permit.
+#[allow(clippy::too_many_arguments)]
+async fn generate_checked(
+    state: &AppState,
+    runtime: &Runtime,
+    headers: &HeaderMap,
+    model: &str,
+    protocol: ModelProtocol,
+    provider: ModelProtocol,
+    policy: ParamPolicy,
+    body: &Value,
+    request: Value,
+    format: &ResponseFormat,
+) -> Result<Value, AppError> {
+    let response = generate_once(state, runtime, headers, body, model, request).await?;
+    let answer = ir::parse_response(provider, &response)?.text();
+    let Err(invalid) = structured::validate(format, &answer) else {
+        return Ok(response);
+    };
+    warn!("Structured answer does not match its schema ({}), asking again", invalid);
+
+    let mut retry = ir::parse_request(protocol, body)?;
+    retry.model = Some(model.to_string());
+    retry.messages.push(Message::new(Role::Assistant, vec![ContentPart::Text(answer)]));
+    retry.messages.push(Message::new(Role::User, vec![ContentPart::Text(structured::retry_prompt(&invalid))]));
+    let request = ir::emit_request_with(provider, &retry, policy)?;
+    let response = generate_once(state, runtime, headers, body, model, request).await?;
+    let answer = ir::parse_response(provider, &response)?.text();
+    if let Err(invalid) = structured::validate(format, &answer) {
+        warn!("Structured answer still does not match its schema: {}", invalid);
+    }
+    Ok(response)
+}
+
+/// Guard that records a client cancel if the request is dropped before it completes. The
+/// dropped handler future (or response stream) takes the upstream request and its retries with it.
+fn client_cancel_guard(state: &AppState, client: &ClientKeyConfig, model: &str) -> CancelGuard {
+    let runtime = state.runtime.clone();
+    let label = client.label.clone();
+    let model = model.to_string();
+    CancelGuard::new(move |elapsed| {
+        info!(
+            "Request from client {} for {} ended by client-cancel after {}ms, upstream request aborted",
+            label,
+            model,
+            elapsed.as_millis()
+        );
+        runtime.client_keys.record_cancelled(&label);
+    })
+}
+
+/// Count the tokens of a response stream against the client key once the stream ends
+fn record_stream_usage(
+    stream: Pin<Box<dyn Stream<Item = Result<Value>> + Send>>,
+    runtime: Arc<RuntimeHandle>,
+    label: String,
+) -> Pin<Box<dyn Stream<Item = Result<Value>> + Send>> {
+    Box::pin(async_stream::stream! {
+        let mut stream = stream;
+        let mut usage = TokenUsage::default();
+        while let Some(item) = stream.next().await {
+            if let Ok(chunk) = &item {
+                usage.observe(chunk);
+            }
+            yield item;
+        }
+        runtime.client_keys.record_tokens(&label, usage.total());
+    })
+}
+
+/// Keep a pool lease and concurrency permit alive for the lifetime of a response stream and
+/// record its outcome
+fn with_lease(
+    stream: Pin<Box<dyn Stream<Item = Result<Value>> + Send>>,
+    lease: Option<PoolLease>,
+    permit: Option<OwnedSemaphorePermit>,
+) -> Pin<Box<dyn Stream<Item = Result<Value>> + Send>> {
+    if lease.is_none() && permit.is_none() {
+        return stream;
+    }
+
+    Box::pin(async_stream::stream! {
+        let _permit = permit;
+        let mut lease = lease;
+        let mut stream = stream;
+        let mut failed = false;
+        while let Some(item) = stream.next().await {
+            failed |= item.is_err();
+            yield item;
+        }
+        if let Some(lease) = lease.as_mut() {
+            if failed {
+                lease.fail();
+            } else {
+                lease.succeed();
+            }
+        }
+    })
+}
+
 /// Gemini models list handler
 async fn gemini_models_handler(
     State(state): State<Arc<AppState>>,
+    ClientIp(peer): ClientIp,
     headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<Response, AppError> {
     // Check authorization
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let api_key_header = headers.get("x-api-key").and_then(|v| v.to_str().ok());
-    let goog_api_key = headers.get("x-goog-api-key").and_then(|v| v.to_str().ok());
-    let query_key = params.get("key").map(|s| s.as_str());
-
-    if !is_authorized(
-        auth_header,
-        api_key_header,
-        goog_api_key,
-        query_key,
-        &state.config.required_api_key,
-    ) {
-        return Err(AppError::Unauthorized);
-    }
+    let runtime = state.current_runtime().await;
+    let client = authorize(&state, &runtime, AuthEndpoint::Gemini, peer, &headers, &params)?;
 
-    info!("Received Gemini models list request");
+    info!("Received Gemini models list request from client {}", client.label);
 
     // TODO: Implement actual model listing
     Ok(Json(json!({
@@ -305,41 +708,27 @@ async fn gemini_models_handler(
 async fn gemini_content_handler(
     State(state): State<Arc<AppState>>,
     Path((model, action)): Path<(String, String)>,
+    ClientIp(peer): ClientIp,
     headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
     Json(body): Json<Value>,
 ) -> Result<Response, AppError> {
     // Check authorization
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let api_key_header = headers.get("x-api-key").and_then(|v| v.to_str().ok());
-    let goog_api_key = headers.get("x-goog-api-key").and_then(|v| v.to_str().ok());
-    let query_key = params.get("key").map(|s| s.as_str());
-
-    if !is_authorized(
-        auth_header,
-        api_key_header,
-        goog_api_key,
-        query_key,
-        &state.config.required_api_key,
-    ) {
-        return Err(AppError::Unauthorized);
-    }
-
-    info!("Received Gemini content request for model: {}, action: {}", model, action);
-
-    // TODO: Implement actual request handling
-    Ok(Json(json!({
-        "candidates": [{
-            "content": {
-                "parts": [{
-                    "text": "This is a placeholder response from Rust implementation"
-                }],
-                "role": "model"
-            },
-            "finishReason": "STOP"
-        }]
-    }))
-    .into_response())
+    let runtime = state.current_runtime().await;
+    let client = authorize(&state, &runtime, AuthEndpoint::Gemini, peer, &headers, &params)?;
+    admit(&state, &runtime, &client, &model)?;
+
+    info!(
+        "Received Gemini content request from client {} for model: {}, action: {}",
+        client.label, model, action
+    );
+
+    let stream = match action.as_str() {
+        "streamGenerateContent" => true,
+        "generateContent" => false,
+        _ => return Err(AppError::NotFound(format!("Unknown action: {}", action))),
+    };
+    generate(&state, &runtime, &client, &headers, ModelProtocol::Gemini, &model, stream, body).await
 }
 
 /// Application error type
@@ -347,6 +736,10 @@ async fn gemini_content_handler(
 pub enum AppError {
     Unauthorized,
     BadRequest(String),
+    Forbidden(String),
+    NotFound(String),
+    TooManyRequests(String),
+    ServiceUnavailable(String),
     InternalError(anyhow::Error),
 }
 
@@ -358,6 +751,10 @@ impl IntoResponse for AppError {
                 "Unauthorized: API key is invalid or missing.".to_string(),
             ),
             Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            Self::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            Self::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            Self::TooManyRequests(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            Self::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             Self::InternalError(e) => {
                 error!("Internal error: {}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
@@ -370,6 +767,10 @@ impl IntoResponse for AppError {
 
 impl From<anyhow::Error> for AppError {
     fn from(err: anyhow::Error) -> Self {
+        // Requests that can't be converted for the upstream are the client's to fix
+        if err.is::<UnsupportedContent>() {
+            return Self::BadRequest(err.to_string());
+        }
         Self::InternalError(err)
     }
 }
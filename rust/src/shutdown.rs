@@ -0,0 +1,136 @@
+/*!
+ * Graceful Shutdown
+ *
+ * On SIGTERM or SIGINT the listeners stop accepting connections and in-flight requests are
+ * allowed to finish. Streaming responses get until the drain timeout; after that they are
+ * ended with an error event so clients can retry, while non-streaming requests still run to
+ * completion. A second signal exits immediately.
+ */
+
+use anyhow::Result;
+use futures::{FutureExt, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+type BoxStream<T> = Pin<Box<dyn Stream<Item = Result<T>> + Send>>;
+
+/// Shutdown state shared by the listeners and streaming responses
+pub struct Shutdown {
+    triggered: watch::Sender<bool>,
+    streams_ended: watch::Sender<bool>,
+    active_streams: Arc<AtomicUsize>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            triggered: watch::channel(false).0,
+            streams_ended: watch::channel(false).0,
+            active_streams: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Begin shutting down: listeners stop accepting connections
+    pub fn trigger(&self) {
+        self.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.triggered.borrow()
+    }
+
+    /// Resolves once `trigger` has been called
+    pub async fn triggered(&self) {
+        let mut rx = self.triggered.subscribe();
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+
+    /// End all streams wrapped by `guard_stream` (the drain timeout has passed)
+    pub fn end_streams(&self) {
+        self.streams_ended.send_replace(true);
+    }
+
+    /// Number of streaming responses still running
+    pub fn active_streams(&self) -> usize {
+        self.active_streams.load(Ordering::SeqCst)
+    }
+
+    /// Track a response stream and end it with an error item once `end_streams` is called
+    pub fn guard_stream<T: Send + 'static>(&self, stream: BoxStream<T>) -> BoxStream<T> {
+        let active = self.active_streams.clone();
+        let mut ended = self.streams_ended.subscribe();
+        Box::pin(async_stream::stream! {
+            active.fetch_add(1, Ordering::SeqCst);
+            let _active = ActiveStream(active);
+            let mut stream = stream;
+            loop {
+                tokio::select! {
+                    item = stream.next() => match item {
+                        Some(item) => yield item,
+                        None => break,
+                    },
+                    // The borrow guard returned by `wait_for` is not `Send`; drop it right away
+                    _ = ended.wait_for(|ended| *ended).map(|_| ()) => {
+                        yield Err(anyhow::anyhow!("Server is shutting down, please retry the request"));
+                        break;
+                    }
+                }
+            }
+        })
+    }
+}
+
+struct ActiveStream(Arc<AtomicUsize>);
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Trigger `shutdown` on the first SIGTERM or SIGINT; exit right away on a second one
+pub fn spawn_signal_handler(shutdown: Arc<Shutdown>) {
+    tokio::spawn(async move {
+        let signal = wait_for_signal().await;
+        info!("Received {}, shutting down gracefully", signal);
+        shutdown.trigger();
+
+        let signal = wait_for_signal().await;
+        warn!("Received {} again, exiting immediately", signal);
+        std::process::exit(130);
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+    let (mut terminate, mut interrupt) = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+        (Ok(terminate), Ok(interrupt)) => (terminate, interrupt),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("Failed to install SIGTERM/SIGINT handlers: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Failed to install Ctrl-C handler: {}", e);
+        return std::future::pending().await;
+    }
+    "Ctrl-C"
+}
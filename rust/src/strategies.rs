@@ -4,7 +4,6 @@
  * Defines strategy interfaces for handling different provider protocols.
  */
 
-use crate::common::*;
 use anyhow::Result;
 use async_trait::async_trait;
 
@@ -39,18 +38,18 @@ pub struct ClaudeStrategy;
 impl ProviderStrategy for GeminiStrategy {
     fn extract_model_and_stream_info(
         &self,
-        request: &serde_json::Value,
+        _request: &serde_json::Value,
     ) -> Result<(String, bool)> {
         // TODO: Implement
         Ok(("gemini-2.5-flash".to_string(), false))
     }
 
-    fn extract_response_text(&self, response: &serde_json::Value) -> Result<String> {
+    fn extract_response_text(&self, _response: &serde_json::Value) -> Result<String> {
         // TODO: Implement
         Ok(String::new())
     }
 
-    fn extract_prompt_text(&self, request: &serde_json::Value) -> Result<String> {
+    fn extract_prompt_text(&self, _request: &serde_json::Value) -> Result<String> {
         // TODO: Implement
         Ok(String::new())
     }
@@ -58,8 +57,8 @@ impl ProviderStrategy for GeminiStrategy {
     async fn apply_system_prompt_from_file(
         &self,
         request: serde_json::Value,
-        system_prompt: Option<&str>,
-        mode: &str,
+        _system_prompt: Option<&str>,
+        _mode: &str,
     ) -> Result<serde_json::Value> {
         // TODO: Implement
         Ok(request)
@@ -85,12 +84,12 @@ impl ProviderStrategy for OpenAIStrategy {
         Ok((model, stream))
     }
 
-    fn extract_response_text(&self, response: &serde_json::Value) -> Result<String> {
+    fn extract_response_text(&self, _response: &serde_json::Value) -> Result<String> {
         // TODO: Implement
         Ok(String::new())
     }
 
-    fn extract_prompt_text(&self, request: &serde_json::Value) -> Result<String> {
+    fn extract_prompt_text(&self, _request: &serde_json::Value) -> Result<String> {
         // TODO: Implement
         Ok(String::new())
     }
@@ -98,8 +97,8 @@ impl ProviderStrategy for OpenAIStrategy {
     async fn apply_system_prompt_from_file(
         &self,
         request: serde_json::Value,
-        system_prompt: Option<&str>,
-        mode: &str,
+        _system_prompt: Option<&str>,
+        _mode: &str,
     ) -> Result<serde_json::Value> {
         // TODO: Implement
         Ok(request)
@@ -125,12 +124,12 @@ impl ProviderStrategy for ClaudeStrategy {
         Ok((model, stream))
     }
 
-    fn extract_response_text(&self, response: &serde_json::Value) -> Result<String> {
+    fn extract_response_text(&self, _response: &serde_json::Value) -> Result<String> {
         // TODO: Implement
         Ok(String::new())
     }
 
-    fn extract_prompt_text(&self, request: &serde_json::Value) -> Result<String> {
+    fn extract_prompt_text(&self, _request: &serde_json::Value) -> Result<String> {
         // TODO: Implement
         Ok(String::new())
     }
@@ -138,8 +137,8 @@ impl ProviderStrategy for ClaudeStrategy {
     async fn apply_system_prompt_from_file(
         &self,
         request: serde_json::Value,
-        system_prompt: Option<&str>,
-        mode: &str,
+        _system_prompt: Option<&str>,
+        _mode: &str,
     ) -> Result<serde_json::Value> {
         // TODO: Implement
         Ok(request)
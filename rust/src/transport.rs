@@ -0,0 +1,379 @@
+/*!
+ * Listeners, TLS and CORS
+ *
+ * The server can listen on several addresses at once: TCP `host:port` addresses (optionally
+ * with TLS) and Unix domain sockets (`unix:/path/to.sock`). TLS certificates are re-read when
+ * the files change, so renewed certificates are picked up without a restart.
+ */
+
+use crate::config::Config;
+use crate::shutdown::Shutdown;
+use anyhow::{Context, Result};
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderName, HeaderValue, Method, Request};
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
+use tracing::{debug, error, info, warn};
+
+/// Prefix of Unix domain socket addresses in `listen`
+const UNIX_PREFIX: &str = "unix:";
+/// Time a client gets to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An address the server listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// `host:port`
+    Tcp(String),
+    /// `unix:/path/to.sock`
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                anyhow::bail!("Unix socket address needs a path: {}", s);
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(Self::Tcp(s.to_string())),
+            _ => anyhow::bail!("Invalid listen address (expected host:port or unix:/path): {}", s),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Addresses to listen on: `listen`, or `host:port` if it is empty
+pub fn listen_addrs(config: &Config) -> Result<Vec<ListenAddr>> {
+    if config.listen.is_empty() {
+        return Ok(vec![ListenAddr::Tcp(format!("{}:{}", config.host, config.port))]);
+    }
+    config.listen.iter().map(|addr| ListenAddr::parse(addr)).collect()
+}
+
+/// CORS layer from the `cors_*` settings; `*` allows any origin or header
+pub fn cors_layer(config: &Config) -> Result<CorsLayer> {
+    let origins = if config.cors_allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::from(Any)
+    } else {
+        let origins = config
+            .cors_allowed_origins
+            .iter()
+            .map(|o| HeaderValue::from_str(o).with_context(|| format!("Invalid CORS origin: {}", o)))
+            .collect::<Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    let methods = config
+        .cors_allowed_methods
+        .iter()
+        .map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()).with_context(|| format!("Invalid CORS method: {}", m)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let headers = if config.cors_allowed_headers.iter().any(|h| h == "*") {
+        AllowHeaders::from(Any)
+    } else {
+        let headers = config
+            .cors_allowed_headers
+            .iter()
+            .map(|h| HeaderName::from_bytes(h.as_bytes()).with_context(|| format!("Invalid CORS header: {}", h)))
+            .collect::<Result<Vec<_>>>()?;
+        AllowHeaders::list(headers)
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers))
+}
+
+/// Certificate and key loaded from PEM files, reloaded when either file changes
+pub struct TlsCertificates {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+    modified: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl fmt::Debug for TlsCertificates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsCertificates")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
+
+impl TlsCertificates {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Arc<Self>> {
+        let certified = load_certified_key(cert_path, key_path)?;
+        Ok(Arc::new(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(Arc::new(certified)),
+            modified: Mutex::new((modified_time(cert_path), modified_time(key_path))),
+        }))
+    }
+
+    /// Reload the certificate if a file changed since the last load. On error the previous
+    /// certificate stays in use.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let stamps = (modified_time(&self.cert_path), modified_time(&self.key_path));
+        let mut modified = self.modified.lock().unwrap_or_else(|e| e.into_inner());
+        if *modified == stamps {
+            return Ok(false);
+        }
+        // Record the attempt so a broken file is reported once, not on every check
+        *modified = stamps;
+
+        let certified = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(certified);
+        Ok(true)
+    }
+
+    /// Certificate chain currently served
+    pub fn current_chain(&self) -> Vec<CertificateDer<'static>> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).cert.clone()
+    }
+
+    /// TLS acceptor that always serves the current certificate
+    pub fn acceptor(self: &Arc<Self>) -> Result<TlsAcceptor> {
+        let mut server_config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(server_config)))
+    }
+}
+
+impl ResolvesServerCert for TlsCertificates {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificate {}", cert_path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {}", cert_path.display());
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read TLS key {}", key_path.display()))?;
+    let signing_key = any_supported_type(&key)
+        .with_context(|| format!("Unsupported TLS key in {}", key_path.display()))?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    // Catches a half-written renewal where only one of the two files was replaced yet
+    certified
+        .keys_match()
+        .with_context(|| format!("TLS key {} does not match certificate {}", key_path.display(), cert_path.display()))?;
+    Ok(certified)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Poll the certificate files and reload them when they change
+pub fn spawn_cert_watcher(certs: Arc<TlsCertificates>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match certs.reload_if_changed() {
+                Ok(true) => info!("Reloaded TLS certificate from {}", certs.cert_path.display()),
+                Ok(false) => {}
+                Err(e) => error!("Failed to reload TLS certificate, keeping the previous one: {:#}", e),
+            }
+        }
+    });
+}
+
+/// A bound listener
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Bind an address. A stale Unix socket left by a previous run is replaced; anything else at
+    /// the path, including a socket something still listens on, is an error.
+    pub async fn bind(addr: &ListenAddr) -> Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("Failed to bind {}", addr))?;
+                Ok(Self::Tcp(listener))
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    use std::os::unix::fs::FileTypeExt;
+                    if !metadata.file_type().is_socket() {
+                        anyhow::bail!("Failed to bind unix socket {}: path exists and is not a socket", path.display());
+                    }
+                    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                        anyhow::bail!("Failed to bind unix socket {}: another process is listening on it", path.display());
+                    }
+                    std::fs::remove_file(path)
+                        .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+                }
+                let listener = tokio::net::UnixListener::bind(path)
+                    .with_context(|| format!("Failed to bind unix socket {}", path.display()))?;
+                Ok(Self::Unix(listener, path.clone()))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(path) => anyhow::bail!("Unix sockets are not supported on this platform: {}", path.display()),
+        }
+    }
+}
+
+/// Serve `app` on all listeners until `shutdown` is triggered, then drain: wait for in-flight
+/// requests, ending streams that are still running after `drain_timeout`.
+///
+/// TCP listeners use `tls` if given; Unix sockets are always plain. Handlers see the peer
+/// address as `ConnectInfo<SocketAddr>` on TCP connections.
+pub async fn serve(
+    listeners: Vec<Listener>,
+    app: Router,
+    tls: Option<TlsAcceptor>,
+    shutdown: Arc<Shutdown>,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let graceful = GracefulShutdown::new();
+    let accept_loops = listeners
+        .into_iter()
+        .map(|listener| accept_loop(listener, &app, tls.as_ref(), &graceful, &shutdown));
+    futures::future::join_all(accept_loops).await;
+
+    info!(
+        "Stopped accepting connections; draining {} connection(s) with {} active stream(s)",
+        graceful.count(),
+        shutdown.active_streams()
+    );
+    let drained = graceful.shutdown();
+    tokio::pin!(drained);
+    tokio::select! {
+        _ = &mut drained => {}
+        _ = tokio::time::sleep(drain_timeout) => {
+            warn!(
+                "Drain timeout of {}s reached, ending {} active stream(s)",
+                drain_timeout.as_secs(),
+                shutdown.active_streams()
+            );
+            shutdown.end_streams();
+            drained.await;
+        }
+    }
+    info!("All connections drained");
+    Ok(())
+}
+
+async fn accept_loop(
+    listener: Listener,
+    app: &Router,
+    tls: Option<&TlsAcceptor>,
+    graceful: &GracefulShutdown,
+    shutdown: &Shutdown,
+) {
+    match listener {
+        Listener::Tcp(listener) => loop {
+            let (stream, peer) = tokio::select! {
+                _ = shutdown.triggered() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        // Usually running out of file descriptors; back off instead of spinning
+                        warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+            };
+            let app = app.clone();
+            let tls = tls.cloned();
+            let watcher = graceful.watcher();
+            tokio::spawn(async move {
+                match tls {
+                    Some(acceptor) => match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => serve_connection(stream, app, Some(peer), watcher).await,
+                        Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+                        Err(_) => debug!("TLS handshake with {} timed out", peer),
+                    },
+                    None => serve_connection(stream, app, Some(peer), watcher).await,
+                }
+            });
+        },
+        #[cfg(unix)]
+        Listener::Unix(listener, path) => {
+            loop {
+                let stream = tokio::select! {
+                    _ = shutdown.triggered() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            warn!("Failed to accept connection on {}: {}", path.display(), e);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+                tokio::spawn(serve_connection(stream, app.clone(), None, graceful.watcher()));
+            }
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
+
+async fn serve_connection<I>(io: I, app: Router, peer: Option<SocketAddr>, watcher: Watcher)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        if let Some(peer) = peer {
+            request.extensions_mut().insert(ConnectInfo(peer));
+        }
+        app.clone().oneshot(request)
+    });
+
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let connection = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    if let Err(e) = watcher.watch(connection.into_owned()).await {
+        debug!("Connection error: {}", e);
+    }
+}
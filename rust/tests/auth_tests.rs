@@ -0,0 +1,107 @@
+/*!
+ * Auth Tests
+ *
+ * Unit tests for key comparison, hashed keys, credential locations and IP lockout.
+ */
+
+use aiclient2api_rust::auth::*;
+use aiclient2api_rust::config::{ClientKeyConfig, Config};
+use axum::http::HeaderMap;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+#[test]
+fn test_key_hash_roundtrip() {
+    assert!(constant_time_eq("sk-secret", "sk-secret"));
+    assert!(!constant_time_eq("sk-secret", "sk-secreT"));
+    assert!(!constant_time_eq("sk-secret", "sk-secret-longer"));
+
+    let hash = hash_key("sk-secret");
+    assert!(hash.starts_with("sha256$"));
+    assert!(is_valid_key_hash(&hash));
+    assert!(verify_key_hash("sk-secret", &hash));
+    assert!(!verify_key_hash("sk-other", &hash));
+    // Salted: the same key hashes differently every time
+    assert_ne!(hash, hash_key("sk-secret"));
+
+    assert!(!is_valid_key_hash("sk-secret"));
+    assert!(!is_valid_key_hash("sha256$c2FsdA$dG9vLXNob3J0"));
+    assert!(!verify_key_hash("sk-secret", "md5$abc$def"));
+}
+
+#[test]
+fn test_hashed_client_keys_in_config() {
+    let hash = hash_key("team-a-key");
+    let client: ClientKeyConfig =
+        serde_json::from_value(json!({"key_hash": hash, "label": "team-a"})).unwrap();
+    assert!(client.matches("team-a-key"));
+    assert!(!client.matches(&hash));
+
+    let mut config = Config {
+        api_keys: vec![client.clone()],
+        ..Config::default()
+    };
+    assert!(config.validate().is_ok());
+
+    config.api_keys[0].key = "team-a-key".into();
+    assert!(config.validate().is_err(), "key and key_hash are exclusive");
+
+    config.api_keys[0].key = Default::default();
+    config.api_keys[0].key_hash = Some("not-a-hash".to_string());
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_credential_locations_per_endpoint() {
+    let mut headers = HeaderMap::new();
+    headers.insert("authorization", "Bearer from-bearer".parse().unwrap());
+    let params = HashMap::from([("key".to_string(), "from-query".to_string())]);
+
+    let config: Config = serde_json::from_value(json!({
+        "auth_locations": {"claude": ["x-api-key"]}
+    }))
+    .unwrap();
+
+    // Query keys are only accepted on Gemini endpoints by default
+    let openai = presented_keys(&headers, &params, &config.credential_locations(AuthEndpoint::OpenAi));
+    assert_eq!(openai, vec!["from-bearer"]);
+    let gemini = presented_keys(&headers, &params, &config.credential_locations(AuthEndpoint::Gemini));
+    assert_eq!(gemini, vec!["from-bearer", "from-query"]);
+    let claude = presented_keys(&headers, &params, &config.credential_locations(AuthEndpoint::Claude));
+    assert!(claude.is_empty());
+
+    let invalid: Config = serde_json::from_value(json!({"auth_locations": {"grpc": ["bearer"]}})).unwrap();
+    assert!(invalid.validate().is_err());
+}
+
+#[test]
+fn test_lockout_after_repeated_failures() {
+    let tracker = AuthFailureTracker::new();
+    let policy = LockoutPolicy {
+        max_failures: 3,
+        window: Duration::from_secs(60),
+        duration: Duration::from_secs(600),
+    };
+    let ip: IpAddr = "192.168.1.20".parse().unwrap();
+    let other: IpAddr = "192.168.1.21".parse().unwrap();
+
+    assert_eq!(tracker.record_failure(ip, &policy), (1, false));
+    // A success resets the count
+    tracker.record_success(ip);
+    assert_eq!(tracker.record_failure(ip, &policy), (1, false));
+    assert_eq!(tracker.record_failure(ip, &policy), (2, false));
+    assert!(tracker.locked_for(ip).is_none());
+    assert_eq!(tracker.record_failure(ip, &policy), (3, true));
+
+    let remaining = tracker.locked_for(ip).unwrap();
+    assert!(remaining > Duration::from_secs(590) && remaining <= Duration::from_secs(600));
+    assert!(tracker.locked_for(other).is_none());
+
+    let disabled = LockoutPolicy { max_failures: 0, ..policy };
+    for _ in 0..10 {
+        assert!(!tracker.record_failure(other, &disabled).1);
+    }
+    assert!(tracker.locked_for(other).is_none());
+}
@@ -0,0 +1,118 @@
+/*!
+ * Cancellation Tests
+ *
+ * Unit tests for recording client cancels and dropping upstream work with the request.
+ */
+
+use aiclient2api_rust::cancellation::CancelGuard;
+use aiclient2api_rust::retry::{RetryBudget, RetryPolicy, RetrySettings};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::Router;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn flag_guard() -> (CancelGuard, Arc<AtomicBool>) {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+    (CancelGuard::new(move |_| flag.store(true, Ordering::SeqCst)), cancelled)
+}
+
+async fn wait_for(flag: &AtomicBool) -> bool {
+    for _ in 0..100 {
+        if flag.load(Ordering::SeqCst) {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn test_guard_records_only_cancelled_requests() {
+    let (guard, cancelled) = flag_guard();
+    guard.complete();
+    assert!(!cancelled.load(Ordering::SeqCst));
+
+    let (guard, cancelled) = flag_guard();
+    drop(guard);
+    assert!(cancelled.load(Ordering::SeqCst));
+
+    let (guard, cancelled) = flag_guard();
+    let items = futures::stream::iter(vec![Ok(1), Ok(2)]);
+    let stream = guard.guard_stream::<i32>(Box::pin(items));
+    assert_eq!(stream.collect::<Vec<_>>().await.len(), 2);
+    assert!(!cancelled.load(Ordering::SeqCst));
+
+    let (guard, cancelled) = flag_guard();
+    let items = futures::stream::iter(vec![Ok(1), Ok(2)]).chain(futures::stream::pending());
+    let mut stream = guard.guard_stream::<i32>(Box::pin(items));
+    assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+    drop(stream);
+    assert!(cancelled.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_dropping_request_stops_retries() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let app = Router::new().route(
+        "/",
+        post(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { StatusCode::SERVICE_UNAVAILABLE }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let settings = RetrySettings {
+        max_retries: 5,
+        base_delay: Duration::from_millis(400),
+        max_delay: Duration::from_millis(400),
+        deadline: Duration::from_secs(30),
+        budget_ratio: 1.0,
+        budget_min_retries: 10,
+        budget_window: Duration::from_secs(10),
+    };
+    let policy = RetryPolicy::new(settings, Arc::new(RetryBudget::new()));
+    let request = tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        policy.send(|| async { Ok(client.post(&url).send().await?) }).await
+    });
+
+    while calls.load(Ordering::SeqCst) == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    request.abort();
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_client_disconnect_drops_handler() {
+    let (guard, cancelled) = flag_guard();
+    let guard = Arc::new(std::sync::Mutex::new(Some(guard)));
+    let app = Router::new().route(
+        "/",
+        get(move || {
+            let guard = guard.lock().unwrap().take().unwrap();
+            async move {
+                // Stands in for a slow upstream call
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                guard.complete();
+                "done"
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let client = reqwest::Client::builder().timeout(Duration::from_millis(200)).build().unwrap();
+    assert!(client.get(&url).send().await.is_err());
+    assert!(wait_for(&cancelled).await);
+}
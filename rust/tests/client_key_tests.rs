@@ -0,0 +1,88 @@
+/*!
+ * Client Key Tests
+ *
+ * Unit tests for client API key scopes, limits and token accounting.
+ */
+
+use aiclient2api_rust::client_keys::*;
+use aiclient2api_rust::config::ClientKeyConfig;
+use serde_json::json;
+
+fn client_key(value: serde_json::Value) -> ClientKeyConfig {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn test_scopes_and_expiry() {
+    let registry = ClientKeyRegistry::new();
+    let key = client_key(json!({
+        "key": "team-a",
+        "label": "team-a",
+        "allowed_providers": ["claude-kiro-oauth"],
+        "allowed_models": ["claude-sonnet-4*", "claude-3-5-haiku-20241022"]
+    }));
+
+    assert!(registry.admit(&key, "claude-kiro-oauth", "claude-sonnet-4-20250514").is_ok());
+    assert!(registry.admit(&key, "claude-kiro-oauth", "claude-3-5-haiku-20241022").is_ok());
+    assert_eq!(
+        registry.admit(&key, "claude-kiro-oauth", "claude-opus-4"),
+        Err(KeyRejection::ModelNotAllowed("claude-opus-4".to_string()))
+    );
+    assert_eq!(
+        registry.admit(&key, "gemini-cli-oauth", "claude-sonnet-4"),
+        Err(KeyRejection::ProviderNotAllowed("gemini-cli-oauth".to_string()))
+    );
+
+    let expired = client_key(json!({"key": "old", "label": "old", "expires_at": "2020-01-01T00:00:00Z"}));
+    assert_eq!(registry.admit(&expired, "openai-custom", "gpt-4o"), Err(KeyRejection::Expired));
+
+    let stats = registry.stats();
+    let team_a = stats.iter().find(|s| s.label == "team-a").unwrap();
+    assert_eq!(team_a.total_requests, 2);
+    assert_eq!(team_a.rejected, 2);
+}
+
+#[test]
+fn test_rate_limits_and_daily_quota() {
+    let registry = ClientKeyRegistry::new();
+    let key = client_key(json!({"key": "k", "label": "rpm", "rpm": 2}));
+    assert!(registry.admit(&key, "openai-custom", "gpt-4o").is_ok());
+    assert!(registry.admit(&key, "openai-custom", "gpt-4o").is_ok());
+    assert!(matches!(
+        registry.admit(&key, "openai-custom", "gpt-4o"),
+        Err(KeyRejection::RateLimited(_))
+    ));
+
+    let key = client_key(json!({"key": "k", "label": "tokens", "tpm": 1000, "daily_token_quota": 5000}));
+    assert!(registry.admit(&key, "openai-custom", "gpt-4o").is_ok());
+    registry.record_tokens("tokens", 1200);
+    assert!(matches!(
+        registry.admit(&key, "openai-custom", "gpt-4o"),
+        Err(KeyRejection::RateLimited(_))
+    ));
+
+    let key = client_key(json!({"key": "k", "label": "quota", "daily_token_quota": 100}));
+    registry.record_tokens("quota", 150);
+    assert_eq!(
+        registry.admit(&key, "openai-custom", "gpt-4o"),
+        Err(KeyRejection::QuotaExhausted(100))
+    );
+}
+
+#[test]
+fn test_token_usage_from_all_formats() {
+    let mut openai = TokenUsage::default();
+    openai.observe(&json!({"usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}}));
+    assert_eq!(openai.total(), 15);
+
+    let mut gemini = TokenUsage::default();
+    gemini.observe(&json!({"usageMetadata": {"promptTokenCount": 7, "candidatesTokenCount": 3}}));
+    assert_eq!(gemini.total(), 10);
+
+    // Claude streams report input tokens in message_start and cumulative output in message_delta
+    let mut claude = TokenUsage::default();
+    claude.observe(&json!({"type": "message_start", "message": {"usage": {"input_tokens": 20, "output_tokens": 1}}}));
+    claude.observe(&json!({"type": "content_block_delta", "delta": {"text": "hi"}}));
+    claude.observe(&json!({"type": "message_delta", "usage": {"output_tokens": 12}}));
+    assert_eq!(claude.total(), 32);
+}
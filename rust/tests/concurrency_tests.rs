@@ -0,0 +1,74 @@
+/*!
+ * Concurrency Tests
+ *
+ * Unit tests for per-credential concurrency limits and the wait queue.
+ */
+
+use aiclient2api_rust::concurrency::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_queue_waits_for_free_slot() {
+    let limiter = Arc::new(ConcurrencyLimiter::new(1, 4));
+    let permit = limiter.acquire(Duration::from_secs(1)).await.unwrap();
+
+    let waiter = {
+        let limiter = limiter.clone();
+        tokio::spawn(async move { limiter.acquire(Duration::from_secs(5)).await.map(|_| ()) })
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(limiter.stats().queued, 1);
+
+    drop(permit);
+    assert!(waiter.await.unwrap().is_ok());
+
+    let stats = limiter.stats();
+    assert_eq!(stats.queued, 0);
+    assert_eq!(stats.total_queued, 1);
+    assert!(stats.max_wait_ms >= 10);
+}
+
+#[tokio::test]
+async fn test_queue_full_and_timeout() {
+    let limiter = Arc::new(ConcurrencyLimiter::new(1, 1));
+    let _permit = limiter.acquire(Duration::from_secs(1)).await.unwrap();
+
+    let waiter = {
+        let limiter = limiter.clone();
+        tokio::spawn(async move { limiter.acquire(Duration::from_millis(100)).await.map(|_| ()) })
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    assert_eq!(
+        limiter.acquire(Duration::from_secs(1)).await.err(),
+        Some(QueueError::QueueFull)
+    );
+    assert_eq!(
+        waiter.await.unwrap().err(),
+        Some(QueueError::Timeout(Duration::from_millis(100)))
+    );
+
+    let stats = limiter.stats();
+    assert_eq!(stats.rejected, 1);
+    assert_eq!(stats.timed_out, 1);
+    assert_eq!(stats.in_flight, 1);
+}
+
+#[test]
+fn test_registry_limits_and_replacement() {
+    let registry = ConcurrencyRegistry::new();
+    assert!(registry.limiter("a", None, 10).is_none());
+    assert!(registry.limiter("a", Some(0), 10).is_none());
+
+    let first = registry.limiter("a", Some(2), 10).unwrap();
+    let same = registry.limiter("a", Some(2), 10).unwrap();
+    assert!(Arc::ptr_eq(&first, &same));
+
+    let resized = registry.limiter("a", Some(3), 10).unwrap();
+    assert_eq!(resized.max_concurrency(), 3);
+
+    registry.limiter(PRIMARY_KEY, Some(1), 10).unwrap();
+    registry.retain(&[PRIMARY_KEY]);
+    assert_eq!(registry.stats().len(), 1);
+}
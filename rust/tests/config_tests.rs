@@ -0,0 +1,312 @@
+/*!
+ * Config Tests
+ *
+ * Unit tests for command-line parsing and configuration loading.
+ */
+
+use aiclient2api_rust::cli::{Cli, Command, ConfigCommand};
+use aiclient2api_rust::config::{expand_env_vars, expand_path, Config, ConfigLayer, ProviderConfig};
+use aiclient2api_rust::auth::CredentialLocation;
+use aiclient2api_rust::http_client::HttpClientConfig;
+use clap::{CommandFactory, Parser};
+
+fn args(list: &[&str]) -> Vec<String> {
+    std::iter::once("aiclient2api-rust")
+        .chain(list.iter().copied())
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn test_flags_override_config() {
+    let config = Config::load_with_args(&args(&[
+        "--config",
+        "/nonexistent/config.json",
+        "--port",
+        "4000",
+        "--api-key",
+        "secret",
+        "--sticky-sessions",
+        "--pool-strategies",
+        "gemini-cli-oauth=weighted,openai-custom=lru",
+        "--max-concurrency",
+        "2",
+    ]))
+    .unwrap();
+
+    assert_eq!(config.port, 4000);
+    assert_eq!(config.required_api_key.expose(), "secret");
+    assert!(config.sticky_sessions);
+    assert_eq!(config.pool_strategies["gemini-cli-oauth"], "weighted");
+    assert_eq!(config.pool_strategies["openai-custom"], "lru");
+    assert_eq!(config.max_concurrency, Some(2));
+    // Untouched fields keep their defaults
+    assert_eq!(config.host, "localhost");
+}
+
+#[test]
+fn test_subcommands_and_unknown_flags() {
+    let cli = Cli::try_parse_from(args(&["config", "check", "--hot-reload", "false"])).unwrap();
+    assert!(matches!(cli.command, Some(Command::Config(ConfigCommand::Check(_)))));
+    assert_eq!(cli.config_args().hot_reload, Some(false));
+
+    let cli = Cli::try_parse_from(args(&["serve", "--port", "8080"])).unwrap();
+    assert_eq!(cli.config_args().port, Some(8080));
+
+    assert!(Cli::try_parse_from(args(&["--prot", "8080"])).is_err());
+    assert!(Cli::try_parse_from(args(&["--pool-strategies", "weighted"])).is_err());
+    assert!(Config::load_with_args(&args(&["serve", "--bogus"])).is_err());
+}
+
+#[test]
+fn test_every_config_field_is_settable_from_the_command_line() {
+    let command = Cli::command();
+    let flags: Vec<String> = command
+        .find_subcommand("serve")
+        .unwrap()
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .collect();
+    // Read from the files that other fields point to
+    let exempt = ["provider_pools", "system_prompt_content"];
+    let fields = serde_json::to_value(Config::default()).unwrap();
+    for field in fields.as_object().unwrap().keys().filter(|f| !exempt.contains(&f.as_str())) {
+        let prefix = format!("{}_", field);
+        assert!(
+            flags.iter().any(|flag| flag == field || (field == "http" && flag.starts_with(&prefix))),
+            "no flag for {}",
+            field
+        );
+    }
+    // Every field of `http` has an `http_*` flag of its own
+    let http = serde_json::to_value(HttpClientConfig {
+        request_timeout_ms: Some(1),
+        connect_timeout_ms: Some(1),
+        first_byte_timeout_ms: Some(1),
+        idle_timeout_ms: Some(1),
+        pool_idle_timeout_secs: Some(1),
+        pool_max_idle_per_host: Some(1),
+        proxy: Some("none".into()),
+    })
+    .unwrap();
+    for field in http.as_object().unwrap().keys() {
+        let flag = format!("http_{}", field);
+        assert!(flags.contains(&flag), "no flag for http.{}", field);
+    }
+
+    // Lists and maps take JSON
+    let config = Config::load_with_args(&args(&[
+        "--config",
+        "/nonexistent/config.json",
+        "--api-keys",
+        r#"[{"label": "ci", "key": "sk-ci", "rpm": 10}]"#,
+        "--auth-locations",
+        r#"{"openai": ["bearer", "query"]}"#,
+        "--provider-http",
+        r#"{"gemini-cli-oauth": {"proxy": "none"}}"#,
+        "--http-idle-timeout-ms",
+        "5000",
+        "--http-pool-idle-timeout-secs",
+        "30",
+        "--http-pool-max-idle-per-host",
+        "4",
+    ]))
+    .unwrap();
+    assert_eq!(config.api_keys[0].label, "ci");
+    assert_eq!(config.api_keys[0].key.expose(), "sk-ci");
+    assert_eq!(config.auth_locations["openai"], vec![CredentialLocation::Bearer, CredentialLocation::Query]);
+    assert_eq!(config.provider_http["gemini-cli-oauth"].proxy.as_ref().unwrap().expose(), "none");
+    assert_eq!(config.http.idle_timeout_ms, Some(5000));
+    assert_eq!(config.http.pool_idle_timeout_secs, Some(30));
+    assert_eq!(config.http.pool_max_idle_per_host, Some(4));
+    for field in ["api_keys", "auth_locations", "provider_http", "http"] {
+        assert_eq!(config.value_source(field), ConfigLayer::Cli, "{}", field);
+    }
+    assert!(Cli::try_parse_from(args(&["--api-keys", "[{"])).is_err());
+}
+
+#[test]
+fn test_redacted_config_hides_secrets() {
+    let mut config = Config {
+        openai_api_key: Some("sk-live".into()),
+        ..Config::default()
+    };
+    config.provider_pools.insert(
+        "claude-kiro-oauth".to_string(),
+        vec![serde_json::from_value(serde_json::json!({
+            "uuid": "kiro-1",
+            "KIRO_OAUTH_CREDS_BASE64": "c2VjcmV0",
+            "KIRO_OAUTH_CREDS_FILE_PATH": "./kiro.json"
+        }))
+        .unwrap()],
+    );
+
+    let redacted = config.redacted();
+    assert_eq!(redacted["openai_api_key"], "***");
+    assert_eq!(redacted["required_api_key"], "***");
+    assert_eq!(redacted["port"], 3000);
+
+    let member = &redacted["provider_pools"]["claude-kiro-oauth"][0];
+    assert_eq!(member["KIRO_OAUTH_CREDS_BASE64"], "***");
+    assert_eq!(member["KIRO_OAUTH_CREDS_FILE_PATH"], "./kiro.json");
+}
+
+#[test]
+fn test_layered_loading_from_toml_and_yaml() {
+    let dir = std::env::temp_dir().join(format!("aic2api-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let toml_path = dir.join("config.toml");
+    std::fs::write(
+        &toml_path,
+        "port = 4100\nhost = \"0.0.0.0\"\nsticky_sessions = true\nretry_budget_window_secs = 30\nretry_budget_ratio = 0.5\n",
+    )
+    .unwrap();
+    let config = Config::load_with_args(&args(&[
+        "--config",
+        toml_path.to_str().unwrap(),
+        "--port",
+        "4200",
+        "--retry-budget-ratio",
+        "0.25",
+    ]))
+    .unwrap();
+    assert_eq!(config.port, 4200);
+    assert_eq!(config.retry_budget_ratio, 0.25);
+    assert_eq!(config.value_source("retry_budget_ratio"), ConfigLayer::Cli);
+    assert_eq!(config.retry_budget_window_secs, 30);
+    assert_eq!(config.value_source("retry_budget_window_secs"), ConfigLayer::File);
+    assert_eq!(config.value_source("retry_budget_min_retries"), ConfigLayer::Default);
+    assert_eq!(config.host, "0.0.0.0");
+    assert!(config.sticky_sessions);
+    assert_eq!(config.value_source("port"), ConfigLayer::Cli);
+    assert_eq!(config.value_source("host"), ConfigLayer::File);
+    assert_eq!(config.value_source("prompt_log_mode"), ConfigLayer::Default);
+
+    let yaml_path = dir.join("config.yaml");
+    std::fs::write(&yaml_path, "model_provider: openai-custom\nqwen_oauth_creds_file_path: ~/qwen.json\n").unwrap();
+    let config = Config::load_with_args(&args(&["--config", yaml_path.to_str().unwrap()])).unwrap();
+    assert_eq!(config.model_provider, "openai-custom");
+    let home = dirs::home_dir().unwrap();
+    assert_eq!(config.qwen_oauth_creds_file_path, Some(home.join("qwen.json")));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_expand_env_vars_and_paths() {
+    std::env::set_var("AIC2API_TEST_EXPAND_DIR", "/srv/creds");
+    assert_eq!(
+        expand_path("${AIC2API_TEST_EXPAND_DIR}/kiro.json").unwrap(),
+        "/srv/creds/kiro.json"
+    );
+    assert_eq!(expand_env_vars("plain-value").unwrap(), "plain-value");
+    assert!(expand_env_vars("${AIC2API_TEST_SURELY_UNSET}").is_err());
+
+    let home = dirs::home_dir().unwrap();
+    assert_eq!(expand_path("~/x.json").unwrap(), format!("{}/x.json", home.display()));
+    assert_eq!(expand_path("./~x").unwrap(), "./~x");
+}
+
+#[test]
+fn test_secret_references_resolve_without_leaking() {
+    let dir = std::env::temp_dir().join(format!("aic2api-secrets-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let secret_file = dir.join("claude_key");
+    std::fs::write(&secret_file, "sk-ant-from-file\n").unwrap();
+    std::env::set_var("AIC2API_TEST_OPENAI_SECRET", "sk-from-env");
+
+    let config_path = dir.join("config.json");
+    std::fs::write(
+        &config_path,
+        serde_json::json!({
+            "openai_api_key": "env:AIC2API_TEST_OPENAI_SECRET",
+            "claude_api_key": format!("file:{}", secret_file.display()),
+            "admin_api_key": "cmd:printf '%s-%s' admin cmd",
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let config = Config::load_with_args(&args(&["--config", config_path.to_str().unwrap()])).unwrap();
+    assert_eq!(config.openai_api_key.as_ref().unwrap().expose(), "sk-from-env");
+    assert_eq!(config.claude_api_key.as_ref().unwrap().expose(), "sk-ant-from-file");
+    assert_eq!(config.admin_api_key.as_ref().unwrap().expose(), "admin-cmd");
+
+    // Serialization writes the references back; Debug never shows resolved values
+    let serialized = serde_json::to_string(&config).unwrap();
+    assert!(serialized.contains("env:AIC2API_TEST_OPENAI_SECRET"));
+    let debug = format!("{:?}", config);
+    for leaked in ["sk-from-env", "sk-ant-from-file", "admin-cmd"] {
+        assert!(!serialized.contains(leaked), "serialized config leaks {}", leaked);
+        assert!(!debug.contains(leaked), "Debug output leaks {}", leaked);
+    }
+    assert!(!debug.contains("123456"));
+    assert_eq!(config.redacted()["openai_api_key"], "env:AIC2API_TEST_OPENAI_SECRET");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pool_member_secret_references() {
+    std::env::set_var("AIC2API_TEST_POOL_SECRET", "sk-pool");
+    let mut member: ProviderConfig = serde_json::from_value(serde_json::json!({
+        "uuid": "m1",
+        "OPENAI_API_KEY": "env:AIC2API_TEST_POOL_SECRET",
+        "OPENAI_BASE_URL": "https://api.example.com/v1"
+    }))
+    .unwrap();
+    member.resolve_credentials().unwrap();
+
+    assert_eq!(member.credential("OPENAI_API_KEY"), Some("sk-pool"));
+    assert_eq!(member.credential("OPENAI_BASE_URL"), Some("https://api.example.com/v1"));
+
+    let member_config = Config::default().for_pool_member("openai-custom", &member);
+    assert_eq!(member_config.openai_api_key.unwrap().expose(), "sk-pool");
+
+    let saved = serde_json::to_string(&member).unwrap();
+    assert!(saved.contains("env:AIC2API_TEST_POOL_SECRET"));
+    assert!(!saved.contains("sk-pool"));
+    assert!(!format!("{:?}", member).contains("sk-pool"));
+
+    let mut missing: ProviderConfig =
+        serde_json::from_value(serde_json::json!({"uuid": "m2", "CLAUDE_API_KEY": "env:AIC2API_TEST_SURELY_UNSET"}))
+            .unwrap();
+    assert!(missing.resolve_credentials().is_err());
+
+    // Only secret keys are references; anything else is taken as written
+    let mut url: ProviderConfig = serde_json::from_value(serde_json::json!({
+        "uuid": "m3",
+        "OPENAI_BASE_URL": "file:/etc/hostname",
+        "OPENAI_API_KEY": "cmd:echo key",
+    }))
+    .unwrap();
+    url.resolve_credentials().unwrap();
+    assert_eq!(url.credential("OPENAI_BASE_URL"), Some("file:/etc/hostname"));
+    assert_eq!(url.local_references(), vec!["OPENAI_API_KEY", "OPENAI_BASE_URL"]);
+}
+
+#[test]
+fn test_admin_members_can_only_hold_literal_credentials() {
+    let references = |member: serde_json::Value| {
+        serde_json::from_value::<ProviderConfig>(member).unwrap().local_references()
+    };
+    // The server's own environment must not be sent to a base URL of the caller's choosing
+    assert_eq!(
+        references(serde_json::json!({
+            "uuid": "m1",
+            "OPENAI_API_KEY": "env:AWS_SECRET_ACCESS_KEY",
+            "OPENAI_BASE_URL": "https://attacker.example.com/v1"
+        })),
+        vec!["OPENAI_API_KEY"]
+    );
+    assert_eq!(
+        references(serde_json::json!({"uuid": "m2", "CLAUDE_API_KEY": "sk-${AWS_SECRET_ACCESS_KEY}"})),
+        vec!["CLAUDE_API_KEY"]
+    );
+    assert_eq!(
+        references(serde_json::json!({"uuid": "m3", "OPENAI_API_KEY": "sk-1", "http": {"proxy": "${HTTPS_PROXY}"}})),
+        vec!["http.proxy"]
+    );
+    assert!(references(serde_json::json!({"uuid": "m4", "OPENAI_API_KEY": "sk-literal"})).is_empty());
+}
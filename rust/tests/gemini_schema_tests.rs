@@ -0,0 +1,110 @@
+/*!
+ * Gemini Schema Tests
+ *
+ * Rewriting tool schemas into the JSON Schema subset Gemini function declarations accept.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::schema::sanitize_for_gemini;
+use serde_json::json;
+
+#[test]
+fn test_refs_are_inlined() {
+    let schema = json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "type": "object",
+        "properties": {
+            "owner": {"$ref": "#/$defs/User", "description": "Who owns it"},
+            "node": {"$ref": "#/definitions/Node"},
+            "missing": {"$ref": "#/$defs/Nope"}
+        },
+        "$defs": {"User": {"type": "object", "properties": {"name": {"type": "string"}}, "required": ["name"]}},
+        "definitions": {"Node": {"type": "object", "properties": {"child": {"$ref": "#/definitions/Node"}}}}
+    });
+    let sanitized = sanitize_for_gemini(&schema, "tool");
+    assert_eq!(
+        sanitized,
+        json!({
+            "type": "object",
+            "properties": {
+                "owner": {
+                    "type": "object",
+                    "description": "Who owns it",
+                    "properties": {"name": {"type": "string"}},
+                    "required": ["name"]
+                },
+                "node": {"type": "object", "properties": {"child": {"type": "object"}}},
+                "missing": {"type": "object"}
+            }
+        })
+    );
+}
+
+#[test]
+fn test_unions_enums_and_unsupported_keywords() {
+    let schema = json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "limit": {"anyOf": [{"type": "integer", "format": "int32"}, {"type": "null"}], "default": null},
+            "path": {"type": ["string", "null"], "format": "uri"},
+            "mode": {"const": "fast"},
+            "level": {"type": "integer", "enum": [1, 2, null]},
+            "value": {"oneOf": [{"type": "string"}, {"type": "number", "exclusiveMinimum": 0}]},
+            "tags": {"type": "array", "items": {"type": "string", "format": "date-time"}, "uniqueItems": true},
+            "merged": {"allOf": [
+                {"type": "object", "properties": {"a": {"type": "string"}}, "required": ["a"]},
+                {"properties": {"b": {"type": "boolean"}}, "required": ["b"]}
+            ], "required": ["a", "c"]}
+        },
+        "required": ["path", "ghost"]
+    });
+    let sanitized = sanitize_for_gemini(&schema, "tool");
+    let properties = &sanitized["properties"];
+
+    assert!(sanitized.get("additionalProperties").is_none());
+    assert_eq!(sanitized["required"], json!(["path"]));
+    assert_eq!(
+        properties["limit"],
+        json!({"type": "integer", "format": "int32", "nullable": true, "default": null})
+    );
+    assert_eq!(properties["path"], json!({"type": "string", "nullable": true}));
+    assert_eq!(properties["mode"], json!({"type": "string", "enum": ["fast"]}));
+    assert_eq!(properties["level"], json!({"type": "string", "enum": ["1", "2"], "nullable": true}));
+    assert_eq!(properties["value"], json!({"anyOf": [{"type": "string"}, {"type": "number"}]}));
+    assert_eq!(properties["tags"], json!({"type": "array", "items": {"type": "string", "format": "date-time"}}));
+    assert_eq!(
+        properties["merged"],
+        json!({
+            "type": "object",
+            "properties": {"a": {"type": "string"}, "b": {"type": "boolean"}},
+            "required": ["a", "b"]
+        })
+    );
+}
+
+#[test]
+fn test_converted_declarations_are_sanitized() {
+    let openai = json!({
+        "messages": [{"role": "user", "content": "Hi"}],
+        "tools": [
+            {"type": "function", "function": {"name": "search", "parameters": {
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "type": "object",
+                "properties": {"query": {"type": "string", "minLength": 1}},
+                "required": ["query"],
+                "additionalProperties": false
+            }}},
+            {"type": "function", "function": {"name": "now", "parameters": {"type": "object", "properties": {}}}}
+        ]
+    });
+    let gemini = convert_data(openai, ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Gemini, None).unwrap();
+    let declarations = &gemini["tools"][0]["functionDeclarations"];
+    assert_eq!(
+        declarations[0]["parameters"],
+        json!({"type": "object", "properties": {"query": {"type": "string", "minLength": 1}}, "required": ["query"]})
+    );
+    // A tool without arguments is declared without parameters
+    assert_eq!(declarations[1], json!({"name": "now"}));
+}
@@ -0,0 +1,188 @@
+/*!
+ * Golden Conversion Tests
+ *
+ * Runs every fixture under `tests/fixtures/conversion` through each conversion direction and
+ * diffs the result against the expected output stored next to it, and checks that OpenAI
+ * requests survive a round trip through Claude.
+ *
+ * Inputs are `<kind>/<protocol>/<case>.json` (a stream is an array of chunks); the expected
+ * output for a target protocol is `<case>.<target>.json`. After an intended change, rewrite the
+ * expected outputs with:
+ *
+ *     UPDATE_FIXTURES=1 cargo test --test golden_tests
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::{self, StreamConverter};
+use anyhow::Result;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+const PROTOCOLS: [ModelProtocol; 4] = [ModelProtocol::OpenAI, ModelProtocol::Claude, ModelProtocol::Gemini, ModelProtocol::Kiro];
+
+fn fixtures(kind: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/conversion").join(kind)
+}
+
+fn protocol(name: &str) -> Option<ModelProtocol> {
+    PROTOCOLS.into_iter().find(|p| p.as_str() == name)
+}
+
+/// Input fixtures of a kind with their source protocol, in a stable order
+fn inputs(kind: &str) -> Vec<(ModelProtocol, PathBuf)> {
+    let mut inputs = Vec::new();
+    for dir in std::fs::read_dir(fixtures(kind)).unwrap() {
+        let dir = dir.unwrap().path();
+        let Some(source) = dir.file_name().and_then(|n| n.to_str()).and_then(protocol) else {
+            continue;
+        };
+        for file in std::fs::read_dir(&dir).unwrap() {
+            let file = file.unwrap().path();
+            let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            // `<case>.<target>.json` is an expected output
+            if file.extension().is_some_and(|e| e == "json") && !stem.contains('.') {
+                inputs.push((source, file));
+            }
+        }
+    }
+    inputs.sort_by(|a, b| a.1.cmp(&b.1));
+    assert!(!inputs.is_empty(), "no {} fixtures", kind);
+    inputs
+}
+
+fn read(path: &Path) -> Value {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    serde_json::from_str(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+/// Replace what changes from run to run: generated IDs and timestamps
+fn normalize(value: &mut Value) {
+    let uuid = Regex::new(r"[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}").unwrap();
+    let call_id = Regex::new(r"^call_[0-9a-f]{8}([0-9a-f]{16})?$").unwrap();
+    fn walk(value: &mut Value, uuid: &Regex, call_id: &Regex) {
+        match value {
+            Value::String(text) => {
+                if call_id.is_match(text) {
+                    *text = "call_<generated>".to_string();
+                } else if uuid.is_match(text) {
+                    *text = uuid.replace_all(text, "<uuid>").into_owned();
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| walk(item, uuid, call_id)),
+            Value::Object(fields) => {
+                for (key, field) in fields.iter_mut() {
+                    if key == "created" && field.is_number() {
+                        *field = Value::from(0);
+                    } else {
+                        walk(field, uuid, call_id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    walk(value, &uuid, &call_id);
+}
+
+/// Path and values of the first place two values differ
+fn first_difference(expected: &Value, actual: &Value, path: String) -> Option<String> {
+    match (expected, actual) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            keys.into_iter().find_map(|key| {
+                let path = format!("{}.{}", path, key);
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => first_difference(a, b, path),
+                    (a, b) => Some(format!("{}: expected {:?}, got {:?}", path, a, b)),
+                }
+            })
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            a.iter().zip(b).enumerate().find_map(|(i, (a, b))| first_difference(a, b, format!("{}[{}]", path, i)))
+        }
+        (a, b) if a != b => Some(format!("{}: expected {}, got {}", path, a, b)),
+        _ => None,
+    }
+}
+
+/// Compare every conversion of every fixture of a kind with its expected output, or rewrite the
+/// expected outputs when `UPDATE_FIXTURES` is set. A conversion that fails is expected to fail
+/// with the same message, stored as `{"error": ...}`.
+fn check_fixtures(kind: &str, targets: &[ModelProtocol], convert: impl Fn(&Value, ModelProtocol, ModelProtocol) -> Result<Value>) {
+    let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+    let mut failures = Vec::new();
+    for (source, input) in inputs(kind) {
+        let data = read(&input);
+        for &target in targets.iter().filter(|t| **t != source) {
+            let mut actual = convert(&data, source, target).unwrap_or_else(|e| json!({"error": e.to_string()}));
+            normalize(&mut actual);
+            let expected_path = input.with_extension(format!("{}.json", target.as_str()));
+            let name = expected_path.strip_prefix(fixtures("")).unwrap().display().to_string();
+            if update {
+                std::fs::write(&expected_path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+                continue;
+            }
+            if !expected_path.exists() {
+                failures.push(format!("{}: missing", name));
+                continue;
+            }
+            if let Some(difference) = first_difference(&read(&expected_path), &actual, "$".to_string()) {
+                failures.push(format!("{}: {}", name, difference));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} conversion fixtures differ (rerun with UPDATE_FIXTURES=1 if the change is intended):\n{}",
+        kind,
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_request_fixtures() {
+    check_fixtures("requests", &PROTOCOLS, |data, from, to| {
+        convert_data(data.clone(), ConversionType::Request, from, to, None)
+    });
+}
+
+#[test]
+fn test_response_and_stream_fixtures() {
+    let targets = &PROTOCOLS[..3];
+    check_fixtures("responses", targets, |data, from, to| {
+        convert_data(data.clone(), ConversionType::Response, from, to, None)
+    });
+    check_fixtures("streams", targets, |data, from, to| {
+        let mut converter = StreamConverter::new(from, to, None)?;
+        let mut chunks = Vec::new();
+        for chunk in data.as_array().unwrap() {
+            chunks.extend(converter.convert(chunk)?);
+        }
+        chunks.extend(converter.finish());
+        Ok(Value::Array(chunks))
+    });
+}
+
+#[test]
+fn test_openai_requests_round_trip_through_claude() {
+    for (_, input) in inputs("requests").into_iter().filter(|(source, _)| *source == ModelProtocol::OpenAI) {
+        let original = ir::parse_request(ModelProtocol::OpenAI, &read(&input)).unwrap();
+        // Claude has no JSON mode; the format comes back as an extra tool
+        if original.response_format.is_some() {
+            continue;
+        }
+        let claude = ir::emit_request(ModelProtocol::Claude, &original).unwrap();
+        let openai = ir::emit_request(ModelProtocol::OpenAI, &ir::parse_request(ModelProtocol::Claude, &claude).unwrap()).unwrap();
+        let back = ir::parse_request(ModelProtocol::OpenAI, &openai).unwrap();
+
+        let name = input.display();
+        assert_eq!(back.system, original.system, "{}: system", name);
+        assert_eq!(back.messages, original.messages, "{}: messages", name);
+        assert_eq!(back.tools, original.tools, "{}: tools", name);
+        assert_eq!(back.tool_choice, original.tool_choice, "{}: tool_choice", name);
+    }
+}
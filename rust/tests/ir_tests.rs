@@ -0,0 +1,215 @@
+/*!
+ * IR Tests
+ *
+ * Unit tests for the protocol-neutral intermediate representation and its parsers and emitters.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::{self, ContentPart, Role, StopReason, StreamConverter};
+use serde_json::{json, Value};
+
+fn openai_request() -> Value {
+    json!({
+        "model": "gpt-4o",
+        "messages": [
+            {"role": "system", "content": "Be brief"},
+            {"role": "user", "content": [
+                {"type": "text", "text": "What is in this image?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}}
+            ]},
+            {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "lookup", "arguments": "{\"q\":\"cat\"}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "call_1", "name": "lookup", "content": "A cat"},
+            {"role": "user", "content": "Thanks"}
+        ],
+        "tools": [{"type": "function", "function": {
+            "name": "lookup", "description": "Look things up",
+            "parameters": {"type": "object", "properties": {"q": {"type": "string"}}}
+        }}],
+        "max_tokens": 256,
+        "temperature": 0.5,
+        "stop": "END"
+    })
+}
+
+fn convert(data: Value, kind: ConversionType, from: ModelProtocol, to: ModelProtocol) -> Value {
+    convert_data(data, kind, from, to, None).unwrap()
+}
+
+#[test]
+fn test_requests_convert_in_every_direction() {
+    let request = ir::parse_request(ModelProtocol::OpenAI, &openai_request()).unwrap();
+    assert_eq!(request.system, vec!["Be brief"]);
+    assert_eq!(request.messages.len(), 4);
+    assert!(matches!(request.messages[2].content[0], ContentPart::ToolResult(ref r) if r.call_id == "call_1"));
+
+    // Every other protocol carries the same conversation
+    for protocol in [ModelProtocol::Claude, ModelProtocol::Gemini, ModelProtocol::Kiro] {
+        let emitted = ir::emit_request(protocol, &request).unwrap();
+        let parsed = ir::parse_request(protocol, &emitted).unwrap();
+        let text: Vec<String> = parsed.messages.iter().map(|m| m.text()).collect();
+        assert!(text.iter().any(|t| t.contains("Thanks")), "{:?}: {:?}", protocol, text);
+        assert_eq!(parsed.tools[0].name, "lookup", "{:?}", protocol);
+        assert!(parsed.messages.iter().any(|m| m.role == Role::Assistant
+            && matches!(m.content.last(), Some(ContentPart::ToolCall(c)) if c.arguments == json!({"q": "cat"}))));
+    }
+
+    let claude = convert(openai_request(), ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["system"], "Be brief");
+    assert_eq!(claude["stop_sequences"], json!(["END"]));
+    assert_eq!(claude["messages"][0]["content"][1]["source"]["media_type"], "image/png");
+    assert_eq!(claude["messages"][1]["content"][0]["input"], json!({"q": "cat"}));
+    assert_eq!(claude["messages"][2]["content"][0]["tool_use_id"], "call_1");
+
+    // Directions that used to pass the body through unchanged
+    let openai = convert(claude.clone(), ConversionType::Request, ModelProtocol::Claude, ModelProtocol::OpenAI);
+    assert_eq!(openai["messages"][0], json!({"role": "system", "content": "Be brief"}));
+    assert_eq!(openai["messages"][3]["role"], "tool");
+    assert_eq!(openai["messages"][3]["content"], "A cat");
+    assert_eq!(openai["messages"][2]["tool_calls"][0]["function"]["arguments"], "{\"q\":\"cat\"}");
+
+    let gemini = convert(claude, ConversionType::Request, ModelProtocol::Claude, ModelProtocol::Gemini);
+    assert_eq!(gemini["generationConfig"]["maxOutputTokens"], 256);
+    let back = convert_data(gemini, ConversionType::Request, ModelProtocol::Gemini, ModelProtocol::Claude, Some("claude-sonnet-4-20250514")).unwrap();
+    assert_eq!(back["model"], "claude-sonnet-4-20250514");
+    assert_eq!(back["system"], "Be brief");
+    assert_eq!(back["max_tokens"], 256);
+}
+
+#[test]
+fn test_responses_and_model_lists_convert() {
+    let openai = json!({
+        "id": "chatcmpl-1",
+        "model": "gpt-4o",
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": "Checking", "tool_calls": [
+                {"id": "call_9", "type": "function", "function": {"name": "lookup", "arguments": "{\"q\":\"dog\"}"}}
+            ]},
+            "finish_reason": "tool_calls"
+        }],
+        "usage": {"prompt_tokens": 12, "completion_tokens": 5, "total_tokens": 17}
+    });
+    let claude = convert(openai, ConversionType::Response, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["stop_reason"], "tool_use");
+    assert_eq!(claude["content"][0], json!({"type": "text", "text": "Checking"}));
+    assert_eq!(claude["content"][1]["input"], json!({"q": "dog"}));
+    assert_eq!(claude["usage"], json!({"input_tokens": 12, "output_tokens": 5}));
+
+    let gemini = convert(claude, ConversionType::Response, ModelProtocol::Claude, ModelProtocol::Gemini);
+    assert_eq!(gemini["candidates"][0]["content"]["parts"][1]["functionCall"]["name"], "lookup");
+    assert_eq!(gemini["usageMetadata"]["totalTokenCount"], 17);
+
+    // Code Assist wraps the response
+    let wrapped = json!({"response": {
+        "candidates": [{"content": {"role": "model", "parts": [{"text": "Too long"}]}, "finishReason": "MAX_TOKENS"}],
+        "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 4}
+    }});
+    let response = ir::parse_response(ModelProtocol::Gemini, &wrapped).unwrap();
+    assert_eq!(response.stop_reason, Some(StopReason::MaxTokens));
+    let openai = ir::emit_response(ModelProtocol::OpenAI, &response).unwrap();
+    assert_eq!(openai["choices"][0]["finish_reason"], "length");
+    assert_eq!(openai["choices"][0]["message"]["content"], "Too long");
+
+    let gemini_models = json!({"models": [{"name": "models/gemini-2.5-pro", "displayName": "Gemini 2.5 Pro"}]});
+    let openai_models = convert(gemini_models, ConversionType::ModelList, ModelProtocol::Gemini, ModelProtocol::OpenAI);
+    assert_eq!(openai_models["data"][0]["id"], "gemini-2.5-pro");
+    let claude_models = convert(openai_models, ConversionType::ModelList, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude_models["data"][0]["type"], "model");
+    assert_eq!(claude_models["last_id"], "gemini-2.5-pro");
+}
+
+#[test]
+fn test_streams_convert_with_state() {
+    let openai_chunks = [
+        json!({"id": "c1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {"role": "assistant", "content": "Hel"}}]}),
+        json!({"id": "c1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {"content": "lo"}}]}),
+        json!({"id": "c1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {"tool_calls": [
+            {"index": 0, "id": "call_1", "type": "function", "function": {"name": "lookup", "arguments": ""}}
+        ]}}]}),
+        json!({"id": "c1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {"tool_calls": [
+            {"index": 0, "function": {"arguments": "{\"q\":1}"}}
+        ]}}]}),
+        json!({"id": "c1", "model": "gpt-4o", "choices": [{"index": 0, "delta": {}, "finish_reason": "tool_calls"}],
+               "usage": {"prompt_tokens": 7, "completion_tokens": 3}}),
+    ];
+    let mut converter = StreamConverter::new(ModelProtocol::OpenAI, ModelProtocol::Claude, None).unwrap();
+    let mut events = Vec::new();
+    for chunk in &openai_chunks {
+        events.extend(converter.convert(chunk).unwrap());
+    }
+    events.extend(converter.finish());
+    let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(types, [
+        "message_start",
+        "content_block_start", "content_block_delta", "content_block_delta", "content_block_stop",
+        "content_block_start", "content_block_delta", "content_block_stop",
+        "message_delta", "message_stop",
+    ]);
+    assert_eq!(events[5]["index"], 1);
+    assert_eq!(events[5]["content_block"]["name"], "lookup");
+    assert_eq!(events[6]["delta"]["partial_json"], "{\"q\":1}");
+    assert_eq!(events[8]["delta"]["stop_reason"], "tool_use");
+    assert_eq!(events[8]["usage"]["output_tokens"], 3);
+
+    // And back: Claude events to OpenAI chunks
+    let mut converter = StreamConverter::new(ModelProtocol::Claude, ModelProtocol::OpenAI, Some("claude")).unwrap();
+    let chunks: Vec<Value> = events.iter().flat_map(|e| converter.convert(e).unwrap()).collect();
+    let text: String = chunks.iter().filter_map(|c| c["choices"][0]["delta"]["content"].as_str()).collect();
+    assert_eq!(text, "Hello");
+    let call = chunks.iter().find(|c| c["choices"][0]["delta"]["tool_calls"][0]["id"] == "call_1").unwrap();
+    assert_eq!(call["choices"][0]["delta"]["tool_calls"][0]["function"]["name"], "lookup");
+    assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "tool_calls");
+
+    // Gemini sends tool calls whole; the emitter buffers argument deltas until the block stops
+    let mut converter = StreamConverter::new(ModelProtocol::Claude, ModelProtocol::Gemini, None).unwrap();
+    let chunks: Vec<Value> = events.iter().flat_map(|e| converter.convert(e).unwrap()).collect();
+    let call = chunks.iter().find(|c| !c["candidates"][0]["content"]["parts"][0]["functionCall"].is_null()).unwrap();
+    assert_eq!(call["candidates"][0]["content"]["parts"][0]["functionCall"]["args"], json!({"q": 1}));
+    assert_eq!(chunks.last().unwrap()["candidates"][0]["finishReason"], "STOP");
+}
+
+#[test]
+fn test_kiro_request_and_event_stream() {
+    let claude = json!({
+        "model": "claude-sonnet-4-20250514",
+        "system": "Be brief",
+        "messages": [
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "lookup", "input": {"q": "x"}}]},
+            {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": "found"}]}
+        ],
+        "tools": [{"name": "lookup", "description": "Look up", "input_schema": {"type": "object"}}]
+    });
+    let request = ir::parse_request(ModelProtocol::Claude, &claude).unwrap();
+    let body = ir::emit_request(ModelProtocol::Kiro, &request).unwrap();
+    let state = &body["conversationState"];
+    // The system prompt is a turn of its own ahead of the conversation
+    assert_eq!(state["history"][0]["userInputMessage"]["content"], "Be brief");
+    assert_eq!(state["history"][2]["userInputMessage"]["content"], "Hi");
+    assert_eq!(state["history"][2]["userInputMessage"]["modelId"], "CLAUDE_SONNET_4_20250514_V1_0");
+    assert_eq!(state["history"][3]["assistantResponseMessage"]["toolUses"][0]["toolUseId"], "t1");
+    let current = &state["currentMessage"]["userInputMessage"];
+    assert_eq!(current["content"], "Continue");
+    assert_eq!(current["userInputMessageContext"]["toolResults"][0]["content"][0]["text"], "found");
+    assert_eq!(current["userInputMessageContext"]["tools"][0]["toolSpecification"]["name"], "lookup");
+
+    // Event payloads sit between binary frame headers
+    let raw = concat!(
+        "\u{0}\u{0}\u{1}:event-type\u{7}assistantResponseEvent",
+        "event{\"content\":\"Let me check\"}\u{0}\u{3}",
+        "event{\"name\":\"lookup\",\"toolUseId\":\"t2\",\"input\":\"{\\\"q\\\":\"}\u{0}",
+        "event{\"name\":\"lookup\",\"toolUseId\":\"t2\",\"input\":\"\\\"y\\\"}\",\"stop\":true}\u{0}",
+    );
+    let response = ir::parse_response(ModelProtocol::Kiro, &json!(raw)).unwrap();
+    assert_eq!(response.stop_reason, Some(StopReason::ToolUse));
+    assert_eq!(response.content[0], ContentPart::Text("Let me check".to_string()));
+    let ContentPart::ToolCall(call) = &response.content[1] else { panic!("expected a tool call") };
+    assert_eq!((call.id.as_str(), call.arguments.clone()), ("t2", json!({"q": "y"})));
+
+    let claude = ir::emit_response(ModelProtocol::Claude, &response).unwrap();
+    assert_eq!(claude["content"][1]["input"], json!({"q": "y"}));
+    assert!(ir::emit_response(ModelProtocol::Kiro, &response).is_err());
+}
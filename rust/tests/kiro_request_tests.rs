@@ -0,0 +1,131 @@
+/*!
+ * Kiro Request Tests
+ *
+ * The CodeWhisperer request built from a Claude one: the system prompt as a turn of its own,
+ * tool choice asked for in words, and long sessions trimmed to the payload limit.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::ir;
+use serde_json::{json, Value};
+
+const PNG: &str = "iVBORw0KGgo=";
+
+fn kiro(claude: Value) -> Value {
+    ir::emit_request(ModelProtocol::Kiro, &ir::parse_request(ModelProtocol::Claude, &claude).unwrap()).unwrap()
+}
+
+fn weather_tool() -> Value {
+    json!({"name": "get_weather", "description": "Weather for a city", "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}}})
+}
+
+#[test]
+fn test_system_prompt_is_a_turn_of_its_own() {
+    let claude = json!({
+        "model": "claude-sonnet-4-20250514",
+        "max_tokens": 1000,
+        "system": [{"type": "text", "text": "You are a coding agent."}, {"type": "text", "text": "Prefer small diffs."}],
+        "tools": [weather_tool()],
+        "messages": [
+            {"role": "user", "content": [
+                {"type": "text", "text": "What is in this screenshot?"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": PNG}},
+            ]},
+            {"role": "assistant", "content": "A terminal."},
+            {"role": "user", "content": "Thanks"},
+        ],
+    });
+    let body = kiro(claude.clone());
+    let history = body["conversationState"]["history"].as_array().unwrap();
+    assert_eq!(history.len(), 4);
+    assert_eq!(history[0]["userInputMessage"]["content"], "You are a coding agent.\n\nPrefer small diffs.");
+    assert!(history[1]["assistantResponseMessage"]["content"].is_string());
+    assert_eq!(body["conversationStateMetadata"]["systemPrompt"], history[0]["userInputMessage"]["content"]);
+
+    // The first user message keeps its image and its text as they were
+    let first = &history[2]["userInputMessage"];
+    assert_eq!(first["content"], "What is in this screenshot?");
+    assert_eq!(first["images"][0], json!({"format": "png", "source": {"bytes": PNG}}));
+
+    // Tool definitions go with the current message only
+    assert!(first["userInputMessageContext"]["tools"].is_null());
+    let current = &body["conversationState"]["currentMessage"]["userInputMessage"];
+    assert_eq!(current["userInputMessageContext"]["tools"][0]["toolSpecification"]["name"], "get_weather");
+
+    // And the system turn is not read back as a message
+    let back = ir::parse_request(ModelProtocol::Kiro, &body).unwrap();
+    let original = ir::parse_request(ModelProtocol::Claude, &claude).unwrap();
+    assert_eq!(back.messages, original.messages);
+    assert_eq!(back.system, vec!["You are a coding agent.\n\nPrefer small diffs.".to_string()]);
+}
+
+#[test]
+fn test_tool_choice_is_asked_for_in_the_system_prompt() {
+    let system_prompt = |tool_choice: Value, tools: Vec<Value>| {
+        let body = kiro(json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 1000,
+            "system": "Be brief.",
+            "tools": tools,
+            "tool_choice": tool_choice,
+            "messages": [{"role": "user", "content": "Weather in Paris?"}],
+        }));
+        body["conversationStateMetadata"]["systemPrompt"].as_str().unwrap().to_string()
+    };
+    assert_eq!(
+        system_prompt(json!({"type": "none"}), vec![weather_tool()]),
+        "Be brief.\n\nDo not call any tools; answer with text only."
+    );
+    assert_eq!(
+        system_prompt(json!({"type": "any"}), vec![weather_tool()]),
+        "Be brief.\n\nYou must call at least one of the available tools in your response."
+    );
+    assert_eq!(
+        system_prompt(json!({"type": "tool", "name": "get_weather", "disable_parallel_tool_use": true}), vec![weather_tool()]),
+        "Be brief.\n\nYou must call the get_weather tool in your response.\n\nCall at most one tool per response."
+    );
+    assert_eq!(system_prompt(json!({"type": "auto"}), vec![weather_tool()]), "Be brief.");
+    // Without tools there is nothing to choose from
+    assert_eq!(system_prompt(json!({"type": "any"}), vec![]), "Be brief.");
+}
+
+#[test]
+fn test_long_sessions_are_trimmed_from_the_oldest_turns() {
+    // A Cline-like session reading one large file after another
+    let mut messages = vec![json!({"role": "user", "content": "Review the whole repository."})];
+    for i in 0..30 {
+        let id = format!("toolu_{:02}", i);
+        messages.push(json!({"role": "assistant", "content": [
+            {"type": "text", "text": format!("Reading file {}.", i)},
+            {"type": "tool_use", "id": id, "name": "read_file", "input": {"path": format!("src/file{}.rs", i)}},
+        ]}));
+        messages.push(json!({"role": "user", "content": [
+            {"type": "tool_result", "tool_use_id": id, "content": "x".repeat(40_000)},
+        ]}));
+    }
+    let body = kiro(json!({
+        "model": "claude-sonnet-4-20250514",
+        "max_tokens": 1000,
+        "system": "You are Cline.",
+        "tools": [{"name": "read_file", "description": "d".repeat(20_000), "input_schema": {"type": "object"}}],
+        "messages": messages,
+    }));
+
+    assert!(body.to_string().len() <= 600_000, "{} bytes", body.to_string().len());
+    let history = body["conversationState"]["history"].as_array().unwrap();
+    assert!(history.len() < 2 + 60, "nothing was trimmed");
+    // The system turn stays, and the conversation after it starts with a user message
+    assert_eq!(history[0]["userInputMessage"]["content"], "You are Cline.");
+    let first = &history[2]["userInputMessage"];
+    assert!(first.is_object(), "{}", history[2]);
+
+    // Its result lost its call to trimming and is sent as text
+    assert!(first["userInputMessageContext"].get("toolResults").is_none());
+    assert!(first["content"].as_str().unwrap().starts_with("[read_file result]\nxxx"));
+    // Later results keep their calls
+    let current = &body["conversationState"]["currentMessage"]["userInputMessage"];
+    assert_eq!(current["userInputMessageContext"]["toolResults"][0]["toolUseId"], "toolu_29");
+
+    let description = current["userInputMessageContext"]["tools"][0]["toolSpecification"]["description"].as_str().unwrap();
+    assert_eq!(description.len(), 10_240);
+}
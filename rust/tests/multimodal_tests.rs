@@ -0,0 +1,127 @@
+/*!
+ * Multimodal Tests
+ *
+ * Images, documents and audio carried between protocols, and the attachments a protocol can't carry.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::{self, UnsupportedContent};
+use serde_json::{json, Value};
+
+const PNG: &str = "iVBORw0KGgo=";
+const PDF: &str = "JVBERi0xLjQ=";
+const WAV: &str = "UklGRiQAAABXQVZF";
+
+fn convert_request(data: Value, from: ModelProtocol, to: ModelProtocol) -> anyhow::Result<Value> {
+    convert_data(data, ConversionType::Request, from, to, None)
+}
+
+fn openai_user(content: Value) -> Value {
+    json!({"model": "gpt-4o", "messages": [{"role": "user", "content": content}]})
+}
+
+#[test]
+fn test_images_convert_between_protocols() {
+    let openai = openai_user(json!([
+        {"type": "text", "text": "Compare these"},
+        {"type": "image_url", "image_url": {"url": format!("data:image/png;base64,{}", PNG), "detail": "high"}},
+        {"type": "image_url", "image_url": {"url": "https://example.com/cat.jpg"}}
+    ]));
+
+    let claude = convert_request(openai.clone(), ModelProtocol::OpenAI, ModelProtocol::Claude).unwrap();
+    let content = &claude["messages"][0]["content"];
+    assert_eq!(content[1]["source"], json!({"type": "base64", "media_type": "image/png", "data": PNG}));
+    assert_eq!(content[2]["source"], json!({"type": "url", "url": "https://example.com/cat.jpg"}));
+
+    let gemini = convert_request(claude, ModelProtocol::Claude, ModelProtocol::Gemini).unwrap();
+    let parts = &gemini["contents"][0]["parts"];
+    assert_eq!(parts[1]["inlineData"], json!({"mimeType": "image/png", "data": PNG}));
+    assert_eq!(parts[2]["fileData"]["fileUri"], "https://example.com/cat.jpg");
+
+    let back = convert_request(gemini, ModelProtocol::Gemini, ModelProtocol::OpenAI).unwrap();
+    let content = &back["messages"][0]["content"];
+    assert_eq!(content[1]["image_url"]["url"], format!("data:image/png;base64,{}", PNG));
+    assert_eq!(content[2]["image_url"]["url"], "https://example.com/cat.jpg");
+
+    // Kiro takes the inline image
+    let kiro = ir::emit_request(ModelProtocol::Kiro, &ir::parse_request(ModelProtocol::Gemini, &json!({
+        "contents": [{"role": "user", "parts": [{"text": "Look"}, {"inlineData": {"mimeType": "image/jpeg", "data": PNG}}]}]
+    })).unwrap())
+    .unwrap();
+    let images = &kiro["conversationState"]["currentMessage"]["userInputMessage"]["images"];
+    assert_eq!(images, &json!([{"format": "jpeg", "source": {"bytes": PNG}}]));
+}
+
+#[test]
+fn test_documents_and_audio_convert_between_protocols() {
+    let openai = openai_user(json!([
+        {"type": "file", "file": {"filename": "report.pdf", "file_data": format!("data:application/pdf;base64,{}", PDF)}},
+        {"type": "input_audio", "input_audio": {"data": WAV, "format": "wav"}}
+    ]));
+
+    // Gemini reads both inline
+    let gemini = convert_request(openai.clone(), ModelProtocol::OpenAI, ModelProtocol::Gemini).unwrap();
+    let parts = &gemini["contents"][0]["parts"];
+    assert_eq!(parts[0]["inlineData"], json!({"mimeType": "application/pdf", "data": PDF}));
+    assert_eq!(parts[1]["inlineData"], json!({"mimeType": "audio/wav", "data": WAV}));
+    let back = convert_request(gemini, ModelProtocol::Gemini, ModelProtocol::OpenAI).unwrap();
+    let content = &back["messages"][0]["content"];
+    assert_eq!(content[0]["file"]["file_data"], format!("data:application/pdf;base64,{}", PDF));
+    assert_eq!(content[1]["input_audio"], json!({"data": WAV, "format": "wav"}));
+
+    // Claude reads the PDF as a document, keeping its name
+    let pdf_only = openai_user(json!([
+        {"type": "text", "text": "Summarize"},
+        {"type": "file", "file": {"filename": "report.pdf", "file_data": format!("data:application/pdf;base64,{}", PDF)}}
+    ]));
+    let claude = convert_request(pdf_only, ModelProtocol::OpenAI, ModelProtocol::Claude).unwrap();
+    assert_eq!(
+        claude["messages"][0]["content"][1],
+        json!({
+            "type": "document",
+            "title": "report.pdf",
+            "source": {"type": "base64", "media_type": "application/pdf", "data": PDF}
+        })
+    );
+    let back = convert_request(claude, ModelProtocol::Claude, ModelProtocol::OpenAI).unwrap();
+    assert_eq!(back["messages"][0]["content"][1]["file"]["filename"], "report.pdf");
+
+    // Plain-text documents become text
+    let claude = json!({"model": "claude-sonnet-4", "max_tokens": 100, "messages": [{"role": "user", "content": [
+        {"type": "document", "source": {"type": "text", "media_type": "text/plain", "data": "Line one"}}
+    ]}]});
+    let openai = convert_request(claude, ModelProtocol::Claude, ModelProtocol::OpenAI).unwrap();
+    assert_eq!(openai["messages"][0]["content"], "Line one");
+}
+
+#[test]
+fn test_unsupported_attachments_are_rejected() {
+    let rejection = |data: Value, from: ModelProtocol, to: ModelProtocol| {
+        let error = convert_request(data, from, to).unwrap_err();
+        error.downcast::<UnsupportedContent>().expect("an UnsupportedContent error")
+    };
+
+    let audio = openai_user(json!([{"type": "input_audio", "input_audio": {"data": WAV, "format": "wav"}}]));
+    let error = rejection(audio.clone(), ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(error.to_string(), "Claude requests can't carry audio inline (audio/wav)");
+    rejection(audio, ModelProtocol::OpenAI, ModelProtocol::Kiro);
+
+    let url_image = openai_user(json!([{"type": "image_url", "image_url": {"url": "https://example.com/cat.jpg"}}]));
+    let error = rejection(url_image, ModelProtocol::OpenAI, ModelProtocol::Kiro);
+    assert_eq!(error.protocol, "Kiro");
+
+    let file_id = openai_user(json!([{"type": "file", "file": {"file_id": "file-abc123"}}]));
+    for target in [ModelProtocol::Claude, ModelProtocol::Gemini] {
+        rejection(file_id.clone(), ModelProtocol::OpenAI, target);
+    }
+
+    let gemini_video = json!({"contents": [{"role": "user", "parts": [
+        {"fileData": {"mimeType": "video/mp4", "fileUri": "gs://bucket/clip.mp4"}}
+    ]}]});
+    rejection(gemini_video, ModelProtocol::Gemini, ModelProtocol::OpenAI);
+    let gemini_ogg = json!({"contents": [{"role": "user", "parts": [
+        {"inlineData": {"mimeType": "audio/ogg", "data": WAV}}
+    ]}]});
+    rejection(gemini_ogg, ModelProtocol::Gemini, ModelProtocol::OpenAI);
+}
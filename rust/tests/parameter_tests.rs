@@ -0,0 +1,171 @@
+/*!
+ * Generation Parameter Tests
+ *
+ * Sampling and length settings across protocols, fitted to the capabilities of the target model
+ * leniently (dropped or clamped) or strictly (rejected).
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, convert_request, ConversionType};
+use aiclient2api_rust::ir::capabilities::{self, lookup};
+use aiclient2api_rust::ir::{self, ParamPolicy, UnsupportedContent};
+use serde_json::{json, Value};
+
+fn convert(data: Value, from: ModelProtocol, to: ModelProtocol) -> Value {
+    convert_data(data, ConversionType::Request, from, to, None).unwrap()
+}
+
+fn openai_request(model: &str, params: Value) -> Value {
+    let mut body = json!({"model": model, "messages": [{"role": "user", "content": "Hi"}]});
+    body.as_object_mut().unwrap().extend(params.as_object().unwrap().clone());
+    body
+}
+
+#[test]
+fn test_parameters_map_between_openai_and_gemini() {
+    let openai = openai_request("gemini-2.0-flash", json!({
+        "max_tokens": 1000,
+        "temperature": 0.5,
+        "top_p": 0.9,
+        "stop": ["END"],
+        "seed": 42,
+        "presence_penalty": 0.5,
+        "frequency_penalty": -0.5,
+        "n": 2,
+        "logprobs": true,
+        "top_logprobs": 3,
+    }));
+    let gemini = convert(openai, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    assert_eq!(
+        gemini["generationConfig"],
+        json!({
+            "maxOutputTokens": 1000,
+            "temperature": 0.5,
+            "topP": 0.9,
+            "stopSequences": ["END"],
+            "seed": 42,
+            "presencePenalty": 0.5,
+            "frequencyPenalty": -0.5,
+            "candidateCount": 2,
+            "responseLogprobs": true,
+            "logprobs": 3,
+        })
+    );
+
+    let mut gemini = gemini;
+    gemini["model"] = json!("gpt-4o");
+    let back = convert(gemini, ModelProtocol::Gemini, ModelProtocol::OpenAI);
+    assert_eq!(back["seed"], 42);
+    assert_eq!(back["n"], 2);
+    assert_eq!(back["top_logprobs"], 3);
+    assert_eq!(back["logprobs"], true);
+
+    // Reasoning models take `max_completion_tokens` and no sampling settings
+    let request = ir::parse_request(ModelProtocol::OpenAI, &openai_request("gpt-4o", json!({"max_tokens": 500, "temperature": 0.3}))).unwrap();
+    let mut o3 = request.clone();
+    o3.model = Some("o3-mini".to_string());
+    let body = ir::emit_request(ModelProtocol::OpenAI, &o3).unwrap();
+    assert_eq!(body["max_completion_tokens"], 500);
+    assert!(body.get("max_tokens").is_none());
+    assert!(body.get("temperature").is_none());
+    let body = ir::emit_request(ModelProtocol::OpenAI, &request).unwrap();
+    assert_eq!(body["max_tokens"], 500);
+    assert_eq!(body["temperature"], 0.3);
+}
+
+#[test]
+fn test_lenient_mode_drops_and_clamps_to_the_model() {
+    let openai = openai_request("claude-3-5-sonnet-20241022", json!({
+        "max_tokens": 20000,
+        "temperature": 1.8,
+        "seed": 7,
+        "presence_penalty": 1.0,
+        "n": 3,
+        "logprobs": true,
+    }));
+    let claude = convert(openai, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["max_tokens"], 8192);
+    assert_eq!(claude["temperature"], 1.0);
+    for param in ["seed", "presence_penalty", "n", "logprobs"] {
+        assert!(claude.get(param).is_none(), "{} should be dropped", param);
+    }
+
+    // Without a limit Claude gets the model's own
+    let claude = convert(openai_request("claude-sonnet-4-20250514", json!({})), ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["max_tokens"], lookup(ModelProtocol::Claude, "claude-sonnet-4-20250514").max_output_tokens);
+    let claude = convert(openai_request("claude-3-haiku-20240307", json!({})), ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["max_tokens"], 4096);
+
+    // Gemini takes five stop sequences and no penalties on 2.5
+    let stops: Vec<String> = (0..7).map(|i| format!("S{}", i)).collect();
+    let openai = openai_request("gemini-2.5-pro", json!({"stop": stops, "frequency_penalty": 0.2, "max_tokens": 100000}));
+    let gemini = convert(openai, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    let config = &gemini["generationConfig"];
+    assert_eq!(config["stopSequences"].as_array().unwrap().len(), 5);
+    assert!(config.get("frequencyPenalty").is_none());
+    assert_eq!(config["maxOutputTokens"], 65536);
+}
+
+#[test]
+fn test_strict_mode_rejects_what_the_model_cant_take() {
+    let request = |params: Value| ir::parse_request(ModelProtocol::OpenAI, &openai_request("claude-sonnet-4", params)).unwrap();
+    let strict = |params: Value| {
+        ir::emit_request_with(ModelProtocol::Claude, &request(params), ParamPolicy::Strict)
+            .unwrap_err()
+            .downcast::<UnsupportedContent>()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(strict(json!({"seed": 1})), "Claude requests can't carry seed (claude-sonnet-4)");
+    assert_eq!(
+        strict(json!({"temperature": 1.5})),
+        "Claude requests can't carry temperature 1.5 (at most 1 for claude-sonnet-4)"
+    );
+    assert_eq!(
+        strict(json!({"max_tokens": 100000})),
+        "Claude requests can't carry max_tokens 100000 (at most 64000 for claude-sonnet-4)"
+    );
+
+    // What fits goes through unchanged
+    let fitting = request(json!({"max_tokens": 1000, "temperature": 0.7, "stop": ["a", "b"]}));
+    assert_eq!(capabilities::fit(&fitting, ModelProtocol::Claude, ParamPolicy::Strict).unwrap(), fitting);
+    assert!(ir::emit_request_with(ModelProtocol::Claude, &fitting, ParamPolicy::Strict).is_ok());
+
+    assert_eq!(ParamPolicy::parse("strict"), Some(ParamPolicy::Strict));
+    assert_eq!(ParamPolicy::parse("loose"), None);
+}
+
+#[test]
+fn test_upstream_requests_are_fitted_under_the_configured_policy() {
+    let rejection = |body: Value, from: ModelProtocol, to: ModelProtocol| {
+        convert_request(body, from, to, None, ParamPolicy::Strict)
+            .unwrap_err()
+            .downcast::<UnsupportedContent>()
+            .map(|e| e.to_string())
+    };
+    // Rejected requests are answered with 400, for every target and also within one protocol
+    assert_eq!(
+        rejection(openai_request("claude-sonnet-4", json!({"seed": 1})), ModelProtocol::OpenAI, ModelProtocol::Claude).unwrap(),
+        "Claude requests can't carry seed (claude-sonnet-4)"
+    );
+    assert_eq!(
+        rejection(openai_request("gemini-2.5-pro", json!({"presence_penalty": 0.5})), ModelProtocol::OpenAI, ModelProtocol::Gemini).unwrap(),
+        "Gemini requests can't carry presence_penalty (gemini-2.5-pro)"
+    );
+    let claude = json!({"model": "claude-sonnet-4", "max_tokens": 1000, "temperature": 1.5, "messages": [{"role": "user", "content": "Hi"}]});
+    assert_eq!(
+        rejection(claude.clone(), ModelProtocol::Claude, ModelProtocol::Claude).unwrap(),
+        "Claude requests can't carry temperature 1.5 (at most 1 for claude-sonnet-4)"
+    );
+
+    // Leniently the same request is clamped, while one that fits passes through as it is
+    let lenient = convert_request(claude.clone(), ModelProtocol::Claude, ModelProtocol::Claude, None, ParamPolicy::Lenient).unwrap();
+    assert_eq!(lenient["temperature"], 1.0);
+    let mut fitting = claude;
+    fitting["temperature"] = json!(0.5);
+    fitting["metadata"] = json!({"user_id": "u1"});
+    assert_eq!(
+        convert_request(fitting.clone(), ModelProtocol::Claude, ModelProtocol::Claude, None, ParamPolicy::Strict).unwrap(),
+        fitting
+    );
+}
@@ -0,0 +1,330 @@
+/*!
+ * Pool Tests
+ *
+ * Unit tests for provider pool selection strategies.
+ */
+
+use aiclient2api_rust::config::ProviderConfig;
+use aiclient2api_rust::pool_manager::ProviderPoolManager;
+use aiclient2api_rust::pool_selection::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn member(uuid: &str) -> MemberStats {
+    MemberStats {
+        uuid: uuid.to_string(),
+        weight: 1,
+        last_used: None,
+        in_flight: 0,
+        latency_ewma_ms: None,
+        remaining_quota: None,
+    }
+}
+
+fn provider(value: serde_json::Value) -> ProviderConfig {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn test_strategy_kind_parsing() {
+    assert_eq!(SelectionStrategyKind::parse("round-robin"), Some(SelectionStrategyKind::RoundRobin));
+    assert_eq!(SelectionStrategyKind::parse("quota-aware"), Some(SelectionStrategyKind::QuotaAware));
+    assert_eq!(SelectionStrategyKind::parse("random"), None);
+}
+
+#[test]
+fn test_round_robin_cycles() {
+    let strategy = create_strategy(SelectionStrategyKind::RoundRobin);
+    let candidates = vec![member("a"), member("b"), member("c")];
+
+    let picks: Vec<usize> = (0..6).map(|_| strategy.select(&candidates).unwrap()).collect();
+    assert_eq!(picks, vec![0, 1, 2, 0, 1, 2]);
+}
+
+#[test]
+fn test_weighted_distribution() {
+    let strategy = create_strategy(SelectionStrategyKind::Weighted);
+    let mut heavy = member("heavy");
+    heavy.weight = 3;
+    let candidates = vec![heavy, member("light")];
+
+    let mut counts = [0; 2];
+    for _ in 0..8 {
+        counts[strategy.select(&candidates).unwrap()] += 1;
+    }
+    assert_eq!(counts, [6, 2]);
+}
+
+#[test]
+fn test_least_in_flight_and_lowest_latency() {
+    let mut busy = member("busy");
+    busy.in_flight = 4;
+    let mut idle = member("idle");
+    idle.in_flight = 1;
+    let candidates = vec![busy, idle];
+    assert_eq!(create_strategy(SelectionStrategyKind::LeastInFlight).select(&candidates), Some(1));
+
+    let mut slow = member("slow");
+    slow.latency_ewma_ms = Some(900.0);
+    let mut fast = member("fast");
+    fast.latency_ewma_ms = Some(120.0);
+    let strategy = create_strategy(SelectionStrategyKind::LowestLatency);
+    assert_eq!(strategy.select(&[slow.clone(), fast.clone()]), Some(1));
+
+    // Unmeasured members are probed first
+    assert_eq!(strategy.select(&[slow, fast, member("new")]), Some(2));
+}
+
+#[test]
+fn test_quota_aware_prefers_remaining_budget() {
+    let mut low = member("low");
+    low.remaining_quota = Some(3);
+    let mut high = member("high");
+    high.remaining_quota = Some(40);
+    let strategy = create_strategy(SelectionStrategyKind::QuotaAware);
+    assert_eq!(strategy.select(&[low.clone(), high]), Some(1));
+
+    let mut empty = member("empty");
+    empty.remaining_quota = Some(0);
+    assert_eq!(strategy.select(&[empty]), None);
+}
+
+#[tokio::test]
+async fn test_pool_manager_quota_and_health() {
+    let mut pools = HashMap::new();
+    pools.insert(
+        "gemini-cli-oauth".to_string(),
+        vec![
+            provider(json!({"uuid": "free", "daily_quota": 1})),
+            provider(json!({"uuid": "paid", "is_healthy": false})),
+        ],
+    );
+    let manager = ProviderPoolManager::new(pools);
+
+    let first = manager.select_provider("gemini-cli-oauth").await.unwrap();
+    assert_eq!(first.uuid, "free");
+    assert_eq!(first.usage_count, 1);
+
+    // Free member exhausted its daily budget and the paid one is unhealthy
+    assert!(manager.select_provider("gemini-cli-oauth").await.is_none());
+
+    manager.mark_provider_healthy("gemini-cli-oauth", "paid").await;
+    let second = manager.select_provider("gemini-cli-oauth").await.unwrap();
+    assert_eq!(second.uuid, "paid");
+
+    for _ in 0..3 {
+        manager
+            .record_result("gemini-cli-oauth", "paid", Duration::from_millis(10), false)
+            .await;
+    }
+    assert!(manager.select_provider("gemini-cli-oauth").await.is_none());
+}
+
+#[test]
+fn test_session_key_sources() {
+    let request = json!({
+        "user": "alice",
+        "messages": [{"role": "user", "content": "Hello"}]
+    });
+    assert_eq!(derive_session_key(Some("conv-1"), &request).as_deref(), Some("header:conv-1"));
+    assert_eq!(derive_session_key(None, &request).as_deref(), Some("user:alice"));
+
+    // Without header or user, later turns of the same conversation hash to the same key
+    let first_turn = json!({"system": "Be terse", "messages": [{"role": "user", "content": "Hi"}]});
+    let later_turn = json!({"system": "Be terse", "messages": [
+        {"role": "user", "content": "Hi"},
+        {"role": "assistant", "content": "Hello"},
+        {"role": "user", "content": "More"}
+    ]});
+    let key = derive_session_key(None, &first_turn).unwrap();
+    assert!(key.starts_with("prefix:"));
+    assert_eq!(Some(key), derive_session_key(None, &later_turn));
+
+    assert_eq!(derive_session_key(None, &json!({})), None);
+
+    // OpenAI conversations of one agent share its system prompt but not their first user turn
+    let conversation = |first: &str| {
+        json!({"messages": [
+            {"role": "system", "content": "You are a coding agent"},
+            {"role": "user", "content": first},
+        ]})
+    };
+    let one = derive_session_key(None, &conversation("Fix the parser")).unwrap();
+    let other = derive_session_key(None, &conversation("Write the docs")).unwrap();
+    assert_ne!(one, other);
+    let mut later = conversation("Fix the parser");
+    later["messages"].as_array_mut().unwrap().extend([
+        json!({"role": "assistant", "content": "Done"}),
+        json!({"role": "user", "content": "Thanks"}),
+    ]);
+    assert_eq!(derive_session_key(None, &later).unwrap(), one);
+    // A different system prompt is a different conversation
+    let mut developer = conversation("Fix the parser");
+    developer["messages"][0] = json!({"role": "developer", "content": "You are a reviewer"});
+    assert_ne!(derive_session_key(None, &developer).unwrap(), one);
+}
+
+#[tokio::test]
+async fn test_sticky_session_falls_back_when_unhealthy() {
+    let mut pools = HashMap::new();
+    pools.insert(
+        "claude-kiro-oauth".to_string(),
+        vec![
+            provider(json!({"uuid": "kiro-1"})),
+            provider(json!({"uuid": "kiro-2"})),
+            provider(json!({"uuid": "kiro-3"})),
+        ],
+    );
+    let manager = ProviderPoolManager::new(pools);
+
+    let pinned = manager
+        .select_provider_for_session("claude-kiro-oauth", Some("user:bob"))
+        .await
+        .unwrap();
+    for _ in 0..5 {
+        let again = manager
+            .select_provider_for_session("claude-kiro-oauth", Some("user:bob"))
+            .await
+            .unwrap();
+        assert_eq!(again.uuid, pinned.uuid);
+    }
+
+    manager.mark_provider_unhealthy("claude-kiro-oauth", &pinned.uuid).await;
+    let fallback = manager
+        .select_provider_for_session("claude-kiro-oauth", Some("user:bob"))
+        .await
+        .unwrap();
+    assert_ne!(fallback.uuid, pinned.uuid);
+}
+
+#[tokio::test]
+async fn test_reconcile_keeps_runtime_state_by_uuid() {
+    let mut pools = HashMap::new();
+    pools.insert(
+        "openai-custom".to_string(),
+        vec![
+            provider(json!({"uuid": "keep", "OPENAI_API_KEY": "sk-old"})),
+            provider(json!({"uuid": "drop", "OPENAI_API_KEY": "sk-drop"})),
+            provider(json!({"uuid": "fixed", "OPENAI_API_KEY": "sk-broken"})),
+        ],
+    );
+    let manager = ProviderPoolManager::new(pools);
+    manager.set_strategy("openai-custom", SelectionStrategyKind::LeastRecentlyUsed).await;
+    manager.select_provider("openai-custom").await.unwrap();
+    manager.select_provider("openai-custom").await.unwrap();
+    manager.mark_provider_unhealthy("openai-custom", "keep").await;
+    manager.mark_provider_unhealthy("openai-custom", "fixed").await;
+
+    let mut reloaded = HashMap::new();
+    reloaded.insert(
+        "openai-custom".to_string(),
+        vec![
+            provider(json!({"uuid": "keep", "OPENAI_API_KEY": "sk-old", "weight": 5})),
+            provider(json!({"uuid": "added", "OPENAI_API_KEY": "sk-added"})),
+            provider(json!({"uuid": "fixed", "OPENAI_API_KEY": "sk-new"})),
+        ],
+    );
+    manager.reconcile(reloaded).await;
+
+    let members = manager.members("openai-custom").await;
+    assert_eq!(members.len(), 3);
+    let kept = members.iter().find(|m| m.uuid == "keep").unwrap();
+    assert_eq!(kept.weight, 5);
+    assert_eq!(kept.usage_count, 1);
+    assert!(!kept.is_healthy);
+
+    // New credentials get a fresh start
+    let fixed = members.iter().find(|m| m.uuid == "fixed").unwrap();
+    assert_eq!(fixed.credentials["OPENAI_API_KEY"], "sk-new");
+    assert!(fixed.is_healthy);
+
+    let added = members.iter().find(|m| m.uuid == "added").unwrap();
+    assert_eq!(added.usage_count, 0);
+    assert!(added.is_healthy);
+}
+
+#[tokio::test]
+async fn test_reset_counters_and_persist_pools() {
+    let mut pools = HashMap::new();
+    pools.insert(
+        "openai-custom".to_string(),
+        vec![provider(json!({"uuid": "a", "OPENAI_API_KEY": "sk-a", "daily_quota": 10}))],
+    );
+    let manager = ProviderPoolManager::new(pools);
+    manager.select_provider("openai-custom").await.unwrap();
+    manager.record_result("openai-custom", "a", Duration::from_millis(50), false).await;
+    manager.mark_provider_unhealthy("openai-custom", "a").await;
+
+    let status = manager.status().await;
+    let a = &status["openai-custom"][0];
+    assert_eq!(a.daily_usage, 1);
+    assert_eq!(a.remaining_quota, Some(9));
+    assert_eq!(a.config.error_count, 1);
+
+    assert!(manager.contains("openai-custom", "a").await);
+    assert!(!manager.contains("openai-custom", "b").await);
+    assert!(!manager.reset_counters("openai-custom", "b").await);
+    assert!(manager.reset_counters("openai-custom", "a").await);
+
+    let status = manager.status().await;
+    let a = &status["openai-custom"][0];
+    assert_eq!(a.config.usage_count, 0);
+    assert_eq!(a.config.error_count, 0);
+    assert_eq!(a.remaining_quota, Some(10));
+
+    let path = std::env::temp_dir().join(format!("pools-{}.json", std::process::id()));
+    manager.save_to_file(&path).await.unwrap();
+    let saved: HashMap<String, Vec<ProviderConfig>> =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let saved = &saved["openai-custom"][0];
+    assert_eq!(saved.credentials["OPENAI_API_KEY"], "sk-a");
+    assert_eq!(saved.daily_quota, Some(10));
+    assert!(!saved.is_healthy);
+}
+
+#[tokio::test]
+async fn test_selection_prefers_members_with_free_slots() {
+    let mut pools = HashMap::new();
+    pools.insert(
+        "gemini-cli-oauth".to_string(),
+        vec![
+            provider(json!({"uuid": "busy", "max_concurrency": 1})),
+            provider(json!({"uuid": "idle", "max_concurrency": 1})),
+        ],
+    );
+    let manager = ProviderPoolManager::new(pools);
+    manager.set_strategy("gemini-cli-oauth", SelectionStrategyKind::RoundRobin).await;
+
+    let first = manager.select_provider("gemini-cli-oauth").await.unwrap();
+    let second = manager.select_provider("gemini-cli-oauth").await.unwrap();
+    assert_ne!(first.uuid, second.uuid);
+
+    // Both are at their limit: selection still succeeds and the request queues
+    assert!(manager.select_provider("gemini-cli-oauth").await.is_some());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_dropped_lease_outcomes_are_recorded_before_saving() {
+    let mut pools = HashMap::new();
+    pools.insert("openai-custom".to_string(), vec![provider(json!({"uuid": "a", "OPENAI_API_KEY": "sk-a"}))]);
+    let manager = std::sync::Arc::new(ProviderPoolManager::new(pools));
+
+    // Outcomes are recorded on spawned tasks, which have not run yet on this thread
+    for _ in 0..3 {
+        let mut lease = manager.lease("openai-custom", "a");
+        lease.fail();
+    }
+    manager.settle_leases().await;
+
+    let dir = std::env::temp_dir().join(format!("aic2api-settle-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("provider_pools.json");
+    manager.save_to_file(&path).await.unwrap();
+    let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved["openai-custom"][0]["error_count"], 3);
+    assert_eq!(saved["openai-custom"][0]["is_healthy"], false);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
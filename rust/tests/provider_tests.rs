@@ -6,16 +6,14 @@
 
 #[cfg(test)]
 mod tests {
-    use serde_json::json;
-
     #[test]
     fn test_model_provider_parsing() {
         use aiclient2api_rust::common::ModelProvider;
         
-        assert_eq!(ModelProvider::from_str("gemini-cli-oauth"), Some(ModelProvider::GeminiCliOAuth));
-        assert_eq!(ModelProvider::from_str("openai-custom"), Some(ModelProvider::OpenAICustom));
-        assert_eq!(ModelProvider::from_str("claude-custom"), Some(ModelProvider::ClaudeCustom));
-        assert_eq!(ModelProvider::from_str("invalid"), None);
+        assert_eq!(ModelProvider::parse("gemini-cli-oauth"), Some(ModelProvider::GeminiCliOAuth));
+        assert_eq!(ModelProvider::parse("openai-custom"), Some(ModelProvider::OpenAICustom));
+        assert_eq!(ModelProvider::parse("claude-custom"), Some(ModelProvider::ClaudeCustom));
+        assert_eq!(ModelProvider::parse("invalid"), None);
     }
 
     #[test]
@@ -83,5 +81,26 @@ mod tests {
         // Test no key
         assert!(!is_authorized(None, None, None, None, required_key));
     }
-}
 
+    #[tokio::test]
+    async fn test_static_key_providers_cannot_force_a_token_refresh() {
+        use aiclient2api_rust::adapter::{create_adapter, TokenRefreshUnsupported};
+        use aiclient2api_rust::common::ModelProvider;
+        use aiclient2api_rust::config::Config;
+        use aiclient2api_rust::retry::RetryBudget;
+        use std::sync::Arc;
+
+        let config = Config {
+            openai_api_key: Some("sk-test".into()),
+            claude_api_key: Some("sk-ant-test".into()),
+            ..Config::default()
+        };
+        let budget = Arc::new(RetryBudget::new());
+        for provider in [ModelProvider::OpenAICustom, ModelProvider::ClaudeCustom] {
+            let adapter = create_adapter(provider, &config, &budget).await.unwrap();
+            // The expiry-gated refresh is a no-op, a forced one says it can't be done
+            assert!(adapter.refresh_token().await.is_ok());
+            assert!(adapter.refresh_token_forced().await.unwrap_err().is::<TokenRefreshUnsupported>());
+        }
+    }
+}
@@ -0,0 +1,166 @@
+/*!
+ * Reasoning Tests
+ *
+ * Thinking settings, thinking blocks with their signatures, and streamed reasoning across protocols.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::{self, Reasoning, ReasoningEffort, StreamConverter};
+use serde_json::{json, Value};
+
+fn convert(data: Value, kind: ConversionType, from: ModelProtocol, to: ModelProtocol) -> Value {
+    convert_data(data, kind, from, to, None).unwrap()
+}
+
+#[test]
+fn test_thinking_settings_convert_between_protocols() {
+    let openai = json!({"model": "o3", "messages": [{"role": "user", "content": "Hi"}], "reasoning_effort": "high"});
+    let claude = convert(openai, ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["thinking"], json!({"type": "enabled", "budget_tokens": 24576}));
+    // max_tokens leaves room for the answer after the budget
+    assert!(claude["max_tokens"].as_u64().unwrap() > 24576);
+
+    let claude = json!({
+        "model": "claude-sonnet-4",
+        "max_tokens": 16000,
+        "temperature": 0.2,
+        "thinking": {"type": "enabled", "budget_tokens": 10000},
+        "messages": [{"role": "user", "content": "Hi"}],
+    });
+    let gemini = convert(claude.clone(), ConversionType::Request, ModelProtocol::Claude, ModelProtocol::Gemini);
+    assert_eq!(
+        gemini["generationConfig"]["thinkingConfig"],
+        json!({"thinkingBudget": 10000, "includeThoughts": true})
+    );
+    let openai = convert(claude.clone(), ConversionType::Request, ModelProtocol::Claude, ModelProtocol::OpenAI);
+    assert_eq!(openai["reasoning_effort"], "medium");
+    // Claude rejects sampling changes while thinking
+    let back = ir::emit_request(ModelProtocol::Claude, &ir::parse_request(ModelProtocol::Claude, &claude).unwrap()).unwrap();
+    assert!(back.get("temperature").is_none());
+
+    let gemini = |thinking: Value| json!({
+        "contents": [{"role": "user", "parts": [{"text": "Hi"}]}],
+        "generationConfig": {"thinkingConfig": thinking},
+    });
+    let parsed = |body: Value| ir::parse_request(ModelProtocol::Gemini, &body).unwrap().reasoning;
+    assert_eq!(parsed(gemini(json!({"thinkingBudget": 0}))), Some(Reasoning::Disabled));
+    assert_eq!(parsed(gemini(json!({"thinkingBudget": -1}))), Some(Reasoning::Effort(ReasoningEffort::Medium)));
+    assert_eq!(parsed(gemini(json!({"thinkingLevel": "LOW"}))), Some(Reasoning::Effort(ReasoningEffort::Low)));
+    let claude = convert(gemini(json!({"thinkingBudget": 0})), ConversionType::Request, ModelProtocol::Gemini, ModelProtocol::Claude);
+    assert_eq!(claude["thinking"], json!({"type": "disabled"}));
+}
+
+#[test]
+fn test_thinking_blocks_keep_their_signatures() {
+    let claude = json!({
+        "id": "msg_1",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-sonnet-4",
+        "content": [
+            {"type": "thinking", "thinking": "Let me check.", "signature": "sig-1"},
+            {"type": "redacted_thinking", "data": "opaque"},
+            {"type": "text", "text": "Done."}
+        ],
+        "stop_reason": "end_turn",
+        "usage": {"input_tokens": 5, "output_tokens": 7},
+    });
+    let openai = convert(claude.clone(), ConversionType::Response, ModelProtocol::Claude, ModelProtocol::OpenAI);
+    let message = &openai["choices"][0]["message"];
+    assert_eq!(message["content"], "Done.");
+    assert_eq!(message["reasoning_content"], "Let me check.");
+    assert_eq!(
+        message["thinking_blocks"],
+        json!([
+            {"type": "thinking", "thinking": "Let me check.", "signature": "sig-1"},
+            {"type": "redacted_thinking", "data": "opaque"}
+        ])
+    );
+
+    // The client sends the blocks back on the next turn, and Claude gets them as they were
+    let request = json!({"model": "claude-sonnet-4", "messages": [
+        {"role": "user", "content": "Check"},
+        message.clone(),
+        {"role": "user", "content": "Thanks"}
+    ]});
+    let back = convert(request, ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(back["messages"][1]["content"], claude["content"]);
+
+    // Unsigned reasoning can't be replayed to Claude
+    let request = json!({"messages": [
+        {"role": "user", "content": "Check"},
+        {"role": "assistant", "content": "Done.", "reasoning_content": "Hmm"},
+    ]});
+    let back = convert(request, ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(back["messages"][1]["content"], json!([{"type": "text", "text": "Done."}]));
+
+    // Gemini thoughts stay out of the text, and a signature goes back on the part that carried it
+    let gemini = json!({"candidates": [{"content": {"role": "model", "parts": [
+        {"text": "Planning", "thought": true},
+        {"functionCall": {"name": "read_file", "args": {"path": "a"}}, "thoughtSignature": "gsig"}
+    ]}, "finishReason": "STOP"}]});
+    let claude = convert(gemini.clone(), ConversionType::Response, ModelProtocol::Gemini, ModelProtocol::Claude);
+    let content = &claude["content"];
+    assert_eq!(content[0], json!({"type": "thinking", "thinking": "Planning", "signature": ""}));
+    assert_eq!(content[1], json!({"type": "thinking", "thinking": "", "signature": "gsig"}));
+    assert_eq!(content[2]["type"], "tool_use");
+    let back = convert(claude, ConversionType::Response, ModelProtocol::Claude, ModelProtocol::Gemini);
+    assert_eq!(back["candidates"][0]["content"]["parts"], gemini["candidates"][0]["content"]["parts"]);
+}
+
+#[test]
+fn test_streamed_reasoning_converts_between_protocols() {
+    let chunk = |delta: Value, finish: Value| {
+        json!({"id": "chatcmpl-1", "model": "o3", "choices": [{"index": 0, "delta": delta, "finish_reason": finish}]})
+    };
+    let chunks = [
+        chunk(json!({"role": "assistant", "reasoning_content": "Think"}), Value::Null),
+        chunk(json!({"reasoning_content": "ing"}), Value::Null),
+        chunk(json!({"thinking_blocks": [{"type": "thinking", "thinking": "", "signature": "sig-1"}]}), Value::Null),
+        chunk(json!({"content": "Answer"}), Value::Null),
+        chunk(json!({}), json!("stop")),
+    ];
+
+    let mut claude = StreamConverter::new(ModelProtocol::OpenAI, ModelProtocol::Claude, None).unwrap();
+    let mut events = Vec::new();
+    for chunk in &chunks {
+        events.extend(claude.convert(chunk).unwrap());
+    }
+    events.extend(claude.finish());
+    let blocks: Vec<&Value> = events.iter().filter(|e| e["type"] == "content_block_start").collect();
+    assert_eq!(blocks[0]["content_block"]["type"], "thinking");
+    assert_eq!(blocks[1]["content_block"]["type"], "text");
+    let deltas: Vec<&Value> = events.iter().filter(|e| e["type"] == "content_block_delta").map(|e| &e["delta"]).collect();
+    assert_eq!(deltas[0], &json!({"type": "thinking_delta", "thinking": "Think"}));
+    assert_eq!(deltas[2], &json!({"type": "signature_delta", "signature": "sig-1"}));
+    assert_eq!(deltas[3], &json!({"type": "text_delta", "text": "Answer"}));
+
+    // Claude events to Gemini: thoughts are marked, and the signature rides on the next part
+    let mut gemini = StreamConverter::new(ModelProtocol::Claude, ModelProtocol::Gemini, Some("gemini-2.5-pro")).unwrap();
+    let mut gemini_chunks = Vec::new();
+    for event in &events {
+        gemini_chunks.extend(gemini.convert(event).unwrap());
+    }
+    gemini_chunks.extend(gemini.finish());
+    let parts: Vec<&Value> = gemini_chunks
+        .iter()
+        .flat_map(|c| c["candidates"][0]["content"]["parts"].as_array().into_iter().flatten())
+        .collect();
+    assert_eq!(parts[0], &json!({"text": "Think", "thought": true}));
+    assert_eq!(parts[2], &json!({"text": "Answer", "thoughtSignature": "sig-1"}));
+
+    // And Gemini back to OpenAI
+    let mut openai = StreamConverter::new(ModelProtocol::Gemini, ModelProtocol::OpenAI, Some("gemini-2.5-pro")).unwrap();
+    let mut deltas = Vec::new();
+    for chunk in &gemini_chunks {
+        deltas.extend(openai.convert(chunk).unwrap());
+    }
+    let field = |key: &str| -> Vec<Value> {
+        deltas.iter().filter_map(|c| c["choices"][0]["delta"].get(key).cloned()).collect()
+    };
+    assert_eq!(field("reasoning_content"), [json!("Think"), json!("ing")]);
+    assert_eq!(field("thinking_blocks")[0][0]["signature"], "sig-1");
+    let content: Vec<Value> = field("content").into_iter().filter(|c| c.as_str().is_some_and(|c| !c.is_empty())).collect();
+    assert_eq!(content, [json!("Answer")]);
+}
@@ -0,0 +1,192 @@
+/*!
+ * Retry Tests
+ *
+ * Unit tests for the shared upstream retry policy.
+ */
+
+use aiclient2api_rust::retry::{
+    google_retry_delay, is_retryable_status, retry_after, upstream_status, DeadlineExceeded, RetryBudget,
+    RetryPolicy, RetrySettings,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn settings() -> RetrySettings {
+    RetrySettings {
+        max_retries: 3,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(50),
+        deadline: Duration::from_secs(5),
+        budget_ratio: 0.2,
+        budget_min_retries: 10,
+        budget_window: Duration::from_secs(10),
+    }
+}
+
+/// Server that answers with `failures` in order, then 200
+async fn flaky_server(failures: Vec<(StatusCode, HeaderMap, &'static str)>) -> (String, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let app = Router::new().route(
+        "/",
+        post(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            let response = failures
+                .get(n)
+                .cloned()
+                .unwrap_or((StatusCode::OK, HeaderMap::new(), "{}"));
+            async move { response }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, calls)
+}
+
+async fn send(policy: &RetryPolicy, url: &str) -> anyhow::Result<reqwest::Response> {
+    let client = reqwest::Client::new();
+    policy.send(|| async { Ok(client.post(url).send().await?) }).await
+}
+
+#[test]
+fn test_server_retry_hints() {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("retry-after", HeaderValue::from_static("7"));
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+    headers.insert("retry-after", HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+    assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+    let body = r#"{"error": {"code": 429, "details": [
+        {"@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "RATE_LIMIT_EXCEEDED"},
+        {"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "1.5s"}
+    ]}}"#;
+    assert_eq!(google_retry_delay(body), Some(Duration::from_millis(1500)));
+    assert_eq!(google_retry_delay(&format!("[{}]", body)), Some(Duration::from_millis(1500)));
+    assert_eq!(google_retry_delay("not json"), None);
+
+    assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+    assert!(is_retryable_status(StatusCode::from_u16(529).unwrap()));
+    assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+    assert!(!is_retryable_status(StatusCode::UNAUTHORIZED));
+}
+
+#[test]
+fn test_backoff_has_full_jitter_and_cap() {
+    let policy = RetryPolicy::new(settings(), Arc::new(RetryBudget::new()));
+    for attempt in 0..10 {
+        let ceiling = Duration::from_millis(10 * 2u64.pow(attempt)).min(Duration::from_millis(50));
+        assert!(policy.backoff(attempt) <= ceiling);
+    }
+    let delays: std::collections::HashSet<_> = (0..20).map(|_| policy.backoff(3)).collect();
+    assert!(delays.len() > 1);
+}
+
+#[tokio::test]
+async fn test_retries_transient_errors_only() {
+    let policy = RetryPolicy::new(settings(), Arc::new(RetryBudget::new()));
+
+    let mut retry_after = HeaderMap::new();
+    retry_after.insert("retry-after", HeaderValue::from_static("0"));
+    let (url, calls) = flaky_server(vec![
+        (StatusCode::SERVICE_UNAVAILABLE, HeaderMap::new(), "overloaded"),
+        (StatusCode::TOO_MANY_REQUESTS, retry_after, "slow down"),
+    ])
+    .await;
+    assert!(send(&policy, &url).await.unwrap().status().is_success());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    let (url, calls) = flaky_server(vec![(StatusCode::BAD_REQUEST, HeaderMap::new(), "bad request")]).await;
+    let error = send(&policy, &url).await.unwrap_err();
+    assert_eq!(upstream_status(&error), Some(StatusCode::BAD_REQUEST));
+    assert!(error.to_string().contains("bad request"));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let failures = vec![(StatusCode::BAD_GATEWAY, HeaderMap::new(), "down"); 10];
+    let (url, calls) = flaky_server(failures).await;
+    let error = send(&policy, &url).await.unwrap_err();
+    assert_eq!(upstream_status(&error), Some(StatusCode::BAD_GATEWAY));
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    // Nothing listens on a closed port: connection errors are retried too
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", closed.local_addr().unwrap());
+    drop(closed);
+    let attempts = AtomicUsize::new(0);
+    let client = reqwest::Client::new();
+    let result = policy
+        .send(|| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Ok(client.post(&url).send().await?)
+        })
+        .await;
+    assert!(result.is_err());
+    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_deadline_and_budget_stop_retries() {
+    // A server hint that ends past the deadline is not waited for
+    let mut retry_after = HeaderMap::new();
+    retry_after.insert("retry-after", HeaderValue::from_static("60"));
+    let (url, calls) = flaky_server(vec![(StatusCode::TOO_MANY_REQUESTS, retry_after, "quota")]).await;
+    let policy = RetryPolicy::new(settings(), Arc::new(RetryBudget::new()));
+    let started = std::time::Instant::now();
+    assert!(send(&policy, &url).await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // Budget of two retries, shared by every policy using it
+    let budget = Arc::new(RetryBudget::new());
+    let limited = RetrySettings {
+        budget_ratio: 0.0,
+        budget_min_retries: 2,
+        ..settings()
+    };
+    let failures = vec![(StatusCode::SERVICE_UNAVAILABLE, HeaderMap::new(), "down"); 10];
+    let (url, calls) = flaky_server(failures).await;
+    let first = RetryPolicy::new(limited, budget.clone());
+    let second = RetryPolicy::new(limited, budget.clone());
+    assert!(send(&first, &url).await.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    assert!(send(&second, &url).await.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_deadline_cuts_off_a_hanging_attempt() {
+    // The first attempt fails fast, the retry hangs past the deadline
+    let app = Router::new().route(
+        "/",
+        post({
+            let calls = Arc::new(AtomicUsize::new(0));
+            move || {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if n == 0 {
+                        return (StatusCode::SERVICE_UNAVAILABLE, "down");
+                    }
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    (StatusCode::OK, "{}")
+                }
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let short = RetrySettings {
+        deadline: Duration::from_millis(300),
+        ..settings()
+    };
+    let policy = RetryPolicy::new(short, Arc::new(RetryBudget::new()));
+    let started = std::time::Instant::now();
+    let error = send(&policy, &url).await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+    assert_eq!(error.downcast_ref::<DeadlineExceeded>(), Some(&DeadlineExceeded(Duration::from_millis(300))));
+}
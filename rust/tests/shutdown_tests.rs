@@ -0,0 +1,91 @@
+/*!
+ * Shutdown Tests
+ *
+ * Unit tests for stream draining and graceful shutdown of the listeners.
+ */
+
+use aiclient2api_rust::shutdown::Shutdown;
+use aiclient2api_rust::transport::{serve, Listener};
+use axum::body::Body;
+use axum::routing::get;
+use axum::Router;
+use futures::StreamExt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::test]
+async fn test_guarded_stream_ends_on_drain_timeout() {
+    let shutdown = Shutdown::new();
+    let items = futures::stream::iter(vec![Ok(1), Ok(2)]).chain(futures::stream::pending());
+    let mut stream = shutdown.guard_stream::<i32>(Box::pin(items));
+
+    assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+    assert_eq!(stream.next().await.unwrap().unwrap(), 2);
+    assert_eq!(shutdown.active_streams(), 1);
+
+    shutdown.end_streams();
+    let last = stream.next().await.unwrap();
+    assert!(last.unwrap_err().to_string().contains("shutting down"));
+    assert!(stream.next().await.is_none());
+    drop(stream);
+    assert_eq!(shutdown.active_streams(), 0);
+}
+
+#[tokio::test]
+async fn test_serve_drains_requests_then_returns() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Arc::new(Shutdown::new());
+
+    // One slow non-streaming request and one stream that never ends on its own
+    let stream_shutdown = shutdown.clone();
+    let app = Router::new()
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "done"
+            }),
+        )
+        .route(
+            "/stream",
+            get(move || {
+                let events = futures::stream::iter(vec![Ok(axum::body::Bytes::from("data: 1\n\n"))])
+                    .chain(futures::stream::pending());
+                let events = stream_shutdown.guard_stream(Box::pin(events));
+                async move { Body::from_stream(events) }
+            }),
+        );
+
+    let server = tokio::spawn(serve(
+        vec![Listener::Tcp(listener)],
+        app,
+        None,
+        shutdown.clone(),
+        Duration::from_millis(500),
+    ));
+
+    let request = |path: &'static str| async move {
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        let req = format!("GET {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n", path);
+        conn.write_all(req.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        let _ = conn.read_to_string(&mut response).await;
+        response
+    };
+    let slow = tokio::spawn(request("/slow"));
+    let stream = tokio::spawn(request("/stream"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let started = Instant::now();
+    shutdown.trigger();
+    server.await.unwrap().unwrap();
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(450), "returned before the drain timeout: {:?}", elapsed);
+
+    assert!(slow.await.unwrap().ends_with("done"));
+    assert!(stream.await.unwrap().contains("data: 1"));
+    assert!(TcpStream::connect(addr).await.is_err(), "listener should be closed");
+}
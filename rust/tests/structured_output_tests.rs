@@ -0,0 +1,162 @@
+/*!
+ * Structured Output Tests
+ *
+ * JSON mode and JSON schema output across protocols, emulated with a tool call where there is no
+ * native mode, and local validation of the answers.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::structured::{validate, InvalidOutput, OUTPUT_TOOL};
+use aiclient2api_rust::ir::{self, JsonSchemaFormat, ResponseFormat, StreamConverter};
+use serde_json::{json, Value};
+
+fn convert(data: Value, kind: ConversionType, from: ModelProtocol, to: ModelProtocol) -> Value {
+    convert_data(data, kind, from, to, None).unwrap()
+}
+
+fn person_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "name": {"type": "string"},
+            "age": {"type": "integer", "minimum": 0},
+            "role": {"enum": ["admin", "user"]}
+        },
+        "required": ["name", "age"],
+        "additionalProperties": false
+    })
+}
+
+fn openai_request(format: Value) -> Value {
+    json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Extract the person"}], "response_format": format})
+}
+
+#[test]
+fn test_response_format_maps_to_gemini() {
+    let format = json!({"type": "json_schema", "json_schema": {"name": "person", "schema": person_schema(), "strict": true}});
+    let gemini = convert(openai_request(format.clone()), ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    let config = &gemini["generationConfig"];
+    assert_eq!(config["responseMimeType"], "application/json");
+    // The schema goes through the Gemini sanitizer
+    assert!(config["responseSchema"].get("additionalProperties").is_none());
+    assert_eq!(config["responseSchema"]["properties"]["role"], json!({"type": "string", "enum": ["admin", "user"]}));
+
+    let back = convert(gemini, ConversionType::Request, ModelProtocol::Gemini, ModelProtocol::OpenAI);
+    assert_eq!(back["response_format"]["type"], "json_schema");
+    assert_eq!(back["response_format"]["json_schema"]["schema"]["required"], json!(["name", "age"]));
+
+    let gemini = convert(openai_request(json!({"type": "json_object"})), ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    assert_eq!(gemini["generationConfig"], json!({"responseMimeType": "application/json"}));
+    let parsed = ir::parse_request(ModelProtocol::Gemini, &gemini).unwrap();
+    assert_eq!(parsed.response_format, Some(ResponseFormat::JsonObject));
+
+    // Plain text asks for nothing
+    let text = convert(openai_request(json!({"type": "text"})), ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    assert!(text.get("generationConfig").is_none());
+}
+
+#[test]
+fn test_claude_and_kiro_answer_through_the_output_tool() {
+    let format = json!({"type": "json_schema", "json_schema": {"name": "person", "schema": person_schema()}});
+    let claude = convert(openai_request(format.clone()), ConversionType::Request, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["tools"][0]["name"], OUTPUT_TOOL);
+    assert_eq!(claude["tools"][0]["input_schema"], person_schema());
+    assert_eq!(claude["tool_choice"], json!({"type": "tool", "name": OUTPUT_TOOL}));
+
+    let kiro = ir::emit_request(ModelProtocol::Kiro, &ir::parse_request(ModelProtocol::OpenAI, &openai_request(format)).unwrap()).unwrap();
+    let context = &kiro["conversationState"]["currentMessage"]["userInputMessage"]["userInputMessageContext"];
+    assert_eq!(context["tools"][0]["toolSpecification"]["name"], OUTPUT_TOOL);
+
+    // The call comes back as the answer text
+    let response = json!({
+        "id": "msg_1",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-sonnet-4",
+        "content": [{"type": "tool_use", "id": "toolu_1", "name": OUTPUT_TOOL, "input": {"name": "Ada", "age": 36}}],
+        "stop_reason": "tool_use",
+        "usage": {"input_tokens": 10, "output_tokens": 5},
+    });
+    let openai = convert(response, ConversionType::Response, ModelProtocol::Claude, ModelProtocol::OpenAI);
+    let choice = &openai["choices"][0];
+    assert_eq!(choice["finish_reason"], "stop");
+    assert!(choice["message"].get("tool_calls").is_none());
+    let answer: Value = serde_json::from_str(choice["message"]["content"].as_str().unwrap()).unwrap();
+    assert_eq!(answer, json!({"name": "Ada", "age": 36}));
+
+    // Streamed, the call's input arrives as text deltas
+    let events = [
+        json!({"type": "message_start", "message": {"id": "msg_1", "model": "claude-sonnet-4", "usage": {"input_tokens": 10}}}),
+        json!({"type": "content_block_start", "index": 0, "content_block": {"type": "tool_use", "id": "toolu_1", "name": OUTPUT_TOOL, "input": {}}}),
+        json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": "{\"name\": \"Ada\","}}),
+        json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": " \"age\": 36}"}}),
+        json!({"type": "content_block_stop", "index": 0}),
+        json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 5}}),
+        json!({"type": "message_stop"}),
+    ];
+    let mut converter = StreamConverter::new(ModelProtocol::Claude, ModelProtocol::OpenAI, None).unwrap();
+    let mut chunks = Vec::new();
+    for event in &events {
+        chunks.extend(converter.convert(event).unwrap());
+    }
+    let text: String = chunks.iter().filter_map(|c| c["choices"][0]["delta"]["content"].as_str()).collect();
+    assert_eq!(text, "{\"name\": \"Ada\", \"age\": 36}");
+    assert!(chunks.iter().all(|c| c["choices"][0]["delta"].get("tool_calls").is_none()));
+    assert!(chunks.iter().any(|c| c["choices"][0]["finish_reason"] == "stop"));
+}
+
+#[test]
+fn test_answers_are_validated_against_the_schema() {
+    let format = ResponseFormat::JsonSchema(JsonSchemaFormat {
+        name: "person".to_string(),
+        description: None,
+        schema: person_schema(),
+        strict: true,
+    });
+    assert_eq!(validate(&format, r#"{"name": "Ada", "age": 36}"#).unwrap()["age"], 36);
+    assert!(validate(&format, "```json\n{\"name\": \"Ada\", \"age\": 36, \"role\": \"admin\"}\n```").is_ok());
+
+    let mismatch = |text: &str| match validate(&format, text) {
+        Err(InvalidOutput::Mismatch { path, message }) => format!("{}: {}", path, message),
+        other => panic!("expected a mismatch, got {:?}", other),
+    };
+    assert_eq!(mismatch(r#"{"name": "Ada"}"#), "$: missing required property \"age\"");
+    assert_eq!(mismatch(r#"{"name": "Ada", "age": "36"}"#), "$.age: expected integer, got string");
+    assert_eq!(mismatch(r#"{"name": "Ada", "age": -1}"#), "$.age: -1 is out of range");
+    assert_eq!(mismatch(r#"{"name": "Ada", "age": 36, "role": "owner"}"#), "$.role: \"owner\" is not one of [\"admin\",\"user\"]");
+    assert_eq!(mismatch(r#"{"name": "Ada", "age": 36, "email": "a@b.c"}"#), "$: unexpected property \"email\"");
+    assert!(matches!(validate(&format, "Sure! Here is the person."), Err(InvalidOutput::NotJson(_))));
+
+    // JSON mode takes any object
+    assert!(validate(&ResponseFormat::JsonObject, r#"{"anything": [1, 2]}"#).is_ok());
+    assert!(validate(&ResponseFormat::JsonObject, "[1, 2]").is_err());
+}
+
+#[test]
+fn test_retry_turns_keep_the_format_in_every_provider_protocol() {
+    // The server validates in the provider's protocol and asks again by appending two turns
+    let format = json!({"type": "json_schema", "json_schema": {"name": "person", "schema": person_schema(), "strict": true}});
+    for provider in [ModelProtocol::OpenAI, ModelProtocol::Claude, ModelProtocol::Gemini] {
+        let body = convert(openai_request(format.clone()), ConversionType::Request, ModelProtocol::OpenAI, provider);
+        let mut request = ir::parse_request(provider, &body).unwrap();
+        request.messages.push(ir::Message::new(ir::Role::Assistant, vec![ir::ContentPart::Text(r#"{"name": "Ada"}"#.to_string())]));
+        request.messages.push(ir::Message::new(ir::Role::User, vec![ir::ContentPart::Text("Try again".to_string())]));
+        let retry = ir::emit_request(provider, &request).unwrap();
+
+        assert_eq!(ir::parse_request(provider, &retry).unwrap().messages.len(), 3, "{:?}", provider);
+        match provider {
+            // The output tool was declared in the first request and is declared once again
+            ModelProtocol::Claude => {
+                let tools: Vec<_> = retry["tools"].as_array().unwrap().iter().filter(|tool| tool["name"] == OUTPUT_TOOL).collect();
+                assert_eq!(tools.len(), 1);
+                assert_eq!(retry["tool_choice"], body["tool_choice"]);
+            }
+            _ => {
+                let asked = ir::parse_request(provider, &body).unwrap().response_format;
+                assert!(asked.is_some(), "{:?}", provider);
+                assert_eq!(ir::parse_request(provider, &retry).unwrap().response_format, asked);
+            }
+        }
+    }
+}
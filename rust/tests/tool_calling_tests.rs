@@ -0,0 +1,203 @@
+/*!
+ * Tool Calling Tests
+ *
+ * Tool definitions, tool choice, call IDs, tool results and streamed tool calls across protocols.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::{self, ContentPart, StreamConverter, ToolChoice};
+use serde_json::{json, Value};
+
+fn tools() -> Value {
+    json!([{"type": "function", "function": {
+        "name": "read_file", "parameters": {"type": "object", "properties": {"path": {"type": "string"}}}
+    }}])
+}
+
+fn convert_request(data: Value, from: ModelProtocol, to: ModelProtocol) -> Value {
+    convert_data(data, ConversionType::Request, from, to, None).unwrap()
+}
+
+#[test]
+fn test_tool_choice_converts_in_every_direction() {
+    let cases = [
+        (json!("auto"), ToolChoice::Auto),
+        (json!("none"), ToolChoice::None),
+        (json!("required"), ToolChoice::Required),
+        (json!({"type": "function", "function": {"name": "read_file"}}), ToolChoice::Tool("read_file".to_string())),
+    ];
+    for (choice, expected) in cases {
+        let openai = json!({
+            "messages": [{"role": "user", "content": "Hi"}],
+            "tools": tools(),
+            "tool_choice": choice,
+        });
+        for protocol in [ModelProtocol::OpenAI, ModelProtocol::Claude, ModelProtocol::Gemini] {
+            let emitted = convert_request(openai.clone(), ModelProtocol::OpenAI, protocol);
+            let parsed = ir::parse_request(protocol, &emitted).unwrap();
+            assert_eq!(parsed.tool_choice, Some(expected.clone()), "{:?}", protocol);
+        }
+    }
+
+    let openai = json!({
+        "messages": [{"role": "user", "content": "Hi"}],
+        "tools": tools(),
+        "tool_choice": {"type": "function", "function": {"name": "read_file"}},
+        "parallel_tool_calls": false,
+    });
+    let claude = convert_request(openai.clone(), ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(claude["tool_choice"], json!({"type": "tool", "name": "read_file", "disable_parallel_tool_use": true}));
+    let gemini = convert_request(openai, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    assert_eq!(
+        gemini["toolConfig"]["functionCallingConfig"],
+        json!({"mode": "ANY", "allowedFunctionNames": ["read_file"]})
+    );
+
+    // Server tools have no equivalent outside Claude
+    let claude = json!({
+        "model": "claude-sonnet-4",
+        "max_tokens": 100,
+        "messages": [{"role": "user", "content": "Hi"}],
+        "tools": [{"type": "web_search_20250305", "name": "web_search"}, {"name": "read_file", "input_schema": {"type": "object"}}],
+    });
+    let openai = convert_request(claude, ModelProtocol::Claude, ModelProtocol::OpenAI);
+    assert_eq!(openai["tools"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_call_ids_and_tool_results_survive_conversion() {
+    // OpenAI tool messages carry no name; it comes from the call with the same ID
+    let openai = json!({
+        "messages": [
+            {"role": "user", "content": "Read both"},
+            {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_a", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\":\"a\"}"}},
+                {"id": "call_b", "type": "function", "function": {"name": "list_dir", "arguments": "{}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "call_b", "content": "a.txt"},
+            {"role": "tool", "tool_call_id": "call_a", "content": "contents"}
+        ],
+        "tools": tools(),
+    });
+    let gemini = convert_request(openai, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    let parts = &gemini["contents"][2]["parts"];
+    assert_eq!(parts[0]["functionResponse"]["name"], "list_dir");
+    assert_eq!(parts[1]["functionResponse"]["name"], "read_file");
+
+    // Gemini calls have no IDs: they are synthesized and each response paired with its call
+    let back = convert_request(gemini, ModelProtocol::Gemini, ModelProtocol::OpenAI);
+    let messages = back["messages"].as_array().unwrap();
+    let calls = messages[1]["tool_calls"].as_array().unwrap();
+    let id_of = |name: &str| calls.iter().find(|c| c["function"]["name"] == name).unwrap()["id"].clone();
+    assert_eq!(messages[2]["tool_call_id"], id_of("list_dir"));
+    assert_eq!(messages[3]["tool_call_id"], id_of("read_file"));
+    assert_ne!(id_of("list_dir"), id_of("read_file"));
+
+    // Response calls get fresh IDs
+    let response = json!({"candidates": [{"content": {"role": "model", "parts": [
+        {"functionCall": {"name": "read_file", "args": {"path": "a"}}},
+        {"functionCall": {"name": "read_file", "args": {"path": "b"}}}
+    ]}, "finishReason": "STOP"}]});
+    let parsed = ir::parse_response(ModelProtocol::Gemini, &response).unwrap();
+    let ids: Vec<&str> = parsed
+        .content
+        .iter()
+        .filter_map(|part| match part {
+            ContentPart::ToolCall(call) => Some(call.id.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids[0].starts_with("call_") && ids[0] != ids[1]);
+
+    // A tool result with an image and an error
+    let claude = json!({
+        "model": "claude-sonnet-4",
+        "max_tokens": 100,
+        "messages": [
+            {"role": "assistant", "content": [{"type": "tool_use", "id": "toolu_1", "name": "screenshot", "input": {}}]},
+            {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "toolu_1", "is_error": true, "content": [
+                {"type": "text", "text": "Partial capture"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}}
+            ]}]}
+        ],
+    });
+    let gemini = convert_request(claude.clone(), ModelProtocol::Claude, ModelProtocol::Gemini);
+    let parts = &gemini["contents"][1]["parts"];
+    assert_eq!(parts[0]["functionResponse"]["name"], "screenshot");
+    assert_eq!(parts[0]["functionResponse"]["response"], json!({"error": "Partial capture"}));
+    assert_eq!(parts[1]["inlineData"]["mimeType"], "image/png");
+
+    let openai = convert_request(claude, ModelProtocol::Claude, ModelProtocol::OpenAI);
+    let messages = openai["messages"].as_array().unwrap();
+    assert_eq!(messages[1]["role"], "tool");
+    assert_eq!(messages[1]["content"], "Partial capture");
+    assert_eq!(messages[2]["role"], "user");
+    assert_eq!(messages[2]["content"][1]["image_url"]["url"], "data:image/png;base64,iVBORw0KGgo=");
+}
+
+#[test]
+fn test_parallel_tool_calls_stream_across_protocols() {
+    let chunk = |delta: Value, finish: Value| {
+        json!({"id": "chatcmpl-1", "model": "gpt-4o", "choices": [{"index": 0, "delta": delta, "finish_reason": finish}]})
+    };
+    let chunks = [
+        chunk(json!({"role": "assistant", "tool_calls": [
+            {"index": 0, "id": "call_a", "type": "function", "function": {"name": "read_file", "arguments": ""}}
+        ]}), Value::Null),
+        chunk(json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"path\":"}}]}), Value::Null),
+        chunk(json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"a\"}"}}]}), Value::Null),
+        chunk(json!({"tool_calls": [
+            {"index": 1, "id": "call_b", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\""}}
+        ]}), Value::Null),
+        chunk(json!({"tool_calls": [{"index": 1, "function": {"arguments": ":\"b\"}"}}]}), Value::Null),
+        chunk(json!({}), json!("tool_calls")),
+    ];
+
+    let mut claude = StreamConverter::new(ModelProtocol::OpenAI, ModelProtocol::Claude, None).unwrap();
+    let mut gemini = StreamConverter::new(ModelProtocol::OpenAI, ModelProtocol::Gemini, None).unwrap();
+    let mut claude_events = Vec::new();
+    let mut gemini_chunks = Vec::new();
+    for chunk in &chunks {
+        claude_events.extend(claude.convert(chunk).unwrap());
+        gemini_chunks.extend(gemini.convert(chunk).unwrap());
+    }
+    claude_events.extend(claude.finish());
+    gemini_chunks.extend(gemini.finish());
+
+    // Claude gets one block per call, each closed before the next starts, with the arguments streamed
+    let kinds: Vec<String> = claude_events
+        .iter()
+        .map(|e| format!("{}:{}", e["type"].as_str().unwrap(), e["index"]))
+        .collect();
+    assert_eq!(&kinds[1..4], ["content_block_start:0", "content_block_delta:0", "content_block_delta:0"]);
+    assert_eq!(&kinds[4..8], ["content_block_stop:0", "content_block_start:1", "content_block_delta:1", "content_block_delta:1"]);
+    assert_eq!(claude_events[5]["content_block"]["id"], "call_b");
+    let message_delta = claude_events.iter().find(|e| e["type"] == "message_delta").unwrap();
+    assert_eq!(message_delta["delta"]["stop_reason"], "tool_use");
+
+    // Gemini gets each call whole
+    let calls: Vec<&Value> = gemini_chunks
+        .iter()
+        .filter_map(|c| c["candidates"][0]["content"]["parts"][0].get("functionCall"))
+        .collect();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0]["args"], json!({"path": "a"}));
+    assert_eq!(calls[1]["args"], json!({"path": "b"}));
+
+    // And back: Gemini calls streamed to OpenAI get distinct IDs and tool call indexes
+    let mut openai = StreamConverter::new(ModelProtocol::Gemini, ModelProtocol::OpenAI, Some("gemini-2.5-pro")).unwrap();
+    let mut deltas = Vec::new();
+    for chunk in &gemini_chunks {
+        deltas.extend(openai.convert(chunk).unwrap());
+    }
+    let starts: Vec<&Value> = deltas
+        .iter()
+        .filter_map(|c| c["choices"][0]["delta"]["tool_calls"].get(0))
+        .filter(|call| call.get("id").is_some())
+        .collect();
+    assert_eq!(starts.len(), 2);
+    assert_eq!((starts[0]["index"].clone(), starts[1]["index"].clone()), (json!(0), json!(1)));
+    assert_ne!(starts[0]["id"], starts[1]["id"]);
+}
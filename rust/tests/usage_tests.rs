@@ -0,0 +1,154 @@
+/*!
+ * Stop Reason and Usage Tests
+ *
+ * Finish reasons, including safety blocks, and token counts with cached input, cache writes and
+ * reasoning, across protocols.
+ */
+
+use aiclient2api_rust::common::ModelProtocol;
+use aiclient2api_rust::convert::{convert_data, ConversionType};
+use aiclient2api_rust::ir::{self, StopReason, StreamConverter, Usage};
+use serde_json::{json, Value};
+
+fn convert(data: Value, from: ModelProtocol, to: ModelProtocol) -> Value {
+    convert_data(data, ConversionType::Response, from, to, None).unwrap()
+}
+
+fn gemini_response(finish_reason: &str) -> Value {
+    json!({"candidates": [{"content": {"role": "model", "parts": [{"text": "..."}]}, "finishReason": finish_reason}]})
+}
+
+#[test]
+fn test_finish_reasons_map_between_protocols() {
+    // Gemini finish reason → IR, OpenAI, Claude
+    let cases = [
+        ("STOP", StopReason::EndTurn, "stop", "end_turn"),
+        ("MAX_TOKENS", StopReason::MaxTokens, "length", "max_tokens"),
+        ("SAFETY", StopReason::ContentFilter, "content_filter", "refusal"),
+        ("PROHIBITED_CONTENT", StopReason::ContentFilter, "content_filter", "refusal"),
+        ("RECITATION", StopReason::Recitation, "content_filter", "refusal"),
+        ("MALFORMED_FUNCTION_CALL", StopReason::MalformedToolCall, "stop", "end_turn"),
+        ("OTHER", StopReason::EndTurn, "stop", "end_turn"),
+    ];
+    for (gemini, stop_reason, openai, claude) in cases {
+        let response = gemini_response(gemini);
+        assert_eq!(ir::parse_response(ModelProtocol::Gemini, &response).unwrap().stop_reason, Some(stop_reason), "{}", gemini);
+        assert_eq!(convert(response.clone(), ModelProtocol::Gemini, ModelProtocol::OpenAI)["choices"][0]["finish_reason"], openai);
+        assert_eq!(convert(response, ModelProtocol::Gemini, ModelProtocol::Claude)["stop_reason"], claude);
+    }
+    // Recitation survives a round trip through the IR
+    let back = ir::emit_response(ModelProtocol::Gemini, &ir::parse_response(ModelProtocol::Gemini, &gemini_response("RECITATION")).unwrap()).unwrap();
+    assert_eq!(back["candidates"][0]["finishReason"], "RECITATION");
+
+    // A blocked prompt has no candidates at all
+    let blocked = json!({"promptFeedback": {"blockReason": "SAFETY"}, "usageMetadata": {"promptTokenCount": 9}});
+    let openai = convert(blocked.clone(), ModelProtocol::Gemini, ModelProtocol::OpenAI);
+    assert_eq!(openai["choices"][0]["finish_reason"], "content_filter");
+    let mut stream = StreamConverter::new(ModelProtocol::Gemini, ModelProtocol::Claude, None).unwrap();
+    let mut events = stream.convert(&blocked).unwrap();
+    events.extend(stream.finish());
+    let delta = events.iter().find(|e| e["type"] == "message_delta").unwrap();
+    assert_eq!(delta["delta"]["stop_reason"], "refusal");
+
+    // Claude's own reasons
+    let claude = |reason: &str| {
+        json!({"id": "msg_1", "type": "message", "role": "assistant", "model": "claude-sonnet-4",
+               "content": [{"type": "text", "text": "..."}], "stop_reason": reason,
+               "usage": {"input_tokens": 1, "output_tokens": 1}})
+    };
+    let reason = |body: Value| ir::parse_response(ModelProtocol::Claude, &body).unwrap().stop_reason;
+    assert_eq!(reason(claude("pause_turn")), Some(StopReason::PauseTurn));
+    assert_eq!(reason(claude("model_context_window_exceeded")), Some(StopReason::MaxTokens));
+    let back = ir::emit_response(ModelProtocol::Claude, &ir::parse_response(ModelProtocol::Claude, &claude("pause_turn")).unwrap()).unwrap();
+    assert_eq!(back["stop_reason"], "pause_turn");
+    assert_eq!(convert(claude("refusal"), ModelProtocol::Claude, ModelProtocol::Gemini)["candidates"][0]["finishReason"], "SAFETY");
+}
+
+#[test]
+fn test_cache_and_reasoning_tokens_convert_in_every_direction() {
+    let claude = json!({
+        "id": "msg_1",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-sonnet-4",
+        "content": [{"type": "text", "text": "Hi"}],
+        "stop_reason": "end_turn",
+        "usage": {"input_tokens": 10, "cache_creation_input_tokens": 200, "cache_read_input_tokens": 1000, "output_tokens": 50},
+    });
+    let usage = ir::parse_response(ModelProtocol::Claude, &claude).unwrap().usage;
+    assert_eq!(
+        usage,
+        Usage { input_tokens: 1210, output_tokens: 50, cache_read_input_tokens: 1000, cache_creation_input_tokens: 200, reasoning_tokens: 0 }
+    );
+    let openai = convert(claude.clone(), ModelProtocol::Claude, ModelProtocol::OpenAI);
+    assert_eq!(openai["usage"]["prompt_tokens"], 1210);
+    assert_eq!(openai["usage"]["prompt_tokens_details"]["cached_tokens"], 1000);
+    assert_eq!(openai["usage"]["cache_creation_input_tokens"], 200);
+    assert_eq!(openai["usage"]["total_tokens"], 1260);
+    // And back, Claude gets its own split
+    let back = convert(openai, ModelProtocol::OpenAI, ModelProtocol::Claude);
+    assert_eq!(back["usage"], claude["usage"]);
+
+    // Gemini counts thoughts apart from the answer
+    let gemini = json!({
+        "candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}, "finishReason": "STOP"}],
+        "usageMetadata": {"promptTokenCount": 100, "cachedContentTokenCount": 60, "candidatesTokenCount": 20, "thoughtsTokenCount": 30, "totalTokenCount": 150},
+    });
+    let openai = convert(gemini.clone(), ModelProtocol::Gemini, ModelProtocol::OpenAI);
+    assert_eq!(
+        openai["usage"],
+        json!({
+            "prompt_tokens": 100,
+            "completion_tokens": 50,
+            "total_tokens": 150,
+            "prompt_tokens_details": {"cached_tokens": 60},
+            "completion_tokens_details": {"reasoning_tokens": 30},
+        })
+    );
+    let back = convert(openai, ModelProtocol::OpenAI, ModelProtocol::Gemini);
+    assert_eq!(back["usageMetadata"], gemini["usageMetadata"]);
+    let claude = convert(gemini, ModelProtocol::Gemini, ModelProtocol::Claude);
+    assert_eq!(claude["usage"], json!({"input_tokens": 40, "output_tokens": 50, "cache_read_input_tokens": 60}));
+}
+
+#[test]
+fn test_streamed_usage_and_kiro_estimates() {
+    // Claude streams input counts at the start and output counts at the end
+    let events = [
+        json!({"type": "message_start", "message": {"id": "msg_1", "model": "claude-sonnet-4",
+               "usage": {"input_tokens": 5, "cache_read_input_tokens": 95, "output_tokens": 1}}}),
+        json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+        json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hi"}}),
+        json!({"type": "content_block_stop", "index": 0}),
+        json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 7}}),
+        json!({"type": "message_stop"}),
+    ];
+    let mut openai = StreamConverter::new(ModelProtocol::Claude, ModelProtocol::OpenAI, None).unwrap();
+    let mut chunks = Vec::new();
+    for event in &events {
+        chunks.extend(openai.convert(event).unwrap());
+    }
+    let usage = chunks.iter().rev().find_map(|c| c.get("usage").filter(|u| !u.is_null())).unwrap();
+    assert_eq!(usage["prompt_tokens"], 100);
+    assert_eq!(usage["prompt_tokens_details"]["cached_tokens"], 95);
+    assert_eq!(usage["completion_tokens"], 7);
+
+    // And Claude events carry the whole count in `message_delta`
+    let mut claude = StreamConverter::new(ModelProtocol::Claude, ModelProtocol::Claude, None).unwrap();
+    let mut out = Vec::new();
+    for event in &events {
+        out.extend(claude.convert(event).unwrap());
+    }
+    let delta = out.iter().find(|e| e["type"] == "message_delta").unwrap();
+    assert_eq!(delta["usage"], json!({"input_tokens": 5, "output_tokens": 7, "cache_read_input_tokens": 95}));
+
+    // CodeWhisperer reports no usage; the input is estimated from the request
+    let request = ir::parse_request(ModelProtocol::Claude, &json!({
+        "model": "claude-sonnet-4",
+        "max_tokens": 100,
+        "system": "x".repeat(400),
+        "messages": [{"role": "user", "content": "y".repeat(800)}],
+    }))
+    .unwrap();
+    assert_eq!(ir::kiro::estimate_input_tokens(&request), 300);
+}